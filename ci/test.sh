@@ -8,6 +8,12 @@ RUSTFLAGS=${RUSTFLAGS:---deny warnings}
 # require enabling Cargo features, which Cargo does not support in that case.
 echo "Running tests with Cargo..."
 cargo test -p rubble
+cargo test -p rubble --features fuzz
+cargo test -p rubble --features testing
+cargo test -p rubble --features async
+cargo test -p rubble --features alloc
+cargo test -p rubble --features profiling
+cargo check -p rubble --features defmt
 
 # Check that the device crates build with all feature combinations.
 # Only use `cargo check` because the PAC crates are very slow to build.
@@ -55,20 +55,23 @@ use {
         target::UARTE0,
         uarte::{Baudrate, Parity, Uarte},
     },
+    rtfm::Mutex,
     rubble::{
         config::Config,
         gatt::BatteryServiceAttrs,
         l2cap::{BleChannelMap, L2CAPState},
         link::{
             ad_structure::AdStructure,
+            encryption::NoKeys,
             queue::{PacketQueue, SimpleConsumer, SimpleProducer, SimpleQueue},
-            AddressKind, DeviceAddress, LinkLayer, Responder, MIN_PDU_BUF,
+            AddressKind, ConnectionHandle, DeviceAddress, LinkLayer, Responder, MIN_PDU_BUF,
         },
         security::NoSecurity,
         time::{Duration, Timer},
     },
     rubble_nrf52::{
         radio::{BleRadio, PacketBuffer},
+        rng::BleRng,
         timer::BleTimer,
     },
 };
@@ -78,11 +81,14 @@ pub enum AppConfig {}
 impl Config for AppConfig {
     type Timer = BleTimer<hal::target::TIMER0>;
     type Transmitter = BleRadio;
+    type Rng = BleRng;
+    type KeyStore = NoKeys;
     type ChannelMapper = BleChannelMap<BatteryServiceAttrs, NoSecurity>;
 
     type PacketQueue = &'static mut SimpleQueue;
     type PacketProducer = SimpleProducer<'static>;
     type PacketConsumer = SimpleConsumer<'static>;
+    type L2CAPBuffer = [u8; 0];
 }
 
 #[rtfm::app(device = crate::hal::target, peripherals = true)]
@@ -161,7 +167,12 @@ const APP: () = {
         let ble_r = Responder::new(
             tx,
             rx,
-            L2CAPState::new(BleChannelMap::with_attributes(BatteryServiceAttrs::new())),
+            L2CAPState::with_buffers(
+                ConnectionHandle::SINGLE,
+                BleChannelMap::with_attributes(BatteryServiceAttrs::new()),
+                [],
+                [],
+            ),
         );
 
         // Send advertisement and set up regular interrupt
@@ -244,8 +255,16 @@ const APP: () = {
         }
     }
 
-    #[task(resources = [ble_r])]
-    fn ble_worker(ctx: ble_worker::Context) {
+    #[task(resources = [ble_r, ble_ll])]
+    fn ble_worker(mut ctx: ble_worker::Context) {
+        // Let the ATT server know when encryption was started before it processes requests
+        let level = ctx.resources.ble_ll.lock(|ble_ll| ble_ll.security_level());
+        ctx.resources
+            .ble_r
+            .l2cap()
+            .att_server()
+            .set_security_level(level);
+
         // Fully drain the packet queue
         while ctx.resources.ble_r.has_work() {
             ctx.resources.ble_r.process_one().unwrap();
@@ -4,4 +4,5 @@
 #![warn(rust_2018_idioms)]
 
 pub mod radio;
+pub mod rng;
 pub mod timer;
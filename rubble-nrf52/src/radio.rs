@@ -59,7 +59,7 @@ use {
     rubble::{
         config::Config,
         link::{advertising, data, Cmd, LinkLayer, RadioCmd, Transmitter, CRC_POLY, MIN_PDU_BUF},
-        phy::{AdvertisingChannel, DataChannel},
+        phy::{AdvertisingChannel, DataChannel, Phy},
         time::{Duration, Instant},
     },
 };
@@ -193,8 +193,8 @@ impl BleRadio {
 
         match cmd {
             RadioCmd::Off => {}
-            RadioCmd::ListenAdvertising { channel } => {
-                self.prepare_txrx_advertising(channel);
+            RadioCmd::ListenAdvertising { channel, phy } => {
+                self.prepare_txrx_advertising(channel, phy);
 
                 let rx_buf = (*self.rx_buf.as_mut().unwrap()) as *mut _ as u32;
                 self.radio.packetptr.write(|w| unsafe { w.bits(rx_buf) });
@@ -230,6 +230,8 @@ impl BleRadio {
                         .enabled()
                         .ready_start()
                         .enabled()
+                        .address_rssistart()
+                        .enabled()
                 });
 
                 let rx_buf = (*self.rx_buf.as_mut().unwrap()) as *mut _ as u32;
@@ -295,6 +297,11 @@ impl BleRadio {
             let rx_buf = self.rx_buf.take().unwrap();
             let pl_lim = cmp::min(2 + usize::from(header.payload_length()), rx_buf.len());
             let payload = &rx_buf[2..pl_lim];
+            if crc_ok {
+                // The RSSI was sampled while receiving the packet (`ADDRESS_RSSISTART` short)
+                let rssi = self.radio.rssisample.read().rssisample().bits();
+                ll.record_rssi(-(rssi as i8));
+            }
             let cmd = ll.process_data_packet(timestamp, self, header, payload, crc_ok);
             self.rx_buf = Some(rx_buf);
             cmd
@@ -306,7 +313,10 @@ impl BleRadio {
     /// Perform preparations to receive or send on an advertising channel.
     ///
     /// This will disable the radio, configure the packet layout, set initial values for CRC and
-    /// whitening, and set the frequency to the given `channel`.
+    /// whitening, and set the frequency to the given `channel` and the mode to the given `phy`.
+    ///
+    /// Only the nRF52840 supports the coded PHY. Passing a coded `phy` on other parts, or `Le2M`
+    /// (which is never used on the advertising channels), will panic.
     ///
     /// To **transmit**, the `txaddress` must be set and the `packetptr` must be set to the TX
     /// buffer.
@@ -315,7 +325,7 @@ impl BleRadio {
     /// `packetptr` must be pointed to the RX buffer.
     ///
     /// Of course, other tasks may also be performed.
-    fn prepare_txrx_advertising(&mut self, channel: AdvertisingChannel) {
+    fn prepare_txrx_advertising(&mut self, channel: AdvertisingChannel, phy: Phy) {
         self.advertising = true;
 
         unsafe {
@@ -334,11 +344,42 @@ impl BleRadio {
         assert!(self.state().is_disabled());
 
         // Now we can freely configure all registers we need
-        unsafe {
-            self.radio
-                .pcnf0
-                .write(|w| w.s0len().bit(true).lflen().bits(8).s1len().bits(0));
+        match phy {
+            Phy::Le1M => unsafe {
+                self.radio.mode.write(|w| w.mode().ble_1mbit());
+                self.radio
+                    .pcnf0
+                    .write(|w| w.s0len().bit(true).lflen().bits(8).s1len().bits(0));
+            },
+            #[cfg(feature = "52840")]
+            Phy::LeCodedS2 | Phy::LeCodedS8 => unsafe {
+                // In RX, both long range modes accept S2 and S8 coded packets (as indicated by the
+                // CI field, which isn't stored in RAM).
+                self.radio.mode.write(|w| w.mode().ble_lr125kbit());
+                self.radio.pcnf0.write(|w| {
+                    w.s0len()
+                        .bit(true)
+                        .lflen()
+                        .bits(8)
+                        .s1len()
+                        .bits(0)
+                        // 2-bit Coding Indicator and 3-bit TERM1 field
+                        .cilen()
+                        .bits(2)
+                        .termlen()
+                        .bits(3)
+                        .plen()
+                        .long_range()
+                });
+            },
+            #[cfg(not(feature = "52840"))]
+            Phy::LeCodedS2 | Phy::LeCodedS8 => {
+                panic!("the coded PHY is only supported by the nRF52840");
+            }
+            Phy::Le2M => panic!("LE 2M PHY can not be used on the advertising channels"),
+        }
 
+        unsafe {
             self.radio
                 .datawhiteiv
                 .write(|w| w.datawhiteiv().bits(channel.whitening_iv()));
@@ -355,6 +396,8 @@ impl BleRadio {
         self.advertising = false;
 
         unsafe {
+            // A previous scan might have switched to the coded PHY
+            self.radio.mode.write(|w| w.mode().ble_1mbit());
             self.radio
                 .pcnf0
                 .write(|w| w.s0len().bit(true).lflen().bits(8).s1len().bits(0));
@@ -428,7 +471,7 @@ impl Transmitter for BleRadio {
         // Length = 6 bits, followed by 2 RFU bits (0)
         self.tx_buf[1] = header.payload_length();
 
-        self.prepare_txrx_advertising(channel);
+        self.prepare_txrx_advertising(channel, Phy::Le1M);
 
         // Set transmission address:
         // Logical addr. 0 uses BASE0 + PREFIX0, which is the canonical adv. Access Address
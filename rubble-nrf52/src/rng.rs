@@ -0,0 +1,43 @@
+//! `Rng` implementation backed by the on-chip hardware random number generator.
+
+#[cfg(feature = "52810")]
+use nrf52810_hal::nrf52810_pac as pac;
+
+#[cfg(feature = "52832")]
+use nrf52832_hal::nrf52832_pac as pac;
+
+#[cfg(feature = "52840")]
+use nrf52840_hal::nrf52840_pac as pac;
+
+use {pac::RNG, rubble::rng::Rng};
+
+/// Implements Rubble's `Rng` trait using the RNG peripheral.
+pub struct BleRng {
+    rng: RNG,
+}
+
+impl BleRng {
+    /// Initializes the RNG peripheral.
+    ///
+    /// Bias correction is enabled, so that the generated bytes are uniformly distributed.
+    pub fn init(rng: RNG) -> Self {
+        rng.config.write(|w| w.dercen().enabled());
+        Self { rng }
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        self.rng.events_valrdy.reset();
+        self.rng.tasks_start.write(|w| unsafe { w.bits(1) });
+        while self.rng.events_valrdy.read().bits() == 0 {}
+        self.rng.tasks_stop.write(|w| unsafe { w.bits(1) });
+        self.rng.value.read().value().bits()
+    }
+}
+
+impl Rng for BleRng {
+    fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for b in buf {
+            *b = self.next_byte();
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Notification throughput benchmark.
+//!
+//! Establishes a connection with a simulated master and streams *Handle Value Notifications* on the
+//! TX characteristic of the Nordic UART service through the ATT, L2CAP and Link-Layer
+//! implementations. The radio and timer are simulated, so the results are deterministic and only
+//! depend on the Link-Layer's scheduling behaviour:
+//!
+//! * The simulated master opens every connection event with an empty PDU and keeps the event open
+//!   for as long as the slave sets the `MD` bit and there's time left until the next event.
+//! * Notifications are enqueued by the application whenever the TX queue has space, so the link is
+//!   always saturated.
+//!
+//! For every configuration, the number of notification PDUs and value Bytes delivered per simulated
+//! second is reported, along with the host CPU time spent in the stack per connection event.
+//!
+//! Run with `cargo bench --bench notify_throughput`. The connection intervals (in milliseconds) and
+//! `ATT_MTU`s to measure can be passed as comma-separated lists:
+//!
+//! ```notrust
+//! cargo bench --bench notify_throughput -- interval=7.5,30 mtu=23
+//! ```
+//!
+//! Notification values are `ATT_MTU - 3` Bytes long. Values that don't fit into the `ATT_MTU`
+//! supported by the ATT server are truncated by the stack, which shows up in the reported Bytes/s.
+
+use {
+    rubble::{
+        config::Config,
+        gatt::NordicUartAttrs,
+        l2cap::{BleChannelMap, L2CAPState, MAX_MESSAGE_LEN},
+        link::{
+            advertising::{self, PduType},
+            data::{self, Llid},
+            encryption::NoKeys,
+            queue::{PacketQueue, SimpleConsumer, SimpleProducer, SimpleQueue},
+            AddressKind, DeviceAddress, LinkLayer, Responder, Transmitter, MIN_PAYLOAD_BUF,
+        },
+        phy::{AdvertisingChannel, DataChannel},
+        rng::Rng,
+        security::NoSecurity,
+        time::{Duration, Instant, Timer},
+    },
+    std::{env, process, time},
+};
+
+/// Simulated time that is measured for every configuration.
+const SIMULATED_TIME_MS: u32 = 20_000;
+
+/// Inter Frame Space between two consecutive packets in a connection event.
+const T_IFS: u32 = 150;
+
+/// A `Timer` that is moved forward by the simulated master.
+struct BenchTimer {
+    now: Instant,
+}
+
+impl Timer for BenchTimer {
+    fn now(&self) -> Instant {
+        self.now
+    }
+}
+
+/// A `Transmitter` that remembers the last data channel PDU sent by the slave.
+struct BenchTransmitter {
+    buf: [u8; MIN_PAYLOAD_BUF],
+    last: Option<data::Header>,
+}
+
+impl Transmitter for BenchTransmitter {
+    fn tx_payload_buf(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    fn transmit_advertising(&mut self, _header: advertising::Header, _channel: AdvertisingChannel) {
+        // Advertising isn't measured
+    }
+
+    fn transmit_data(
+        &mut self,
+        _access_address: u32,
+        _crc_iv: u32,
+        header: data::Header,
+        _channel: DataChannel,
+    ) {
+        self.last = Some(header);
+    }
+}
+
+/// Deterministic (and very much not random) `Rng`, only used for encryption, which isn't enabled.
+struct BenchRng;
+
+impl Rng for BenchRng {
+    fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for b in buf {
+            *b = 0;
+        }
+    }
+}
+
+enum BenchConfig {}
+
+impl Config for BenchConfig {
+    type Timer = BenchTimer;
+    type Transmitter = BenchTransmitter;
+    type Rng = BenchRng;
+    type KeyStore = NoKeys;
+    type ChannelMapper = BleChannelMap<NordicUartAttrs, NoSecurity>;
+
+    type PacketQueue = &'static mut SimpleQueue;
+    type PacketProducer = SimpleProducer<'static>;
+    type PacketConsumer = SimpleConsumer<'static>;
+    type L2CAPBuffer = [u8; MAX_MESSAGE_LEN];
+}
+
+/// Results of a single benchmark run.
+struct Throughput {
+    pdus: u32,
+    value_bytes: u32,
+    events: u32,
+    cpu_time: time::Duration,
+}
+
+/// Returns the on-air time of a data channel PDU with `payload_len` Bytes on the LE 1M PHY.
+fn air_time(payload_len: u8) -> u32 {
+    // Preamble, Access Address, Header and CRC take 10 Bytes, each Byte takes 8 µs
+    (10 + u32::from(payload_len)) * 8
+}
+
+/// Builds the `CONNECT_REQ` payload sent by the master to `adv`.
+fn connect_request(adv: &DeviceAddress, interval_units: u16) -> [u8; 34] {
+    let mut pdu = [0; 34];
+    pdu[..6].copy_from_slice(&[0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]); // InitA
+    pdu[6..12].copy_from_slice(adv.raw()); // AdvA
+    pdu[12..16].copy_from_slice(&0x5065_9A2Bu32.to_le_bytes()); // Access Address
+    pdu[16..19].copy_from_slice(&[0x12, 0x34, 0x56]); // CRC init
+    pdu[19] = 1; // WinSize
+    pdu[20..22].copy_from_slice(&0u16.to_le_bytes()); // WinOffset
+    pdu[22..24].copy_from_slice(&interval_units.to_le_bytes()); // Interval
+    pdu[24..26].copy_from_slice(&0u16.to_le_bytes()); // Latency
+    pdu[26..28].copy_from_slice(&400u16.to_le_bytes()); // Timeout (4 s)
+    pdu[28..33].copy_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x1f]); // Channel map
+    pdu[33] = 7; // Hop increment and SCA
+    pdu
+}
+
+/// Streams notifications over a connection with the given interval (in units of 1.25 ms) and
+/// `ATT_MTU`.
+fn run(interval_units: u16, mtu: u16) -> Throughput {
+    let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+    let mut ll = LinkLayer::<BenchConfig>::new(
+        addr,
+        BenchTimer {
+            now: Instant::from_raw_micros(0),
+        },
+    );
+    let mut radio = BenchTransmitter {
+        buf: [0; MIN_PAYLOAD_BUF],
+        last: None,
+    };
+
+    let tx_queue: &'static mut SimpleQueue = Box::leak(Box::new(SimpleQueue::new()));
+    let rx_queue: &'static mut SimpleQueue = Box::leak(Box::new(SimpleQueue::new()));
+    let (tx, tx_cons) = tx_queue.split();
+    let (rx_prod, rx) = rx_queue.split();
+    let mut responder = Responder::<BenchConfig>::new(
+        tx,
+        rx,
+        L2CAPState::new(BleChannelMap::with_attributes(NordicUartAttrs::new())),
+    );
+
+    ll.start_advertise(
+        Duration::from_millis(100),
+        &[],
+        &mut radio,
+        tx_cons,
+        rx_prod,
+    )
+    .unwrap();
+
+    let initiator = DeviceAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF], AddressKind::Public);
+    let mut header =
+        advertising::Header::with_addresses(PduType::ConnectReq, &initiator, Some(&addr));
+    header.set_payload_length(34);
+    let _cmd = ll.process_adv_packet(
+        Instant::from_raw_micros(0),
+        &mut radio,
+        header,
+        &connect_request(&addr, interval_units),
+        true,
+    );
+
+    let value = vec![0xAB; usize::from(mtu.saturating_sub(3))];
+    let interval = u32::from(interval_units) * 1250;
+    // The first anchor point lies at the start of the transmit window
+    let mut anchor = 1250;
+    let (mut sn, mut nesn) = (false, false);
+    let mut result = Throughput {
+        pdus: 0,
+        value_bytes: 0,
+        events: 0,
+        cpu_time: time::Duration::from_secs(0),
+    };
+
+    let start = time::Instant::now();
+    while anchor < SIMULATED_TIME_MS * 1000 {
+        let mut now = anchor;
+        loop {
+            // The application refills the TX queue whenever there's space
+            while let Some(att) = responder.l2cap().att() {
+                att.notify_raw(NordicUartAttrs::TX_HANDLE, &value).unwrap();
+            }
+
+            // Master sends an empty PDU, acknowledging the slave's last PDU
+            let mut raw = [0; 2];
+            raw[0] = Llid::DataCont as u8 | (u8::from(nesn) << 2) | (u8::from(sn) << 3);
+            let header = data::Header::parse(&raw);
+            now += air_time(0);
+            ll.timer().now = Instant::from_raw_micros(now);
+            let _cmd = ll.process_data_packet(
+                Instant::from_raw_micros(now),
+                &mut radio,
+                header,
+                &[],
+                true,
+            );
+
+            // Slave responds after T_IFS
+            let rsp = radio.last.take().expect("slave didn't respond");
+            let rsp_raw = rsp.to_u16();
+            let payload_len = rsp.payload_length();
+            now += T_IFS + air_time(payload_len) + T_IFS;
+
+            // Accept the slave's PDU and advance our own sequence number if it acknowledged ours
+            let (rsp_nesn, rsp_sn) = (rsp_raw & 0b0100 != 0, rsp_raw & 0b1000 != 0);
+            if rsp_nesn != sn {
+                sn = !sn;
+            }
+            if rsp_sn == nesn {
+                nesn = !nesn;
+                if rsp.llid() == Llid::DataStart && payload_len > 0 {
+                    // L2CAP header, ATT opcode and handle precede the value
+                    result.pdus += 1;
+                    result.value_bytes += u32::from(payload_len) - 4 - 3;
+                }
+            }
+
+            // Keep the event open if the slave has more data and the next exchange fits
+            let next_exchange = air_time(0) + T_IFS + air_time(payload_len) + T_IFS;
+            if !rsp.md() || now + next_exchange > anchor + interval {
+                break;
+            }
+        }
+
+        result.events += 1;
+        anchor += interval;
+    }
+    result.cpu_time = start.elapsed();
+
+    result
+}
+
+/// Parses a comma-separated list of values from an argument of the form `<name>=<list>`.
+fn parse_list<T>(arg: &str, name: &str, parse: impl Fn(&str) -> Option<T>) -> Option<Vec<T>> {
+    let prefix = format!("{}=", name);
+    if !arg.starts_with(&prefix) {
+        return None;
+    }
+
+    let values = arg[prefix.len()..]
+        .split(',')
+        .map(|v| parse(v.trim()))
+        .collect::<Option<Vec<_>>>();
+    if values.is_none() {
+        eprintln!("invalid value in `{}`", arg);
+        process::exit(1);
+    }
+    values
+}
+
+fn main() {
+    // Connection intervals in units of 1.25 ms: 7.5, 15, 30, 50 ms
+    let mut intervals = vec![6, 12, 24, 40];
+    let mut mtus = vec![23];
+
+    // `cargo bench` passes `--bench`, ignore all flags
+    for arg in env::args().skip(1).filter(|arg| !arg.starts_with("--")) {
+        if let Some(list) = parse_list(&arg, "interval", |ms| {
+            let units = ms.parse::<f32>().ok()? / 1.25;
+            // Valid connection intervals are 7.5 ms to 4 s
+            if units.fract() == 0.0 && (6.0..=3200.0).contains(&units) {
+                Some(units as u16)
+            } else {
+                None
+            }
+        }) {
+            intervals = list;
+        } else if let Some(list) = parse_list(&arg, "mtu", |mtu| {
+            mtu.parse::<u16>().ok().filter(|mtu| *mtu >= 23)
+        }) {
+            mtus = list;
+        } else {
+            eprintln!("unknown argument `{}`", arg);
+            eprintln!("usage: notify_throughput [interval=<ms>,...] [mtu=<bytes>,...]");
+            process::exit(1);
+        }
+    }
+
+    println!(
+        "{:>13} {:>7} {:>10} {:>11} {:>14}",
+        "interval (ms)", "ATT_MTU", "PDUs/s", "Bytes/s", "µs CPU/event"
+    );
+    for &interval in &intervals {
+        for &mtu in &mtus {
+            let result = run(interval, mtu);
+            let secs = SIMULATED_TIME_MS as f64 / 1000.0;
+            println!(
+                "{:>13} {:>7} {:>10.1} {:>11.1} {:>14.2}",
+                f64::from(interval) * 1.25,
+                mtu,
+                f64::from(result.pdus) / secs,
+                f64::from(result.value_bytes) / secs,
+                result.cpu_time.as_secs_f64() * 1e6 / f64::from(result.events),
+            );
+        }
+    }
+}
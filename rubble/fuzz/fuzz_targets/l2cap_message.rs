@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(pdu) = rubble::fuzz::l2cap_message(data) {
+        // Formatting the PDU exercises the `Debug` impls, which decode lazily parsed fields
+        let _ = format!("{:?}", pdu);
+    }
+});
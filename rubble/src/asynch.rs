@@ -0,0 +1,323 @@
+//! Executor-agnostic `async` wrappers around Link-Layer and ATT events.
+//!
+//! Rubble's core is synchronous: The Link-Layer is driven by the radio and timer interrupts (or
+//! `LinkLayer::poll`), and the `Responder` is called from the application's idle loop. This module,
+//! enabled by the `async` Cargo feature, lets `async` application code (eg. running on embassy or
+//! RTIC) wait for events produced by those synchronous parts instead of polling for them.
+//!
+//! A [`LinkEvents`] instance is shared between the stack and the application, usually as a
+//! `static`. Register it with `LinkLayer::set_events` and `AttributeServer::set_events`, after
+//! which the application can await [`LinkEvents::next_connection_event`] and
+//! [`LinkEvents::wait_indication_confirm`].
+//!
+//! # Waker contract
+//!
+//! The futures work with any executor. When polled, they store the `Waker` of the polling task,
+//! and the stack calls `Waker::wake` from whatever context it runs in when the event occurs. This
+//! can be an interrupt handler, so the executor's wakers must be safe to call from there (the
+//! wakers of common embedded executors are).
+//!
+//! Each [`Signal`] stores a single `Waker`, so only one task should wait for a particular event at
+//! a time. A future waiting for an event that was signalled while it wasn't being polled completes
+//! the next time it is polled, so no events are lost between creating a future and awaiting it.
+//!
+//! This module requires atomic compare-and-swap operations, which some targets (eg.
+//! `thumbv6m-none-eabi`) don't provide.
+//!
+//! # Example
+//!
+//! ```
+//! use rubble::asynch::LinkEvents;
+//!
+//! static EVENTS: LinkEvents = LinkEvents::new();
+//!
+//! async fn send_reading() {
+//!     // The future is created before sending the indication, so a confirmation that arrives
+//!     // before it is polled isn't missed
+//!     let confirmed = EVENTS.wait_indication_confirm();
+//!     // ...send the indication via `AttributeServerTx::indicate_raw`...
+//!     if confirmed.await.is_err() {
+//!         // ...the client didn't confirm in time, close the connection...
+//!     }
+//! }
+//! ```
+//!
+//! [`LinkEvents`]: struct.LinkEvents.html
+//! [`LinkEvents::next_connection_event`]: struct.LinkEvents.html#method.next_connection_event
+//! [`LinkEvents::wait_indication_confirm`]: struct.LinkEvents.html#method.wait_indication_confirm
+//! [`Signal`]: struct.Signal.html
+
+use {
+    crate::att::ConfirmationTimeout,
+    core::{
+        cell::UnsafeCell,
+        future::Future,
+        pin::Pin,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        task::{Context, Poll, Waker},
+    },
+};
+
+/// An event that tasks can wait for.
+pub struct Signal {
+    /// Incremented every time the event occurs.
+    generation: AtomicUsize,
+
+    /// Guards `waker`.
+    locked: AtomicBool,
+
+    /// Waker of the task waiting for the event.
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+// `waker` is only accessed while `locked` is held.
+unsafe impl Sync for Signal {}
+
+impl Signal {
+    /// Creates a `Signal` that hasn't occurred yet.
+    pub const fn new() -> Self {
+        Self {
+            generation: AtomicUsize::new(0),
+            locked: AtomicBool::new(false),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    /// Signals that the event occurred, waking the waiting task.
+    pub fn signal(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+
+        if !self.locked.swap(true, Ordering::Acquire) {
+            let waker = unsafe { (*self.waker.get()).take() };
+            self.locked.store(false, Ordering::Release);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+        // Otherwise, we interrupted `register`. The `Wait` future checks `generation` again
+        // afterwards, so it notices the event without being woken.
+    }
+
+    /// Returns a future that completes the next time the event occurs.
+    ///
+    /// Occurrences after this method returns count, even if the future wasn't polled yet.
+    pub fn wait(&self) -> Wait<'_> {
+        Wait {
+            signal: self,
+            generation: self.generation.load(Ordering::Acquire),
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        if self.locked.swap(true, Ordering::Acquire) {
+            // `signal` is taking the previous waker right now, and won't take this one. Make sure
+            // the task polls again.
+            waker.wake_by_ref();
+            return;
+        }
+
+        let slot = unsafe { &mut *self.waker.get() };
+        match slot {
+            Some(old) if old.will_wake(waker) => {}
+            _ => *slot = Some(waker.clone()),
+        }
+        self.locked.store(false, Ordering::Release);
+    }
+
+    fn occurred_since(&self, generation: usize) -> bool {
+        self.generation.load(Ordering::Acquire) != generation
+    }
+}
+
+impl Default for Signal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by `Signal::wait`.
+#[must_use = "futures do nothing unless polled"]
+pub struct Wait<'a> {
+    signal: &'a Signal,
+    generation: usize,
+}
+
+impl Future for Wait<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.signal.occurred_since(self.generation) {
+            return Poll::Ready(());
+        }
+
+        self.signal.register(cx.waker());
+
+        // The event might have occurred while the waker was being registered
+        if self.signal.occurred_since(self.generation) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Future returned by `LinkEvents::wait_indication_confirm`.
+#[must_use = "futures do nothing unless polled"]
+pub struct IndicationConfirm<'a> {
+    confirmed: Wait<'a>,
+    timed_out: Wait<'a>,
+}
+
+impl Future for IndicationConfirm<'_> {
+    type Output = Result<(), ConfirmationTimeout>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if Pin::new(&mut self.confirmed).poll(cx).is_ready() {
+            Poll::Ready(Ok(()))
+        } else if Pin::new(&mut self.timed_out).poll(cx).is_ready() {
+            Poll::Ready(Err(ConfirmationTimeout))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Events signalled by the Link-Layer and the ATT server.
+pub struct LinkEvents {
+    connection_event: Signal,
+    indication_confirmed: Signal,
+    indication_timed_out: Signal,
+}
+
+impl LinkEvents {
+    /// Creates a new set of events.
+    pub const fn new() -> Self {
+        Self {
+            connection_event: Signal::new(),
+            indication_confirmed: Signal::new(),
+            indication_timed_out: Signal::new(),
+        }
+    }
+
+    /// Returns a future that completes when the Link-Layer handles the next connection event.
+    ///
+    /// The event is signalled by `LinkLayer::update_timer` (and `LinkLayer::poll`) whenever the
+    /// timer fires during a connection. Received data is queued for the `Responder` by then, so
+    /// this is a good point for the application to process it.
+    pub fn next_connection_event(&self) -> Wait<'_> {
+        self.connection_event.wait()
+    }
+
+    /// Returns a future that completes when the client confirms an indication.
+    ///
+    /// If the client doesn't confirm the indication within the confirmation timeout, the future
+    /// completes with `Err(ConfirmationTimeout)` once the ATT server notices (see
+    /// `AttributeServer::check_confirmation_timeout`). No further ATT PDUs can be exchanged over
+    /// the connection in that case.
+    ///
+    /// Create the future before sending the indication, so that an early confirmation isn't missed.
+    pub fn wait_indication_confirm(&self) -> IndicationConfirm<'_> {
+        IndicationConfirm {
+            confirmed: self.indication_confirmed.wait(),
+            timed_out: self.indication_timed_out.wait(),
+        }
+    }
+
+    /// Returns the signal for connection events.
+    pub fn connection_event(&self) -> &Signal {
+        &self.connection_event
+    }
+
+    /// Returns the signal for confirmed indications.
+    pub fn indication_confirmed(&self) -> &Signal {
+        &self.indication_confirmed
+    }
+
+    /// Returns the signal for indications that weren't confirmed in time.
+    pub fn indication_timed_out(&self) -> &Signal {
+        &self.indication_timed_out
+    }
+}
+
+impl Default for LinkEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{sync::Arc, task::Wake},
+    };
+
+    /// A `Waker` that counts how often it was woken.
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn poll<F: Future + Unpin>(future: &mut F, waker: &Arc<CountingWaker>) -> Poll<F::Output> {
+        let waker = Waker::from(waker.clone());
+        Pin::new(future).poll(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn signal_wakes_waiter() {
+        let signal = Signal::new();
+        let waker = Arc::new(CountingWaker::default());
+
+        let mut wait = signal.wait();
+        assert_eq!(poll(&mut wait, &waker), Poll::Pending);
+        assert_eq!(poll(&mut wait, &waker), Poll::Pending);
+        assert_eq!(waker.0.load(Ordering::SeqCst), 0);
+
+        signal.signal();
+        assert_eq!(waker.0.load(Ordering::SeqCst), 1);
+        assert_eq!(poll(&mut wait, &waker), Poll::Ready(()));
+
+        // The waker was consumed
+        signal.signal();
+        assert_eq!(waker.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn signal_before_poll() {
+        let events = LinkEvents::new();
+        let waker = Arc::new(CountingWaker::default());
+
+        // Events before the future is created don't count, events after it do
+        events.connection_event().signal();
+        let mut wait = events.next_connection_event();
+        assert_eq!(poll(&mut wait, &waker), Poll::Pending);
+
+        let mut confirm = events.wait_indication_confirm();
+        events.indication_confirmed().signal();
+        assert_eq!(poll(&mut confirm, &waker), Poll::Ready(Ok(())));
+        assert_eq!(poll(&mut wait, &waker), Poll::Pending);
+    }
+
+    #[test]
+    fn indication_timeout() {
+        let events = LinkEvents::new();
+        let waker = Arc::new(CountingWaker::default());
+
+        let mut confirm = events.wait_indication_confirm();
+        assert_eq!(poll(&mut confirm, &waker), Poll::Pending);
+        events.indication_timed_out().signal();
+        assert_eq!(waker.0.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            poll(&mut confirm, &waker),
+            Poll::Ready(Err(ConfirmationTimeout))
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! ATT client implementation.
+//!
+//! The client currently only handles server-initiated PDUs: *Handle Value Notifications* and
+//! *Handle Value Indications*. Both are delivered to a `ValueHandler` supplied by the application,
+//! and indications are confirmed automatically.
+
+use {
+    super::{
+        pdus::{AttPdu, ErrorCode, Opcode},
+        Handle,
+    },
+    crate::{
+        bytes::{ByteReader, FromBytes},
+        l2cap::{Protocol, ProtocolObj, Sender},
+        Error,
+    },
+};
+
+/// Trait for application handlers of attribute values pushed by the server.
+pub trait ValueHandler {
+    /// Called when the server sends a *Handle Value Notification*.
+    ///
+    /// `value` may have been truncated by the server to fit into its `ATT_MTU`.
+    fn notification(&mut self, handle: Handle, value: &[u8]);
+
+    /// Called when the server sends a *Handle Value Indication*.
+    ///
+    /// The client confirms the indication right after this returns.
+    ///
+    /// By default, this forwards to `notification`.
+    fn indication(&mut self, handle: Handle, value: &[u8]) {
+        self.notification(handle, value);
+    }
+}
+
+/// An Attribute Protocol client receiving attribute values from a remote server.
+///
+/// This is meant to be connected to L2CAP channel `0x0004` by a `ChannelMapper` on devices that
+/// want to receive notifications, such as a central subscribed to a Nordic UART Service's RX
+/// characteristic.
+///
+/// An indication is confirmed while it is being processed, so the *Handle Value Confirmation* is
+/// always queued for transmission before any request the application sends afterwards. This is
+/// required by the specification.
+pub struct AttributeClient<H: ValueHandler> {
+    handler: H,
+}
+
+impl<H: ValueHandler> AttributeClient<H> {
+    /// Creates an `AttributeClient` that delivers received values to `handler`.
+    pub fn new(handler: H) -> Self {
+        Self { handler }
+    }
+
+    /// Returns a mutable reference to the `ValueHandler`.
+    pub fn handler(&mut self) -> &mut H {
+        &mut self.handler
+    }
+}
+
+impl<H: ValueHandler> ProtocolObj for AttributeClient<H> {
+    fn process_message(&mut self, message: &[u8], mut responder: Sender<'_>) -> Result<(), Error> {
+        let pdu = AttPdu::from_bytes(&mut ByteReader::new(message))?;
+        debug!("ATT<- {:?}", pdu);
+
+        match pdu {
+            AttPdu::HandleValueNotification { handle, value } => {
+                self.handler.notification(handle, value.0);
+                Ok(())
+            }
+            AttPdu::HandleValueIndication { handle, value } => {
+                self.handler.indication(handle, value.0);
+                responder.send(AttPdu::HandleValueConfirmation)
+            }
+            _ => {
+                let opcode = pdu.opcode();
+                if opcode.is_command() || is_server_pdu(opcode) {
+                    // We never send requests or indications, so there's nothing a response or
+                    // confirmation could belong to
+                    warn!("ATT client: ignoring unexpected {:?}", opcode);
+                    Ok(())
+                } else {
+                    responder.send(AttPdu::ErrorRsp {
+                        opcode,
+                        handle: Handle::NULL,
+                        error_code: ErrorCode::RequestNotSupported,
+                    })
+                }
+            }
+        }
+    }
+}
+
+impl<H: ValueHandler> Protocol for AttributeClient<H> {
+    // Error Response is the largest PDU we send
+    const RSP_PDU_SIZE: u8 = 5;
+}
+
+/// Returns whether `opcode` belongs to a response PDU or a Handle Value Confirmation, none of which
+/// may be answered.
+fn is_server_pdu(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::ErrorRsp
+            | Opcode::ExchangeMtuRsp
+            | Opcode::FindInformationRsp
+            | Opcode::FindByTypeValueRsp
+            | Opcode::ReadByTypeRsp
+            | Opcode::ReadRsp
+            | Opcode::ReadBlobRsp
+            | Opcode::ReadMultipleRsp
+            | Opcode::ReadByGroupRsp
+            | Opcode::WriteRsp
+            | Opcode::PrepareWriteRsp
+            | Opcode::ExecuteWriteRsp
+            | Opcode::HandleValueConfirmation
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            att::{AttributeServer, NoAttributes},
+            l2cap::{exchange, Channel, ChannelData, ChannelMapper, L2CAPState},
+        },
+        std::vec::Vec,
+    };
+
+    #[derive(Default)]
+    struct Recorder {
+        values: Vec<(u16, Vec<u8>, bool)>,
+    }
+
+    impl ValueHandler for Recorder {
+        fn notification(&mut self, handle: Handle, value: &[u8]) {
+            self.values.push((handle.as_u16(), value.to_vec(), false));
+        }
+
+        fn indication(&mut self, handle: Handle, value: &[u8]) {
+            self.values.push((handle.as_u16(), value.to_vec(), true));
+        }
+    }
+
+    /// Channel map of a central that connects the ATT channel to an `AttributeClient`.
+    struct ClientMap {
+        client: AttributeClient<Recorder>,
+        server: AttributeServer<NoAttributes>,
+    }
+
+    impl ChannelMapper for ClientMap {
+        type AttributeProvider = NoAttributes;
+
+        fn lookup(&mut self, channel: Channel) -> Option<ChannelData<'_, dyn ProtocolObj + '_>> {
+            match channel {
+                Channel::ATT => Some(ChannelData::new_dyn(channel, &mut self.client)),
+                _ => None,
+            }
+        }
+
+        fn att(&mut self) -> ChannelData<'_, AttributeServer<NoAttributes>> {
+            ChannelData::new(Channel::ATT, &mut self.server)
+        }
+    }
+
+    /// Sends `pdu` to the client and returns the ATT PDU sent in response, if any.
+    fn receive(l2cap: &mut L2CAPState<ClientMap>, pdu: &[u8]) -> Option<Vec<u8>> {
+        exchange(l2cap, Channel::ATT, pdu)
+    }
+
+    fn client() -> L2CAPState<ClientMap> {
+        L2CAPState::new(ClientMap {
+            client: AttributeClient::new(Recorder::default()),
+            server: AttributeServer::new(NoAttributes),
+        })
+    }
+
+    #[test]
+    fn notification() {
+        let mut l2cap = client();
+        assert_eq!(receive(&mut l2cap, &[0x1B, 0x12, 0x00, b'h', b'i']), None);
+        assert_eq!(
+            l2cap.channel_mapper().client.handler().values,
+            [(0x0012, b"hi".to_vec(), false)]
+        );
+    }
+
+    #[test]
+    fn indication_confirmed() {
+        let mut l2cap = client();
+        assert_eq!(
+            receive(&mut l2cap, &[0x1D, 0x12, 0x00, 1, 2, 3]),
+            Some(vec![0x1E])
+        );
+        assert_eq!(
+            l2cap.channel_mapper().client.handler().values,
+            [(0x0012, vec![1, 2, 3], true)]
+        );
+    }
+
+    #[test]
+    fn unexpected_pdus() {
+        let mut l2cap = client();
+
+        // Responses and confirmations are ignored
+        assert_eq!(receive(&mut l2cap, &[0x13]), None);
+        assert_eq!(receive(&mut l2cap, &[0x1E]), None);
+
+        // Requests are rejected
+        assert_eq!(
+            receive(&mut l2cap, &[0x0A, 0x01, 0x00]),
+            Some(vec![0x01, 0x0A, 0x00, 0x00, 0x06])
+        );
+        assert!(l2cap.channel_mapper().client.handler().values.is_empty());
+    }
+}
@@ -21,12 +21,12 @@ impl Handle {
     pub const NULL: Self = Handle(0x0000);
 
     /// Returns the raw 16-bit integer representing this handle.
-    pub fn as_u16(&self) -> u16 {
+    pub const fn as_u16(&self) -> u16 {
         self.0
     }
 
     /// Create an attribute handle from a raw u16
-    pub fn from_raw(raw: u16) -> Self {
+    pub const fn from_raw(raw: u16) -> Self {
         Handle(raw)
     }
 }
@@ -37,6 +37,13 @@ impl fmt::Debug for Handle {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Handle {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "{=u16:#06X}", self.0)
+    }
+}
+
 impl FromBytes<'_> for Handle {
     fn from_bytes(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
         Ok(Handle(bytes.read_u16_le()?))
@@ -88,12 +95,23 @@ impl ToBytes for RawHandleRange {
 }
 
 /// A (de)serializable handle range that has been checked for validity.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HandleRange(RangeInclusive<Handle>);
 
 impl HandleRange {
-    pub fn new(from: Handle, to: Handle) -> Self {
-        HandleRange(from..=to)
+    /// The range containing all valid handles, `0x0001..=0xFFFF`.
+    pub const FULL: Self = HandleRange(Handle(0x0001)..=Handle(0xFFFF));
+
+    /// Creates a handle range containing all handles from `start` to `end` (inclusive).
+    ///
+    /// Returns `Error::InvalidValue` if `start` is greater than `end`, or if either of them is the
+    /// `NULL` handle.
+    pub fn new(start: Handle, end: Handle) -> Result<Self, Error> {
+        if start.0 > end.0 || start == Handle::NULL || end == Handle::NULL {
+            Err(Error::InvalidValue)
+        } else {
+            Ok(HandleRange(start..=end))
+        }
     }
 
     /// Checks if an Handle is in a HandleRange
@@ -110,4 +128,60 @@ impl HandleRange {
     pub fn end(&self) -> Handle {
         *self.0.end()
     }
+
+    /// Returns an iterator over all handles in the range, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = Handle> {
+        (self.start().0..=self.end().0).map(Handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handle_range_new() {
+        let h = Handle::from_raw;
+
+        let range = HandleRange::new(h(1), h(3)).unwrap();
+        assert_eq!(range.start(), h(1));
+        assert_eq!(range.end(), h(3));
+        assert!(range.iter().eq([h(1), h(2), h(3)].iter().cloned()));
+
+        let single = HandleRange::new(h(5), h(5)).unwrap();
+        assert!(single.iter().eq(Some(h(5))));
+
+        // Inverted
+        assert_eq!(HandleRange::new(h(3), h(2)), Err(Error::InvalidValue));
+
+        // Zero
+        assert_eq!(
+            HandleRange::new(Handle::NULL, h(2)),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(
+            HandleRange::new(Handle::NULL, Handle::NULL),
+            Err(Error::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn handle_range_full() {
+        let full = HandleRange::FULL;
+        assert_eq!(full.start(), Handle::from_raw(0x0001));
+        assert_eq!(full.end(), Handle::from_raw(0xFFFF));
+        assert_eq!(full.iter().count(), 0xFFFF);
+        assert!(!full.contains(Handle::NULL));
+        assert_eq!(
+            HandleRange::new(Handle::from_raw(1), Handle::from_raw(0xFFFF)),
+            Ok(HandleRange::FULL)
+        );
+
+        // Checked raw ranges agree with `new`
+        let raw = RawHandleRange {
+            start: Handle::from_raw(1),
+            end: Handle::from_raw(0xFFFF),
+        };
+        assert_eq!(raw.check().unwrap(), HandleRange::FULL);
+    }
 }
@@ -29,20 +29,63 @@
 //!
 //! [`Handle`]: struct.Handle.html
 
+mod client;
 mod handle;
 mod pdus;
 mod server;
 mod uuid;
 
 use {
-    self::{handle::*, pdus::*},
-    crate::{utils::HexSlice, Error},
+    self::handle::*,
+    crate::{link::encryption::ConnectionSecurity, utils::HexSlice, uuid::Uuid16, Error},
 };
 
+pub use self::client::{AttributeClient, ValueHandler};
 pub use self::handle::{Handle, HandleRange};
-pub use self::server::{AttributeServer, AttributeServerTx};
+pub use self::pdus::{AttError, ErrorCode};
+pub use self::server::{
+    AttributeServer, AttributeServerTx, ConfirmationTimeout, IndicationPending, NotifyError,
+    CONFIRMATION_TIMEOUT, DEFAULT_MTU,
+};
 pub use self::uuid::AttUuid;
 
+#[cfg(feature = "fuzz")]
+pub(crate) use self::pdus::AttPdu;
+
+/// The maximum length of an attribute value in Bytes, as defined by the specification.
+pub const MAX_VALUE_LEN: usize = 512;
+
+/// An attribute value that is read on demand.
+///
+/// Most attribute values are kept in RAM and passed around as byte slices, which implement this
+/// trait. Values stored in flash or computed when they're read can implement it instead of
+/// buffering the whole value: The `AttributeServer` only reads the part of the value that fits into
+/// its response.
+pub trait ValueSource {
+    /// Returns the length of the value in Bytes.
+    fn len(&self) -> usize;
+
+    /// Returns whether the value is empty.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies the part of the value starting at `offset` into `buf`.
+    ///
+    /// The caller ensures that `offset + buf.len()` does not exceed `len()`.
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]);
+}
+
+impl ValueSource for &'_ [u8] {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(&self[offset..offset + buf.len()]);
+    }
+}
+
 /// An ATT server attribute
 pub struct Attribute<'a> {
     /// The type of the attribute as a UUID16, EG "Primary Service" or "Anaerobic Heart Rate Lower Limit"
@@ -54,6 +97,17 @@ pub struct Attribute<'a> {
     pub value: HexSlice<&'a [u8]>,
 }
 
+impl<'a> Attribute<'a> {
+    /// Creates a new attribute.
+    pub const fn new(att_type: AttUuid, handle: Handle, value: &'a [u8]) -> Self {
+        Self {
+            att_type,
+            handle,
+            value: HexSlice(value),
+        }
+    }
+}
+
 /// Trait for attribute sets that can be hosted by an `AttributeServer`.
 pub trait AttributeProvider {
     /// Calls a closure `f` with every attribute whose handle is inside `range`, ascending.
@@ -74,13 +128,280 @@ pub trait AttributeProvider {
     /// Group Type* requests.
     fn is_grouping_attr(&self, uuid: AttUuid) -> bool;
 
-    /// Queries the last attribute that is part of the attribute group denoted by the grouping
-    /// attribute at `handle`.
+    /// Queries the handle of the last attribute that is part of the attribute group denoted by the
+    /// grouping attribute at `handle`.
     ///
     /// If `handle` does not refer to a grouping attribute, returns `None`.
     ///
     /// TODO: Human-readable docs that explain what grouping is
-    fn group_end(&self, handle: Handle) -> Option<&Attribute<'_>>;
+    fn group_end(&self, handle: Handle) -> Option<Handle>;
+
+    /// Returns the maximum length of the value of the attribute at `handle`, in Bytes.
+    ///
+    /// Writes of longer values are rejected with an *Invalid Attribute Value Length* error, and
+    /// reads never return more than this many Bytes of the value.
+    ///
+    /// By default, this returns `MAX_VALUE_LEN` for all attributes.
+    fn max_value_len(&self, _handle: Handle) -> usize {
+        MAX_VALUE_LEN
+    }
+
+    /// Returns the security level the connection must have for the attribute at `handle` to be
+    /// read or written.
+    ///
+    /// The `AttributeServer` rejects *Read*, *Read Blob* and *Write Requests* for the attribute if
+    /// the connection's security level is lower. By default, this returns
+    /// `ConnectionSecurity::Unencrypted` for all attributes, so no security is required.
+    fn required_security(&self, _handle: Handle) -> ConnectionSecurity {
+        ConnectionSecurity::Unencrypted
+    }
+
+    /// Reads the value of the attribute at `handle` and passes it to `f`.
+    ///
+    /// This is used to answer *Read* and *Read Blob Requests*. Returning an error rejects the read,
+    /// and the error is sent to the client instead.
+    ///
+    /// The default implementation looks up the attribute using `for_attrs_in_range`, and returns
+    /// an *Invalid Handle* error if it doesn't exist.
+    fn read_attr(&mut self, handle: Handle, f: impl FnOnce(&[u8])) -> Result<(), AttError> {
+        let range =
+            HandleRange::new(handle, handle).map_err(|e| AttError::new(e.into(), handle))?;
+
+        let mut f = Some(f);
+        self.for_attrs_in_range(range, |_, attr| {
+            if let Some(f) = f.take() {
+                f(attr.value.0);
+            }
+            Ok(())
+        })
+        .ok();
+
+        if f.is_none() {
+            Ok(())
+        } else {
+            Err(AttError::new(ErrorCode::InvalidHandle, handle))
+        }
+    }
+
+    /// Reads the value of the attribute at `handle` and passes it to `f` as a `ValueSource`.
+    ///
+    /// This is used to answer *Read* and *Read Blob Requests* (unless a snapshot is in use, see
+    /// `begin_read_snapshot`). Providers can override it for values that aren't kept in RAM, so
+    /// that only the requested part of the value is read. Such attributes can be reported with an
+    /// empty value by `for_attrs_in_range`, which means that *Read By Type Requests* will return
+    /// an empty value for them.
+    ///
+    /// By default, this calls `read_attr` and passes the value slice to `f`.
+    fn read_attr_source(
+        &mut self,
+        handle: Handle,
+        f: impl FnOnce(&mut dyn ValueSource),
+    ) -> Result<(), AttError> {
+        self.read_attr(handle, |mut value| f(&mut value))
+    }
+
+    /// Called when the client starts reading the value at `handle`.
+    ///
+    /// Values longer than `ATT_MTU - 1` Bytes are read using a *Read Request* followed by *Read
+    /// Blob Requests* for the rest of the value. If the value changes in between, the client ends
+    /// up with a mix of the old and new value. Providers of such values can prevent this by
+    /// freezing a copy of the value here, and returning a generation number identifying the copy.
+    /// The server then reads the rest of the value using `read_snapshot` with that generation.
+    ///
+    /// This is called for every *Read Request*, and for *Read Blob Requests* at offset 0. The
+    /// snapshot is used until the client starts reading another value, or writes to this one.
+    ///
+    /// By default, this returns `None`, and all reads use `read_attr`.
+    fn begin_read_snapshot(&mut self, _handle: Handle) -> Option<u32> {
+        None
+    }
+
+    /// Reads the value of the attribute at `handle` as it was when `begin_read_snapshot` returned
+    /// `generation`.
+    ///
+    /// If the provider no longer has the snapshot, it should return an error rather than the
+    /// current value, so that the client doesn't assemble a torn value.
+    ///
+    /// By default, this ignores `generation` and calls `read_attr`.
+    fn read_snapshot(
+        &mut self,
+        handle: Handle,
+        _generation: u32,
+        f: impl FnOnce(&[u8]),
+    ) -> Result<(), AttError> {
+        self.read_attr(handle, f)
+    }
+
+    /// Checks that the attribute at `handle` exists and may be written by the client.
+    ///
+    /// The `AttributeServer` calls this before checking the security level and value length of a
+    /// write, so that the client gets the error mandated by the specification when several
+    /// conditions apply: *Invalid Handle* if the attribute doesn't exist, then *Write Not
+    /// Permitted*, then *Insufficient Encryption* or *Insufficient Authentication*, then *Invalid
+    /// Attribute Value Length*.
+    ///
+    /// The default implementation looks up the attribute using `for_attrs_in_range` and returns an
+    /// *Invalid Handle* error if it doesn't exist. GATT declarations and descriptors other than the
+    /// Client and Server Characteristic Configuration Descriptors are rejected with a *Write Not
+    /// Permitted* error. All other attributes are considered writable, leaving the decision to
+    /// `write_attr`.
+    fn check_writable(&mut self, handle: Handle) -> Result<(), AttError> {
+        check_writable_type(self, handle)
+    }
+
+    /// Handles a client writing `value` to the attribute at `handle` using a *Write Request*.
+    ///
+    /// `check_writable` has accepted the write, and `value` is never longer than
+    /// `max_value_len(handle)`. Returning an error rejects the write, and the error is sent to the
+    /// client.
+    ///
+    /// The default implementation rejects all writes with a *Write Not Permitted* error. Providers
+    /// hosting writable attributes (including CCCDs) have to override it.
+    fn write_attr(&mut self, handle: Handle, _value: &[u8]) -> Result<(), AttError> {
+        Err(AttError::new(ErrorCode::WriteNotPermitted, handle))
+    }
+
+    /// Handles a client writing `value` to the attribute at `handle` using a *Write Command*
+    /// (also known as *Write Without Response*).
+    ///
+    /// Write Commands are never answered, so the client doesn't learn whether the write succeeded.
+    /// An error returned from this method is dropped. Clients use them for higher throughput, since
+    /// they can send several writes per connection event.
+    ///
+    /// By default, this calls `write_attr`.
+    fn write_command(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        self.write_attr(handle, value)
+    }
+
+    /// Checks that the attribute handles start at `0x0001` and are strictly increasing.
+    ///
+    /// Duplicate or out-of-order handles are easy to introduce when writing attribute tables by
+    /// hand, and confuse clients during service discovery. Applications can call this once after
+    /// building such a table, eg. in a `debug_assert!`.
+    ///
+    /// This iterates over all attributes using `for_attrs_in_range`, so providers computing values
+    /// in there (like `BatteryServiceAttrs`) will compute them. For this reason, the
+    /// `AttributeServer` never calls it on its own.
+    fn validate(&mut self) -> Result<(), InvalidHandleOrder> {
+        let mut previous: Option<Handle> = None;
+        let mut result = Ok(());
+        self.for_attrs_in_range(HandleRange::FULL, |_, attr| {
+            let valid = match previous {
+                None => attr.handle.as_u16() == 0x0001,
+                Some(prev) => attr.handle.as_u16() > prev.as_u16(),
+            };
+            if !valid {
+                result = Err(InvalidHandleOrder {
+                    handle: attr.handle,
+                    previous,
+                });
+                return Err(Error::InvalidValue);
+            }
+
+            previous = Some(attr.handle);
+            Ok(())
+        })
+        .ok();
+        result
+    }
+}
+
+/// Returns whether `att_type` is the type of a service, include or characteristic declaration.
+pub(crate) fn is_declaration_type(att_type: AttUuid) -> bool {
+    match att_type {
+        AttUuid::Uuid16(uuid) => (0x2800..=0x2803).contains(&uuid.0),
+        AttUuid::Uuid128(_) => false,
+    }
+}
+
+/// Returns whether clients may write to attributes of type `att_type`.
+///
+/// Service, include and characteristic declarations are always read-only. Of the descriptors
+/// defined by GATT, only the Client and Server Characteristic Configuration Descriptors are written
+/// by clients, so writes to any other (or unknown) descriptor are rejected.
+fn is_writable_type(att_type: AttUuid) -> bool {
+    match att_type {
+        AttUuid::Uuid16(uuid) => match uuid.0 {
+            0x2800..=0x2803 => false,
+            0x2900..=0x29FF => {
+                uuid == Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION
+                    || uuid == Uuid16::SERVER_CHARACTERISTIC_CONFIGURATION
+            }
+            _ => true,
+        },
+        AttUuid::Uuid128(_) => true,
+    }
+}
+
+/// Implements the default `AttributeProvider::check_writable`, which only looks at the type of the
+/// attribute at `handle`.
+pub(crate) fn check_writable_type<A: AttributeProvider + ?Sized>(
+    attrs: &mut A,
+    handle: Handle,
+) -> Result<(), AttError> {
+    let invalid_handle = AttError::new(ErrorCode::InvalidHandle, handle);
+    let range = HandleRange::new(handle, handle).map_err(|e| AttError::new(e.into(), handle))?;
+
+    let mut result = Err(invalid_handle);
+    attrs
+        .for_attrs_in_range(range, |_, attr| {
+            result = if is_writable_type(attr.att_type) {
+                Ok(())
+            } else {
+                Err(AttError::new(ErrorCode::WriteNotPermitted, handle))
+            };
+            Ok(())
+        })
+        .ok();
+
+    result
+}
+
+/// Error returned by `AttributeProvider::validate` when the attribute handles are invalid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidHandleOrder {
+    /// The offending attribute handle.
+    pub handle: Handle,
+
+    /// Handle of the preceding attribute, or `None` if the first attribute doesn't have the handle
+    /// `0x0001`.
+    pub previous: Option<Handle>,
+}
+
+/// Checks a static attribute table during constant evaluation.
+///
+/// Panics if the handles don't start at `0x0001`, aren't strictly increasing, or if the value
+/// handle embedded in a characteristic declaration doesn't refer to the attribute directly
+/// following the declaration (where GATT requires the characteristic value to be).
+///
+/// This performs the same handle check as `AttributeProvider::validate`, but can be evaluated in a
+/// constant, so that a broken table fails to compile. Use [`assert_attribute_table!`] for that.
+///
+/// [`assert_attribute_table!`]: ../macro.assert_attribute_table.html
+pub const fn check_attribute_table(attrs: &[Attribute<'_>]) {
+    let mut i = 0;
+    while i < attrs.len() {
+        let handle = attrs[i].handle.as_u16();
+        if i == 0 && handle != 0x0001 {
+            panic!("the first attribute handle must be 0x0001");
+        }
+        if i > 0 && handle <= attrs[i - 1].handle.as_u16() {
+            panic!("attribute handles must be strictly increasing");
+        }
+
+        if let AttUuid::Uuid16(Uuid16::CHARACTERISTIC) = attrs[i].att_type {
+            let value = attrs[i].value.0;
+            if value.len() < 3 {
+                panic!("characteristic declaration is too short");
+            }
+            let value_handle = u16::from_le_bytes([value[1], value[2]]);
+            if i + 1 >= attrs.len() || attrs[i + 1].handle.as_u16() != value_handle {
+                panic!("characteristic declaration doesn't point to the following attribute");
+            }
+        }
+
+        i += 1;
+    }
 }
 
 /// An empty attribute set.
@@ -101,7 +422,7 @@ impl AttributeProvider for NoAttributes {
         false
     }
 
-    fn group_end(&self, _handle: Handle) -> Option<&Attribute<'_>> {
+    fn group_end(&self, _handle: Handle) -> Option<Handle> {
         None
     }
 }
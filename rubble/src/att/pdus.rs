@@ -10,7 +10,8 @@ enum_with_unknown! {
     /// Error codes that can be sent from the ATT server to the client in response to a request.
     ///
     /// Used as the payload of `ErrorRsp` PDUs.
-    #[derive(Copy, Clone, Debug)]
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub enum ErrorCode(u8) {
         /// Attempted to use an `Handle` that isn't valid on this server.
         InvalidHandle = 0x01,
@@ -46,11 +47,21 @@ enum_with_unknown! {
         UnsupportedGroupType = 0x10,
         /// Server didn't have enough resources to complete a request.
         InsufficientResources = 0x11,
+        /// The client's cached attribute table is out of sync with the server.
+        DatabaseOutOfSync = 0x12,
+        /// The attribute value is not allowed.
+        ValueNotAllowed = 0x13,
+        /// The written value is outside the *Valid Range* of the characteristic.
+        ///
+        /// This is one of the *Common Profile and Service Error Codes* defined in the Core
+        /// Specification Supplement.
+        OutOfRange = 0xFF,
     }
 }
 
 /// An error on the ATT protocol layer. Can be sent as a response.
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct AttError {
     code: ErrorCode,
     handle: Handle,
@@ -80,6 +91,28 @@ impl AttError {
     }
 }
 
+/// Maps errors of the BLE stack to the ATT error code that should be sent to the client.
+///
+/// | `Error`           | `ErrorCode`     |
+/// |-------------------|-----------------|
+/// | `InvalidLength`   | `InvalidPdu`    |
+/// | `Eof`             | `InvalidPdu`    |
+/// | `IncompleteParse` | `InvalidPdu`    |
+/// | `InvalidValue`    | `InvalidHandle` |
+/// | Others            | `UnlikelyError` |
+///
+/// The first three indicate a malformed PDU. The only values validated on the ATT layer are
+/// handles and handle ranges, so `InvalidValue` is reported as an invalid handle.
+impl From<Error> for ErrorCode {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::InvalidLength | Error::Eof | Error::IncompleteParse => ErrorCode::InvalidPdu,
+            Error::InvalidValue => ErrorCode::InvalidHandle,
+            Error::ConfirmationTimeout | Error::__Nonexhaustive => ErrorCode::UnlikelyError,
+        }
+    }
+}
+
 /// Attribute Data returned in *Read By Type* response.
 #[derive(Debug)]
 pub struct ByTypeAttData<'a> {
@@ -90,8 +123,12 @@ pub struct ByTypeAttData<'a> {
 impl<'a> ByTypeAttData<'a> {
     /// Creates a *Read By Type Response* attribute data structure from the attribute's handle and
     /// value.
+    ///
+    /// If `value` is too long to fit into a response PDU, it will be truncated to `att_mtu - 4`
+    /// Bytes. The client can read the rest using *Read Blob Requests*.
     pub fn new(att_mtu: u8, handle: Handle, mut value: &'a [u8]) -> Self {
-        let max_val_len = usize::from(att_mtu - 2);
+        // 1 Byte opcode, 1 Byte length, 2 Bytes for `handle`
+        let max_val_len = usize::from(att_mtu - 1 - 1 - 2);
         if value.len() > max_val_len {
             value = &value[..max_val_len];
         }
@@ -140,8 +177,8 @@ pub struct ByGroupAttData<'a> {
 
 impl<'a> ByGroupAttData<'a> {
     pub fn new(att_mtu: u8, handle: Handle, group_end_handle: Handle, mut value: &'a [u8]) -> Self {
-        // 2 Bytes for `handle`, 2 Bytes for `group_end_handle`
-        let max_val_len = usize::from(att_mtu - 2 - 2);
+        // 1 Byte opcode, 1 Byte length, 2 Bytes for `handle`, 2 Bytes for `group_end_handle`
+        let max_val_len = usize::from(att_mtu - 1 - 1 - 2 - 2);
         if value.len() > max_val_len {
             value = &value[..max_val_len];
         }
@@ -203,7 +240,7 @@ enum_with_unknown! {
     ///   can ignore unknown commands. Unlike *Requests*, Commands are not followed by a server
     ///   response.
     /// * **`Method`** defines which operation to perform.
-    #[derive(Debug, Copy, Clone)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum Opcode(u8) {
         ErrorRsp = 0x01,
         ExchangeMtuReq = 0x02,
@@ -237,17 +274,29 @@ enum_with_unknown! {
 }
 
 impl Opcode {
+    const SIGNATURE_BIT: u8 = 0x80;
+    const COMMAND_BIT: u8 = 0x40;
+    const METHOD_MASK: u8 = 0x3F;
+
     /// Returns the raw byte corresponding to the opcode `self`.
     pub fn raw(&self) -> u8 {
         u8::from(*self)
     }
 
+    /// Returns the 6-bit `Method` of this opcode, without the `Command` and `Signature` bits.
+    ///
+    /// The *Write Request*, *Write Command* and *Signed Write Command* all share the same method,
+    /// and only differ in the other two bits.
+    pub fn method(&self) -> u8 {
+        self.raw() & Self::METHOD_MASK
+    }
+
     /// Returns whether the `Signature` bit in this opcode is set.
     ///
     /// If the bit is set, this is an authenticated operation. The opcode parameters are followed by
     /// a 12-Byte signature.
     pub fn is_authenticated(&self) -> bool {
-        self.raw() & 0x80 != 0
+        self.raw() & Self::SIGNATURE_BIT != 0
     }
 
     /// Returns whether the `Command` bit in this opcode is set.
@@ -255,7 +304,7 @@ impl Opcode {
     /// Commands sent to the server are not followed by a server response (ie. it is not indicated
     /// whether they succeed). Unimplemented commands should be ignored, according to the spec.
     pub fn is_command(&self) -> bool {
-        self.raw() & 0x40 != 0
+        self.raw() & Self::COMMAND_BIT != 0
     }
 }
 
@@ -468,7 +517,10 @@ impl<'a> FromBytes<'a> for AttPdu<'a> {
             },
             Opcode::SignedWriteCommand => AttPdu::SignedWriteCommand {
                 handle: Handle::from_bytes(bytes)?,
-                value: HexSlice(bytes.read_slice(bytes.bytes_left() - 12)?),
+                value: {
+                    let len = bytes.bytes_left().checked_sub(12);
+                    HexSlice(bytes.read_slice(len.ok_or(Error::InvalidLength)?)?)
+                },
                 signature: HexSlice(bytes.read_slice(12)?.try_into().unwrap()),
             },
             Opcode::PrepareWriteReq => AttPdu::PrepareWriteReq {
@@ -677,3 +729,46 @@ impl AttPdu<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::att::HandleRange};
+
+    #[test]
+    fn opcode_bits() {
+        let signed = Opcode::from(0xD2);
+        assert_eq!(signed, Opcode::SignedWriteCommand);
+        assert_eq!(signed.method(), 0x12);
+        assert!(signed.is_command());
+        assert!(signed.is_authenticated());
+
+        // Same method as the request and the unsigned command
+        assert_eq!(Opcode::WriteReq.method(), signed.method());
+        assert_eq!(Opcode::WriteCommand.method(), signed.method());
+        assert!(!Opcode::WriteReq.is_command());
+        assert!(Opcode::WriteCommand.is_command());
+        assert!(!Opcode::WriteCommand.is_authenticated());
+
+        // The bits are decoded for unknown opcodes too
+        let unknown = Opcode::from(0x7F);
+        assert_eq!(unknown, Opcode::Unknown(0x7F));
+        assert_eq!(unknown.method(), 0x3F);
+        assert!(unknown.is_command());
+        assert!(!unknown.is_authenticated());
+    }
+
+    #[test]
+    fn error_codes() {
+        let code = |e: Error| u8::from(ErrorCode::from(e));
+        assert_eq!(code(Error::Eof), 0x04);
+        assert_eq!(code(Error::InvalidLength), 0x04);
+        assert_eq!(code(Error::IncompleteParse), 0x04);
+        assert_eq!(code(Error::InvalidValue), 0x01);
+
+        // Truncated requests and out-of-range handles
+        let truncated = AttPdu::from_bytes(&mut ByteReader::new(&[0x0A, 0x03])).unwrap_err();
+        assert_eq!(code(truncated), 0x04);
+        let range = HandleRange::new(Handle::from_raw(5), Handle::from_raw(2));
+        assert_eq!(code(range.unwrap_err()), 0x01);
+    }
+}
@@ -2,32 +2,262 @@
 
 use {
     super::{
+        is_declaration_type,
         pdus::{AttPdu, ByGroupAttData, ByTypeAttData, ErrorCode, Opcode},
-        AttError, AttributeProvider, Handle, HandleRange,
+        AttError, AttUuid, Attribute, AttributeProvider, Handle, HandleRange, ValueSource,
     },
     crate::{
-        bytes::{ByteReader, FromBytes, ToBytes},
-        l2cap::{Protocol, ProtocolObj, Sender},
+        bytes::{ByteReader, ByteWriter, FromBytes, ToBytes},
+        l2cap::{Protocol, ProtocolObj, Sender, TxTicket, MAX_PAYLOAD_LEN},
+        link::{encryption::ConnectionSecurity, ConnectionHandle},
+        time::{self, Duration, Instant},
         utils::HexSlice,
         Error,
     },
+    core::cmp,
 };
 
+/// The default `ATT_MTU` of LE connections, which is also the smallest allowed `ATT_MTU`.
+pub const DEFAULT_MTU: u16 = 23;
+
+/// The default time after which an unconfirmed indication fails (30 seconds, the ATT transaction
+/// timeout).
+pub const CONFIRMATION_TIMEOUT: Duration = Duration::from_micros(30_000_000);
+
 /// An Attribute Protocol server providing read and write access to stored attributes.
+///
+/// The server doesn't own any PDU buffers. PDUs that fit into a single data channel PDU are parsed
+/// in place from the Link-Layer's RX queue, and responses are written directly into free space of
+/// the TX queue via the `Sender`. Larger PDUs (with an `ATT_MTU` above the default) are
+/// reassembled in, and fragmented from, the message buffers of the `L2CAPState`, which the
+/// application can provide via `L2CAPState::with_buffers`. Attribute values are stored by the
+/// `AttributeProvider`, which may need a buffer to read them into (`GattServer::with_buffer` lets
+/// the application provide that buffer as well).
 pub struct AttributeServer<A: AttributeProvider> {
     attrs: A,
+
+    /// State of the last indication sent to the client.
+    indication: IndicationState,
+
+    /// Time the client has to confirm an indication.
+    confirmation_timeout: Duration,
+
+    /// Handle and generation of the value snapshot taken when the client started reading it.
+    snapshot: Option<(Handle, u32)>,
+
+    /// The largest `ATT_MTU` supported by the server.
+    max_mtu: u16,
+
+    /// The `ATT_MTU` negotiated with the client.
+    mtu: u16,
+
+    /// Security level of the connection, as reported by the Link-Layer.
+    security: ConnectionSecurity,
+
+    /// Whether the Link-Layer is connected, as reported by the `Responder`.
+    connected: bool,
+
+    /// Ticket of the response to the last request.
+    ///
+    /// Requests received before this response has left the TX queue are rejected.
+    response: Option<TxTicket>,
+
+    /// Events to signal to `async` application code.
+    #[cfg(feature = "async")]
+    events: Option<&'static crate::asynch::LinkEvents>,
 }
 
 impl<A: AttributeProvider> AttributeServer<A> {
     /// Creates an `AttributeServer` hosting attributes from an `AttributeProvider`.
+    ///
+    /// This doesn't access the attributes of `attrs`. Hand-written attribute tables can be checked
+    /// by calling `AttributeProvider::validate` beforehand.
     pub fn new(attrs: A) -> Self {
-        Self { attrs }
+        Self {
+            attrs,
+            indication: IndicationState::Idle,
+            confirmation_timeout: CONFIRMATION_TIMEOUT,
+            snapshot: None,
+            max_mtu: DEFAULT_MTU,
+            mtu: DEFAULT_MTU,
+            security: ConnectionSecurity::Unencrypted,
+            connected: true,
+            response: None,
+            #[cfg(feature = "async")]
+            events: None,
+        }
+    }
+
+    /// Sets whether the Link-Layer is connected.
+    ///
+    /// Notifications can only be sent while connected. The `Responder` calls this whenever a
+    /// connection is established or closed, so it only has to be called manually when the server
+    /// isn't driven by a `Responder` (the server then assumes to be connected).
+    ///
+    /// When a new connection is established, the state belonging to the previous connection (the
+    /// negotiated `ATT_MTU`, security level and any unconfirmed indication) is reset.
+    pub fn set_connected(&mut self, connected: bool) {
+        if connected && !self.connected {
+            self.indication = IndicationState::Idle;
+            self.snapshot = None;
+            self.mtu = DEFAULT_MTU;
+            self.security = ConnectionSecurity::Unencrypted;
+            self.response = None;
+        }
+        self.connected = connected;
+    }
+
+    /// Returns whether the Link-Layer is connected, as last set by `set_connected`.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Sets the largest `ATT_MTU` the server will agree to when the client requests an MTU
+    /// exchange.
+    ///
+    /// This is normally set to `Config::MAX_ATT_MTU` (clamped to what the link can carry) by the
+    /// `Responder`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` and leaves the current value unchanged if `max_mtu` is larger
+    /// than the L2CAP payload the link can carry (`l2cap::MAX_PAYLOAD_LEN`). Advertising a larger
+    /// MTU would allow the client to send PDUs that can't be received.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_mtu` is smaller than `DEFAULT_MTU` (23).
+    pub fn set_max_mtu(&mut self, max_mtu: u16) -> Result<(), Error> {
+        assert!(max_mtu >= DEFAULT_MTU, "ATT_MTU must be at least 23");
+        if max_mtu > MAX_PAYLOAD_LEN {
+            return Err(Error::InvalidValue);
+        }
+        self.max_mtu = max_mtu;
+        Ok(())
+    }
+
+    /// Returns the largest `ATT_MTU` supported by the server.
+    pub fn max_mtu(&self) -> u16 {
+        self.max_mtu
+    }
+
+    /// Returns the `ATT_MTU` negotiated with the client.
+    ///
+    /// This is `DEFAULT_MTU` until the client performs an MTU exchange.
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Sets the security level of the connection.
+    ///
+    /// The server rejects reads and writes of attributes that require a higher security level (see
+    /// `AttributeProvider::required_security`). The `Responder` calls this whenever the Link-Layer
+    /// reports a new level, which happens when encryption is started with a (new or stored) key.
+    /// It only has to be called manually when the server isn't driven by a `Responder`.
+    pub fn set_security_level(&mut self, level: ConnectionSecurity) {
+        if level != self.security {
+            debug!("ATT: security level {:?}", level);
+        }
+        self.security = level;
+    }
+
+    /// Returns the security level of the connection, as last set by `set_security_level`.
+    ///
+    /// This starts out as `ConnectionSecurity::Unencrypted`.
+    pub fn security_level(&self) -> ConnectionSecurity {
+        self.security
+    }
+
+    /// Returns whether an indication has been sent that the client has not yet confirmed.
+    ///
+    /// No further indications can be sent while this is the case. If the client fails to confirm
+    /// an indication in time, this stays `true` for the rest of the connection.
+    pub fn is_indication_pending(&self) -> bool {
+        self.indication != IndicationState::Idle
+    }
+
+    /// Sets the time the client has to confirm an indication.
+    ///
+    /// This defaults to `CONFIRMATION_TIMEOUT` (30 seconds), as mandated by the specification.
+    /// Changing it only affects indications sent afterwards.
+    pub fn set_confirmation_timeout(&mut self, timeout: Duration) {
+        self.confirmation_timeout = timeout;
+    }
+
+    /// Returns the time the client has to confirm an indication.
+    pub fn confirmation_timeout(&self) -> Duration {
+        self.confirmation_timeout
+    }
+
+    /// Returns the instant at which the pending indication fails if it isn't confirmed.
+    ///
+    /// Returns `None` if no indication is waiting for confirmation, or if the indication was sent
+    /// with `indicate_raw` and its deadline isn't known yet (see `check_confirmation_timeout`).
+    pub fn confirmation_deadline(&self) -> Option<Instant> {
+        match self.indication {
+            IndicationState::Pending { deadline } => deadline,
+            _ => None,
+        }
+    }
+
+    /// Checks whether the pending indication has timed out at `now`.
+    ///
+    /// The `Responder` calls this periodically with the time of the current connection event, so
+    /// applications don't have to. For indications sent with `indicate_raw`, the first call starts
+    /// the timeout.
+    ///
+    /// If the client hasn't confirmed the indication before its deadline, this returns
+    /// `Err(ConfirmationTimeout)`. This is only reported once (the `Responder` reports it from
+    /// `Responder::process_one`, and `async` code waiting for the confirmation is woken). The
+    /// specification requires that no further ATT PDUs are sent over the connection in this case,
+    /// so the server drops all incoming PDUs without answering them and refuses to send
+    /// notifications and indications until the next connection. The application should
+    /// disconnect (see `is_confirmation_timed_out`).
+    pub fn check_confirmation_timeout(&mut self, now: Instant) -> Result<(), ConfirmationTimeout> {
+        match self.indication {
+            IndicationState::Pending { deadline: None } => {
+                self.indication = IndicationState::Pending {
+                    deadline: Some(now + self.confirmation_timeout),
+                };
+                Ok(())
+            }
+            IndicationState::Pending {
+                deadline: Some(deadline),
+            } if !time::is_before(now, deadline) => {
+                warn!("ATT: indication not confirmed in time");
+                self.indication = IndicationState::TimedOut;
+                #[cfg(feature = "async")]
+                {
+                    if let Some(events) = self.events {
+                        events.indication_timed_out().signal();
+                    }
+                }
+                Err(ConfirmationTimeout)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns whether the client failed to confirm an indication in time.
+    ///
+    /// Once this is the case, the application should close the connection.
+    pub fn is_confirmation_timed_out(&self) -> bool {
+        self.indication == IndicationState::TimedOut
+    }
+
+    /// Signals confirmed (and timed out) indications to `async` code waiting on `events`.
+    ///
+    /// See the [`asynch`] module for details.
+    ///
+    /// [`asynch`]: ../asynch/index.html
+    #[cfg(feature = "async")]
+    pub fn set_events(&mut self, events: &'static crate::asynch::LinkEvents) {
+        self.events = Some(events);
     }
 
     /// Prepares for performing a server-initiated action (eg. sending a notification/indication).
     ///
-    /// The caller must ensure that `sender` has at least `RSP_PDU_SIZE` bytes of free space
-    /// available.
+    /// The caller must ensure that `sender` has space for a PDU of the negotiated `ATT_MTU`.
     ///
     /// It is usually not necessary to use this function. Instead, call `L2CAPStateTx::att`.
     pub fn with_sender<'a>(&'a mut self, sender: Sender<'a>) -> AttributeServerTx<'a, A> {
@@ -37,10 +267,69 @@ impl<A: AttributeProvider> AttributeServer<A> {
         }
     }
 
-    /// Returns the `ATT_MTU` value, the maximum size of an ATT PDU that can be processed and sent
-    /// out by the server.
+    /// Returns a mutable reference to the hosted `AttributeProvider`.
+    pub fn provider(&mut self) -> &mut A {
+        &mut self.attrs
+    }
+
+    /// Returns the maximum size of an ATT PDU that can be sent out by the server.
+    ///
+    /// This is the negotiated `ATT_MTU`, which never exceeds `l2cap::MAX_PAYLOAD_LEN` (247) and so
+    /// always fits in a `u8`.
     fn att_mtu(&self) -> u8 {
-        Self::RSP_PDU_SIZE
+        self.mtu as u8
+    }
+
+    /// Checks whether the client may write to the attribute at `handle`.
+    ///
+    /// Declarations are rejected with *Write Not Permitted* even if the `AttributeProvider` accepts
+    /// the write, since modifying them would corrupt the structure of the attribute table.
+    fn check_writable(&mut self, handle: Handle) -> Result<(), AttError> {
+        self.attrs.check_writable(handle)?;
+
+        let mut is_declaration = false;
+        if let Ok(range) = HandleRange::new(handle, handle) {
+            let _ = self.attrs.for_attrs_in_range(range, |_, attr| {
+                is_declaration = is_declaration_type(attr.att_type);
+                Ok(())
+            });
+        }
+        if is_declaration {
+            warn!("ATT: provider allowed write to declaration {:?}", handle);
+            return Err(AttError::new(ErrorCode::WriteNotPermitted, handle));
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the connection's security level permits access to the attribute at
+    /// `handle`.
+    fn check_security(&self, handle: Handle) -> Result<(), AttError> {
+        let required = self.attrs.required_security(handle);
+        if self.security >= required {
+            return Ok(());
+        }
+
+        let code = match required {
+            ConnectionSecurity::Authenticated => ErrorCode::InsufficientAuthentication,
+            _ => ErrorCode::InsufficientEncryption,
+        };
+        Err(AttError::new(code, handle))
+    }
+
+    /// Reads the value at `handle`, using the snapshot taken when the client started reading it.
+    fn read_value(
+        &mut self,
+        handle: Handle,
+        f: impl FnOnce(&mut dyn ValueSource),
+    ) -> Result<(), AttError> {
+        match self.snapshot {
+            Some((h, generation)) if h == handle => {
+                self.attrs
+                    .read_snapshot(handle, generation, |mut value| f(&mut value))
+            }
+            _ => self.attrs.read_attr_source(handle, f),
+        }
     }
 
     /// Process an incoming request (or command) PDU and return a response.
@@ -71,11 +360,13 @@ impl<A: AttributeProvider> AttributeServer<A> {
         }
 
         match msg {
-            AttPdu::ExchangeMtuReq { mtu: _mtu } => {
+            AttPdu::ExchangeMtuReq { mtu } => {
+                // Both devices use the smaller of the two MTUs, which can't go below the default
+                self.mtu = cmp::max(cmp::min(*mtu, self.max_mtu), DEFAULT_MTU);
+                debug!("ATT_MTU negotiated: {}", self.mtu);
+
                 responder
-                    .send(AttPdu::ExchangeMtuRsp {
-                        mtu: u16::from(Self::RSP_PDU_SIZE),
-                    })
+                    .send(AttPdu::ExchangeMtuRsp { mtu: self.max_mtu })
                     .unwrap();
                 Ok(())
             }
@@ -96,15 +387,23 @@ impl<A: AttributeProvider> AttributeServer<A> {
                     let mut size = None;
                     let att_mtu = self.att_mtu();
                     self.attrs
-                        .for_attrs_in_range(range, |_provider, attr| {
+                        .for_attrs_in_range(range, |provider, attr| {
                             if attr.att_type == *attribute_type {
-                                let data =
-                                    ByTypeAttData::new(att_mtu, attr.handle, attr.value.as_ref());
-                                if size == Some(data.encoded_size()) || size.is_none() {
-                                    // Can try to encode `data`. If we run out of space, end the list.
-                                    data.to_bytes(writer)?;
-                                    size = Some(data.encoded_size());
+                                let value = bounded_value(provider, &attr);
+                                let data = ByTypeAttData::new(att_mtu, attr.handle, value);
+                                if size.is_some_and(|size| size != data.encoded_size()) {
+                                    // All entries in the response must have the same length. The
+                                    // client will request the rest of the range later, so end the
+                                    // list here instead of skipping this attribute.
+                                    return Err(Error::InvalidLength);
                                 }
+                                if writer.space_left() < usize::from(data.encoded_size()) {
+                                    // Out of space, end the list.
+                                    return Err(Error::Eof);
+                                }
+
+                                data.to_bytes(writer)?;
+                                size = Some(data.encoded_size());
                             }
 
                             Ok(())
@@ -126,18 +425,63 @@ impl<A: AttributeProvider> AttributeServer<A> {
                 }
             }
 
+            AttPdu::FindInformationReq { handle_range } => {
+                let range = handle_range.check()?;
+                let start = range.start();
+
+                let result = responder.send_with(|writer| {
+                    writer.write_u8(Opcode::FindInformationRsp.into())?;
+                    let format = writer.split_next_mut().ok_or(Error::Eof)?;
+
+                    // Format 1 carries 16-bit UUIDs, format 2 carries 128-bit UUIDs
+                    let mut fmt = None;
+                    self.attrs
+                        .for_attrs_in_range(range, |_, attr| {
+                            let (this_fmt, size) = match attr.att_type {
+                                AttUuid::Uuid16(_) => (0x01, 2 + 2),
+                                AttUuid::Uuid128(_) => (0x02, 2 + 16),
+                            };
+                            if fmt.is_some_and(|fmt| fmt != this_fmt) {
+                                // A response can only contain one kind of UUID. The client will
+                                // continue at this attribute with its next request.
+                                return Err(Error::InvalidLength);
+                            }
+                            if writer.space_left() < size {
+                                // Out of space, end the list.
+                                return Err(Error::Eof);
+                            }
+
+                            writer.write_u16_le(attr.handle.as_u16())?;
+                            attr.att_type.to_bytes(writer)?;
+                            fmt = Some(this_fmt);
+                            Ok(())
+                        })
+                        .ok();
+
+                    if let Some(fmt) = fmt {
+                        *format = fmt;
+                        Ok(())
+                    } else {
+                        Err(AttError::new(ErrorCode::AttributeNotFound, start).into())
+                    }
+                });
+
+                match result {
+                    Ok(()) => Ok(()),
+                    Err(RspError(e)) => Err(e),
+                }
+            }
+
             AttPdu::ReadByGroupReq {
                 handle_range,
                 group_type,
             } => {
                 let range = handle_range.check()?;
+                let start = range.start();
 
                 // Reject if `group_type` is not a grouping attribute
                 if !self.attrs.is_grouping_attr(*group_type) {
-                    return Err(AttError::new(
-                        ErrorCode::UnsupportedGroupType,
-                        range.start(),
-                    ));
+                    return Err(AttError::new(ErrorCode::UnsupportedGroupType, start));
                 }
 
                 let result = responder.send_with(|writer| {
@@ -155,14 +499,23 @@ impl<A: AttributeProvider> AttributeServer<A> {
                                 let data = ByGroupAttData::new(
                                     att_mtu,
                                     attr.handle,
-                                    provider.group_end(attr.handle).unwrap().handle,
-                                    attr.value.as_ref(),
+                                    provider.group_end(attr.handle).unwrap(),
+                                    bounded_value(provider, &attr),
                                 );
-                                if size == Some(data.encoded_size()) || size.is_none() {
-                                    // Can try to encode `data`. If we run out of space, end the list.
-                                    data.to_bytes(writer)?;
-                                    size = Some(data.encoded_size());
+                                if size.is_some_and(|size| size != data.encoded_size()) {
+                                    // All entries must have the same length, so groups with 16-
+                                    // and 128-bit UUIDs can't be mixed. The client will request
+                                    // the rest of the range later, so end the list here instead
+                                    // of skipping this group.
+                                    return Err(Error::InvalidLength);
+                                }
+                                if writer.space_left() < usize::from(data.encoded_size()) {
+                                    // Out of space, end the list.
+                                    return Err(Error::Eof);
                                 }
+
+                                data.to_bytes(writer)?;
+                                size = Some(data.encoded_size());
                             }
 
                             Ok(())
@@ -179,7 +532,7 @@ impl<A: AttributeProvider> AttributeServer<A> {
                         );
                         Ok(())
                     } else {
-                        Err(AttError::attribute_not_found().into())
+                        Err(AttError::new(ErrorCode::AttributeNotFound, start).into())
                     }
                 });
 
@@ -190,33 +543,76 @@ impl<A: AttributeProvider> AttributeServer<A> {
             }
 
             AttPdu::ReadReq { handle } => {
-                responder
-                    .send_with(|writer| -> Result<(), Error> {
-                        writer.write_u8(Opcode::ReadRsp.into())?;
-
-                        self.attrs.for_attrs_in_range(
-                            HandleRange::new(*handle, *handle),
-                            |_provider, attr| {
-                                let value = if writer.space_left() < attr.value.as_ref().len() {
-                                    &attr.value.as_ref()[..writer.space_left()]
-                                } else {
-                                    attr.value.as_ref()
-                                };
-                                writer.write_slice(value)
-                            },
-                        )?;
+                self.check_security(*handle)?;
+                let max_len = self.attrs.max_value_len(*handle);
+                self.snapshot = self
+                    .attrs
+                    .begin_read_snapshot(*handle)
+                    .map(|generation| (*handle, generation));
+
+                let result = responder.send_with(|writer| -> Result<(), RspError> {
+                    writer.write_u8(Opcode::ReadRsp.into())?;
+
+                    self.read_value(*handle, |value| {
+                        // Long values are truncated to `ATT_MTU - 1` Bytes, the client can read
+                        // the rest using Read Blob Requests
+                        write_value(writer, value, 0, max_len);
+                    })?;
+
+                    Ok(())
+                });
+
+                match result {
+                    Ok(()) => Ok(()),
+                    Err(RspError(e)) => Err(e),
+                }
+            }
 
+            AttPdu::ReadBlobReq { handle, offset } => {
+                self.check_security(*handle)?;
+                let max_len = self.attrs.max_value_len(*handle);
+                if *offset == 0 {
+                    self.snapshot = self
+                        .attrs
+                        .begin_read_snapshot(*handle)
+                        .map(|generation| (*handle, generation));
+                }
+
+                let result = responder.send_with(|writer| -> Result<(), RspError> {
+                    writer.write_u8(Opcode::ReadBlobRsp.into())?;
+
+                    let mut offset_valid = true;
+                    self.read_value(*handle, |value| {
+                        offset_valid = write_value(writer, value, usize::from(*offset), max_len);
+                    })?;
+
+                    if offset_valid {
                         Ok(())
-                    })
-                    .unwrap();
+                    } else {
+                        Err(AttError::new(ErrorCode::InvalidOffset, *handle).into())
+                    }
+                });
 
-                Ok(())
+                match result {
+                    Ok(()) => Ok(()),
+                    Err(RspError(e)) => Err(e),
+                }
             }
 
-            AttPdu::WriteReq { .. } => {
-                // FIXME: ATT Writes are not yet supported, but we pretend they work so that some
-                // applications that only need CCCD writes work (eg. BLE MIDI).
-                warn!("NYI: ATT Write Req");
+            AttPdu::WriteReq { handle, value } => {
+                self.check_writable(*handle)?;
+                self.check_security(*handle)?;
+                if value.as_ref().len() > self.attrs.max_value_len(*handle) {
+                    return Err(AttError::new(
+                        ErrorCode::InvalidAttributeValueLength,
+                        *handle,
+                    ));
+                }
+
+                if self.snapshot.is_some_and(|(h, _)| h == *handle) {
+                    self.snapshot = None;
+                }
+                self.attrs.write_attr(*handle, value.0)?;
 
                 responder
                     .send_with(|writer| -> Result<(), Error> {
@@ -227,6 +623,48 @@ impl<A: AttributeProvider> AttributeServer<A> {
                 Ok(())
             }
 
+            AttPdu::WriteCommand { handle, value } => {
+                // Commands are never answered, so a rejected or failed write is silently dropped
+                let allowed = self.check_writable(*handle).is_ok()
+                    && self.check_security(*handle).is_ok()
+                    && value.as_ref().len() <= self.attrs.max_value_len(*handle);
+                if !allowed {
+                    debug!("ATT: dropping Write Command to {:?}", handle);
+                    return Ok(());
+                }
+
+                if self.snapshot.is_some_and(|(h, _)| h == *handle) {
+                    self.snapshot = None;
+                }
+                if let Err(e) = self.attrs.write_command(*handle, value.0) {
+                    debug!("ATT: Write Command failed: {:?}", e);
+                }
+                Ok(())
+            }
+
+            AttPdu::HandleValueConfirmation => {
+                // Confirmations are never answered. An unexpected one is ignored, since there's no
+                // way to report the error to the client.
+                match self.indication {
+                    IndicationState::Pending { .. } => {
+                        self.indication = IndicationState::Idle;
+                        #[cfg(feature = "async")]
+                        {
+                            if let Some(events) = self.events {
+                                events.indication_confirmed().signal();
+                            }
+                        }
+                    }
+                    IndicationState::TimedOut => {
+                        warn!("ATT: Handle Value Confirmation after timeout");
+                    }
+                    IndicationState::Idle => {
+                        warn!("ATT: unexpected Handle Value Confirmation");
+                    }
+                }
+                Ok(())
+            }
+
             // Responses are always invalid here
             AttPdu::ErrorRsp { .. }
             | AttPdu::ExchangeMtuRsp { .. }
@@ -247,17 +685,21 @@ impl<A: AttributeProvider> AttributeServer<A> {
 
             // Unknown (undecoded) or unimplemented requests and commands
             AttPdu::Unknown { .. }
-            | AttPdu::FindInformationReq { .. }
             | AttPdu::FindByTypeValueReq { .. }
-            | AttPdu::ReadBlobReq { .. }
             | AttPdu::ReadMultipleReq { .. }
-            | AttPdu::WriteCommand { .. }
             | AttPdu::SignedWriteCommand { .. }
             | AttPdu::PrepareWriteReq { .. }
-            | AttPdu::ExecuteWriteReq { .. }
-            | AttPdu::HandleValueConfirmation { .. } => {
-                if msg.opcode().is_command() {
-                    // According to the spec, unknown Command PDUs should be ignored
+            | AttPdu::ExecuteWriteReq { .. } => {
+                let opcode = msg.opcode();
+                if opcode.is_command() {
+                    // According to the spec, unknown Command PDUs should be ignored. Signed writes
+                    // share their method with the other writes, but can't be verified without a
+                    // signing key, so they end up here too.
+                    debug!(
+                        "ATT: ignoring command (method {:#04x}, signed: {})",
+                        opcode.method(),
+                        opcode.is_authenticated()
+                    );
                     Ok(())
                 } else {
                     // Unknown requests are rejected with a `RequestNotSupported` error
@@ -268,13 +710,76 @@ impl<A: AttributeProvider> AttributeServer<A> {
     }
 }
 
+/// Returns whether an ATT PDU starting with `opcode` is a request the server has to answer.
+///
+/// Commands and confirmations are never answered, and may be sent by the client at any time.
+fn is_request(opcode: Opcode) -> bool {
+    !opcode.is_command() && opcode != Opcode::HandleValueConfirmation
+}
+
 impl<A: AttributeProvider> ProtocolObj for AttributeServer<A> {
+    /// Processes an ATT PDU sent by the client.
+    ///
+    /// A client must not send a new request before it has received the response to the previous
+    /// one. Requests received while that response is still in the TX queue are rejected with an
+    /// `UnlikelyError`. Deferring them instead would hold up the commands and confirmations behind
+    /// them in the RX queue.
     fn process_message(&mut self, message: &[u8], mut responder: Sender<'_>) -> Result<(), Error> {
-        let pdu = &AttPdu::from_bytes(&mut ByteReader::new(message))?;
+        if self.is_confirmation_timed_out() {
+            // After a transaction timeout, no more ATT PDUs may be sent, so requests can't be
+            // answered
+            debug!("ATT: dropping PDU after confirmation timeout");
+            return Ok(());
+        }
+
+        if let (Some(&raw), Some(ticket)) = (message.first(), self.response) {
+            let opcode = Opcode::from(raw);
+            if is_request(opcode) && !responder.is_dequeued(ticket) {
+                warn!("ATT: request while a response is pending, rejecting it");
+                responder.send(AttPdu::ErrorRsp {
+                    opcode,
+                    handle: Handle::NULL,
+                    error_code: ErrorCode::UnlikelyError,
+                })?;
+                self.response = Some(responder.last_ticket());
+                return Ok(());
+            }
+        }
+
+        let result = self.process_pdu(message, &mut responder);
+        if let (Ok(()), Some(&raw)) = (&result, message.first()) {
+            if is_request(Opcode::from(raw)) {
+                self.response = Some(responder.last_ticket());
+            }
+        }
+        result
+    }
+}
+
+impl<A: AttributeProvider> AttributeServer<A> {
+    /// Parses and processes an incoming ATT PDU, answering it through `responder` if necessary.
+    fn process_pdu(&mut self, message: &[u8], responder: &mut Sender<'_>) -> Result<(), Error> {
+        let pdu = &match AttPdu::from_bytes(&mut ByteReader::new(message)) {
+            Ok(pdu) => pdu,
+            Err(e) => {
+                // Malformed requests are answered with an error, malformed commands are dropped
+                let opcode = match message.first() {
+                    Some(&raw) if !Opcode::from(raw).is_command() => Opcode::from(raw),
+                    _ => return Err(e),
+                };
+                debug!("ATT<- malformed {:?} ({})", opcode, e);
+
+                return responder.send(AttPdu::ErrorRsp {
+                    opcode,
+                    handle: Handle::NULL,
+                    error_code: e.into(),
+                });
+            }
+        };
         let opcode = pdu.opcode();
         debug!("ATT<- {:?}", pdu);
 
-        match self.process_request(pdu, &mut responder) {
+        match self.process_request(pdu, responder) {
             Ok(()) => Ok(()),
             Err(att_error) => {
                 debug!("ATT-> {:?}", att_error);
@@ -290,8 +795,39 @@ impl<A: AttributeProvider> ProtocolObj for AttributeServer<A> {
 }
 
 impl<A: AttributeProvider> Protocol for AttributeServer<A> {
-    // FIXME: Would it be useful to have this as a runtime parameter instead?
-    const RSP_PDU_SIZE: u8 = 23;
+    const RSP_PDU_SIZE: u8 = DEFAULT_MTU as u8;
+
+    /// Responses and server-initiated PDUs may use the whole negotiated `ATT_MTU`.
+    fn max_pdu_size(&self) -> u16 {
+        self.mtu
+    }
+}
+
+/// Returns the value of `attr`, limited to the maximum length reported by `provider`.
+fn bounded_value<'a, A: AttributeProvider>(provider: &A, attr: &Attribute<'a>) -> &'a [u8] {
+    let value = attr.value.0;
+    &value[..value.len().min(provider.max_value_len(attr.handle))]
+}
+
+/// Writes the part of `value` starting at `offset` to `writer`.
+///
+/// The value is truncated to `max_len` Bytes and to the space left in `writer`. Returns `false`
+/// without writing anything if `offset` lies past the end of the truncated value.
+fn write_value(
+    writer: &mut ByteWriter<'_>,
+    value: &mut dyn ValueSource,
+    offset: usize,
+    max_len: usize,
+) -> bool {
+    let len = value.len().min(max_len);
+    if offset > len {
+        return false;
+    }
+
+    let n = (len - offset).min(writer.space_left());
+    value.read_at(offset, &mut writer.rest()[..n]);
+    writer.skip(n).unwrap();
+    true
 }
 
 /// An ATT server handle that can send packets and initiate actions.
@@ -299,22 +835,56 @@ impl<A: AttributeProvider> Protocol for AttributeServer<A> {
 /// This type is needed for any server-initiated procedure, where the server sends out a packet on
 /// its own instead of reacting to a client packet.
 pub struct AttributeServerTx<'a, A: AttributeProvider> {
-    #[allow(unused)]
     server: &'a mut AttributeServer<A>,
 
     sender: Sender<'a>,
 }
 
 impl<'a, A: AttributeProvider> AttributeServerTx<'a, A> {
+    /// Returns a mutable reference to the `AttributeProvider` of the server.
+    pub fn provider(&mut self) -> &mut A {
+        self.server.provider()
+    }
+
+    /// Returns the `ATT_MTU` negotiated with the client.
+    ///
+    /// Notifications and indications carry at most `ATT_MTU - 3` Bytes of the attribute value.
+    pub fn mtu(&self) -> u16 {
+        self.server.mtu()
+    }
+
+    /// Returns the handle of the connection over which notifications and indications are sent.
+    pub fn connection_handle(&self) -> ConnectionHandle {
+        self.sender.connection_handle()
+    }
+
     /// Sends an attribute value notification to the connected client.
     ///
     /// Notifications are not acknowledged by the client.
     ///
     /// If `value` is too large to be transmitted in a single `ATT_MTU`, it will be truncated to
     /// fit. A client may fetch the rest of the truncated value by using a *Read Blob Request*.
-    /// If this is unwanted, only notify with a `value` of 19 Bytes or less.
-    pub fn notify_raw(mut self, handle: Handle, value: &[u8]) {
-        // This cannot fail. The `self` guarantees that there's `RSP_PDU_SIZE` bytes free in
+    /// If this is unwanted, only notify with a `value` of `ATT_MTU - 3` Bytes or less.
+    ///
+    /// Returns a `TxTicket` identifying the enqueued notification. Once the Link-Layer has
+    /// transmitted it and the master acknowledged it, `Connection::is_acknowledged` will return
+    /// `true` for the ticket. If the connection is closed first, `LinkLayer::tx_status` reports the
+    /// notification as dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotifyError::NotConnected` without sending anything if there is no connection (see
+    /// `AttributeServer::is_connected`), and `NotifyError::ConfirmationTimeout` if the client
+    /// failed to confirm an indication in time (see `AttributeServer::is_confirmation_timed_out`).
+    pub fn notify_raw(mut self, handle: Handle, value: &[u8]) -> Result<TxTicket, NotifyError> {
+        if !self.server.is_connected() {
+            return Err(NotifyError::NotConnected);
+        }
+        if self.server.is_confirmation_timed_out() {
+            return Err(NotifyError::ConfirmationTimeout);
+        }
+
+        // This cannot fail. The `self` guarantees that there's `ATT_MTU` bytes free in
         // `sender`, and is consumed by this method. `AttPdu`s encoder will truncate `value` to fit
         // and doesn't error.
         self.sender
@@ -322,6 +892,1294 @@ impl<'a, A: AttributeProvider> AttributeServerTx<'a, A> {
                 handle,
                 value: HexSlice(value),
             })
+            .unwrap();
+        Ok(self.sender.last_ticket())
+    }
+
+    /// Returns whether the Link-Layer is connected, so notifications can be sent.
+    pub fn is_connected(&self) -> bool {
+        self.server.is_connected()
+    }
+
+    /// Sends an attribute value indication to the connected client.
+    ///
+    /// Unlike notifications, indications are confirmed by the client. Only one indication may be
+    /// outstanding at a time, so this will return an error if the client hasn't yet confirmed the
+    /// last indication. Nothing will be sent in that case.
+    ///
+    /// The client has to confirm the indication within `AttributeServer::confirmation_timeout`.
+    /// The timeout starts at the next connection event reported by the Link-Layer (see
+    /// `AttributeServer::check_confirmation_timeout`). Use `indicate_raw_at` to start it right
+    /// away.
+    ///
+    /// Like `notify_raw`, this will truncate `value` if it doesn't fit in a single `ATT_MTU`.
+    pub fn indicate_raw(self, handle: Handle, value: &[u8]) -> Result<(), IndicationPending> {
+        self.indicate(handle, value, None)
+    }
+
+    /// Sends an attribute value indication to the connected client at `now`.
+    ///
+    /// This works like `indicate_raw`, but starts the confirmation timeout at `now`, so that
+    /// `AttributeServer::confirmation_deadline` is known right away.
+    pub fn indicate_raw_at(
+        self,
+        handle: Handle,
+        value: &[u8],
+        now: Instant,
+    ) -> Result<(), IndicationPending> {
+        self.indicate(handle, value, Some(now))
+    }
+
+    fn indicate(
+        mut self,
+        handle: Handle,
+        value: &[u8],
+        now: Option<Instant>,
+    ) -> Result<(), IndicationPending> {
+        if self.server.is_indication_pending() {
+            return Err(IndicationPending);
+        }
+
+        // Like in `notify_raw`, sending cannot fail here
+        self.sender
+            .send(AttPdu::HandleValueIndication {
+                handle,
+                value: HexSlice(value),
+            })
+            .unwrap();
+        let timeout = self.server.confirmation_timeout;
+        self.server.indication = IndicationState::Pending {
+            deadline: now.map(|now| now + timeout),
+        };
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum IndicationState {
+    /// No indication is waiting for confirmation.
+    Idle,
+
+    /// An indication was sent and must be confirmed before `deadline`.
+    ///
+    /// The deadline is `None` until the time the indication was sent is known.
+    Pending { deadline: Option<Instant> },
+
+    /// The client didn't confirm an indication in time.
+    TimedOut,
+}
+
+/// Error returned when trying to send an indication while the previous one hasn't been confirmed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IndicationPending;
+
+/// Error returned by `AttributeServer::check_confirmation_timeout` when the client didn't confirm
+/// an indication in time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConfirmationTimeout;
+
+/// Error returned by `AttributeServerTx::notify_raw` when a notification can't be sent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NotifyError {
+    /// The Link-Layer isn't connected.
+    NotConnected,
+
+    /// The client didn't confirm an indication in time, so no ATT PDUs may be sent over the
+    /// connection anymore.
+    ConfirmationTimeout,
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            att::{Attribute, HandleRange, NoAttributes, MAX_VALUE_LEN},
+            gatt::{BatteryServiceAttrs, GapServiceAttrs, NordicUartAttrs},
+            l2cap::{self, BleChannelMap, Channel, L2CAPState},
+            link::{
+                mock::MockQueue,
+                queue::{Consume, Consumer, PacketQueue, Producer, SimpleQueue},
+            },
+            security::NoSecurity,
+            uuid::Uuid16,
+        },
+        std::vec::Vec,
+    };
+
+    /// An attribute provider backed by a list of attributes, with no grouping attributes.
+    struct TestAttrs {
+        attributes: Vec<Attribute<'static>>,
+        /// Maximum value length of all attributes.
+        max_len: usize,
+        /// Security level required to access all attributes.
+        required: ConnectionSecurity,
+        /// Writes performed by the client, and whether they used a Write Command.
+        writes: Vec<(u16, Vec<u8>, bool)>,
+        /// Accept writes to all attributes, instead of rejecting declarations and descriptors.
+        writable: bool,
+    }
+
+    impl TestAttrs {
+        fn new(attrs: &[(u16, u16, &'static [u8])]) -> Self {
+            Self {
+                attributes: attrs
+                    .iter()
+                    .map(|&(handle, uuid, value)| Attribute {
+                        att_type: AttUuid::Uuid16(Uuid16(uuid)),
+                        handle: Handle::from_raw(handle),
+                        value: HexSlice(value),
+                    })
+                    .collect(),
+                max_len: MAX_VALUE_LEN,
+                required: ConnectionSecurity::Unencrypted,
+                writes: Vec::new(),
+                writable: false,
+            }
+        }
+    }
+
+    impl AttributeProvider for TestAttrs {
+        fn for_attrs_in_range(
+            &mut self,
+            range: HandleRange,
+            mut f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            for attr in &self.attributes {
+                if range.contains(attr.handle) {
+                    f(
+                        self,
+                        Attribute {
+                            att_type: attr.att_type,
+                            handle: attr.handle,
+                            value: attr.value,
+                        },
+                    )?;
+                }
+            }
+            Ok(())
+        }
+
+        fn is_grouping_attr(&self, _uuid: AttUuid) -> bool {
+            false
+        }
+
+        fn group_end(&self, _handle: Handle) -> Option<Handle> {
+            None
+        }
+
+        fn max_value_len(&self, _handle: Handle) -> usize {
+            self.max_len
+        }
+
+        fn required_security(&self, _handle: Handle) -> ConnectionSecurity {
+            self.required
+        }
+
+        fn check_writable(&mut self, handle: Handle) -> Result<(), AttError> {
+            let attr = self
+                .attributes
+                .iter()
+                .find(|attr| attr.handle == handle)
+                .ok_or_else(|| AttError::new(ErrorCode::InvalidHandle, handle))?;
+            if self.writable || crate::att::is_writable_type(attr.att_type) {
+                Ok(())
+            } else {
+                Err(AttError::new(ErrorCode::WriteNotPermitted, handle))
+            }
+        }
+
+        fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+            self.writes.push((handle.as_u16(), value.to_vec(), false));
+            Ok(())
+        }
+
+        fn write_command(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+            self.writes.push((handle.as_u16(), value.to_vec(), true));
+            Ok(())
+        }
+    }
+
+    /// Builds an L2CAP message addressed to the ATT channel.
+    fn att_message(pdu: &[u8]) -> Vec<u8> {
+        l2cap::message(pdu.len() as u16, Channel::ATT, pdu)
+    }
+
+    /// Removes the next ATT PDU from `rx`, if there is one.
+    fn next_pdu(rx: &mut impl Consumer) -> Option<Vec<u8>> {
+        rx.consume_raw_with(|_, payload| Consume::always(Ok(payload[4..].to_vec())))
+            .ok()
+    }
+
+    /// Sends an ATT PDU to the server and returns the response PDU.
+    fn request<A: AttributeProvider>(
+        l2cap: &mut L2CAPState<BleChannelMap<A, NoSecurity>>,
+        pdu: &[u8],
+    ) -> Vec<u8> {
+        l2cap::exchange(l2cap, Channel::ATT, pdu).expect("no response sent")
+    }
+
+    #[test]
+    fn read_by_type_mixed_lengths() {
+        let attrs = TestAttrs::new(&[
+            (1, 0xAAAA, &[1, 1]),
+            (2, 0xAAAA, &[2, 2]),
+            (3, 0xBBBB, &[0xFF]),
+            (4, 0xAAAA, &[4, 4, 4, 4]),
+            (5, 0xAAAA, &[5, 5]),
+        ]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+
+        // Read By Type Request for 0xAAAA, starting at the given handle
+        let req = |start: u16| {
+            let mut req = vec![0x08];
+            req.extend_from_slice(&start.to_le_bytes());
+            req.extend_from_slice(&[0xFF, 0xFF, 0xAA, 0xAA]);
+            req
+        };
+
+        // Only the run of 2-byte values is returned, not the later one at handle 5
+        assert_eq!(
+            request(&mut l2cap, &req(1)),
+            [0x09, 4, 1, 0, 1, 1, 2, 0, 2, 2]
+        );
+        assert_eq!(request(&mut l2cap, &req(3)), [0x09, 6, 4, 0, 4, 4, 4, 4]);
+        assert_eq!(request(&mut l2cap, &req(5)), [0x09, 4, 5, 0, 5, 5]);
+
+        // Attribute Not Found
+        let rsp = request(&mut l2cap, &req(6));
+        assert_eq!((rsp[0], rsp[1], rsp[4]), (0x01, 0x08, 0x0A));
+    }
+
+    #[test]
+    fn read_by_type_fills_mtu() {
+        // 4-byte entries: 5 of them fit into the 23-byte response (2 + 5 * 4 = 22 bytes)
+        let attrs = TestAttrs::new(&[
+            (1, 0xAAAA, &[1, 1]),
+            (2, 0xAAAA, &[2, 2]),
+            (3, 0xAAAA, &[3, 3]),
+            (4, 0xAAAA, &[4, 4]),
+            (5, 0xAAAA, &[5, 5]),
+            (6, 0xAAAA, &[6, 6]),
+        ]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+
+        let rsp = request(&mut l2cap, &[0x08, 1, 0, 0xFF, 0xFF, 0xAA, 0xAA]);
+        assert_eq!(rsp.len(), 22);
+        assert_eq!(&rsp[18..], [5, 0, 5, 5]);
+    }
+
+    #[test]
+    fn malformed_request() {
+        let attrs = TestAttrs::new(&[(1, 0xAAAA, &[1, 1])]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+
+        // Truncated Read Request
+        assert_eq!(request(&mut l2cap, &[0x0A, 0x01]), [0x01, 0x0A, 0, 0, 0x04]);
+
+        // Malformed commands aren't answered
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = (&mut queue).split();
+        let _ = l2cap.tx(&mut tx).process_start(&att_message(&[0x52, 0x01]));
+        assert!(next_pdu(&mut rx).is_none());
+    }
+
+    #[test]
+    fn find_information_splits_uuid_kinds() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(NordicUartAttrs::new()));
+        let req = |start: u16| {
+            let mut req = vec![0x04];
+            req.extend_from_slice(&start.to_le_bytes());
+            req.extend_from_slice(&[0xFF, 0xFF]);
+            req
+        };
+        let nus_uuid = |n: u8| {
+            let mut uuid = vec![
+                0x9E, 0xCA, 0xDC, 0x24, 0x0E, 0xE5, 0xA9, 0xE0, 0x93, 0xF3, 0xA3, 0xB5,
+            ];
+            uuid.extend_from_slice(&[n, 0x00, 0x40, 0x6E]);
+            uuid
+        };
+
+        // The 16-bit service and characteristic declarations end at the 128-bit RX value
+        assert_eq!(
+            request(&mut l2cap, &req(1)),
+            [0x05, 0x01, 1, 0, 0x00, 0x28, 2, 0, 0x03, 0x28]
+        );
+
+        // Only one 128-bit entry fits into the default ATT_MTU
+        let mut rx_value = vec![0x05, 0x02, 3, 0];
+        rx_value.extend_from_slice(&nus_uuid(2));
+        assert_eq!(request(&mut l2cap, &req(3)), rx_value);
+
+        assert_eq!(request(&mut l2cap, &req(4)), [0x05, 0x01, 4, 0, 0x03, 0x28]);
+
+        let mut tx_value = vec![0x05, 0x02, 5, 0];
+        tx_value.extend_from_slice(&nus_uuid(3));
+        assert_eq!(request(&mut l2cap, &req(5)), tx_value);
+
+        assert_eq!(request(&mut l2cap, &req(6)), [0x05, 0x01, 6, 0, 0x02, 0x29]);
+
+        // Attribute Not Found, reported for the starting handle
+        assert_eq!(request(&mut l2cap, &req(7)), [0x01, 0x04, 7, 0, 0x0A]);
+    }
+
+    #[test]
+    fn validate_handles() {
+        assert_eq!(
+            TestAttrs::new(&[(1, 0xAAAA, &[]), (3, 0xAAAA, &[])]).validate(),
+            Ok(())
+        );
+        assert_eq!(GapServiceAttrs::new("rubble", 0).validate(), Ok(()));
+        assert_eq!(NoAttributes.validate(), Ok(()));
+
+        let err = TestAttrs::new(&[(1, 0xAAAA, &[]), (3, 0xAAAA, &[]), (2, 0xAAAA, &[])])
+            .validate()
+            .unwrap_err();
+        assert_eq!(err.handle, Handle::from_raw(2));
+        assert_eq!(err.previous, Some(Handle::from_raw(3)));
+
+        let err = TestAttrs::new(&[(1, 0xAAAA, &[]), (1, 0xAAAA, &[])])
+            .validate()
+            .unwrap_err();
+        assert_eq!(err.previous, Some(Handle::from_raw(1)));
+
+        let err = TestAttrs::new(&[(2, 0xAAAA, &[])]).validate().unwrap_err();
+        assert_eq!(err.handle, Handle::from_raw(2));
+        assert_eq!(err.previous, None);
+    }
+
+    #[test]
+    fn new_reads_no_values() {
+        let mut reads = 0;
+        AttributeServer::new(BatteryServiceAttrs::with_level_source(|| {
+            reads += 1;
+            100
+        }));
+        assert_eq!(reads, 0);
+    }
+
+    #[test]
+    fn read_blob_long_device_name() {
+        let name = "A rather long device name, 38 bytes :)";
+        let gap = GapServiceAttrs::new(name, 0x03C1);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(gap));
+        let handle = GapServiceAttrs::DEVICE_NAME_HANDLE.as_u16().to_le_bytes();
+
+        // Appearance is encoded as a little-endian u16
+        let rsp = request(&mut l2cap, &[0x0A, 0x03, 0x00]);
+        assert_eq!(rsp, [0x0B, 0xC1, 0x03]);
+
+        // Read Request returns the first ATT_MTU - 1 bytes
+        let rsp = request(&mut l2cap, &[0x0A, handle[0], handle[1]]);
+        assert_eq!(rsp[0], 0x0B);
+        assert_eq!(&rsp[1..], &name.as_bytes()[..22]);
+
+        // Read Blob Request returns the rest
+        let rsp = request(&mut l2cap, &[0x0C, handle[0], handle[1], 22, 0]);
+        assert_eq!(rsp[0], 0x0D);
+        assert_eq!(&rsp[1..], &name.as_bytes()[22..]);
+
+        // Reading at the very end returns an empty value
+        let rsp = request(&mut l2cap, &[0x0C, handle[0], handle[1], 38, 0]);
+        assert_eq!(rsp, [0x0D]);
+
+        // Invalid Offset
+        let rsp = request(&mut l2cap, &[0x0C, handle[0], handle[1], 39, 0]);
+        assert_eq!(rsp, [0x01, 0x0C, handle[0], handle[1], 0x07]);
+
+        // Invalid Handle
+        let rsp = request(&mut l2cap, &[0x0C, 0x20, 0x00, 0, 0]);
+        assert_eq!(rsp, [0x01, 0x0C, 0x20, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn write_device_name() {
+        let handle = GapServiceAttrs::DEVICE_NAME_HANDLE.as_u16().to_le_bytes();
+        let write = |name: &[u8]| {
+            let mut pdu = vec![0x12, handle[0], handle[1]];
+            pdu.extend_from_slice(name);
+            pdu
+        };
+
+        let mut buf = *b"rubble\0\0";
+        let gap = GapServiceAttrs::writable(&mut buf, 6, 0x0000);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(gap));
+        let rsp = request(&mut l2cap, &[0x0A, handle[0], handle[1]]);
+        assert_eq!(rsp, b"\x0Brubble");
+
+        // The written name is stored and read back
+        let rsp = request(&mut l2cap, &write(b"new name"));
+        assert_eq!(rsp, [0x13]);
+        let rsp = request(&mut l2cap, &[0x0A, handle[0], handle[1]]);
+        assert_eq!(rsp, b"\x0Bnew name");
+
+        // Names that don't fit in the buffer are rejected without changing the name
+        let rsp = request(&mut l2cap, &write(b"too long!"));
+        assert_eq!(rsp, [0x01, 0x12, handle[0], handle[1], 0x0D]);
+        let rsp = request(&mut l2cap, &[0x0A, handle[0], handle[1]]);
+        assert_eq!(rsp, b"\x0Bnew name");
+
+        // A name created with `new` is read-only
+        let gap = GapServiceAttrs::new("rubble", 0x0000);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(gap));
+        let rsp = request(&mut l2cap, &write(b"new"));
+        assert_eq!(rsp, [0x01, 0x12, handle[0], handle[1], 0x03]);
+    }
+
+    #[test]
+    fn read_grouping_attributes() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(BatteryServiceAttrs::new()));
+
+        // The primary service declaration is a grouping attribute, but can be read like any other
+        let rsp = request(&mut l2cap, &[0x0A, 0x01, 0x00]);
+        assert_eq!(rsp, [0x0B, 0x0F, 0x18]);
+        let rsp = request(&mut l2cap, &[0x0A, 0x02, 0x00]);
+        assert_eq!(rsp, [0x0B, 0x02, 0x03, 0x00, 0x19, 0x2A]);
+        let rsp = request(&mut l2cap, &[0x0A, 0x03, 0x00]);
+        assert_eq!(rsp, [0x0B, 48]);
+
+        // ...and still groups the attributes of the service
+        let rsp = request(&mut l2cap, &[0x10, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28]);
+        assert_eq!(rsp, [0x11, 0x06, 0x01, 0x00, 0x03, 0x00, 0x0F, 0x18]);
+    }
+
+    #[test]
+    fn read_long_value_truncates() {
+        static VALUE: [u8; 40] = [
+            0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23,
+            24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39,
+        ];
+        let attrs = TestAttrs::new(&[(1, 0xAAAA, &VALUE), (2, 0xAAAA, &[2])]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+
+        // Read Request: The value is truncated to ATT_MTU - 1 = 22 Bytes
+        let rsp = request(&mut l2cap, &[0x0A, 0x01, 0x00]);
+        assert_eq!(rsp.len(), 23);
+        assert_eq!(rsp[0], 0x0B);
+        assert_eq!(&rsp[1..], &VALUE[..22]);
+
+        // Read By Type: A single entry with the value truncated to ATT_MTU - 4 = 19 Bytes
+        let rsp = request(&mut l2cap, &[0x08, 0x01, 0x00, 0xFF, 0xFF, 0xAA, 0xAA]);
+        assert_eq!(rsp.len(), 23);
+        assert_eq!(&rsp[..4], [0x09, 21, 0x01, 0x00]);
+        assert_eq!(&rsp[4..], &VALUE[..19]);
+
+        // Read Blob continues where the Read Response ended
+        let rsp = request(&mut l2cap, &[0x0C, 0x01, 0x00, 22, 0]);
+        assert_eq!(rsp[0], 0x0D);
+        assert_eq!(&rsp[1..], &VALUE[22..]);
+    }
+
+    /// A single long attribute whose value is updated by the application, and which can freeze a
+    /// snapshot of the value for reads.
+    struct GrowingAttr {
+        value: Vec<u8>,
+        /// Incremented on every update of `value`.
+        generation: u32,
+        /// Whether to take snapshots.
+        snapshots: bool,
+        snapshot: Option<(u32, Vec<u8>)>,
+    }
+
+    impl GrowingAttr {
+        fn update(&mut self, value: &[u8]) {
+            self.value = value.to_vec();
+            self.generation += 1;
+        }
+    }
+
+    impl AttributeProvider for GrowingAttr {
+        fn for_attrs_in_range(
+            &mut self,
+            range: HandleRange,
+            mut f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            let attr = Attribute {
+                att_type: AttUuid::Uuid16(Uuid16(0xAAAA)),
+                handle: Handle::from_raw(1),
+                value: HexSlice(&self.value),
+            };
+            if range.contains(attr.handle) {
+                f(self, attr)?;
+            }
+            Ok(())
+        }
+
+        fn is_grouping_attr(&self, _uuid: AttUuid) -> bool {
+            false
+        }
+
+        fn group_end(&self, _handle: Handle) -> Option<Handle> {
+            None
+        }
+
+        fn begin_read_snapshot(&mut self, _handle: Handle) -> Option<u32> {
+            if self.snapshots {
+                self.snapshot = Some((self.generation, self.value.clone()));
+                Some(self.generation)
+            } else {
+                None
+            }
+        }
+
+        fn read_snapshot(
+            &mut self,
+            handle: Handle,
+            generation: u32,
+            f: impl FnOnce(&[u8]),
+        ) -> Result<(), AttError> {
+            match &self.snapshot {
+                Some((gen, value)) if *gen == generation => {
+                    f(value);
+                    Ok(())
+                }
+                _ => Err(AttError::new(ErrorCode::UnlikelyError, handle)),
+            }
+        }
+    }
+
+    #[test]
+    fn read_snapshot_prevents_torn_reads() {
+        let old = [b'a'; 30];
+        let new = [b'b'; 30];
+        for &snapshots in &[false, true] {
+            let attr = GrowingAttr {
+                value: old.to_vec(),
+                generation: 0,
+                snapshots,
+                snapshot: None,
+            };
+            let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attr));
+
+            let rsp = request(&mut l2cap, &[0x0A, 0x01, 0x00]);
+            assert_eq!(&rsp[1..], &old[..22]);
+
+            // The application updates the value between the Read and the Read Blob Request
+            l2cap.att_server().provider().update(&new);
+            let rsp = request(&mut l2cap, &[0x0C, 0x01, 0x00, 22, 0]);
+            if snapshots {
+                // The client gets the rest of the value it started reading
+                assert_eq!(&rsp[1..], &old[22..]);
+            } else {
+                // Torn read: The client assembles `aaa...abbbbbbbb`
+                assert_eq!(&rsp[1..], &new[22..]);
+            }
+
+            // Reading from the start again returns the new value
+            let rsp = request(&mut l2cap, &[0x0C, 0x01, 0x00, 0, 0]);
+            assert_eq!(&rsp[1..], &new[..22]);
+            let rsp = request(&mut l2cap, &[0x0C, 0x01, 0x00, 22, 0]);
+            assert_eq!(&rsp[1..], &new[22..]);
+        }
+    }
+
+    #[test]
+    fn writes_rejected_by_default() {
+        // `GrowingAttr` doesn't override `write_attr`
+        let attr = GrowingAttr {
+            value: vec![1],
+            generation: 0,
+            snapshots: false,
+            snapshot: None,
+        };
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attr));
+
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x01, 0x00, 2]),
+            [0x01, 0x12, 0x01, 0x00, 0x03]
+        );
+        assert_eq!(request(&mut l2cap, &[0x0A, 0x01, 0x00]), [0x0B, 1]);
+    }
+
+    /// A 300-Byte value that is computed when it's read, and never held in memory.
+    struct ComputedValue {
+        /// Number of Bytes of the value read so far.
+        bytes_read: usize,
+    }
+
+    impl ValueSource for ComputedValue {
+        fn len(&self) -> usize {
+            300
+        }
+
+        fn read_at(&mut self, offset: usize, buf: &mut [u8]) {
+            for (i, byte) in buf.iter_mut().enumerate() {
+                *byte = (offset + i) as u8;
+            }
+            self.bytes_read += buf.len();
+        }
+    }
+
+    impl AttributeProvider for ComputedValue {
+        fn for_attrs_in_range(
+            &mut self,
+            range: HandleRange,
+            mut f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            let attr = Attribute {
+                att_type: AttUuid::Uuid16(Uuid16(0xAAAA)),
+                handle: Handle::from_raw(1),
+                value: HexSlice(&[]),
+            };
+            if range.contains(attr.handle) {
+                f(self, attr)?;
+            }
+            Ok(())
+        }
+
+        fn is_grouping_attr(&self, _uuid: AttUuid) -> bool {
+            false
+        }
+
+        fn group_end(&self, _handle: Handle) -> Option<Handle> {
+            None
+        }
+
+        fn read_attr_source(
+            &mut self,
+            handle: Handle,
+            f: impl FnOnce(&mut dyn ValueSource),
+        ) -> Result<(), AttError> {
+            if handle.as_u16() != 1 {
+                return Err(AttError::new(ErrorCode::InvalidHandle, handle));
+            }
+            f(self);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read_computed_value() {
+        let attrs = ComputedValue { bytes_read: 0 };
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+        let expected = (0..300).map(|i| i as u8).collect::<Vec<_>>();
+
+        // Only the part of the value sent in each response is computed
+        let rsp = request(&mut l2cap, &[0x0A, 0x01, 0x00]);
+        assert_eq!(rsp[1..], expected[..22]);
+        assert_eq!(l2cap.att_server().provider().bytes_read, 22);
+
+        let rsp = request(&mut l2cap, &[0x0C, 0x01, 0x00, 0x1E, 0x01]);
+        assert_eq!(rsp[1..], expected[286..]);
+        assert_eq!(l2cap.att_server().provider().bytes_read, 22 + 14);
+
+        // Offsets are checked against the length of the value
+        assert_eq!(request(&mut l2cap, &[0x0C, 0x01, 0x00, 0x2C, 0x01]), [0x0D]);
+        assert_eq!(
+            request(&mut l2cap, &[0x0C, 0x01, 0x00, 0x2D, 0x01]),
+            [0x01, 0x0C, 0x01, 0x00, 0x07]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x0A, 0x02, 0x00]),
+            [0x01, 0x0A, 0x02, 0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn max_value_len() {
+        let mut attrs = TestAttrs::new(&[(1, 0xAAAA, &[1, 2, 3, 4, 5, 6])]);
+        attrs.max_len = 4;
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+
+        // Reads never return more than `max_value_len` Bytes
+        assert_eq!(request(&mut l2cap, &[0x0A, 0x01, 0x00]), [0x0B, 1, 2, 3, 4]);
+        assert_eq!(
+            request(&mut l2cap, &[0x08, 0x01, 0x00, 0xFF, 0xFF, 0xAA, 0xAA]),
+            [0x09, 6, 0x01, 0x00, 1, 2, 3, 4]
+        );
+        assert_eq!(request(&mut l2cap, &[0x0C, 0x01, 0x00, 2, 0]), [0x0D, 3, 4]);
+
+        // Reading past the bounded value is an invalid offset
+        let rsp = request(&mut l2cap, &[0x0C, 0x01, 0x00, 5, 0]);
+        assert_eq!((rsp[0], rsp[1], rsp[4]), (0x01, 0x0C, 0x07));
+
+        // Writes of longer values are rejected
+        assert_eq!(request(&mut l2cap, &[0x12, 0x01, 0x00, 1, 2, 3, 4]), [0x13]);
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x01, 0x00, 1, 2, 3, 4, 5]),
+            [0x01, 0x12, 0x01, 0x00, 0x0D]
+        );
+    }
+
+    /// Performs an MTU exchange with the client MTU `mtu`.
+    ///
+    /// Returns the server MTU sent in the response and the negotiated MTU.
+    fn exchange_mtu(
+        l2cap: &mut L2CAPState<BleChannelMap<NoAttributes, NoSecurity>>,
+        mtu: u16,
+    ) -> (u16, u16) {
+        let mut req = vec![0x02];
+        req.extend_from_slice(&mtu.to_le_bytes());
+        let rsp = request(l2cap, &req);
+        assert_eq!(rsp[0], 0x03);
+        (
+            u16::from_le_bytes([rsp[1], rsp[2]]),
+            l2cap.att_server().mtu(),
+        )
+    }
+
+    #[test]
+    fn mtu_exchange_clamps_to_max() {
+        // Small max. MTU: The default MTU is always used
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(NoAttributes));
+        assert_eq!(l2cap.att_server().mtu(), DEFAULT_MTU);
+        assert_eq!(exchange_mtu(&mut l2cap, 100), (23, 23));
+
+        // Never less than the default
+        assert_eq!(exchange_mtu(&mut l2cap, 10), (23, 23));
+    }
+
+    #[test]
+    fn max_mtu_limited_by_link() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(NoAttributes));
+        assert_eq!(MAX_PAYLOAD_LEN, 247);
+        assert_eq!(l2cap.att_server().set_max_mtu(247), Ok(()));
+
+        // An MTU larger than the L2CAP payload that can be reassembled is rejected
+        assert_eq!(
+            l2cap.att_server().set_max_mtu(248),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(l2cap.att_server().max_mtu(), 247);
+        assert_eq!(l2cap.att_server().mtu(), DEFAULT_MTU);
+    }
+
+    #[test]
+    fn large_mtu_used_for_responses() {
+        let attrs = TestAttrs::new(&[(0x0001, 0x2A00, &[0x55; 60])]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+        l2cap.att_server().set_max_mtu(100).unwrap();
+
+        let (mut tx, mut rx) = MockQueue::with_capacity(4).split();
+        let mut exchange = |l2cap: &mut L2CAPState<_>, request: &[u8]| {
+            let _ = l2cap.tx(&mut tx).process_start(&l2cap::message(
+                request.len() as u16,
+                Channel::ATT,
+                request,
+            ));
+            let mut response = Vec::new();
+            while let Ok(fragment) =
+                rx.consume_raw_with(|_, payload| Consume::always(Ok(payload.to_vec())))
+            {
+                response.extend_from_slice(&fragment);
+            }
+            response.split_off(4)
+        };
+
+        // Exchange MTU Request: the client supports an MTU of 517, 100 is used
+        assert_eq!(exchange(&mut l2cap, &[0x02, 0x05, 0x02]), [0x03, 100, 0]);
+        assert_eq!(l2cap.att_server().mtu(), 100);
+
+        // The whole value fits into the Read Response, which is fragmented
+        let rsp = exchange(&mut l2cap, &[0x0A, 0x01, 0x00]);
+        assert_eq!(rsp[0], 0x0B);
+        assert_eq!(rsp[1..], [0x55; 60][..]);
+    }
+
+    #[test]
+    fn notify_on_connection() {
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = (&mut queue).split();
+        let conn = ConnectionHandle::from_raw(0x0042).unwrap();
+        let mut l2cap =
+            L2CAPState::with_connection(conn, BleChannelMap::with_attributes(NoAttributes));
+        assert_eq!(l2cap.connection_handle(), conn);
+
+        // Other connections are rejected
+        let mut l2cap = l2cap.tx(&mut tx);
+        assert!(l2cap.att_on(ConnectionHandle::SINGLE).is_none());
+
+        let att = l2cap.att_on(conn).unwrap();
+        assert_eq!(att.connection_handle(), conn);
+        att.notify_raw(Handle::from_raw(0x0003), &[1, 2]).unwrap();
+        assert_eq!(next_pdu(&mut rx).unwrap(), [0x1B, 0x03, 0x00, 1, 2]);
+
+        // `att` uses the state's own connection
+        let att = l2cap.att().unwrap();
+        assert_eq!(att.connection_handle(), conn);
+    }
+
+    #[test]
+    fn empty_provider() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(NoAttributes));
+
+        // Read By Type: Attribute Not Found
+        let rsp = request(&mut l2cap, &[0x08, 0x01, 0x00, 0xFF, 0xFF, 0xAA, 0xAA]);
+        assert_eq!((rsp[0], rsp[1], rsp[4]), (0x01, 0x08, 0x0A));
+
+        // Read and Read Blob: Invalid Handle
+        assert_eq!(
+            request(&mut l2cap, &[0x0A, 0x01, 0x00]),
+            [0x01, 0x0A, 0x01, 0x00, 0x01]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x0C, 0xFF, 0xFF, 0, 0]),
+            [0x01, 0x0C, 0xFF, 0xFF, 0x01]
+        );
+    }
+
+    #[test]
+    fn back_to_back_requests() {
+        let attrs = TestAttrs::new(&[(1, 0xAAAA, &[1]), (2, 0xAAAA, &[2])]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+        let (mut tx, mut rx) = MockQueue::with_capacity(4).split();
+        let first = att_message(&[0x0A, 0x01, 0x00]);
+        let second = att_message(&[0x0A, 0x02, 0x00]);
+        let command = att_message(&[0x52, 0x01, 0x00, 0xFF]);
+
+        // The second request arrives while the first response is still queued, and is rejected
+        let (consumed, result) = l2cap.tx(&mut tx).process_start(&first).into_parts();
+        assert!(consumed);
+        assert_eq!(result, Ok(()));
+        let (consumed, result) = l2cap.tx(&mut tx).process_start(&second).into_parts();
+        assert!(consumed);
+        assert_eq!(result, Ok(()));
+
+        // Commands aren't affected
+        let (consumed, _) = l2cap.tx(&mut tx).process_start(&command).into_parts();
+        assert!(consumed);
+
+        assert_eq!(next_pdu(&mut rx).unwrap(), [0x0B, 1]);
+        assert_eq!(next_pdu(&mut rx).unwrap(), [0x01, 0x0A, 0x00, 0x00, 0x0E]);
+        assert_eq!(next_pdu(&mut rx), None);
+
+        // Once the responses are sent, the second request is answered as usual
+        let (consumed, _) = l2cap.tx(&mut tx).process_start(&second).into_parts();
+        assert!(consumed);
+        assert_eq!(next_pdu(&mut rx).unwrap(), [0x0B, 2]);
+    }
+
+    #[test]
+    fn one_indication_at_a_time() {
+        let attrs = TestAttrs::new(&[(1, 0xAAAA, &[1])]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = (&mut queue).split();
+        let handle = Handle::from_raw(1);
+
+        l2cap
+            .tx(&mut tx)
+            .att()
+            .unwrap()
+            .indicate_raw(handle, &[1])
+            .unwrap();
+        assert_eq!(next_pdu(&mut rx).unwrap(), [0x1D, 0x01, 0x00, 1]);
+
+        // Not confirmed yet
+        assert_eq!(
+            l2cap.tx(&mut tx).att().unwrap().indicate_raw(handle, &[2]),
+            Err(IndicationPending)
+        );
+        assert_eq!(next_pdu(&mut rx), None);
+
+        // The confirmation is not answered, but allows the next indication
+        let _ = l2cap.tx(&mut tx).process_start(&att_message(&[0x1E]));
+        assert_eq!(next_pdu(&mut rx), None);
+        l2cap
+            .tx(&mut tx)
+            .att()
+            .unwrap()
+            .indicate_raw(handle, &[3])
+            .unwrap();
+        assert_eq!(next_pdu(&mut rx).unwrap(), [0x1D, 0x01, 0x00, 3]);
+
+        // Unexpected confirmations are ignored as well
+        let _ = l2cap.tx(&mut tx).process_start(&att_message(&[0x1E]));
+        let _ = l2cap.tx(&mut tx).process_start(&att_message(&[0x1E]));
+        assert_eq!(next_pdu(&mut rx), None);
+    }
+
+    #[test]
+    fn confirmation_timeout() {
+        fn indicate(
+            l2cap: &mut L2CAPState<BleChannelMap<TestAttrs, NoSecurity>>,
+            tx: &mut impl Producer,
+            value: &[u8],
+            now: Instant,
+        ) -> Result<(), IndicationPending> {
+            l2cap
+                .tx(tx)
+                .att()
+                .unwrap()
+                .indicate_raw_at(Handle::from_raw(1), value, now)
+        }
+
+        let attrs = TestAttrs::new(&[(1, 0xAAAA, &[1])]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = (&mut queue).split();
+        let start = Instant::from_raw_micros(1_000_000);
+        let deadline = start + CONFIRMATION_TIMEOUT;
+
+        // A confirmed indication doesn't time out
+        indicate(&mut l2cap, &mut tx, &[1], start).unwrap();
+        assert_eq!(next_pdu(&mut rx).unwrap(), [0x1D, 0x01, 0x00, 1]);
+        assert_eq!(l2cap.att_server().confirmation_deadline(), Some(deadline));
+        let _ = l2cap.tx(&mut tx).process_start(&att_message(&[0x1E]));
+        let server = l2cap.att_server();
+        assert_eq!(server.confirmation_deadline(), None);
+        assert_eq!(server.check_confirmation_timeout(deadline), Ok(()));
+
+        // An unconfirmed one fails once the deadline is reached
+        indicate(&mut l2cap, &mut tx, &[2], start).unwrap();
+        assert_eq!(next_pdu(&mut rx).unwrap(), [0x1D, 0x01, 0x00, 2]);
+        let server = l2cap.att_server();
+        assert_eq!(
+            server.check_confirmation_timeout(deadline - Duration::from_micros(1)),
+            Ok(())
+        );
+        assert_eq!(
+            server.check_confirmation_timeout(deadline),
+            Err(ConfirmationTimeout)
+        );
+
+        // The failure is reported once, and no further indications can be sent
+        let later = deadline + Duration::from_secs(10);
+        assert_eq!(server.check_confirmation_timeout(later), Ok(()));
+        assert_eq!(server.confirmation_deadline(), None);
+        assert!(server.is_indication_pending());
+        let _ = l2cap.tx(&mut tx).process_start(&att_message(&[0x1E]));
+        assert_eq!(
+            indicate(&mut l2cap, &mut tx, &[3], later),
+            Err(IndicationPending)
+        );
+        assert_eq!(next_pdu(&mut rx), None);
+    }
+
+    #[test]
+    fn custom_confirmation_timeout() {
+        let attrs = TestAttrs::new(&[(1, 0xAAAA, &[1])]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+        let mut queue = SimpleQueue::new();
+        let (mut tx, _rx) = (&mut queue).split();
+        let start = Instant::from_raw_micros(0);
+
+        l2cap
+            .att_server()
+            .set_confirmation_timeout(Duration::from_millis(500));
+        l2cap
+            .tx(&mut tx)
+            .att()
             .unwrap()
+            .indicate_raw(Handle::from_raw(1), &[1])
+            .unwrap();
+
+        // The deadline is unknown until the first check, which starts the timeout
+        let server = l2cap.att_server();
+        assert_eq!(server.confirmation_deadline(), None);
+        assert_eq!(server.check_confirmation_timeout(start), Ok(()));
+        assert_eq!(
+            server.confirmation_deadline(),
+            Some(start + Duration::from_millis(500))
+        );
+        assert_eq!(
+            server.check_confirmation_timeout(start + Duration::from_millis(499)),
+            Ok(())
+        );
+        assert_eq!(
+            server.check_confirmation_timeout(start + Duration::from_millis(500)),
+            Err(ConfirmationTimeout)
+        );
+        assert!(server.is_confirmation_timed_out());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn confirmation_signalled() {
+        use {
+            crate::asynch::LinkEvents,
+            core::{future::Future, pin::Pin, task::Poll},
+            std::{sync::Arc, task::Wake},
+        };
+
+        struct NoopWaker;
+        impl Wake for NoopWaker {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        let events: &'static LinkEvents = Box::leak(Box::new(LinkEvents::new()));
+        let attrs = TestAttrs::new(&[(1, 0xAAAA, &[1])]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+        l2cap.att_server().set_events(events);
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = (&mut queue).split();
+        let waker = Arc::new(NoopWaker).into();
+        let mut cx = core::task::Context::from_waker(&waker);
+
+        let mut confirmed = events.wait_indication_confirm();
+        l2cap
+            .tx(&mut tx)
+            .att()
+            .unwrap()
+            .indicate_raw(Handle::from_raw(1), &[1])
+            .unwrap();
+        assert!(next_pdu(&mut rx).is_some());
+        assert_eq!(Pin::new(&mut confirmed).poll(&mut cx), Poll::Pending);
+
+        let _ = l2cap.tx(&mut tx).process_start(&att_message(&[0x1E]));
+        assert_eq!(Pin::new(&mut confirmed).poll(&mut cx), Poll::Ready(Ok(())));
+
+        // A timeout completes the future as well
+        let mut confirmed = events.wait_indication_confirm();
+        let start = Instant::from_raw_micros(0);
+        l2cap
+            .tx(&mut tx)
+            .att()
+            .unwrap()
+            .indicate_raw_at(Handle::from_raw(1), &[2], start)
+            .unwrap();
+        assert_eq!(Pin::new(&mut confirmed).poll(&mut cx), Poll::Pending);
+        assert_eq!(
+            l2cap
+                .att_server()
+                .check_confirmation_timeout(start + CONFIRMATION_TIMEOUT),
+            Err(ConfirmationTimeout)
+        );
+        assert_eq!(
+            Pin::new(&mut confirmed).poll(&mut cx),
+            Poll::Ready(Err(ConfirmationTimeout))
+        );
+    }
+
+    #[test]
+    fn write_request_and_command() {
+        let attrs = TestAttrs::new(&[(1, 0xAAAA, &[]), (2, 0xAAAA, &[])]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = (&mut queue).split();
+
+        // A Write Request is answered with a Write Response
+        let _ = l2cap
+            .tx(&mut tx)
+            .process_start(&att_message(&[0x12, 0x01, 0x00, 1, 2]));
+        assert_eq!(next_pdu(&mut rx), Some(vec![0x13]));
+
+        // A Write Command is not answered
+        let _ = l2cap
+            .tx(&mut tx)
+            .process_start(&att_message(&[0x52, 0x02, 0x00, 3]));
+        assert_eq!(next_pdu(&mut rx), None);
+
+        let writes = &l2cap.att_server().provider().writes;
+        assert_eq!(writes, &[(1, vec![1, 2], false), (2, vec![3], true)]);
+    }
+
+    #[test]
+    fn write_error_precedence() {
+        // Attribute 2 is a Characteristic Presentation Format descriptor, which is read-only
+        let mut attrs = TestAttrs::new(&[(1, 0xAAAA, &[]), (2, 0x2904, &[])]);
+        attrs.max_len = 2;
+        attrs.required = ConnectionSecurity::Unauthenticated;
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+
+        // Every write below is too long and lacks encryption. The handle is checked first...
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x03, 0x00, 1, 2, 3]),
+            [0x01, 0x12, 0x03, 0x00, 0x01]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x00, 0x00, 1, 2, 3]),
+            [0x01, 0x12, 0x00, 0x00, 0x01]
+        );
+
+        // ...then whether the attribute is writable at all...
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x02, 0x00, 1, 2, 3]),
+            [0x01, 0x12, 0x02, 0x00, 0x03]
+        );
+
+        // ...then the security level...
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x01, 0x00, 1, 2, 3]),
+            [0x01, 0x12, 0x01, 0x00, 0x0F]
+        );
+
+        // ...and finally the length
+        l2cap
+            .att_server()
+            .set_security_level(ConnectionSecurity::Unauthenticated);
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x02, 0x00, 1, 2, 3]),
+            [0x01, 0x12, 0x02, 0x00, 0x03]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x01, 0x00, 1, 2, 3]),
+            [0x01, 0x12, 0x01, 0x00, 0x0D]
+        );
+        assert!(l2cap.att_server().provider().writes.is_empty());
+
+        assert_eq!(request(&mut l2cap, &[0x12, 0x01, 0x00, 1, 2]), [0x13]);
+        assert_eq!(
+            l2cap.att_server().provider().writes,
+            [(1, vec![1, 2], false)]
+        );
+    }
+
+    #[test]
+    fn descriptor_writes() {
+        let attrs = TestAttrs::new(&[
+            (1, 0x2803, &[]), // Characteristic Declaration
+            (2, 0xAAAA, &[]), // Characteristic Value
+            (3, 0x2902, &[]), // CCCD
+            (4, 0x2904, &[]), // Characteristic Presentation Format
+            (5, 0x29AA, &[]), // Unknown descriptor
+        ]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+
+        // Declarations and descriptors other than CCCDs can't be written
+        for &handle in &[1, 4, 5] {
+            assert_eq!(
+                request(&mut l2cap, &[0x12, handle, 0x00, 1, 0]),
+                [0x01, 0x12, handle, 0x00, 0x03]
+            );
+        }
+
+        // Writing to a descriptor that doesn't exist fails with Invalid Handle
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x06, 0x00, 1, 0]),
+            [0x01, 0x12, 0x06, 0x00, 0x01]
+        );
+
+        assert_eq!(request(&mut l2cap, &[0x12, 0x02, 0x00, 1, 0]), [0x13]);
+        assert_eq!(request(&mut l2cap, &[0x12, 0x03, 0x00, 1, 0]), [0x13]);
+        assert_eq!(
+            l2cap.att_server().provider().writes,
+            [(2, vec![1, 0], false), (3, vec![1, 0], false)]
+        );
+    }
+
+    #[test]
+    fn declarations_read_only() {
+        let mut attrs = TestAttrs::new(&[
+            (1, 0x2800, &[0x0F, 0x18]),                   // Primary Service
+            (2, 0x2803, &[0x0A, 0x03, 0x00, 0x19, 0x2A]), // Characteristic Declaration
+            (3, 0x2A19, &[48]),                           // Characteristic Value
+            (4, 0x2901, &[]),                             // Characteristic User Description
+        ]);
+        // The provider doesn't check what is written
+        attrs.writable = true;
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = (&mut queue).split();
+
+        // Writes to declarations are rejected anyway
+        for &handle in &[1, 2] {
+            assert_eq!(
+                request(&mut l2cap, &[0x12, handle, 0x00, 0xFF, 0xFF]),
+                [0x01, 0x12, handle, 0x00, 0x03]
+            );
+            let _ = l2cap
+                .tx(&mut tx)
+                .process_start(&att_message(&[0x52, handle, 0x00, 0xFF, 0xFF]));
+            assert_eq!(next_pdu(&mut rx), None);
+        }
+
+        // Other attributes are left to the provider
+        assert_eq!(request(&mut l2cap, &[0x12, 0x03, 0x00, 47]), [0x13]);
+        assert_eq!(request(&mut l2cap, &[0x12, 0x04, 0x00, b'x']), [0x13]);
+        assert_eq!(
+            l2cap.att_server().provider().writes,
+            [(3, vec![47], false), (4, vec![b'x'], false)]
+        );
+    }
+
+    #[test]
+    fn write_to_read_only_characteristic() {
+        // The Battery Level characteristic only has the READ property
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(BatteryServiceAttrs::new()));
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = (&mut queue).split();
+
+        // A Write Request is rejected
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x03, 0x00, 99]),
+            [0x01, 0x12, 0x03, 0x00, 0x03]
+        );
+
+        // A Write Command is silently dropped
+        let _ = l2cap
+            .tx(&mut tx)
+            .process_start(&att_message(&[0x52, 0x03, 0x00, 99]));
+        assert_eq!(next_pdu(&mut rx), None);
+
+        assert_eq!(request(&mut l2cap, &[0x0A, 0x03, 0x00]), [0x0B, 48]);
+    }
+
+    #[test]
+    fn write_command_to_uart_rx() {
+        // The RX characteristic of the Nordic UART Service supports both kinds of writes
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(NordicUartAttrs::new()));
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = (&mut queue).split();
+
+        let _ = l2cap
+            .tx(&mut tx)
+            .process_start(&att_message(&[0x12, 0x03, 0x00, b'h', b'i']));
+        assert_eq!(next_pdu(&mut rx), Some(vec![0x13]));
+
+        let _ = l2cap
+            .tx(&mut tx)
+            .process_start(&att_message(&[0x52, 0x03, 0x00, b'h', b'i']));
+        assert_eq!(next_pdu(&mut rx), None);
+
+        // Not even failed Write Commands are answered
+        let _ = l2cap
+            .tx(&mut tx)
+            .process_start(&att_message(&[0x52, 0x06, 0x00, 1]));
+        assert_eq!(next_pdu(&mut rx), None);
+    }
+
+    #[test]
+    fn short_signed_write_command() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::empty());
+        let mut queue = SimpleQueue::new();
+        let (mut tx, mut rx) = (&mut queue).split();
+
+        // The 12-Byte signature is missing, which must not cause an underflow
+        let _ = l2cap
+            .tx(&mut tx)
+            .process_start(&att_message(&[0xD2, 0x01, 0x00, 0xAA]));
+        assert_eq!(next_pdu(&mut rx), None);
+    }
+
+    #[test]
+    fn security_level() {
+        let mut attrs = TestAttrs::new(&[(1, 0xAAAA, &[1, 2])]);
+        attrs.required = ConnectionSecurity::Unauthenticated;
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(attrs));
+        assert_eq!(
+            l2cap.att_server().security_level(),
+            ConnectionSecurity::Unencrypted
+        );
+
+        // Insufficient Encryption
+        assert_eq!(
+            request(&mut l2cap, &[0x0A, 0x01, 0x00]),
+            [0x01, 0x0A, 0x01, 0x00, 0x0F]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x01, 0x00, 3]),
+            [0x01, 0x12, 0x01, 0x00, 0x0F]
+        );
+
+        // Encryption was started
+        l2cap
+            .att_server()
+            .set_security_level(ConnectionSecurity::Unauthenticated);
+        assert_eq!(request(&mut l2cap, &[0x0A, 0x01, 0x00]), [0x0B, 1, 2]);
+        assert_eq!(request(&mut l2cap, &[0x0C, 0x01, 0x00, 1, 0]), [0x0D, 2]);
+        assert_eq!(request(&mut l2cap, &[0x12, 0x01, 0x00, 3]), [0x13]);
+
+        // Insufficient Authentication
+        l2cap.att_server().provider().required = ConnectionSecurity::Authenticated;
+        assert_eq!(
+            request(&mut l2cap, &[0x0C, 0x01, 0x00, 0, 0]),
+            [0x01, 0x0C, 0x01, 0x00, 0x05]
+        );
+        l2cap
+            .att_server()
+            .set_security_level(ConnectionSecurity::Authenticated);
+        assert_eq!(request(&mut l2cap, &[0x0A, 0x01, 0x00]), [0x0B, 1, 2]);
     }
 }
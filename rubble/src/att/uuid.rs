@@ -1,6 +1,6 @@
 use {
     crate::{bytes::*, uuid::*, Error},
-    core::{cmp::PartialEq, fmt},
+    core::{cmp::PartialEq, convert::TryFrom, fmt},
 };
 
 /// ATT protocol UUID (either a 16 or a 128-bit UUID).
@@ -76,20 +76,44 @@ impl From<Uuid> for AttUuid {
     }
 }
 
-impl Into<Uuid> for AttUuid {
-    fn into(self) -> Uuid {
-        match self {
+impl From<AttUuid> for Uuid {
+    fn from(uu: AttUuid) -> Self {
+        match uu {
             AttUuid::Uuid16(u) => u.into(),
             AttUuid::Uuid128(u) => u,
         }
     }
 }
 
+impl TryFrom<AttUuid> for Uuid16 {
+    type Error = Error;
+
+    /// Fails with `Error::InvalidValue` if `uu` is a 128-bit UUID that isn't the equivalent of a
+    /// 16-bit alias.
+    fn try_from(uu: AttUuid) -> Result<Self, Error> {
+        match uu {
+            AttUuid::Uuid16(u) => Ok(u),
+            AttUuid::Uuid128(u) => Uuid16::try_from(u),
+        }
+    }
+}
+
 impl fmt::Debug for AttUuid {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             AttUuid::Uuid16(u) => u.fmt(f),
-            AttUuid::Uuid128(u) => u.fmt(f),
+            // `Uuid` stores its bytes in on-air order, print them in textual order
+            AttUuid::Uuid128(u) => Uuid::from_bytes(u.to_bytes_be()).fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for AttUuid {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        match self {
+            AttUuid::Uuid16(u) => defmt::Format::format(u, f),
+            AttUuid::Uuid128(u) => crate::uuid::format_uuid(f, u),
         }
     }
 }
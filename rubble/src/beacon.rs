@@ -8,18 +8,39 @@ use {
             filter::{self, AddressFilter, ScanFilter},
             Cmd, DeviceAddress, NextUpdate, RadioCmd, Transmitter,
         },
-        phy::AdvertisingChannel,
+        phy::{AdvertisingChannel, Phy},
     },
     crate::{
         bytes::*,
+        rng::Rng,
         time::{Duration, Instant},
         Error,
     },
+    core::cmp,
+    heapless::{consts::U8, Vec},
 };
 
+/// Smallest advertising interval allowed for non-connectable advertising (20 ms).
+pub const MIN_BEACON_INTERVAL: Duration = Duration::from_micros(20_000);
+
+/// Largest advertising interval allowed (10.24 s).
+pub const MAX_BEACON_INTERVAL: Duration = Duration::from_micros(10_240_000);
+
+/// Time between the transmissions on consecutive advertising channels of an advertising event.
+const CHANNEL_SPACING: Duration = Duration::from_micros(1_000);
+
+/// Upper bound of the random `advDelay` added to every advertising interval, in µs.
+const MAX_ADV_DELAY: u32 = 10_000;
+
+/// Maximum length of the advertising data in a beacon PDU.
+const MAX_ADV_DATA: usize = 31;
+
 /// A BLE beacon.
 ///
-/// FIXME: This has to randomly offset the broadcast interval
+/// `broadcast` sends the beacon on all advertising channels at once. To send it periodically with
+/// the timing required by the spec, use a [`BeaconScheduler`].
+///
+/// [`BeaconScheduler`]: struct.BeaconScheduler.html
 pub struct Beacon {
     pdu: PduBuf,
 }
@@ -59,6 +80,133 @@ impl Beacon {
             tx.transmit_advertising(self.pdu.header(), channel);
         }
     }
+
+    /// Broadcasts the beacon data on a single advertising `channel`.
+    fn transmit<T: Transmitter>(&self, tx: &mut T, channel: AdvertisingChannel) {
+        let payload = self.pdu.payload();
+        let buf = tx.tx_payload_buf();
+        buf[..payload.len()].copy_from_slice(payload);
+
+        tx.transmit_advertising(self.pdu.header(), channel);
+    }
+}
+
+/// Periodically broadcasts a `Beacon`.
+///
+/// Every *advertising event* sends the beacon once on each advertising channel, in turn.
+/// Consecutive advertising events are `interval + advDelay` apart, where `advDelay` is a random
+/// delay between 0 and 10 ms. This prevents repeated collisions with other devices advertising at
+/// the same interval.
+///
+/// The scheduler is driven like the `LinkLayer`: Call `start` once, and then `timer_update` every
+/// time the instant in the `next_update` field of the last returned `Cmd` is reached. The radio
+/// is only used for transmitting, so the `Cmd`s always turn it off afterwards.
+pub struct BeaconScheduler<R: Rng> {
+    beacon: Beacon,
+    interval: Duration,
+    rng: R,
+
+    /// Start of the ongoing advertising event.
+    event_start: Instant,
+
+    /// Instant of the next transmission.
+    next_tx: Instant,
+
+    /// Advertising channel used by the last transmission.
+    channel: AdvertisingChannel,
+}
+
+impl<R: Rng> BeaconScheduler<R> {
+    /// Creates a scheduler that broadcasts `beacon` every `interval`.
+    ///
+    /// `rng` is used to pick the `advDelay` of each advertising event.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if `interval` is shorter than [`MIN_BEACON_INTERVAL`] or
+    /// longer than [`MAX_BEACON_INTERVAL`].
+    ///
+    /// [`MIN_BEACON_INTERVAL`]: constant.MIN_BEACON_INTERVAL.html
+    /// [`MAX_BEACON_INTERVAL`]: constant.MAX_BEACON_INTERVAL.html
+    pub fn new(beacon: Beacon, interval: Duration, rng: R) -> Result<Self, Error> {
+        check_interval(interval)?;
+        Ok(Self {
+            beacon,
+            interval,
+            rng,
+            event_start: Instant::from_raw_micros(0),
+            next_tx: Instant::from_raw_micros(0),
+            channel: AdvertisingChannel::first(),
+        })
+    }
+
+    /// Returns the configured advertising interval.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Changes the advertising interval.
+    ///
+    /// The new interval is used starting with the next advertising event that is scheduled.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` if `interval` is out of range (see `new`).
+    pub fn set_interval(&mut self, interval: Duration) -> Result<(), Error> {
+        check_interval(interval)?;
+        self.interval = interval;
+        Ok(())
+    }
+
+    /// Starts the first advertising event at `now` and broadcasts the beacon on the first
+    /// advertising channel.
+    pub fn start<T: Transmitter>(&mut self, now: Instant, tx: &mut T) -> Cmd {
+        self.event_start = now;
+        self.next_tx = now;
+        self.channel = AdvertisingChannel::first();
+        self.transmit(tx)
+    }
+
+    /// Broadcasts the beacon on the next advertising channel after the timer has expired.
+    ///
+    /// After the last channel of an advertising event, this starts the next event.
+    pub fn timer_update<T: Transmitter>(&mut self, tx: &mut T) -> Cmd {
+        self.channel = self.channel.cycle();
+        if self.channel == AdvertisingChannel::first() {
+            self.event_start = self.next_tx;
+        }
+        self.transmit(tx)
+    }
+
+    /// Sends the beacon on `self.channel` and schedules the next transmission.
+    fn transmit<T: Transmitter>(&mut self, tx: &mut T) -> Cmd {
+        self.beacon.transmit(tx, self.channel);
+
+        self.next_tx = if self.channel.cycle() == AdvertisingChannel::first() {
+            // This was the last channel, the next advertising event is delayed by `advDelay`
+            let mut delay = [0; 2];
+            self.rng.fill_bytes(&mut delay);
+            let delay = u32::from(u16::from_le_bytes(delay)) % (MAX_ADV_DELAY + 1);
+            self.event_start + self.interval + Duration::from_micros(delay)
+        } else {
+            self.next_tx + CHANNEL_SPACING
+        };
+
+        Cmd {
+            next_update: NextUpdate::At(self.next_tx),
+            radio: RadioCmd::Off,
+            queued_work: false,
+            turnaround: None,
+        }
+    }
+}
+
+fn check_interval(interval: Duration) -> Result<(), Error> {
+    if interval < MIN_BEACON_INTERVAL || interval > MAX_BEACON_INTERVAL {
+        Err(Error::InvalidValue)
+    } else {
+        Ok(())
+    }
 }
 
 /// Callback for the `BeaconScanner`.
@@ -74,12 +222,88 @@ pub trait ScanCallback {
         I: Iterator<Item = AdStructure<'a>>;
 }
 
+/// The last report of a beacon, used to suppress duplicates.
+struct Report {
+    addr: DeviceAddress,
+    time: Instant,
+    len: u8,
+    data: [u8; MAX_ADV_DATA],
+}
+
+impl Report {
+    fn new(addr: DeviceAddress, data: &[u8], time: Instant) -> Self {
+        let mut report = Self {
+            addr,
+            time,
+            len: 0,
+            data: [0; MAX_ADV_DATA],
+        };
+        report.update(data, time);
+        report
+    }
+
+    fn update(&mut self, data: &[u8], time: Instant) {
+        self.time = time;
+        self.len = data.len() as u8;
+        self.data[..data.len()].copy_from_slice(data);
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data[..usize::from(self.len)]
+    }
+}
+
+/// Suppresses repeated reports of the same beacon.
+///
+/// Remembers the last report of up to 8 devices. When more devices are in range, the device that
+/// was reported longest ago is forgotten.
+struct Deduplicator {
+    window: Duration,
+    reports: Vec<Report, U8>,
+}
+
+impl Deduplicator {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            reports: Vec::new(),
+        }
+    }
+
+    /// Returns whether a beacon from `addr` carrying `data`, received at `now`, should be reported
+    /// to the application.
+    fn should_report(&mut self, addr: DeviceAddress, data: &[u8], now: Instant) -> bool {
+        let data = &data[..cmp::min(data.len(), MAX_ADV_DATA)];
+
+        if let Some(report) = self.reports.iter_mut().find(|report| report.addr == addr) {
+            if report.data() == data && now.duration_since(report.time) < self.window {
+                return false;
+            }
+
+            report.update(data, now);
+            return true;
+        }
+
+        if let Err(report) = self.reports.push(Report::new(addr, data, now)) {
+            let oldest = self
+                .reports
+                .iter_mut()
+                .max_by_key(|report| now.duration_since(report.time).as_micros())
+                .unwrap();
+            *oldest = report;
+        }
+        true
+    }
+}
+
 /// A passive scanner for non-connectable beacon advertisements.
 pub struct BeaconScanner<C: ScanCallback, F: AddressFilter> {
     cb: C,
     filter: ScanFilter<F>,
     interval: Duration,
     channel: AdvertisingChannel,
+    phy: Phy,
+    dedup: Option<Deduplicator>,
 }
 
 impl<C: ScanCallback> BeaconScanner<C, filter::AllowAll> {
@@ -97,7 +321,48 @@ impl<C: ScanCallback, F: AddressFilter> BeaconScanner<C, F> {
             filter: ScanFilter::new(scan_filter),
             interval: Duration::from_micros(0),
             channel: AdvertisingChannel::first(),
+            phy: Phy::Le1M,
+            dedup: None,
+        }
+    }
+
+    /// Returns the PHY the scanner listens on.
+    pub fn phy(&self) -> Phy {
+        self.phy
+    }
+
+    /// Selects the PHY to scan on.
+    ///
+    /// By default, the scanner listens on `Phy::Le1M`, which is used by all legacy advertisers.
+    /// Long range advertisers use one of the coded PHYs instead. Receivers can't choose between
+    /// `LeCodedS2` and `LeCodedS8` (the coding is signaled in each packet), so both select the
+    /// coded PHY.
+    ///
+    /// The new PHY is used by the `Cmd`s returned from now on.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidValue` for `Phy::Le2M`, which is never used on the primary
+    /// advertising channels.
+    pub fn set_phy(&mut self, phy: Phy) -> Result<(), Error> {
+        if phy == Phy::Le2M {
+            return Err(Error::InvalidValue);
         }
+
+        self.phy = phy;
+        Ok(())
+    }
+
+    /// Enables or disables the deduplication of beacon reports.
+    ///
+    /// With a `window`, a device's beacon is only reported again when its advertising data has
+    /// changed, or when `window` has elapsed since it was last reported. Up to 8 devices are
+    /// remembered. When more devices are in range, the device reported longest ago is forgotten,
+    /// so its next beacon is reported again.
+    ///
+    /// By default (and with `None`), every received beacon is reported.
+    pub fn set_dedup_window(&mut self, window: Option<Duration>) {
+        self.dedup = window.map(Deduplicator::new);
     }
 
     /// Configures the `BeaconScanner` and returns a `Cmd` to apply to the radio.
@@ -113,11 +378,10 @@ impl<C: ScanCallback, F: AddressFilter> BeaconScanner<C, F> {
             // Switch channels
             next_update: NextUpdate::At(now + self.interval),
 
-            radio: RadioCmd::ListenAdvertising {
-                channel: self.channel,
-            },
+            radio: self.listen(),
 
             queued_work: false,
+            turnaround: None,
         }
     }
 
@@ -131,35 +395,252 @@ impl<C: ScanCallback, F: AddressFilter> BeaconScanner<C, F> {
             // Switch channels
             next_update: NextUpdate::At(now + self.interval),
 
-            radio: RadioCmd::ListenAdvertising {
-                channel: self.channel,
-            },
+            radio: self.listen(),
 
             queued_work: false,
+            turnaround: None,
         }
     }
 
     /// Processes a received advertising channel packet.
     ///
     /// This should be called whenever the radio receives a packet on the configured advertising
-    /// channel.
-    pub fn process_adv_packet(&mut self, header: Header, payload: &[u8], crc_ok: bool) -> Cmd {
+    /// channel. `rx_end` is the time at which the packet was received, and is used for
+    /// deduplicating beacons (see `set_dedup_window`).
+    pub fn process_adv_packet(
+        &mut self,
+        rx_end: Instant,
+        header: Header,
+        payload: &[u8],
+        crc_ok: bool,
+    ) -> Cmd {
         if crc_ok && header.type_().is_beacon() {
             // Partially decode to get the device ID and run it through the filter
             if let Ok(pdu) = Pdu::from_header_and_payload(header, &mut ByteReader::new(payload)) {
-                if self.filter.should_scan(*pdu.sender()) {
-                    let ad = pdu.advertising_data().unwrap();
-                    self.cb.beacon(*pdu.sender(), ad);
+                let sender = *pdu.sender();
+                if self.filter.should_scan(sender) {
+                    // The advertising data follows the 6-Byte address
+                    let len = cmp::min(usize::from(header.payload_length()), payload.len());
+                    let data = payload.get(6..len).unwrap_or(&[]);
+                    let report = match &mut self.dedup {
+                        Some(dedup) => dedup.should_report(sender, data, rx_end),
+                        None => true,
+                    };
+                    if report {
+                        let ad = pdu.advertising_data().unwrap();
+                        self.cb.beacon(sender, ad);
+                    }
                 }
             }
         }
 
         Cmd {
             next_update: NextUpdate::Keep,
-            radio: RadioCmd::ListenAdvertising {
-                channel: self.channel,
-            },
+            radio: self.listen(),
             queued_work: false,
+            turnaround: None,
+        }
+    }
+
+    /// Returns the radio command for listening on the current channel.
+    fn listen(&self) -> RadioCmd {
+        RadioCmd::ListenAdvertising {
+            channel: self.channel,
+            phy: self.phy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::link::{
+            mock::{MockRng, MockTransmitter, Sent},
+            AddressKind,
+        },
+        std::vec::Vec,
+    };
+
+    #[test]
+    fn scheduler_cycles_channels() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let beacon = Beacon::new(addr, &[AdStructure::CompleteLocalName("rubble")]).unwrap();
+        let interval = Duration::from_millis(100);
+        let mut scheduler = BeaconScheduler::new(beacon, interval, MockRng::new()).unwrap();
+        let mut radio = MockTransmitter::new();
+
+        let start = Instant::from_raw_micros(1_000);
+        let mut cmds = vec![scheduler.start(start, &mut radio)];
+        for _ in 0..5 {
+            cmds.push(scheduler.timer_update(&mut radio));
+        }
+
+        let channels = radio
+            .sent
+            .iter()
+            .map(|sent| match sent {
+                Sent::Advertising {
+                    channel, payload, ..
+                } => {
+                    assert_eq!(&payload[..6], addr.raw());
+                    channel.rf_channel()
+                }
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        let first = AdvertisingChannel::first();
+        let all = [
+            first.rf_channel(),
+            first.cycle().rf_channel(),
+            first.cycle().cycle().rf_channel(),
+        ];
+        assert_eq!(channels[..3], all);
+        assert_eq!(channels[3..], all);
+
+        let times = cmds
+            .iter()
+            .map(|cmd| {
+                assert!(cmd.radio.rx_params().is_none());
+                match cmd.next_update {
+                    NextUpdate::At(at) => at,
+                    _ => unreachable!(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Channels within an event follow each other closely
+        assert_eq!(times[0], start + CHANNEL_SPACING);
+        assert_eq!(times[1], start + CHANNEL_SPACING + CHANNEL_SPACING);
+
+        // The next event starts after the interval plus up to 10 ms of `advDelay`
+        for &(event, next) in &[(start, times[2]), (times[2], times[5])] {
+            let delay = next - event - interval;
+            assert!(delay.as_micros() <= MAX_ADV_DELAY);
+        }
+        assert_eq!(times[3], times[2] + CHANNEL_SPACING);
+    }
+
+    /// Records the addresses of reported beacons.
+    struct Reports(Vec<DeviceAddress>);
+
+    impl ScanCallback for Reports {
+        fn beacon<'a, I>(&mut self, adv_addr: DeviceAddress, _adv_data: I)
+        where
+            I: Iterator<Item = AdStructure<'a>>,
+        {
+            self.0.push(adv_addr);
+        }
+    }
+
+    #[test]
+    fn scanner_deduplicates() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let other = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Public);
+        let name = |addr, name| PduBuf::beacon(addr, &[AdStructure::CompleteLocalName(name)]);
+        let (beacon, renamed, other) = (
+            name(addr, "rubble").unwrap(),
+            name(addr, "rubble2").unwrap(),
+            name(other, "rubble").unwrap(),
+        );
+
+        let mut scanner = BeaconScanner::new(Reports(Vec::new()));
+        scanner.set_dedup_window(Some(Duration::from_millis(1_000)));
+        let mut receive = |pdu: &PduBuf, ms: u32| {
+            let rx_end = Instant::from_raw_micros(ms * 1_000);
+            let _ = scanner.process_adv_packet(rx_end, pdu.header(), pdu.payload(), true);
+            let reports = scanner.cb.0.len();
+            scanner.cb.0.clear();
+            reports
+        };
+
+        // Repeated beacons are suppressed until the window elapses
+        assert_eq!(receive(&beacon, 0), 1);
+        assert_eq!(receive(&beacon, 100), 0);
+        assert_eq!(receive(&beacon, 999), 0);
+        assert_eq!(receive(&beacon, 1_000), 1);
+        assert_eq!(receive(&beacon, 1_100), 0);
+
+        // Changed data and different devices are reported immediately
+        assert_eq!(receive(&renamed, 1_200), 1);
+        assert_eq!(receive(&renamed, 1_300), 0);
+        assert_eq!(receive(&beacon, 1_400), 1);
+        assert_eq!(receive(&other, 1_500), 1);
+        assert_eq!(receive(&other, 1_600), 0);
+
+        // Without a window, every beacon is reported
+        scanner.set_dedup_window(None);
+        let reports = (0..3)
+            .map(|ms| {
+                let _ = scanner.process_adv_packet(
+                    Instant::from_raw_micros(ms),
+                    beacon.header(),
+                    beacon.payload(),
+                    true,
+                );
+                scanner.cb.0.len()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(reports, [1, 2, 3]);
+    }
+
+    #[test]
+    fn scanner_phy() {
+        let mut scanner = BeaconScanner::new(Reports(Vec::new()));
+        let now = Instant::from_raw_micros(0);
+        let phy = |cmd: Cmd| cmd.radio.rx_params().unwrap().phy;
+        assert_eq!(
+            phy(scanner.configure(now, Duration::from_millis(100))),
+            Phy::Le1M
+        );
+
+        assert_eq!(scanner.set_phy(Phy::Le2M), Err(Error::InvalidValue));
+        assert_eq!(scanner.phy(), Phy::Le1M);
+
+        scanner.set_phy(Phy::LeCodedS8).unwrap();
+        assert_eq!(
+            phy(scanner.configure(now, Duration::from_millis(100))),
+            Phy::LeCodedS8
+        );
+        assert_eq!(phy(scanner.timer_update(now)), Phy::LeCodedS8);
+        let beacon = PduBuf::beacon(DeviceAddress::new([0; 6], AddressKind::Random), &[]).unwrap();
+        let cmd = scanner.process_adv_packet(now, beacon.header(), beacon.payload(), true);
+        assert_eq!(phy(cmd), Phy::LeCodedS8);
+    }
+
+    #[test]
+    fn dedup_evicts_oldest() {
+        let mut dedup = Deduplicator::new(Duration::from_millis(1_000));
+        let addr = |i| DeviceAddress::new([i, 0, 0, 0, 0, 0], AddressKind::Random);
+        let at = |ms| Instant::from_raw_micros(ms * 1_000);
+
+        for i in 0..8 {
+            assert!(dedup.should_report(addr(i), &[], at(u32::from(i))));
         }
+        assert!(!dedup.should_report(addr(0), &[], at(10)));
+
+        // A 9th device replaces the one reported longest ago
+        assert!(dedup.should_report(addr(8), &[], at(11)));
+        assert!(dedup.should_report(addr(0), &[], at(12)));
+        assert!(!dedup.should_report(addr(8), &[], at(13)));
+    }
+
+    #[test]
+    fn interval_range() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let beacon = || Beacon::new(addr, &[]).unwrap();
+        let new = |ms| BeaconScheduler::new(beacon(), Duration::from_millis(ms), MockRng::new());
+
+        assert!(new(19).is_err());
+        assert!(new(20).is_ok());
+        assert!(new(10_240).is_ok());
+        assert!(new(10_241).is_err());
+
+        let mut scheduler = new(100).unwrap();
+        assert_eq!(
+            scheduler.set_interval(Duration::from_millis(10)),
+            Err(Error::InvalidValue)
+        );
+        assert_eq!(scheduler.interval(), Duration::from_millis(100));
     }
 }
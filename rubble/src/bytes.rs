@@ -234,25 +234,30 @@ impl<'a, T: Copy + FromBytes<'a>> Iterator for IterBytesOr<'a, T> {
 ///
 /// All `write_*` methods on this type will return `Error::Eof` when the underlying buffer slice is
 /// full.
-pub struct ByteWriter<'a>(&'a mut [u8]);
+pub struct ByteWriter<'a> {
+    /// The part of the buffer that has not been written to yet.
+    buf: &'a mut [u8],
+    /// Number of bytes that have already been written (or skipped).
+    written: usize,
+}
 
 impl<'a> ByteWriter<'a> {
     /// Creates a writer that will write to `buf`.
     pub fn new(buf: &'a mut [u8]) -> Self {
-        ByteWriter(buf)
+        ByteWriter { buf, written: 0 }
     }
 
     /// Consumes `self` and returns the part of the contained buffer that has not yet been written
     /// to.
     pub fn into_rest(self) -> &'a mut [u8] {
-        self.0
+        self.buf
     }
 
     /// Returns the raw buffer this `ByteWriter` would write to.
     ///
     /// Combined with `skip`, this method allows advanced operations on the underlying byte buffer.
     pub fn rest(&mut self) -> &mut [u8] {
-        self.0
+        self.buf
     }
 
     /// Skips the given number of bytes in the output data without writing anything there.
@@ -264,8 +269,9 @@ impl<'a> ByteWriter<'a> {
         if self.space_left() < bytes {
             Err(Error::Eof)
         } else {
-            let this = mem::replace(&mut self.0, &mut []);
-            self.0 = &mut this[bytes..];
+            let this = mem::replace(&mut self.buf, &mut []);
+            self.buf = &mut this[bytes..];
+            self.written += bytes;
             Ok(())
         }
     }
@@ -283,9 +289,10 @@ impl<'a> ByteWriter<'a> {
         if self.space_left() < len {
             Err(Error::Eof)
         } else {
-            let this = mem::replace(&mut self.0, &mut []);
+            let this = mem::replace(&mut self.buf, &mut []);
             let (head, tail) = this.split_at_mut(len);
-            self.0 = tail;
+            self.buf = tail;
+            self.written += len;
             Ok(ByteWriter::new(head))
         }
     }
@@ -300,21 +307,30 @@ impl<'a> ByteWriter<'a> {
     ///
     /// [`split_off`]: #method.split_off
     pub fn split_next_mut(&mut self) -> Option<&'a mut u8> {
-        let this = mem::replace(&mut self.0, &mut []);
+        let this = mem::replace(&mut self.buf, &mut []);
         // Slight contortion to please the borrow checker:
         if this.is_empty() {
-            self.0 = this;
+            self.buf = this;
             None
         } else {
             let (first, rest) = this.split_first_mut().unwrap();
-            self.0 = rest;
+            self.buf = rest;
+            self.written += 1;
             Some(first)
         }
     }
 
     /// Returns the number of bytes that can be written to `self` until it is full.
     pub fn space_left(&self) -> usize {
-        self.0.len()
+        self.buf.len()
+    }
+
+    /// Returns the number of bytes that have been written to `self` since it was created.
+    ///
+    /// Bytes skipped via `skip` and bytes split off via `split_off` or `split_next_mut` count as
+    /// written, since `self` has advanced past them.
+    pub fn bytes_written(&self) -> usize {
+        self.written
     }
 
     /// Writes all bytes from `other` to `self`.
@@ -325,9 +341,10 @@ impl<'a> ByteWriter<'a> {
         if self.space_left() < other.len() {
             Err(Error::Eof)
         } else {
-            self.0[..other.len()].copy_from_slice(other);
-            let this = mem::replace(&mut self.0, &mut []);
-            self.0 = &mut this[other.len()..];
+            self.buf[..other.len()].copy_from_slice(other);
+            let this = mem::replace(&mut self.buf, &mut []);
+            self.buf = &mut this[other.len()..];
+            self.written += other.len();
             Ok(())
         }
     }
@@ -521,6 +538,16 @@ pub trait ToBytes {
     /// If `writer` does not contain enough space, an error will be returned and the state of the
     /// buffer is unspecified (eg. `self` may be partially written into `writer`).
     fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error>;
+
+    /// Converts `self` to bytes and writes them to the start of `buf`.
+    ///
+    /// Returns the number of bytes written, which is the encoded size of `self`. If `buf` is too
+    /// small, an error will be returned and the contents of `buf` are unspecified.
+    fn to_bytes_into(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut writer = ByteWriter::new(buf);
+        self.to_bytes(&mut writer)?;
+        Ok(writer.bytes_written())
+    }
 }
 
 /// Trait for decoding values from a byte slice.
@@ -559,3 +586,52 @@ impl<'a> FromBytes<'a> for u8 {
         bytes.read_u8()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writer_bytes_written() {
+        let mut buf = [0; 16];
+        let mut writer = ByteWriter::new(&mut buf);
+        assert_eq!(writer.bytes_written(), 0);
+        writer.write_u8(0xAB).unwrap();
+        writer.write_u16_le(0x1234).unwrap();
+        assert_eq!(writer.bytes_written(), 3);
+        writer.skip(2).unwrap();
+        assert_eq!(writer.bytes_written(), 5);
+        let mut sub = writer.split_off(4).unwrap();
+        sub.write_u8(0).unwrap();
+        assert_eq!(sub.bytes_written(), 1);
+        assert_eq!(writer.bytes_written(), 9);
+        writer.split_next_mut().unwrap();
+        assert_eq!(writer.bytes_written(), 10);
+
+        // Failed writes don't count
+        assert_eq!(writer.write_u64_le(0), Err(Error::Eof));
+        assert_eq!(writer.bytes_written(), 10);
+        assert_eq!(writer.write_slice_truncate(&[1; 8]), 6);
+        assert_eq!(writer.bytes_written(), 16);
+        assert_eq!(writer.space_left(), 0);
+    }
+
+    #[test]
+    fn reader_read_slice() {
+        let data = [1, 2, 3, 4, 5];
+        let mut reader = ByteReader::new(&data);
+        assert_eq!(reader.read_slice(2).unwrap(), &[1, 2]);
+        assert_eq!(reader.bytes_left(), 3);
+        assert_eq!(reader.read_slice(0).unwrap(), &[]);
+
+        // Reading past the end fails without advancing
+        assert_eq!(reader.read_slice(4), Err(Error::Eof));
+        assert_eq!(reader.bytes_left(), 3);
+
+        // The slice borrows from the input, not from the reader
+        let rest = reader.read_slice(3).unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(rest.as_ptr(), data[2..].as_ptr());
+        assert_eq!(reader.read_slice(1), Err(Error::Eof));
+    }
+}
@@ -3,10 +3,13 @@
 use crate::{
     l2cap::ChannelMapper,
     link::{
+        encryption::KeyStore,
         queue::{self, PacketQueue},
-        Transmitter,
+        trace::{Direction, EventKind, PduKind},
+        CompanyId, Transmitter,
     },
-    time::Timer,
+    rng::Rng,
+    time::{Duration, Instant, Timer},
 };
 
 // TODO: Use associated type defaults in the trait once stable
@@ -24,6 +27,20 @@ pub trait Config {
     /// The BLE packet transmitter (radio).
     type Transmitter: Transmitter;
 
+    /// The random number generator.
+    ///
+    /// Used by [`LinkLayer::with_random_static_address`] to generate a random device address, and
+    /// to generate the slave's session key diversifier and IV when starting encryption.
+    ///
+    /// [`LinkLayer::with_random_static_address`]: ../link/struct.LinkLayer.html#method.with_random_static_address
+    type Rng: Rng;
+
+    /// Storage for the Long Term Keys of bonded devices.
+    ///
+    /// Used to resume encryption when a bonded device reconnects. Use `NoKeys` if bonding is not
+    /// supported.
+    type KeyStore: KeyStore;
+
     /// The L2CAP channel mapper in use.
     ///
     /// This type also provides access to the attributes hosted by the ATT server.
@@ -35,4 +52,127 @@ pub trait Config {
 
     type PacketProducer: queue::Producer;
     type PacketConsumer: queue::Consumer;
+
+    /// The type of the L2CAP message buffers used by the `Responder`.
+    ///
+    /// L2CAP messages that don't fit into a single data channel PDU are reassembled in, and
+    /// fragmented from, two buffers of this type (see `L2CAPState::with_buffers`). To support
+    /// `MAX_ATT_MTU`, they must hold `MAX_ATT_MTU + 4` Bytes, and `[u8; l2cap::MAX_MESSAGE_LEN]`
+    /// fits any message. With the default `ATT_MTU` of 23, an empty `[u8; 0]` suffices, unless
+    /// the security manager supports *LE Secure Connections* (whose 65-Byte PDUs need 69 Bytes).
+    type L2CAPBuffer: AsMut<[u8]>;
+
+    /// The Bluetooth SIG-assigned company identifier of the device manufacturer.
+    ///
+    /// This is sent to the peer in `LL_VERSION_IND` PDUs. The default value of `0xFFFF` is
+    /// reserved for internal use and testing, and should be replaced by the manufacturer's own
+    /// identifier in production devices.
+    const COMPANY_ID: CompanyId = CompanyId::from_raw(0xFFFF);
+
+    /// The largest `ATT_MTU` supported by the ATT server, in Bytes.
+    ///
+    /// When the client requests an MTU exchange, the server responds with this value and both sides
+    /// use the smaller of the two MTUs. Must be at least 23 (the default `ATT_MTU`), which is also
+    /// the default value. Memory-constrained devices should keep the default.
+    ///
+    /// ATT PDUs larger than a single data channel PDU are fragmented by L2CAP, and all fragments
+    /// of a PDU are enqueued at once. The `Responder` therefore limits the MTU to
+    /// `l2cap::MAX_PAYLOAD_LEN` (247) and to what fits into the TX packet queue: 27 Bytes per
+    /// queued packet, minus 4 Bytes of L2CAP header. With a single-packet queue, this is 23. The
+    /// MTU is also limited to what fits into the `L2CAPBuffer`s.
+    const MAX_ATT_MTU: u16 = 23;
+
+    /// Worst-case drift of the `Timer` used by the Link-Layer, in ppm (parts per million).
+    ///
+    /// The slave widens its receive window before each connection event by the drift its own clock
+    /// and the master's sleep clock (whose accuracy is sent in the `CONNECT_REQ`) can accumulate
+    /// since the last packet received from the master. This value must cover the actual accuracy
+    /// of the timer, or connections will be dropped, particularly with long connection intervals
+    /// and slave latency. The default of 50 ppm fits a timer driven by a crystal oscillator.
+    /// Devices using an RC oscillator need a much larger value (up to 500 ppm).
+    const SLEEP_CLOCK_ACCURACY: u16 = 50;
+
+    /// Delay between the end of a received packet on air and the `rx_end` timestamp the radio
+    /// driver passes to `LinkLayer::process_adv_packet` and `LinkLayer::process_data_packet`.
+    ///
+    /// This covers the latency of the receive chain and of the interrupt that takes the timestamp.
+    /// The Link-Layer subtracts it from `rx_end`, so that connection anchors, receive windows and
+    /// the `T_IFS` turnaround are computed from the actual end of the packet. If it is too small,
+    /// the slave expects the master's packets too late, which eats into the window widening and
+    /// makes connections with long intervals flaky.
+    ///
+    /// The default of 0 is correct for drivers that timestamp packets in hardware.
+    const RADIO_RX_DELAY: Duration = Duration::from_micros(0);
+
+    /// Time between the radio driver starting a transmission and the first bit going on air (the
+    /// TX ramp-up time of the radio).
+    ///
+    /// The Link-Layer subtracts this from `Cmd::turnaround`. A driver that starts transmitting at
+    /// the returned instant therefore puts the response on air exactly `T_IFS` (150 µs) after the
+    /// end of the received packet, as required by the specification. Drivers that let the radio
+    /// hardware enforce `T_IFS` should leave this at the default of 0.
+    const RADIO_TX_DELAY: Duration = Duration::from_micros(0);
+
+    /// Maximum number of times a data channel PDU is retransmitted without being acknowledged.
+    ///
+    /// When the master hasn't acknowledged a PDU after this many retransmissions, the Link-Layer
+    /// considers the connection lost and closes it with `DisconnectReason::RetransmissionLimit`.
+    /// This detects a lost connection faster than the supervision timeout, at the risk of giving
+    /// up on a connection that is merely suffering from interference.
+    ///
+    /// The default of `None` retransmits indefinitely, leaving it to the supervision timeout to
+    /// detect lost connections.
+    const MAX_RETRANSMISSIONS: Option<u16> = None;
+
+    /// Maximum number of PDUs the slave sends in a single connection event.
+    ///
+    /// Once this many PDUs have been exchanged, the Link-Layer clears the `MD` bit and closes the
+    /// connection event, even if either side has more data. Remaining data is sent in the next
+    /// connection event. Together with `MAX_EVENT_LENGTH`, this bounds the time the radio is active
+    /// per connection interval, which saves power and leaves time for other radio activity.
+    ///
+    /// The default of `None` keeps the event open as long as there's data and enough time before
+    /// the next connection event.
+    const MAX_EVENT_PDUS: Option<u8> = None;
+
+    /// Maximum duration of a connection event, measured from the end of the master's first PDU.
+    ///
+    /// The Link-Layer only continues a connection event if another exchange of maximum-length PDUs
+    /// fits into this duration, so the limit is never exceeded. The default of `None` only limits
+    /// the event by the connection interval.
+    const MAX_EVENT_LENGTH: Option<Duration> = None;
+
+    /// Called by the Link-Layer with every PDU it sends or receives.
+    ///
+    /// `kind` identifies the protocol the PDU belongs to and `pdu` contains its raw bytes (refer to
+    /// [`PduKind`] for details). Encrypted PDUs are passed in plain text, empty data channel PDUs
+    /// are not traced.
+    ///
+    /// This is called from the real-time part of the Link-Layer, so implementations must return
+    /// quickly (eg. by copying the data into a buffer or an RTT channel). The default
+    /// implementation does nothing.
+    ///
+    /// [`PduKind`]: ../link/trace/enum.PduKind.html
+    fn trace_pdu(_kind: PduKind, _direction: Direction, _pdu: &[u8]) {}
+
+    /// Called by the Link-Layer when an advertising or connection event starts at `at`.
+    ///
+    /// Every call is followed by a call to `event_ended` for the same `kind` before the next event
+    /// starts. Refer to [`EventKind`] for when exactly events start and end. Together, both hooks
+    /// allow measuring the time the radio is active, eg. by toggling a GPIO pin.
+    ///
+    /// This runs in the radio-timing-critical path of the Link-Layer, immediately before or after
+    /// the radio is reconfigured, so implementations must be cheap (ideally a single register
+    /// write). Any delay here is added to the turnaround time. This is only called with the
+    /// `profiling` feature enabled. The default implementation does nothing.
+    ///
+    /// [`EventKind`]: ../link/trace/enum.EventKind.html
+    fn event_started(_kind: EventKind, _at: Instant) {}
+
+    /// Called by the Link-Layer when the advertising or connection event started by the last call
+    /// to `event_started` ends at `at`.
+    ///
+    /// The same timing constraints as for `event_started` apply. This is only called with the
+    /// `profiling` feature enabled. The default implementation does nothing.
+    fn event_ended(_kind: EventKind, _at: Instant) {}
 }
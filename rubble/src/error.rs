@@ -2,6 +2,7 @@ use core::fmt;
 
 /// Errors returned by the BLE stack.
 #[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     /// Packet specified an invalid length value or was too short.
     ///
@@ -22,6 +23,11 @@ pub enum Error {
     /// Parsing didn't consume the entire buffer.
     IncompleteParse,
 
+    /// The client didn't confirm an indication within the ATT transaction timeout.
+    ///
+    /// No further ATT PDUs may be exchanged over the connection, so it should be closed.
+    ConfirmationTimeout,
+
     #[doc(hidden)]
     __Nonexhaustive,
 }
@@ -33,6 +39,7 @@ impl fmt::Display for Error {
             Error::InvalidValue => "invalid value for field",
             Error::Eof => "end of buffer",
             Error::IncompleteParse => "excess data in buffer",
+            Error::ConfirmationTimeout => "indication not confirmed in time",
             Error::__Nonexhaustive => unreachable!(),
         })
     }
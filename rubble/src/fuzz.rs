@@ -0,0 +1,92 @@
+//! Parser entry points for fuzzing.
+//!
+//! Most of Rubble's parsers are only reachable through the radio path, which requires a Link-Layer
+//! in the right state. The functions in this module expose them directly: Each one takes the raw
+//! bytes of a PDU as received from a peer and parses it exactly like the stack does when receiving
+//! it.
+//!
+//! None of these functions may panic or read out of bounds, no matter the input. Malformed input
+//! must be rejected with an `Error` instead. The fuzz targets in the `fuzz` directory check this,
+//! and can be run with `cargo fuzz` from the `rubble` directory:
+//!
+//! ```notrust
+//! cargo +nightly fuzz run att_pdu
+//! ```
+//!
+//! This module is only available when the `fuzz` Cargo feature is enabled.
+
+use {
+    crate::{
+        att::AttPdu,
+        bytes::{ByteReader, FromBytes},
+        l2cap::{self, Channel},
+        link::{advertising, data},
+        Error,
+    },
+    core::fmt,
+};
+
+/// Parses an advertising channel PDU, consisting of the 2-Byte header and the payload.
+pub fn advertising_pdu(raw: &[u8]) -> Result<advertising::Pdu<'_>, Error> {
+    advertising::Pdu::from_bytes(&mut ByteReader::new(raw))
+}
+
+/// Parses a data channel PDU, consisting of the 2-Byte header and the (unencrypted) payload.
+///
+/// The payload is not parsed any further unless it is an LL Control PDU. Use `l2cap_message` to
+/// parse the payload of a PDU with `LLID` `DataStart`.
+pub fn data_pdu(raw: &[u8]) -> Result<data::Pdu<'_, &[u8]>, Error> {
+    data::Pdu::from_raw(raw).map(|(_, pdu)| pdu)
+}
+
+/// Parses an unfragmented L2CAP message, consisting of the basic L2CAP header and the payload.
+///
+/// Returns the destination channel and the payload.
+pub fn l2cap_message(raw: &[u8]) -> Result<(Channel, &[u8]), Error> {
+    l2cap::parse_message(raw)
+}
+
+/// Parses an ATT PDU, as carried in the payload of an L2CAP message sent to channel `0x0004`.
+pub fn att_pdu(raw: &[u8]) -> Result<impl fmt::Debug + '_, Error> {
+    AttPdu::from_bytes(&mut ByteReader::new(raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_inputs() {
+        for len in 0..4 {
+            let raw = &[0xFF; 4][..len];
+            let _ = advertising_pdu(raw);
+            let _ = data_pdu(raw);
+            let _ = l2cap_message(raw);
+            let _ = att_pdu(raw);
+        }
+    }
+
+    #[test]
+    fn att_pdus() {
+        // Every opcode, with every length up to a few Bytes
+        for opcode in 0..=0xFF {
+            for len in 0..20 {
+                let mut raw = [0xFF; 20];
+                raw[0] = opcode;
+                let _ = att_pdu(&raw[..=len]);
+            }
+        }
+    }
+
+    #[test]
+    fn l2cap_length_mismatch() {
+        assert_eq!(
+            l2cap_message(&[0x01, 0x00, 0x04, 0x00, 0x1E]).unwrap(),
+            (Channel::ATT, &[0x1E][..])
+        );
+        assert_eq!(
+            l2cap_message(&[0x02, 0x00, 0x04, 0x00, 0x1E]).unwrap_err(),
+            Error::InvalidLength
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! GATT caching support: the *Database Hash* and *Client Supported Features* characteristics.
+//!
+//! Clients supporting GATT caching remember the attribute table of a server across connections.
+//! Instead of discovering all services again when reconnecting, they read the *Database Hash*
+//! characteristic of the *Generic Attribute* service and compare it with the hash of their cached
+//! table. A client announces that it supports *robust caching* by writing to the *Client Supported
+//! Features* characteristic.
+//!
+//! The hash is computed with AES-CMAC (using a key of all zeros) over the handle, type and, for
+//! some types, the value of the declarations and descriptors in the attribute table (see *Vol. 3,
+//! Part G, 7.3*). Characteristic values are not included, so the hash only changes when the
+//! structure of the table changes.
+//!
+//! A [`GattServer`] can host both characteristics (see `GattServer::add_database_hash` and
+//! `GattServer::add_client_features`). For other `AttributeProvider`s, [`database_hash`] computes
+//! the hash of the whole table.
+//!
+//! [`GattServer`]: ../server/struct.GattServer.html
+//! [`database_hash`]: fn.database_hash.html
+
+use {
+    crate::{
+        att::{AttUuid, Attribute, AttributeProvider, ErrorCode, HandleRange},
+        crypto::Cmac,
+        uuid::Uuid16,
+    },
+    bitflags::bitflags,
+};
+
+/// Computes the *Database Hash* of an attribute table incrementally.
+///
+/// All attributes of the table have to be added in ascending handle order. Attributes that don't
+/// contribute to the hash are ignored.
+#[derive(Clone)]
+pub struct DatabaseHasher {
+    cmac: Cmac,
+}
+
+impl DatabaseHasher {
+    /// Creates a hasher for an empty attribute table.
+    pub fn new() -> Self {
+        Self {
+            cmac: Cmac::new(&[0; 16]),
+        }
+    }
+
+    /// Adds the next attribute of the table.
+    pub fn add(&mut self, attr: &Attribute<'_>) {
+        let with_value = match hashed_fields(attr.att_type) {
+            Some(with_value) => with_value,
+            None => return,
+        };
+
+        self.cmac.update(&attr.handle.as_u16().to_le_bytes());
+        if let AttUuid::Uuid16(uuid) = attr.att_type {
+            self.cmac.update(&uuid.0.to_le_bytes());
+        }
+        if with_value {
+            self.cmac.update(attr.value.0);
+        }
+    }
+
+    /// Returns the *Database Hash* value of all added attributes.
+    ///
+    /// The value is returned in the order it is transmitted in (least significant octet first).
+    pub fn finish(self) -> [u8; 16] {
+        let mut hash = self.cmac.finalize();
+        hash.reverse();
+        hash
+    }
+}
+
+impl Default for DatabaseHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Computes the *Database Hash* of all attributes in `attrs`.
+///
+/// The value is returned in the order it is transmitted in (least significant octet first).
+pub fn database_hash<A: AttributeProvider>(attrs: &mut A) -> [u8; 16] {
+    let mut hasher = DatabaseHasher::new();
+    let _ = attrs.for_attrs_in_range(HandleRange::FULL, |_, attr| {
+        hasher.add(&attr);
+        Ok(())
+    });
+    hasher.finish()
+}
+
+/// Returns which fields of an attribute of type `att_type` contribute to the *Database Hash*.
+///
+/// Returns `Some(true)` if the handle, type and value are included, `Some(false)` if only handle
+/// and type are included, and `None` if the attribute isn't included at all.
+pub(crate) fn hashed_fields(att_type: AttUuid) -> Option<bool> {
+    let uuid = match att_type {
+        AttUuid::Uuid16(uuid) => uuid,
+        AttUuid::Uuid128(_) => return None,
+    };
+
+    match uuid {
+        Uuid16::PRIMARY_SERVICE
+        | Uuid16::SECONDARY_SERVICE
+        | Uuid16::INCLUDE
+        | Uuid16::CHARACTERISTIC
+        | Uuid16::CHARACTERISTIC_EXTENDED_PROPERTIES => Some(true),
+        Uuid16::CHARACTERISTIC_USER_DESCRIPTION
+        | Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION
+        | Uuid16::SERVER_CHARACTERISTIC_CONFIGURATION
+        | Uuid16::CHARACTERISTIC_PRESENTATION_FORMAT
+        | Uuid16::CHARACTERISTIC_AGGREGATE_FORMAT => Some(false),
+        _ => None,
+    }
+}
+
+bitflags! {
+    /// Value of the *Client Supported Features* characteristic.
+    pub struct ClientFeatures: u8 {
+        /// The client supports robust caching.
+        const ROBUST_CACHING = 0x01;
+        /// The client supports the Enhanced Attribute Protocol.
+        const ENHANCED_ATT = 0x02;
+        /// The client supports *Multiple Handle Value Notifications*.
+        const MULTIPLE_HANDLE_VALUE_NOTIFICATIONS = 0x04;
+    }
+}
+
+impl ClientFeatures {
+    /// Applies a client's write of `value` to the characteristic.
+    ///
+    /// Only the first octet of `value` is used, and unknown features are ignored. A client may not
+    /// disable a feature it has enabled before, so this fails with `ValueNotAllowed` if `value`
+    /// lacks any feature in `self`.
+    pub fn update(&mut self, value: &[u8]) -> Result<(), ErrorCode> {
+        let first = *value
+            .first()
+            .ok_or(ErrorCode::InvalidAttributeValueLength)?;
+        let features = Self::from_bits_truncate(first);
+        if !features.contains(*self) {
+            return Err(ErrorCode::ValueNotAllowed);
+        }
+
+        *self = features;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{att::Handle, gatt::BatteryServiceAttrs, utils::HexSlice},
+    };
+
+    #[test]
+    fn battery_service_hash() {
+        // AES-CMAC over `0100 0028 0F18 0200 0328 020300192A`, computed with an independent
+        // implementation
+        assert_eq!(
+            database_hash(&mut BatteryServiceAttrs::new()),
+            [
+                0x80, 0x38, 0x4e, 0x4e, 0xf7, 0x99, 0x3b, 0x5f, 0x1f, 0x11, 0x2a, 0xc2, 0x5f, 0xdc,
+                0x9c, 0x2e,
+            ]
+        );
+    }
+
+    #[test]
+    fn hashed_attributes() {
+        let hash = |attrs: &[(u16, Uuid16, &[u8])]| {
+            let mut hasher = DatabaseHasher::new();
+            for &(handle, att_type, value) in attrs {
+                hasher.add(&Attribute {
+                    att_type: att_type.into(),
+                    handle: Handle::from_raw(handle),
+                    value: HexSlice(value),
+                });
+            }
+            hasher.finish()
+        };
+
+        let service = (1, Uuid16::PRIMARY_SERVICE, &[0x0F, 0x18][..]);
+        let decl = (
+            2,
+            Uuid16::CHARACTERISTIC,
+            &[0x12, 0x03, 0x00, 0x19, 0x2A][..],
+        );
+        let cccd = (
+            4,
+            Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION,
+            &[0x00, 0x00][..],
+        );
+        let reference = hash(&[service, decl, cccd]);
+
+        // Values of characteristics and CCCDs don't matter
+        let value = (3, Uuid16::BATTERY_LEVEL, &[48][..]);
+        let subscribed = (
+            4,
+            Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION,
+            &[0x01, 0x00][..],
+        );
+        assert_eq!(hash(&[service, decl, value, subscribed]), reference);
+
+        // Handles and declaration values do
+        let moved = (
+            5,
+            Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION,
+            &[0x00, 0x00][..],
+        );
+        assert_ne!(hash(&[service, decl, moved]), reference);
+        let read_only = (
+            2,
+            Uuid16::CHARACTERISTIC,
+            &[0x02, 0x03, 0x00, 0x19, 0x2A][..],
+        );
+        assert_ne!(hash(&[service, read_only, cccd]), reference);
+    }
+
+    #[test]
+    fn client_features() {
+        let mut features = ClientFeatures::empty();
+        assert_eq!(
+            features.update(&[]),
+            Err(ErrorCode::InvalidAttributeValueLength)
+        );
+        features.update(&[0x01]).unwrap();
+        assert_eq!(features, ClientFeatures::ROBUST_CACHING);
+
+        // Unknown bits and additional octets are ignored
+        features.update(&[0xF3, 0xFF]).unwrap();
+        assert_eq!(
+            features,
+            ClientFeatures::ROBUST_CACHING | ClientFeatures::ENHANCED_ATT
+        );
+
+        // Features can't be disabled again
+        assert_eq!(features.update(&[0x01]), Err(ErrorCode::ValueNotAllowed));
+        assert_eq!(
+            features,
+            ClientFeatures::ROBUST_CACHING | ClientFeatures::ENHANCED_ATT
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! Client Characteristic Configuration Descriptors (CCCDs).
+//!
+//! A client subscribes to notifications or indications of a characteristic by writing to its CCCD.
+//! For bonded clients, the Bluetooth specification requires that the configuration is preserved
+//! across connections, so a client that reconnects receives notifications without having to
+//! subscribe again. Unbonded clients always start out unsubscribed.
+//!
+//! Attribute providers expose their CCCDs by implementing `ClientConfigs`. The values written by
+//! bonded clients are persisted in the `KeyStore`, keyed by the identity address of the client (see
+//! `Connection::peer_identity`), and restored by calling `ClientConfigs::restore_cccds` when the
+//! client reconnects.
+
+use {
+    crate::{
+        att::Handle,
+        link::{encryption::KeyStore, DeviceAddress},
+    },
+    bitflags::bitflags,
+};
+
+bitflags! {
+    /// Value of a Client Characteristic Configuration Descriptor.
+    pub struct Cccd: u16 {
+        /// The client wants to receive notifications.
+        const NOTIFY   = 0x0001;
+        /// The client wants to receive indications.
+        const INDICATE = 0x0002;
+    }
+}
+
+impl Cccd {
+    /// Decodes the 2-Byte little-endian attribute value of a CCCD.
+    ///
+    /// Reserved bits are ignored.
+    pub fn from_value(value: [u8; 2]) -> Self {
+        Self::from_bits_truncate(u16::from_le_bytes(value))
+    }
+
+    /// Encodes `self` as a 2-Byte little-endian attribute value.
+    pub fn to_value(self) -> [u8; 2] {
+        self.bits().to_le_bytes()
+    }
+}
+
+/// Trait for attribute providers that contain CCCDs.
+pub trait ClientConfigs {
+    /// Returns the handles of all CCCDs.
+    fn cccd_handles(&self) -> &[Handle];
+
+    /// Returns the current value of the CCCD at `handle`.
+    fn cccd(&self, handle: Handle) -> Cccd;
+
+    /// Sets the value of the CCCD at `handle`.
+    ///
+    /// This does not persist the value. Use `write_cccd` when handling a write by the client.
+    fn set_cccd(&mut self, handle: Handle, value: Cccd);
+
+    /// Restores the CCCD values for a newly connected client `peer`.
+    ///
+    /// `peer` must be the identity address of the client. CCCDs for which `key_store` has a stored
+    /// value are set to that value, all others are cleared. This means that an unbonded client
+    /// starts with all notifications and indications disabled.
+    fn restore_cccds<K: KeyStore>(&mut self, key_store: &mut K, peer: &DeviceAddress) {
+        for i in 0..self.cccd_handles().len() {
+            let handle = self.cccd_handles()[i];
+            let value = key_store.load_cccd(peer, handle).unwrap_or(Cccd::empty());
+            self.set_cccd(handle, value);
+        }
+    }
+
+    /// Handles a write of `value` to the CCCD at `handle` by the client `peer`.
+    ///
+    /// The value is applied and handed to `key_store`, which persists it if `peer` is bonded.
+    fn write_cccd<K: KeyStore>(
+        &mut self,
+        key_store: &mut K,
+        peer: &DeviceAddress,
+        handle: Handle,
+        value: Cccd,
+    ) {
+        self.set_cccd(handle, value);
+        key_store.store_cccd(peer, handle, value);
+    }
+}
@@ -1,5 +1,10 @@
 use {
-    crate::{att::AttUuid, uuid::Uuid16},
+    crate::{
+        att::AttUuid,
+        bytes::{ByteWriter, ToBytes},
+        uuid::Uuid16,
+        Error,
+    },
     bitflags::bitflags,
 };
 
@@ -54,7 +59,241 @@ impl BatteryLevel {
 
 impl Characteristic for BatteryLevel {
     const PROPS: Properties = const_or!(Properties::READ | Properties::WRITE);
-    const UUID: AttUuid = AttUuid::Uuid16(Uuid16(0x2A19));
+    const UUID: AttUuid = AttUuid::Uuid16(Uuid16::BATTERY_LEVEL);
+}
+
+enum_with_unknown! {
+    /// Data types of characteristic values, as used in a *Characteristic Presentation Format*
+    /// descriptor.
+    ///
+    /// Multi-Byte values are little-endian.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum Format(u8) {
+        Boolean = 0x01,
+        /// Unsigned 2-bit integer.
+        U2 = 0x02,
+        /// Unsigned 4-bit integer.
+        U4 = 0x03,
+        U8 = 0x04,
+        /// Unsigned 12-bit integer.
+        U12 = 0x05,
+        U16 = 0x06,
+        /// Unsigned 24-bit integer.
+        U24 = 0x07,
+        U32 = 0x08,
+        /// Unsigned 48-bit integer.
+        U48 = 0x09,
+        U64 = 0x0A,
+        U128 = 0x0B,
+        I8 = 0x0C,
+        /// Signed 12-bit integer.
+        I12 = 0x0D,
+        I16 = 0x0E,
+        /// Signed 24-bit integer.
+        I24 = 0x0F,
+        I32 = 0x10,
+        /// Signed 48-bit integer.
+        I48 = 0x11,
+        I64 = 0x12,
+        I128 = 0x13,
+        /// IEEE-754 32-bit floating point.
+        F32 = 0x14,
+        /// IEEE-754 64-bit floating point.
+        F64 = 0x15,
+        /// IEEE-11073 16-bit SFLOAT.
+        SFloat = 0x16,
+        /// IEEE-11073 32-bit FLOAT.
+        Float = 0x17,
+        /// IEEE-20601 format (two 16-bit values).
+        Duint16 = 0x18,
+        /// UTF-8 string.
+        Utf8 = 0x19,
+        /// UTF-16 string.
+        Utf16 = 0x1A,
+        /// Opaque structure.
+        Struct = 0x1B,
+    }
+}
+
+/// The *Bluetooth SIG* namespace for the `description` of a `PresentationFormat`.
+pub const NAMESPACE_BLUETOOTH_SIG: u8 = 0x01;
+
+/// Value of a *Characteristic Presentation Format* descriptor (`0x2904`).
+///
+/// Describes how a client should present a characteristic value to the user. The value is
+/// `raw * 10^exponent`, measured in `unit`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PresentationFormat {
+    /// Data type of the characteristic value.
+    pub format: Format,
+
+    /// Base-10 exponent to apply to integer values.
+    pub exponent: i8,
+
+    /// Unit of the value, one of the SIG-assigned unit UUIDs (eg. `Uuid16::DEGREE_CELSIUS`).
+    pub unit: Uuid16,
+
+    /// Organization that assigns the `description` values (`NAMESPACE_BLUETOOTH_SIG`).
+    pub namespace: u8,
+
+    /// Identifies the characteristic among several ones of the same type (eg. "first", "left").
+    ///
+    /// `0x0000` means "unknown".
+    pub description: u16,
+}
+
+impl PresentationFormat {
+    /// Creates a `PresentationFormat` in the Bluetooth SIG namespace with an unknown description.
+    pub fn new(format: Format, exponent: i8, unit: Uuid16) -> Self {
+        Self {
+            format,
+            exponent,
+            unit,
+            namespace: NAMESPACE_BLUETOOTH_SIG,
+            description: 0x0000,
+        }
+    }
+
+    /// Decodes the 7-Byte attribute value of a presentation format descriptor.
+    pub fn from_value(value: [u8; 7]) -> Self {
+        Self {
+            format: value[0].into(),
+            exponent: value[1] as i8,
+            unit: Uuid16(u16::from_le_bytes([value[2], value[3]])),
+            namespace: value[4],
+            description: u16::from_le_bytes([value[5], value[6]]),
+        }
+    }
+
+    /// Encodes `self` as a 7-Byte attribute value.
+    pub fn to_value(&self) -> [u8; 7] {
+        let unit = self.unit.0.to_le_bytes();
+        let description = self.description.to_le_bytes();
+        [
+            self.format.into(),
+            self.exponent as u8,
+            unit[0],
+            unit[1],
+            self.namespace,
+            description[0],
+            description[1],
+        ]
+    }
+}
+
+/// Value of a *Valid Range* descriptor (`0x2906`).
+///
+/// Specifies the inclusive lower and upper bound of a numeric characteristic value. Both bounds are
+/// encoded in the format of the characteristic value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ValidRange {
+    format: Format,
+    lower: i64,
+    upper: i64,
+}
+
+impl ValidRange {
+    /// Creates a `ValidRange` for values in `format` from `lower` to `upper` (inclusive).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `format` isn't one of the 8- to 64-bit integer formats, if either bound can't be
+    /// represented in `format`, or if `lower` is greater than `upper`.
+    pub fn new(format: Format, lower: i64, upper: i64) -> Self {
+        let (len, signed) = int_layout(format).expect("valid range of a non-integer format");
+        let bits = 8 * len as u32;
+        let (min, max) = if signed {
+            (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+        } else {
+            (0, (1i128 << bits) - 1)
+        };
+        assert!(
+            min <= i128::from(lower) && i128::from(upper) <= max,
+            "valid range bounds don't fit the format"
+        );
+        assert!(
+            lower <= upper,
+            "lower bound of valid range above upper bound"
+        );
+
+        Self {
+            format,
+            lower,
+            upper,
+        }
+    }
+
+    /// Returns the format of the bounds and of the characteristic value.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Returns the lower bound.
+    pub fn lower(&self) -> i64 {
+        self.lower
+    }
+
+    /// Returns the upper bound.
+    pub fn upper(&self) -> i64 {
+        self.upper
+    }
+
+    /// Returns the length of a value in `self.format()`, in Bytes.
+    pub fn value_len(&self) -> usize {
+        int_layout(self.format).unwrap().0
+    }
+
+    /// Returns whether the encoded characteristic `value` lies inside the range.
+    ///
+    /// Returns `false` if `value` doesn't have a length of `self.value_len()` Bytes.
+    pub fn contains(&self, value: &[u8]) -> bool {
+        let (len, signed) = int_layout(self.format).unwrap();
+        if value.len() != len {
+            return false;
+        }
+
+        let mut raw = [0; 8];
+        raw[..len].copy_from_slice(value);
+        let value = if signed {
+            // Sign-extend to 64 bits
+            let shift = 64 - 8 * len as u32;
+            i128::from((i64::from_le_bytes(raw) << shift) >> shift)
+        } else {
+            i128::from(u64::from_le_bytes(raw))
+        };
+        i128::from(self.lower) <= value && value <= i128::from(self.upper)
+    }
+}
+
+impl ToBytes for ValidRange {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        let len = self.value_len();
+        writer.write_slice(&self.lower.to_le_bytes()[..len])?;
+        writer.write_slice(&self.upper.to_le_bytes()[..len])?;
+        Ok(())
+    }
+}
+
+/// Returns the length in Bytes and the signedness of values in the integer format `format`.
+///
+/// Returns `None` for non-integer formats and integers that don't fill whole Bytes or are longer
+/// than 64 bits.
+fn int_layout(format: Format) -> Option<(usize, bool)> {
+    Some(match format {
+        Format::U8 => (1, false),
+        Format::U16 => (2, false),
+        Format::U24 => (3, false),
+        Format::U32 => (4, false),
+        Format::U48 => (6, false),
+        Format::U64 => (8, false),
+        Format::I8 => (1, true),
+        Format::I16 => (2, true),
+        Format::I24 => (3, true),
+        Format::I32 => (4, true),
+        Format::I48 => (6, true),
+        Format::I64 => (8, true),
+        _ => return None,
+    })
 }
 
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -127,3 +366,47 @@ pub enum Appearance {
     LocationPod = 5187,
     LocationAndNavigationPod = 5188,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presentation_format_temperature() {
+        // Temperature in 0.01 °C, as a signed 16-bit integer
+        let format = PresentationFormat::new(Format::I16, -2, Uuid16::DEGREE_CELSIUS);
+        let value = [0x0E, 0xFE, 0x2F, 0x27, 0x01, 0x00, 0x00];
+        assert_eq!(format.to_value(), value);
+        assert_eq!(PresentationFormat::from_value(value), format);
+
+        let format = PresentationFormat::from_value([0xFF, 0x00, 0x00, 0x27, 0x02, 0x34, 0x12]);
+        assert_eq!(format.format, Format::Unknown(0xFF));
+        assert_eq!(format.unit, Uuid16::UNITLESS);
+        assert_eq!(format.namespace, 0x02);
+        assert_eq!(format.description, 0x1234);
+    }
+
+    #[test]
+    fn valid_range_contains() {
+        let range = ValidRange::new(Format::I24, -2, 0x10000);
+        let mut buf = [0; 6];
+        assert_eq!(range.to_bytes_into(&mut buf), Ok(6));
+        assert_eq!(buf, [0xFE, 0xFF, 0xFF, 0x00, 0x00, 0x01]);
+
+        assert!(range.contains(&[0xFE, 0xFF, 0xFF]));
+        assert!(range.contains(&[0x00, 0x00, 0x01]));
+        assert!(!range.contains(&[0xFD, 0xFF, 0xFF]));
+        assert!(!range.contains(&[0x01, 0x00, 0x01]));
+        assert!(!range.contains(&[0x00, 0x00]));
+
+        let range = ValidRange::new(Format::U8, 10, 255);
+        assert!(range.contains(&[0xFF]));
+        assert!(!range.contains(&[9]));
+    }
+
+    #[test]
+    #[should_panic(expected = "valid range bounds don't fit the format")]
+    fn valid_range_too_wide() {
+        let _ = ValidRange::new(Format::U8, 0, 256);
+    }
+}
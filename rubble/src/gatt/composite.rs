@@ -0,0 +1,532 @@
+//! Serving several statically sized attribute providers from one `AttributeServer`.
+//!
+//! An `AttributeServer` hosts a single `AttributeProvider`. Static providers like
+//! [`BatteryServiceAttrs`] or [`GapServiceAttrs`] all number their attributes starting at handle
+//! `0x0001`, so they can't be combined by simply looking up handles in each of them.
+//!
+//! Providers implementing [`StaticAttributes`] declare the number of handles they use. A tuple
+//! `(A, B)` of such providers is an `AttributeProvider` itself: the attributes of `A` keep their
+//! handles, and the handles of `B` are shifted by `A::HANDLE_COUNT`. The offsets are constants, so
+//! dispatching a request to the right provider compiles down to a few comparisons, without any
+//! dynamic dispatch. Since the tuple implements `StaticAttributes` as well, more than two providers
+//! can be combined by nesting tuples:
+//!
+//! ```
+//! use rubble::att::{AttributeProvider, Handle};
+//! use rubble::gatt::{
+//!     composite::StaticAttributes, BatteryServiceAttrs, DeviceInfoAttrs, GapServiceAttrs,
+//! };
+//!
+//! type Attrs<'a> = (GapServiceAttrs<'a>, (BatteryServiceAttrs, DeviceInfoAttrs<'a>));
+//!
+//! let mut attrs: Attrs<'_> = (
+//!     GapServiceAttrs::new("Rubble", 0x0000),
+//!     (BatteryServiceAttrs::new(), DeviceInfoAttrs::new("Rubble", "Demo")),
+//! );
+//! assert_eq!(Attrs::HANDLE_COUNT, 13);
+//! assert!(attrs.validate().is_ok());
+//!
+//! // The battery service starts right after the 5 attributes of the GAP service
+//! assert_eq!(attrs.group_end(Handle::from_raw(6)), Some(Handle::from_raw(8)));
+//! ```
+//!
+//! Handles and error responses are translated in both directions, and the value handles inside
+//! characteristic and include declarations are shifted as well. Handles used by the providers
+//! themselves (eg. as the target of notifications) are *not* translated; the application has to add
+//! the offset of the provider in the tuple.
+//!
+//! [`BatteryServiceAttrs`]: ../struct.BatteryServiceAttrs.html
+//! [`GapServiceAttrs`]: ../struct.GapServiceAttrs.html
+//! [`StaticAttributes`]: trait.StaticAttributes.html
+
+use {
+    crate::{
+        att::{
+            is_declaration_type, AttError, AttUuid, Attribute, AttributeProvider, ErrorCode,
+            Handle, HandleRange, ValueSource, MAX_VALUE_LEN,
+        },
+        link::encryption::ConnectionSecurity,
+        utils::HexSlice,
+        uuid::Uuid16,
+        Error,
+    },
+    core::cmp,
+};
+
+/// An `AttributeProvider` whose attribute table has a size known at compile time.
+///
+/// The provider may only use handles in the range `0x0001..=HANDLE_COUNT`. Handles in that range
+/// may be left unused.
+///
+/// The attributes are iterated in two steps: `update_values` computes the values that change over
+/// time, then `for_static_attrs` visits the attributes through a shared reference. This allows a
+/// tuple to pass itself to the `for_attrs_in_range` callback while visiting its members.
+/// Implementors can forward `for_attrs_in_range` to [`for_attrs_in_range`].
+///
+/// [`for_attrs_in_range`]: fn.for_attrs_in_range.html
+pub trait StaticAttributes: AttributeProvider {
+    /// The number of handles used by the provider.
+    const HANDLE_COUNT: u16;
+
+    /// Updates the values of the attributes inside `range` that are computed on demand.
+    ///
+    /// By default, this does nothing.
+    fn update_values(&mut self, _range: &HandleRange) {}
+
+    /// Calls `f` with every attribute whose handle is inside `range`, ascending.
+    ///
+    /// Attribute values are reported as of the last call to `update_values`. Iteration stops at
+    /// the first error returned by `f`, which is propagated.
+    fn for_static_attrs(
+        &self,
+        range: &HandleRange,
+        f: impl FnMut(Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+}
+
+/// Implements `AttributeProvider::for_attrs_in_range` for a `StaticAttributes` provider.
+pub fn for_attrs_in_range<P: StaticAttributes>(
+    provider: &mut P,
+    range: HandleRange,
+    mut f: impl FnMut(&P, Attribute<'_>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    provider.update_values(&range);
+    let provider = &*provider;
+    provider.for_static_attrs(&range, |attr| f(provider, attr))
+}
+
+impl<A: StaticAttributes, B: StaticAttributes> StaticAttributes for (A, B) {
+    const HANDLE_COUNT: u16 = A::HANDLE_COUNT + B::HANDLE_COUNT;
+
+    fn update_values(&mut self, range: &HandleRange) {
+        if let Some(local) = local_range(range, 0, A::HANDLE_COUNT) {
+            self.0.update_values(&local);
+        }
+        if let Some(local) = local_range(range, A::HANDLE_COUNT, B::HANDLE_COUNT) {
+            self.1.update_values(&local);
+        }
+    }
+
+    fn for_static_attrs(
+        &self,
+        range: &HandleRange,
+        mut f: impl FnMut(Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if let Some(local) = local_range(range, 0, A::HANDLE_COUNT) {
+            self.0.for_static_attrs(&local, &mut f)?;
+        }
+        if let Some(local) = local_range(range, A::HANDLE_COUNT, B::HANDLE_COUNT) {
+            self.1
+                .for_static_attrs(&local, |attr| shift_attr(attr, A::HANDLE_COUNT, &mut f))?;
+        }
+        Ok(())
+    }
+}
+
+/// Maximum length of the value of a characteristic or include declaration.
+///
+/// A characteristic declaration with a 128-bit UUID is the longest: 1 Byte of properties, 2 Bytes
+/// of value handle and 16 Bytes of UUID.
+const MAX_DECL_LEN: usize = 19;
+
+/// The member of a provider tuple that a handle belongs to.
+enum Member {
+    /// The first provider, with the handle unchanged.
+    First(Handle),
+    /// The second provider, with the handle relative to its first attribute.
+    Second(Handle),
+    /// The handle lies past the attributes of both providers.
+    Outside,
+}
+
+fn member<A: StaticAttributes, B: StaticAttributes>(handle: Handle) -> Member {
+    let raw = handle.as_u16();
+    if raw <= A::HANDLE_COUNT {
+        Member::First(handle)
+    } else if raw - A::HANDLE_COUNT <= B::HANDLE_COUNT {
+        Member::Second(Handle::from_raw(raw - A::HANDLE_COUNT))
+    } else {
+        Member::Outside
+    }
+}
+
+fn shift(handle: Handle, offset: u16) -> Handle {
+    Handle::from_raw(handle.as_u16() + offset)
+}
+
+/// Translates an error returned by a provider at `offset` back to the handles of the tuple.
+fn shift_error(error: AttError, offset: u16) -> AttError {
+    if error.handle() == Handle::NULL {
+        error
+    } else {
+        AttError::new(error.error_code(), shift(error.handle(), offset))
+    }
+}
+
+/// Returns the part of `range` that is provided by a provider at `offset`, relative to that
+/// provider's first attribute.
+fn local_range(range: &HandleRange, offset: u16, count: u16) -> Option<HandleRange> {
+    let start = cmp::max(range.start().as_u16(), offset.saturating_add(1));
+    let end = cmp::min(
+        u32::from(range.end().as_u16()),
+        u32::from(offset) + u32::from(count),
+    );
+    if u32::from(start) > end {
+        return None;
+    }
+
+    HandleRange::new(
+        Handle::from_raw(start - offset),
+        Handle::from_raw(end as u16 - offset),
+    )
+    .ok()
+}
+
+/// Shifts the handles embedded in the value of a declaration by `offset`.
+fn shift_declaration(att_type: AttUuid, value: &mut [u8], offset: u16) {
+    let handles = match att_type {
+        AttUuid::Uuid16(Uuid16::CHARACTERISTIC) => 1..3,
+        // An include declaration references the first and last handle of the included service
+        AttUuid::Uuid16(Uuid16::INCLUDE) => 0..4,
+        _ => return,
+    };
+
+    if let Some(handles) = value.get_mut(handles) {
+        for handle in handles.chunks_exact_mut(2) {
+            let raw = u16::from_le_bytes([handle[0], handle[1]]).wrapping_add(offset);
+            handle.copy_from_slice(&raw.to_le_bytes());
+        }
+    }
+}
+
+/// Passes `attr` of a provider at `offset` to `f`, with its handle shifted.
+///
+/// Only the values of declarations are copied (to shift the handles inside them), all other values
+/// are passed through.
+fn shift_attr(
+    attr: Attribute<'_>,
+    offset: u16,
+    f: &mut impl FnMut(Attribute<'_>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let handle = shift(attr.handle, offset);
+    if !is_declaration_type(attr.att_type) {
+        return f(Attribute { handle, ..attr });
+    }
+
+    let mut buf = [0; MAX_DECL_LEN];
+    let len = cmp::min(attr.value.0.len(), MAX_DECL_LEN);
+    buf[..len].copy_from_slice(&attr.value.0[..len]);
+    shift_declaration(attr.att_type, &mut buf[..len], offset);
+    f(Attribute {
+        att_type: attr.att_type,
+        handle,
+        value: HexSlice(&buf[..len]),
+    })
+}
+
+/// Reads the declaration at `handle` of a provider at `offset` into `buf`, with its handles
+/// shifted.
+///
+/// Returns `None` if the attribute doesn't exist or isn't a declaration. Declarations don't
+/// change, so the values of `provider` aren't updated.
+fn read_declaration<P: StaticAttributes>(
+    provider: &P,
+    handle: Handle,
+    offset: u16,
+    buf: &mut [u8; MAX_DECL_LEN],
+) -> Option<usize> {
+    let range = HandleRange::new(handle, handle).ok()?;
+    let mut found = None;
+    provider
+        .for_static_attrs(&range, |attr| {
+            if is_declaration_type(attr.att_type) {
+                let len = cmp::min(attr.value.0.len(), buf.len());
+                buf[..len].copy_from_slice(&attr.value.0[..len]);
+                shift_declaration(attr.att_type, &mut buf[..len], offset);
+                found = Some(len);
+            }
+            Ok(())
+        })
+        .ok();
+    found
+}
+
+/// Two attribute providers served as one attribute table.
+///
+/// The attributes of `B` follow those of `A`, so their handles are shifted by `A::HANDLE_COUNT`.
+/// See the [module documentation] for details.
+///
+/// [module documentation]: index.html
+impl<A: StaticAttributes, B: StaticAttributes> AttributeProvider for (A, B) {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for_attrs_in_range(self, range, f)
+    }
+
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        self.0.is_grouping_attr(uuid) || self.1.is_grouping_attr(uuid)
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
+        match member::<A, B>(handle) {
+            Member::First(handle) => self.0.group_end(handle),
+            Member::Second(handle) => self
+                .1
+                .group_end(handle)
+                .map(|end| shift(end, A::HANDLE_COUNT)),
+            Member::Outside => None,
+        }
+    }
+
+    fn max_value_len(&self, handle: Handle) -> usize {
+        match member::<A, B>(handle) {
+            Member::First(handle) => self.0.max_value_len(handle),
+            Member::Second(handle) => self.1.max_value_len(handle),
+            Member::Outside => MAX_VALUE_LEN,
+        }
+    }
+
+    fn required_security(&self, handle: Handle) -> ConnectionSecurity {
+        match member::<A, B>(handle) {
+            Member::First(handle) => self.0.required_security(handle),
+            Member::Second(handle) => self.1.required_security(handle),
+            Member::Outside => ConnectionSecurity::Unencrypted,
+        }
+    }
+
+    fn read_attr(&mut self, handle: Handle, f: impl FnOnce(&[u8])) -> Result<(), AttError> {
+        match member::<A, B>(handle) {
+            Member::First(handle) => self.0.read_attr(handle, f),
+            Member::Second(handle) => {
+                let mut buf = [0; MAX_DECL_LEN];
+                match read_declaration(&self.1, handle, A::HANDLE_COUNT, &mut buf) {
+                    Some(len) => {
+                        f(&buf[..len]);
+                        Ok(())
+                    }
+                    None => self
+                        .1
+                        .read_attr(handle, f)
+                        .map_err(|e| shift_error(e, A::HANDLE_COUNT)),
+                }
+            }
+            Member::Outside => Err(AttError::new(ErrorCode::InvalidHandle, handle)),
+        }
+    }
+
+    fn read_attr_source(
+        &mut self,
+        handle: Handle,
+        f: impl FnOnce(&mut dyn ValueSource),
+    ) -> Result<(), AttError> {
+        match member::<A, B>(handle) {
+            Member::First(handle) => self.0.read_attr_source(handle, f),
+            Member::Second(handle) => {
+                let mut buf = [0; MAX_DECL_LEN];
+                match read_declaration(&self.1, handle, A::HANDLE_COUNT, &mut buf) {
+                    Some(len) => {
+                        f(&mut &buf[..len]);
+                        Ok(())
+                    }
+                    None => self
+                        .1
+                        .read_attr_source(handle, f)
+                        .map_err(|e| shift_error(e, A::HANDLE_COUNT)),
+                }
+            }
+            Member::Outside => Err(AttError::new(ErrorCode::InvalidHandle, handle)),
+        }
+    }
+
+    fn begin_read_snapshot(&mut self, handle: Handle) -> Option<u32> {
+        match member::<A, B>(handle) {
+            Member::First(handle) => self.0.begin_read_snapshot(handle),
+            Member::Second(handle) => self.1.begin_read_snapshot(handle),
+            Member::Outside => None,
+        }
+    }
+
+    fn read_snapshot(
+        &mut self,
+        handle: Handle,
+        generation: u32,
+        f: impl FnOnce(&[u8]),
+    ) -> Result<(), AttError> {
+        match member::<A, B>(handle) {
+            Member::First(handle) => self.0.read_snapshot(handle, generation, f),
+            Member::Second(handle) => self
+                .1
+                .read_snapshot(handle, generation, f)
+                .map_err(|e| shift_error(e, A::HANDLE_COUNT)),
+            Member::Outside => Err(AttError::new(ErrorCode::InvalidHandle, handle)),
+        }
+    }
+
+    fn check_writable(&mut self, handle: Handle) -> Result<(), AttError> {
+        match member::<A, B>(handle) {
+            Member::First(handle) => self.0.check_writable(handle),
+            Member::Second(handle) => self
+                .1
+                .check_writable(handle)
+                .map_err(|e| shift_error(e, A::HANDLE_COUNT)),
+            Member::Outside => Err(AttError::new(ErrorCode::InvalidHandle, handle)),
+        }
+    }
+
+    fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        match member::<A, B>(handle) {
+            Member::First(handle) => self.0.write_attr(handle, value),
+            Member::Second(handle) => self
+                .1
+                .write_attr(handle, value)
+                .map_err(|e| shift_error(e, A::HANDLE_COUNT)),
+            Member::Outside => Err(AttError::new(ErrorCode::InvalidHandle, handle)),
+        }
+    }
+
+    fn write_command(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        match member::<A, B>(handle) {
+            Member::First(handle) => self.0.write_command(handle, value),
+            Member::Second(handle) => self
+                .1
+                .write_command(handle, value)
+                .map_err(|e| shift_error(e, A::HANDLE_COUNT)),
+            Member::Outside => Err(AttError::new(ErrorCode::InvalidHandle, handle)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::gatt::{BatteryServiceAttrs, DeviceInfoAttrs, GapServiceAttrs},
+        std::vec::Vec,
+    };
+
+    type Attrs = (
+        GapServiceAttrs<'static>,
+        (BatteryServiceAttrs, DeviceInfoAttrs<'static>),
+    );
+
+    fn attrs() -> Attrs {
+        (
+            GapServiceAttrs::new("rubble", 0x0000),
+            (
+                BatteryServiceAttrs::new(),
+                DeviceInfoAttrs::new("ACME", "R1"),
+            ),
+        )
+    }
+
+    fn handle(raw: u16) -> Handle {
+        Handle::from_raw(raw)
+    }
+
+    fn collect(attrs: &mut Attrs, range: HandleRange) -> Vec<(u16, Vec<u8>)> {
+        let mut list = Vec::new();
+        attrs
+            .for_attrs_in_range(range, |_, attr| {
+                list.push((attr.handle.as_u16(), attr.value.0.to_vec()));
+                Ok(())
+            })
+            .unwrap();
+        list
+    }
+
+    fn read(attrs: &mut Attrs, raw: u16) -> Result<Vec<u8>, AttError> {
+        let mut value = Vec::new();
+        attrs.read_attr(handle(raw), |v| value.extend_from_slice(v))?;
+        Ok(value)
+    }
+
+    fn error(result: Result<impl core::fmt::Debug, AttError>) -> (ErrorCode, u16) {
+        let error = result.unwrap_err();
+        (error.error_code(), error.handle().as_u16())
+    }
+
+    #[test]
+    fn combined_table() {
+        let mut attrs = attrs();
+        assert_eq!(Attrs::HANDLE_COUNT, 13);
+        assert_eq!(attrs.validate(), Ok(()));
+
+        let table = collect(&mut attrs, HandleRange::FULL);
+        let handles = table.iter().map(|(handle, _)| *handle).collect::<Vec<_>>();
+        assert_eq!(handles, (1..=13).collect::<Vec<_>>());
+
+        // Value handles in the declarations of the shifted services point to the shifted values
+        assert_eq!(table[6].1, [0x02, 0x08, 0x00, 0x19, 0x2A]);
+        assert_eq!(table[9].1, [0x02, 0x0B, 0x00, 0x29, 0x2A]);
+        assert_eq!(table[11].1, [0x02, 0x0D, 0x00, 0x24, 0x2A]);
+        assert_eq!(table[12].1, b"R1");
+
+        // Ranges spanning several providers only contain the requested handles
+        let part = collect(&mut attrs, HandleRange::new(handle(4), handle(10)).unwrap());
+        let handles = part.iter().map(|(handle, _)| *handle).collect::<Vec<_>>();
+        assert_eq!(handles, (4..=10).collect::<Vec<_>>());
+        assert!(collect(
+            &mut attrs,
+            HandleRange::new(handle(14), handle(20)).unwrap()
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn dispatch() {
+        let mut attrs = attrs();
+
+        assert_eq!(attrs.group_end(handle(1)), Some(handle(5)));
+        assert_eq!(attrs.group_end(handle(6)), Some(handle(8)));
+        assert_eq!(attrs.group_end(handle(9)), Some(handle(13)));
+        assert_eq!(attrs.group_end(handle(10)), None);
+
+        assert_eq!(read(&mut attrs, 5).unwrap(), b"rubble");
+        assert_eq!(read(&mut attrs, 8).unwrap(), [48]);
+        assert_eq!(
+            read(&mut attrs, 10).unwrap(),
+            [0x02, 0x0B, 0x00, 0x29, 0x2A]
+        );
+        assert_eq!(read(&mut attrs, 11).unwrap(), b"ACME");
+        assert_eq!(error(read(&mut attrs, 14)), (ErrorCode::InvalidHandle, 14));
+
+        // Errors of shifted providers refer to the handles of the combined table
+        assert_eq!(
+            error(attrs.check_writable(handle(10))),
+            (ErrorCode::WriteNotPermitted, 10)
+        );
+    }
+
+    #[test]
+    fn single_pass() {
+        let reads = core::cell::Cell::new(0);
+        let mut attrs = (
+            GapServiceAttrs::new("rubble", 0x0000),
+            BatteryServiceAttrs::with_level_source(|| {
+                reads.set(reads.get() + 1);
+                50
+            }),
+        );
+
+        let mut handles = Vec::new();
+        attrs
+            .for_attrs_in_range(HandleRange::FULL, |_, attr| {
+                handles.push(attr.handle.as_u16());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(handles, (1..=8).collect::<Vec<_>>());
+        assert_eq!(reads.get(), 1);
+
+        // The level is only computed when it's part of the range
+        attrs
+            .for_attrs_in_range(HandleRange::new(handle(1), handle(7)).unwrap(), |_, _| {
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(reads.get(), 1);
+    }
+}
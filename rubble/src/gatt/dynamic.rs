@@ -0,0 +1,350 @@
+//! An attribute table that is built at runtime and owns its values.
+//!
+//! This module requires the `alloc` Cargo feature. It is meant for hosted applications and for
+//! prototyping, where a heap is available and the fixed capacity and borrowed callbacks of a
+//! [`GattServer`] are more of a burden than a benefit.
+//!
+//! # Example
+//!
+//! ```
+//! use rubble::gatt::characteristic::Properties;
+//! use rubble::gatt::dynamic::DynamicProvider;
+//! use rubble::uuid::Uuid16;
+//!
+//! let mut provider = DynamicProvider::new();
+//! provider.add_service(Uuid16(0x180F));
+//! let level = provider.add_characteristic(
+//!     Uuid16(0x2A19),
+//!     Properties::READ | Properties::NOTIFY,
+//!     &[100],
+//! );
+//!
+//! provider.value_mut(level).unwrap()[0] = 99;
+//! assert_eq!(provider.value(level), Some(&[99][..]));
+//! ```
+//!
+//! [`GattServer`]: ../server/struct.GattServer.html
+
+use {
+    super::characteristic::Properties,
+    crate::{
+        att::{AttError, AttUuid, Attribute, AttributeProvider, ErrorCode, Handle, HandleRange},
+        bytes::{ByteWriter, ToBytes},
+        utils::HexSlice,
+        uuid::Uuid16,
+        Error,
+    },
+    alloc::vec::Vec,
+};
+
+/// An attribute owned by a `DynamicProvider`.
+struct DynamicAttribute {
+    handle: Handle,
+    att_type: AttUuid,
+    value: Vec<u8>,
+    writable: bool,
+    /// For service declarations, the last attribute belonging to the service. Only the handle and
+    /// type are used.
+    group_end: Option<Attribute<'static>>,
+}
+
+/// An `AttributeProvider` that stores attributes and their values on the heap.
+///
+/// Services and characteristics can be added at any time, and are assigned consecutive handles
+/// starting at `0x0001`. Client writes to writable characteristic values replace the stored value,
+/// and the application can inspect and modify values via `value` and `value_mut`.
+pub struct DynamicProvider {
+    attributes: Vec<DynamicAttribute>,
+}
+
+impl DynamicProvider {
+    /// Creates a provider without any attributes.
+    pub fn new() -> Self {
+        Self {
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Adds a primary service.
+    ///
+    /// All characteristics added after this belong to the service. Returns the handle of the
+    /// service declaration.
+    pub fn add_service(&mut self, uuid: impl Into<AttUuid>) -> Handle {
+        let uuid = uuid.into();
+        let handle = self.next_handle();
+        self.push(DynamicAttribute {
+            handle,
+            att_type: Uuid16::PRIMARY_SERVICE.into(),
+            value: encode(|writer| uuid.to_bytes(writer)),
+            writable: false,
+            group_end: Some(Attribute {
+                att_type: Uuid16::PRIMARY_SERVICE.into(),
+                handle,
+                value: HexSlice(&[]),
+            }),
+        });
+        handle
+    }
+
+    /// Adds a characteristic with the initial value `value` to the last added service.
+    ///
+    /// The client can write to the value if `props` contains `WRITE` or `WRITE_NO_RSP`. If `props`
+    /// contains `NOTIFY` or `INDICATE`, a CCCD is added after the value.
+    ///
+    /// Returns the handle of the characteristic value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no service was added yet.
+    pub fn add_characteristic(
+        &mut self,
+        uuid: impl Into<AttUuid>,
+        props: Properties,
+        value: &[u8],
+    ) -> Handle {
+        let uuid = uuid.into();
+        let decl_handle = self.next_handle();
+        let value_handle = Handle::from_raw(decl_handle.as_u16() + 1);
+
+        self.push(DynamicAttribute {
+            handle: decl_handle,
+            att_type: Uuid16::CHARACTERISTIC.into(),
+            value: encode(|writer| {
+                writer.write_u8(props.bits())?;
+                writer.write_u16_le(value_handle.as_u16())?;
+                uuid.to_bytes(writer)
+            }),
+            writable: false,
+            group_end: None,
+        });
+        self.push(DynamicAttribute {
+            handle: value_handle,
+            att_type: uuid,
+            value: value.to_vec(),
+            writable: props.intersects(Properties::WRITE | Properties::WRITE_NO_RSP),
+            group_end: None,
+        });
+        if props.intersects(Properties::NOTIFY | Properties::INDICATE) {
+            self.push(DynamicAttribute {
+                handle: self.next_handle(),
+                att_type: Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION.into(),
+                value: [0, 0].to_vec(),
+                writable: true,
+                group_end: None,
+            });
+        }
+
+        value_handle
+    }
+
+    /// Returns the value of the attribute at `handle`, or `None` if it doesn't exist.
+    pub fn value(&self, handle: Handle) -> Option<&[u8]> {
+        self.get(handle).map(|attr| &*attr.value)
+    }
+
+    /// Returns a mutable reference to the value of the attribute at `handle`, or `None` if it
+    /// doesn't exist.
+    pub fn value_mut(&mut self, handle: Handle) -> Option<&mut Vec<u8>> {
+        let index = usize::from(handle.as_u16()).checked_sub(1)?;
+        self.attributes.get_mut(index).map(|attr| &mut attr.value)
+    }
+
+    fn next_handle(&self) -> Handle {
+        Handle::from_raw(self.attributes.len() as u16 + 1)
+    }
+
+    /// Appends an attribute and extends the last service's group to include it.
+    fn push(&mut self, attr: DynamicAttribute) {
+        if attr.group_end.is_none() {
+            let end = self
+                .attributes
+                .iter_mut()
+                .rev()
+                .find_map(|attr| attr.group_end.as_mut())
+                .expect("characteristic added before any service");
+            end.handle = attr.handle;
+            end.att_type = attr.att_type;
+        }
+
+        self.attributes.push(attr);
+    }
+
+    fn get(&self, handle: Handle) -> Option<&DynamicAttribute> {
+        let index = usize::from(handle.as_u16()).checked_sub(1)?;
+        self.attributes.get(index)
+    }
+}
+
+impl Default for DynamicProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AttributeProvider for DynamicProvider {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        mut f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for attr in &self.attributes {
+            if range.contains(attr.handle) {
+                f(
+                    self,
+                    Attribute {
+                        att_type: attr.att_type,
+                        handle: attr.handle,
+                        value: HexSlice(&attr.value[..]),
+                    },
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        uuid == Uuid16::PRIMARY_SERVICE
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
+        self.get(handle)?.group_end.as_ref().map(|attr| attr.handle)
+    }
+
+    fn read_attr(&mut self, handle: Handle, f: impl FnOnce(&[u8])) -> Result<(), AttError> {
+        let attr = self
+            .get(handle)
+            .ok_or_else(|| AttError::new(ErrorCode::InvalidHandle, handle))?;
+        f(&attr.value);
+        Ok(())
+    }
+
+    fn check_writable(&mut self, handle: Handle) -> Result<(), AttError> {
+        match self.get(handle) {
+            None => Err(AttError::new(ErrorCode::InvalidHandle, handle)),
+            Some(attr) if attr.writable => Ok(()),
+            Some(_) => Err(AttError::new(ErrorCode::WriteNotPermitted, handle)),
+        }
+    }
+
+    fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        if self.get(handle).map(|attr| attr.att_type)
+            == Some(Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION.into())
+            && value.len() != 2
+        {
+            return Err(AttError::new(
+                ErrorCode::InvalidAttributeValueLength,
+                handle,
+            ));
+        }
+
+        let stored = self
+            .value_mut(handle)
+            .ok_or_else(|| AttError::new(ErrorCode::InvalidHandle, handle))?;
+        stored.clear();
+        stored.extend_from_slice(value);
+        Ok(())
+    }
+}
+
+/// Encodes a declaration value using `f`.
+fn encode(f: impl FnOnce(&mut ByteWriter<'_>) -> Result<(), Error>) -> Vec<u8> {
+    // Declarations consist of at most 3 Bytes of properties and handle, and a 16-Byte UUID
+    let mut buf = [0; 19];
+    let mut writer = ByteWriter::new(&mut buf);
+    f(&mut writer).unwrap();
+    let len = writer.bytes_written();
+    buf[..len].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider() -> (DynamicProvider, Handle, Handle) {
+        let mut provider = DynamicProvider::new();
+        provider.add_service(Uuid16(0x180F));
+        let level = provider.add_characteristic(
+            Uuid16(0x2A19),
+            Properties::READ | Properties::NOTIFY,
+            &[100],
+        );
+        provider.add_service(Uuid16(0xAAAA));
+        let name = provider.add_characteristic(
+            Uuid16(0xBBBB),
+            Properties::READ | Properties::WRITE,
+            b"rubble",
+        );
+        (provider, level, name)
+    }
+
+    #[test]
+    fn table_layout() {
+        let (mut provider, level, name) = provider();
+        assert_eq!(level, Handle::from_raw(0x0003));
+        assert_eq!(name, Handle::from_raw(0x0007));
+        provider.validate().unwrap();
+
+        let uuid = |uuid| AttUuid::from(Uuid16(uuid));
+        let mut attrs = std::vec::Vec::new();
+        provider
+            .for_attrs_in_range(
+                HandleRange::new(Handle::from_raw(1), name).unwrap(),
+                |_, attr| {
+                    attrs.push((attr.handle.as_u16(), attr.att_type, attr.value.0.to_vec()));
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            attrs,
+            [
+                (1, uuid(0x2800), vec![0x0F, 0x18]),
+                (2, uuid(0x2803), vec![0x12, 0x03, 0x00, 0x19, 0x2A]),
+                (3, uuid(0x2A19), vec![100]),
+                (4, uuid(0x2902), vec![0, 0]),
+                (5, uuid(0x2800), vec![0xAA, 0xAA]),
+                (6, uuid(0x2803), vec![0x0A, 0x07, 0x00, 0xBB, 0xBB]),
+                (7, uuid(0xBBBB), b"rubble".to_vec()),
+            ]
+        );
+
+        let end = |handle| {
+            provider
+                .group_end(Handle::from_raw(handle))
+                .map(|h| h.as_u16())
+        };
+        assert_eq!(end(1), Some(4));
+        assert_eq!(end(5), Some(7));
+        assert_eq!(end(2), None);
+    }
+
+    #[test]
+    fn writes_update_values() {
+        let (mut provider, level, name) = provider();
+
+        provider.check_writable(name).unwrap();
+        provider.write_attr(name, b"hi").unwrap();
+        assert_eq!(provider.value(name), Some(&b"hi"[..]));
+
+        let cccd = Handle::from_raw(level.as_u16() + 1);
+        provider.check_writable(cccd).unwrap();
+        assert_eq!(
+            provider.write_attr(cccd, &[1]).unwrap_err().error_code(),
+            ErrorCode::InvalidAttributeValueLength
+        );
+        provider.write_attr(cccd, &[1, 0]).unwrap();
+        assert_eq!(provider.value(cccd), Some(&[1, 0][..]));
+
+        assert_eq!(
+            provider.check_writable(level).unwrap_err().error_code(),
+            ErrorCode::WriteNotPermitted
+        );
+        assert_eq!(
+            provider
+                .check_writable(Handle::from_raw(8))
+                .unwrap_err()
+                .error_code(),
+            ErrorCode::InvalidHandle
+        );
+    }
+}
@@ -0,0 +1,412 @@
+//! Reconstruction of the logical GATT structure from a flat attribute table.
+//!
+//! An `AttributeProvider` only exposes a list of attributes. [`services`] walks that list and
+//! parses the service and characteristic declarations in it, returning the services with their
+//! characteristics and descriptors. This is mainly useful for tooling and tests, to check that a
+//! hand-written attribute table describes the intended services.
+//!
+//! This module requires the `alloc` Cargo feature.
+//!
+//! # Example
+//!
+//! ```
+//! use rubble::gatt::{layout, BatteryServiceAttrs};
+//! use rubble::uuid::Uuid16;
+//!
+//! let services = layout::services(&mut BatteryServiceAttrs::new()).unwrap();
+//! assert_eq!(services.len(), 1);
+//! assert_eq!(services[0].uuid, Uuid16::BATTERY_SERVICE);
+//! assert_eq!(services[0].characteristics[0].uuid, Uuid16::BATTERY_LEVEL);
+//! ```
+//!
+//! [`services`]: fn.services.html
+
+use {
+    super::characteristic::Properties,
+    crate::{
+        att::{AttUuid, Attribute, AttributeProvider, Handle, HandleRange},
+        bytes::{ByteReader, FromBytes},
+        uuid::Uuid16,
+        Error,
+    },
+    alloc::vec::Vec,
+};
+
+/// A service found in an attribute table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceInfo {
+    /// Handle of the service declaration.
+    pub handle: Handle,
+
+    /// Handle of the last attribute belonging to the service.
+    pub end_handle: Handle,
+
+    /// Whether this is a primary (`0x2800`) or secondary (`0x2801`) service.
+    pub primary: bool,
+
+    /// The service UUID.
+    pub uuid: AttUuid,
+
+    /// The characteristics of the service, in handle order.
+    pub characteristics: Vec<CharacteristicInfo>,
+}
+
+/// A characteristic found in an attribute table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharacteristicInfo {
+    /// Handle of the characteristic declaration.
+    pub handle: Handle,
+
+    /// Handle of the characteristic value.
+    pub value_handle: Handle,
+
+    /// Properties listed in the declaration.
+    pub properties: Properties,
+
+    /// The characteristic UUID (the type of the value attribute).
+    pub uuid: AttUuid,
+
+    /// The descriptors following the characteristic value, in handle order.
+    pub descriptors: Vec<DescriptorInfo>,
+}
+
+/// A characteristic descriptor found in an attribute table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DescriptorInfo {
+    /// Handle of the descriptor.
+    pub handle: Handle,
+
+    /// The descriptor type (eg. `Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION`).
+    pub uuid: AttUuid,
+}
+
+/// Error returned by `services` when an attribute table doesn't have a valid GATT structure.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LayoutError {
+    /// The value of the service or characteristic declaration at this handle is malformed.
+    MalformedDeclaration(Handle),
+
+    /// The attribute at this handle precedes the first service declaration.
+    OutsideService(Handle),
+
+    /// The characteristic declared at this handle isn't immediately followed by its value (with
+    /// the handle and type given in the declaration).
+    MissingValue(Handle),
+}
+
+/// Reconstructs the services, characteristics and descriptors defined by `provider`.
+///
+/// Both 16- and 128-bit UUIDs are supported. Include declarations (`0x2802`) are skipped.
+pub fn services<A: AttributeProvider>(provider: &mut A) -> Result<Vec<ServiceInfo>, LayoutError> {
+    let mut services = Vec::new();
+    let mut result = Ok(());
+    let _ = provider.for_attrs_in_range(HandleRange::FULL, |_, attr| {
+        if let Err(e) = visit(&mut services, &attr) {
+            result = Err(e);
+            return Err(Error::InvalidValue);
+        }
+        Ok(())
+    });
+    result?;
+
+    // The last characteristic must have a value as well
+    check_value_present(&services, None)?;
+    Ok(services)
+}
+
+/// Adds the attribute `attr` to the structure in `services`.
+fn visit(services: &mut Vec<ServiceInfo>, attr: &Attribute<'_>) -> Result<(), LayoutError> {
+    let handle = attr.handle;
+    let value = attr.value.0;
+
+    let primary = attr.att_type == Uuid16::PRIMARY_SERVICE;
+    if primary || attr.att_type == Uuid16::SECONDARY_SERVICE {
+        check_value_present(services, None)?;
+        let uuid = AttUuid::from_bytes(&mut ByteReader::new(value))
+            .map_err(|_| LayoutError::MalformedDeclaration(handle))?;
+        services.push(ServiceInfo {
+            handle,
+            end_handle: handle,
+            primary,
+            uuid,
+            characteristics: Vec::new(),
+        });
+        return Ok(());
+    }
+
+    // A declaration can't be the value of the previous characteristic
+    let is_declaration =
+        attr.att_type == Uuid16::CHARACTERISTIC || attr.att_type == Uuid16::INCLUDE;
+    check_value_present(services, if is_declaration { None } else { Some(handle) })?;
+    let service = services
+        .last_mut()
+        .ok_or(LayoutError::OutsideService(handle))?;
+    service.end_handle = handle;
+
+    if attr.att_type == Uuid16::CHARACTERISTIC {
+        let (properties, value_handle, uuid) =
+            parse_declaration(value).ok_or(LayoutError::MalformedDeclaration(handle))?;
+        if value_handle.as_u16() != handle.as_u16().wrapping_add(1) {
+            return Err(LayoutError::MissingValue(handle));
+        }
+        service.characteristics.push(CharacteristicInfo {
+            handle,
+            value_handle,
+            properties,
+            uuid,
+            descriptors: Vec::new(),
+        });
+    } else if attr.att_type == Uuid16::INCLUDE {
+        // Includes come before all characteristics and aren't reported
+    } else if let Some(ch) = service.characteristics.last_mut() {
+        if handle == ch.value_handle {
+            if attr.att_type != ch.uuid {
+                return Err(LayoutError::MissingValue(ch.handle));
+            }
+        } else {
+            ch.descriptors.push(DescriptorInfo {
+                handle,
+                uuid: attr.att_type,
+            });
+        }
+    }
+    // Other attributes between the service declaration and the first characteristic don't belong
+    // to anything and are ignored
+
+    Ok(())
+}
+
+/// Checks that the value of the last characteristic in `services` was seen, or is the next
+/// attribute (at handle `next`).
+fn check_value_present(services: &[ServiceInfo], next: Option<Handle>) -> Result<(), LayoutError> {
+    if let Some(service) = services.last() {
+        if let Some(ch) = service.characteristics.last() {
+            let seen = ch.value_handle.as_u16() <= service.end_handle.as_u16();
+            if !seen && next != Some(ch.value_handle) {
+                return Err(LayoutError::MissingValue(ch.handle));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses the value of a characteristic declaration into properties, value handle and UUID.
+fn parse_declaration(value: &[u8]) -> Option<(Properties, Handle, AttUuid)> {
+    let mut bytes = ByteReader::new(value);
+    let props = Properties::from_bits_truncate(bytes.read_u8().ok()?);
+    let value_handle = Handle::from_raw(bytes.read_u16_le().ok()?);
+    let uuid = AttUuid::from_bytes(&mut bytes).ok()?;
+    Some((props, value_handle, uuid))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            att::NoAttributes,
+            gatt::{server::GattServer, BatteryServiceAttrs, NordicUartAttrs},
+            utils::HexSlice,
+            uuid::Uuid,
+        },
+        heapless::consts::U16,
+    };
+
+    /// An attribute table without any checks.
+    struct Table(Vec<Attribute<'static>>);
+
+    impl Table {
+        fn new(attrs: &[(u16, u16, &'static [u8])]) -> Self {
+            Table(
+                attrs
+                    .iter()
+                    .map(|&(handle, uuid, value)| Attribute {
+                        att_type: Uuid16(uuid).into(),
+                        handle: Handle::from_raw(handle),
+                        value: HexSlice(value),
+                    })
+                    .collect(),
+            )
+        }
+    }
+
+    impl AttributeProvider for Table {
+        fn for_attrs_in_range(
+            &mut self,
+            range: HandleRange,
+            mut f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            for attr in &self.0 {
+                if range.contains(attr.handle) {
+                    f(
+                        self,
+                        Attribute {
+                            att_type: attr.att_type,
+                            handle: attr.handle,
+                            value: attr.value,
+                        },
+                    )?;
+                }
+            }
+            Ok(())
+        }
+
+        fn is_grouping_attr(&self, _uuid: AttUuid) -> bool {
+            false
+        }
+
+        fn group_end(&self, _handle: Handle) -> Option<Handle> {
+            None
+        }
+    }
+
+    fn handle(raw: u16) -> Handle {
+        Handle::from_raw(raw)
+    }
+
+    #[test]
+    fn demo_tables() {
+        let battery = services(&mut BatteryServiceAttrs::new()).unwrap();
+        assert_eq!(
+            battery,
+            [ServiceInfo {
+                handle: handle(1),
+                end_handle: handle(3),
+                primary: true,
+                uuid: Uuid16::BATTERY_SERVICE.into(),
+                characteristics: [CharacteristicInfo {
+                    handle: handle(2),
+                    value_handle: handle(3),
+                    properties: Properties::READ,
+                    uuid: Uuid16::BATTERY_LEVEL.into(),
+                    descriptors: Vec::new(),
+                }]
+                .to_vec(),
+            }]
+        );
+
+        let uart = services(&mut NordicUartAttrs::new()).unwrap();
+        assert_eq!(uart.len(), 1);
+        let uart = &uart[0];
+        assert_eq!(
+            uart.uuid,
+            Uuid::from_u128_le(0x6E400001_B5A3_F393_E0A9_E50E24DCCA9E)
+        );
+        assert_eq!((uart.handle, uart.end_handle), (handle(1), handle(6)));
+        let chars = &uart.characteristics;
+        assert_eq!(chars.len(), 2);
+        assert_eq!(
+            chars[0].uuid,
+            Uuid::from_u128_le(0x6E400002_B5A3_F393_E0A9_E50E24DCCA9E)
+        );
+        assert_eq!(
+            chars[0].properties,
+            Properties::WRITE | Properties::WRITE_NO_RSP
+        );
+        assert!(chars[0].descriptors.is_empty());
+        assert_eq!(
+            chars[1].uuid,
+            Uuid::from_u128_le(0x6E400003_B5A3_F393_E0A9_E50E24DCCA9E)
+        );
+        assert_eq!(chars[1].value_handle, NordicUartAttrs::TX_HANDLE);
+        assert_eq!(
+            chars[1].descriptors,
+            [DescriptorInfo {
+                handle: NordicUartAttrs::TX_CCCD_HANDLE,
+                uuid: Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION.into(),
+            }]
+        );
+
+        assert_eq!(services(&mut NoAttributes), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn gatt_server_table() {
+        let nus = Uuid::from_u128_le(0x6E400001_B5A3_F393_E0A9_E50E24DCCA9E);
+        let mut server = GattServer::<U16>::new();
+        server.add_service(Uuid16::BATTERY_SERVICE).unwrap();
+        let level = server
+            .add_characteristic(
+                Uuid16::BATTERY_LEVEL,
+                Properties::READ | Properties::NOTIFY,
+                None,
+                None,
+            )
+            .unwrap();
+        server.add_service(nus).unwrap();
+        server
+            .add_characteristic(nus, Properties::WRITE, None, None)
+            .unwrap();
+
+        let layout = services(&mut server).unwrap();
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[0].characteristics[0].value_handle, level);
+        assert_eq!(
+            layout[0].characteristics[0].descriptors[0].uuid,
+            Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION
+        );
+        assert_eq!(layout[1].uuid, nus);
+        assert_eq!(layout[1].characteristics[0].uuid, nus);
+        assert_eq!(layout[0].end_handle.as_u16() + 1, layout[1].handle.as_u16());
+    }
+
+    #[test]
+    fn invalid_tables() {
+        // Characteristic before any service
+        let mut table = Table::new(&[(1, 0x2803, &[0x02, 0x02, 0x00, 0x19, 0x2A])]);
+        assert_eq!(
+            services(&mut table),
+            Err(LayoutError::OutsideService(handle(1)))
+        );
+
+        // Truncated service and characteristic declarations
+        let mut table = Table::new(&[(1, 0x2800, &[0x0F])]);
+        assert_eq!(
+            services(&mut table),
+            Err(LayoutError::MalformedDeclaration(handle(1)))
+        );
+        let mut table = Table::new(&[(1, 0x2800, &[0x0F, 0x18]), (2, 0x2803, &[0x02, 0x03])]);
+        assert_eq!(
+            services(&mut table),
+            Err(LayoutError::MalformedDeclaration(handle(2)))
+        );
+
+        // Value with the wrong type, at the wrong handle, or missing entirely
+        let decl = &[0x02, 0x03, 0x00, 0x19, 0x2A];
+        for table in &mut [
+            Table::new(&[
+                (1, 0x2800, &[0x0F, 0x18]),
+                (2, 0x2803, decl),
+                (3, 0x2A00, &[]),
+            ]),
+            Table::new(&[
+                (1, 0x2800, &[0x0F, 0x18]),
+                (2, 0x2803, decl),
+                (4, 0x2A19, &[]),
+            ]),
+            Table::new(&[(1, 0x2800, &[0x0F, 0x18]), (2, 0x2803, decl)]),
+            Table::new(&[
+                (1, 0x2800, &[0x0F, 0x18]),
+                (2, 0x2803, decl),
+                (3, 0x2800, &[0x0A, 0x18]),
+            ]),
+            Table::new(&[
+                (1, 0x2800, &[0x0F, 0x18]),
+                (2, 0x2803, decl),
+                (3, 0x2803, &[0x02, 0x04, 0x00, 0x19, 0x2A]),
+                (4, 0x2A19, &[]),
+            ]),
+            Table::new(&[
+                (1, 0x2800, &[0x0F, 0x18]),
+                (2, 0x2803, &[0x02, 0x04, 0x00, 0x19, 0x2A]),
+                (3, 0x2A19, &[]),
+            ]),
+        ] {
+            assert_eq!(services(table), Err(LayoutError::MissingValue(handle(2))));
+        }
+
+        // Secondary services are reported as such
+        let mut table = Table::new(&[(1, 0x2801, &[0x0F, 0x18])]);
+        assert!(!services(&mut table).unwrap()[0].primary);
+    }
+}
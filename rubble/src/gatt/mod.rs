@@ -3,108 +3,559 @@
 //! GATT describes a service framework that uses the Attribute Protocol for discovery and
 //! interaction
 
+pub mod caching;
+pub mod cccd;
 pub mod characteristic;
+pub mod composite;
+#[cfg(feature = "alloc")]
+pub mod dynamic;
+#[cfg(feature = "alloc")]
+pub mod layout;
+pub mod sccd;
+pub mod server;
 
 use {
+    self::{
+        cccd::{Cccd, ClientConfigs},
+        characteristic::Properties,
+        composite::StaticAttributes,
+    },
     crate::{
-        att::{AttUuid, Attribute, AttributeProvider, Handle, HandleRange},
+        att::{
+            self, AttError, AttUuid, Attribute, AttributeProvider, AttributeServerTx, ErrorCode,
+            Handle, HandleRange, MAX_VALUE_LEN,
+        },
+        l2cap::TxTicket,
         utils::HexSlice,
         uuid::{Uuid, Uuid16},
         Error,
     },
     core::{cmp, slice},
+    heapless::{consts::U160, spsc::Queue, Vec},
 };
 
 /// A demo `AttributeProvider` that will enumerate as a *Battery Service*.
-pub struct BatteryServiceAttrs {
+///
+/// The *Battery Level* is obtained from `get_level` whenever the client reads it, so it can be
+/// measured on demand (eg. using an ADC). By default, a fixed level of 48% is reported.
+pub struct BatteryServiceAttrs<F = fn() -> u8> {
     attributes: [Attribute<'static>; 3],
+    level: [u8; 1],
+    get_level: F,
 }
 
+const BATTERY_SERVICE_ATTRS: [Attribute<'static>; 3] = [
+    Attribute {
+        att_type: AttUuid::Uuid16(Uuid16::PRIMARY_SERVICE),
+        handle: Handle::from_raw(0x0001),
+        value: HexSlice(&[0x0F, 0x18]), // "Battery Service" = 0x180F
+    },
+    Attribute {
+        att_type: AttUuid::Uuid16(Uuid16::CHARACTERISTIC),
+        handle: Handle::from_raw(0x0002),
+        value: HexSlice(&[
+            0x02, // 1 byte properties: READ = 0x02
+            0x03, 0x00, // 2 bytes handle = 0x0003
+            0x19, 0x2A, // 2 bytes UUID = 0x2A19 (Battery Level)
+        ]),
+    },
+    // Characteristic value (Battery Level). The value is obtained from `get_level` in
+    // `update_values`.
+    Attribute {
+        att_type: AttUuid::Uuid16(Uuid16::BATTERY_LEVEL),
+        handle: Handle::from_raw(0x0003),
+        value: HexSlice(&[]),
+    },
+];
+
 impl BatteryServiceAttrs {
+    /// Handle of the *Battery Level* characteristic value.
+    pub const BATTERY_LEVEL_HANDLE: Handle = Handle::from_raw(0x0003);
+
+    /// Creates a Battery Service reporting a fixed battery level of 48%.
     pub fn new() -> Self {
+        Self::with_level_source(|| 48)
+    }
+}
+
+impl<F: FnMut() -> u8> BatteryServiceAttrs<F> {
+    /// Creates a Battery Service that calls `get_level` to obtain the battery level in percent.
+    ///
+    /// `get_level` is called every time the client reads the level. Values above 100 are reported
+    /// as 100%.
+    pub fn with_level_source(get_level: F) -> Self {
+        assert_attribute_table!(BATTERY_SERVICE_ATTRS);
+
+        Self {
+            attributes: BATTERY_SERVICE_ATTRS,
+            level: [0],
+            get_level,
+        }
+    }
+}
+
+impl<F: FnMut() -> u8> AttributeProvider for BatteryServiceAttrs<F> {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        composite::for_attrs_in_range(self, range, f)
+    }
+
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        uuid == Uuid16::PRIMARY_SERVICE // FIXME not characteristics?
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
+        match handle.as_u16() {
+            0x0001 => Some(self.attributes[2].handle),
+            0x0002 => Some(self.attributes[2].handle),
+            _ => None,
+        }
+    }
+
+    fn check_writable(&mut self, handle: Handle) -> Result<(), AttError> {
+        check_declared_writable(self, handle)
+    }
+}
+
+impl<F: FnMut() -> u8> StaticAttributes for BatteryServiceAttrs<F> {
+    const HANDLE_COUNT: u16 = 3;
+
+    fn update_values(&mut self, range: &HandleRange) {
+        if range.contains(BatteryServiceAttrs::BATTERY_LEVEL_HANDLE) {
+            self.level = [cmp::min((self.get_level)(), 100)];
+        }
+    }
+
+    fn for_static_attrs(
+        &self,
+        range: &HandleRange,
+        mut f: impl FnMut(Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for attr in attrs_in_range(&self.attributes, range) {
+            let value = if attr.handle == BatteryServiceAttrs::BATTERY_LEVEL_HANDLE {
+                HexSlice(&self.level[..])
+            } else {
+                attr.value
+            };
+
+            f(Attribute {
+                att_type: attr.att_type,
+                handle: attr.handle,
+                value,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks whether the client may write to the attribute at `handle`.
+///
+/// In addition to the checks done by the default `AttributeProvider::check_writable`, a
+/// characteristic value is only writable if its declaration has the *Write* or *Write Without
+/// Response* property set.
+fn check_declared_writable<A: AttributeProvider>(
+    attrs: &mut A,
+    handle: Handle,
+) -> Result<(), AttError> {
+    att::check_writable_type(attrs, handle)?;
+
+    let decl_handle = match handle.as_u16().checked_sub(1) {
+        Some(0) | None => return Ok(()),
+        Some(raw) => Handle::from_raw(raw),
+    };
+    let range = HandleRange::new(decl_handle, decl_handle).unwrap();
+
+    let mut props = None;
+    attrs
+        .for_attrs_in_range(range, |_, attr| {
+            let value = attr.value.as_ref();
+            if attr.att_type == AttUuid::from(Uuid16::CHARACTERISTIC)
+                && value.len() >= 3
+                && u16::from_le_bytes([value[1], value[2]]) == handle.as_u16()
+            {
+                props = Some(Properties::from_bits_truncate(value[0]));
+            }
+            Ok(())
+        })
+        .ok();
+
+    match props {
+        Some(props) if !props.intersects(Properties::WRITE | Properties::WRITE_NO_RSP) => {
+            Err(AttError::new(ErrorCode::WriteNotPermitted, handle))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns the part of `attributes` whose handles are inside `range`.
+///
+/// The attribute at index `i` must have handle `i + 1`. Parts of `range` that lie past the last
+/// attribute are ignored, so an empty `attributes` slice always results in an empty slice.
+fn attrs_in_range<'a, 'b>(
+    attributes: &'a [Attribute<'b>],
+    range: &HandleRange,
+) -> &'a [Attribute<'b>] {
+    // Handles start at 1, not 0
+    let start = usize::from(range.start().as_u16()).saturating_sub(1);
+    let end = cmp::min(usize::from(range.end().as_u16()), attributes.len());
+    attributes.get(start..end).unwrap_or(&[])
+}
+
+pub struct Attributes<'a> {
+    to_yield: slice::Iter<'a, Attribute<'a>>,
+}
+
+impl<'a> Iterator for Attributes<'a> {
+    type Item = Attribute<'a>;
+
+    fn next(&mut self) -> Option<Attribute<'a>> {
+        self.to_yield.next().map(|attr| Attribute {
+            att_type: attr.att_type,
+            handle: attr.handle,
+            value: attr.value,
+        })
+    }
+}
+
+/// An `AttributeProvider` for the *Generic Access* service (GAP, `0x1800`).
+///
+/// Every GATT server should host this service. It exposes the *Device Name* and *Appearance*
+/// characteristics. The device name may be longer than what fits in a single ATT PDU, in which case
+/// clients use *Read Blob Requests* to fetch the rest.
+pub struct GapServiceAttrs<'a> {
+    attributes: [Attribute<'a>; 5],
+    appearance: [u8; 2],
+    device_name: DeviceName<'a>,
+}
+
+/// Storage of the *Device Name* characteristic value.
+enum DeviceName<'a> {
+    /// A name that only the application can change.
+    Fixed(&'a [u8]),
+    /// A name that clients can write. The current name is `buf[..len]`.
+    Writable { buf: &'a mut [u8], len: usize },
+}
+
+impl DeviceName<'_> {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            DeviceName::Fixed(name) => name,
+            DeviceName::Writable { buf, len } => &buf[..*len],
+        }
+    }
+}
+
+impl<'a> GapServiceAttrs<'a> {
+    /// Handle of the *Device Name* characteristic value.
+    pub const DEVICE_NAME_HANDLE: Handle = Handle::from_raw(0x0005);
+
+    /// Handle of the *Appearance* characteristic value.
+    pub const APPEARANCE_HANDLE: Handle = Handle::from_raw(0x0003);
+
+    /// Creates a GAP service with a read-only device name.
+    ///
+    /// `appearance` is a value from the SIG's assigned numbers list (eg. `0x0000` for "Unknown").
+    pub fn new(device_name: &'a str, appearance: u16) -> Self {
+        Self::with_name(
+            DeviceName::Fixed(device_name.as_bytes()),
+            appearance,
+            &NAME_DECL_READ,
+        )
+    }
+
+    /// Creates a GAP service whose device name can be written by clients.
+    ///
+    /// The device name is stored in `name_buf`, and its first `name_len` Bytes are the initial
+    /// name. Clients may write names up to the length of `name_buf`, longer names are rejected with
+    /// an *Invalid Attribute Value Length* error. Use `device_name` to get the current name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name_len` is larger than the length of `name_buf`.
+    pub fn writable(name_buf: &'a mut [u8], name_len: usize, appearance: u16) -> Self {
+        assert!(
+            name_len <= name_buf.len(),
+            "device name longer than its buffer"
+        );
+        Self::with_name(
+            DeviceName::Writable {
+                buf: name_buf,
+                len: name_len,
+            },
+            appearance,
+            &NAME_DECL_READ_WRITE,
+        )
+    }
+
+    fn with_name(device_name: DeviceName<'a>, appearance: u16, name_decl: &'static [u8]) -> Self {
         Self {
             attributes: [
                 Attribute {
-                    att_type: Uuid16(0x2800).into(), // "Primary Service"
+                    att_type: Uuid16::PRIMARY_SERVICE.into(),
                     handle: Handle::from_raw(0x0001),
-                    value: HexSlice(&[0x0F, 0x18]), // "Battery Service" = 0x180F
+                    value: HexSlice(&[0x00, 0x18]), // "Generic Access" = 0x1800
                 },
                 Attribute {
-                    att_type: Uuid16(0x2803).into(), // "Characteristic"
+                    att_type: Uuid16::CHARACTERISTIC.into(),
                     handle: Handle::from_raw(0x0002),
                     value: HexSlice(&[
                         0x02, // 1 byte properties: READ = 0x02
                         0x03, 0x00, // 2 bytes handle = 0x0003
-                        0x19, 0x2A, // 2 bytes UUID = 0x2A19 (Battery Level)
+                        0x01, 0x2A, // 2 bytes UUID = 0x2A01 (Appearance)
                     ]),
                 },
-                // Characteristic value (Battery Level)
+                // Characteristic value (Appearance). The value is stored in `self.appearance` and
+                // filled in by `for_static_attrs`.
                 Attribute {
-                    att_type: AttUuid::Uuid16(Uuid16(0x2A19)), // "Battery Level"
-                    handle: Handle::from_raw(0x0003),
-                    value: HexSlice(&[48u8]),
+                    att_type: AttUuid::Uuid16(Uuid16::APPEARANCE),
+                    handle: Self::APPEARANCE_HANDLE,
+                    value: HexSlice(&[]),
+                },
+                Attribute {
+                    att_type: Uuid16::CHARACTERISTIC.into(),
+                    handle: Handle::from_raw(0x0004),
+                    value: HexSlice(name_decl),
+                },
+                // Characteristic value (Device Name). The value is stored in `self.device_name`
+                // and filled in by `for_static_attrs`.
+                Attribute {
+                    att_type: AttUuid::Uuid16(Uuid16::DEVICE_NAME),
+                    handle: Self::DEVICE_NAME_HANDLE,
+                    value: HexSlice(&[]),
                 },
             ],
+            appearance: appearance.to_le_bytes(),
+            device_name,
+        }
+    }
+
+    /// Returns the current device name.
+    ///
+    /// If the device name is writable, this is the name last written by a client (or set with
+    /// `set_device_name`). Note that clients aren't required to write valid UTF-8.
+    pub fn device_name(&self) -> &[u8] {
+        self.device_name.as_bytes()
+    }
+
+    /// Changes the device name reported to clients.
+    ///
+    /// If the device name is writable, `device_name` is copied into its buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the device name is writable and `device_name` doesn't fit in its buffer.
+    pub fn set_device_name(&mut self, device_name: &'a str) {
+        let name = device_name.as_bytes();
+        match &mut self.device_name {
+            DeviceName::Fixed(fixed) => *fixed = name,
+            DeviceName::Writable { buf, len } => {
+                buf[..name.len()].copy_from_slice(name);
+                *len = name.len();
+            }
         }
     }
+
+    /// Changes the appearance value reported to clients.
+    pub fn set_appearance(&mut self, appearance: u16) {
+        self.appearance = appearance.to_le_bytes();
+    }
 }
 
-impl AttributeProvider for BatteryServiceAttrs {
+// Characteristic declarations of the Device Name
+const NAME_DECL_READ: [u8; 5] = [
+    0x02, // 1 byte properties: READ = 0x02
+    0x05, 0x00, // 2 bytes handle = 0x0005
+    0x00, 0x2A, // 2 bytes UUID = 0x2A00 (Device Name)
+];
+const NAME_DECL_READ_WRITE: [u8; 5] = [
+    0x02 | 0x08, // 1 byte properties: READ = 0x02, WRITE_REQ = 0x08
+    0x05,
+    0x00, // 2 bytes handle = 0x0005
+    0x00,
+    0x2A, // 2 bytes UUID = 0x2A00 (Device Name)
+];
+
+impl AttributeProvider for GapServiceAttrs<'_> {
     fn for_attrs_in_range(
         &mut self,
         range: HandleRange,
-        mut f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+        f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
     ) -> Result<(), Error> {
-        let count = self.attributes.len();
-        let start = usize::from(range.start().as_u16() - 1); // handles start at 1, not 0
-        let end = usize::from(range.end().as_u16() - 1);
+        composite::for_attrs_in_range(self, range, f)
+    }
 
-        let attrs = if start >= count {
-            &[]
-        } else {
-            let end = cmp::min(count - 1, end);
-            &self.attributes[start..=end]
-        };
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        uuid == Uuid16::PRIMARY_SERVICE // FIXME not characteristics?
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
+        match handle.as_u16() {
+            0x0001 => Some(self.attributes[4].handle),
+            0x0004 => Some(self.attributes[4].handle),
+            _ => None,
+        }
+    }
+
+    fn max_value_len(&self, handle: Handle) -> usize {
+        match &self.device_name {
+            DeviceName::Writable { buf, .. } if handle == Self::DEVICE_NAME_HANDLE => buf.len(),
+            _ => MAX_VALUE_LEN,
+        }
+    }
+
+    fn check_writable(&mut self, handle: Handle) -> Result<(), AttError> {
+        check_declared_writable(self, handle)
+    }
+
+    fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        match &mut self.device_name {
+            DeviceName::Writable { buf, len } if handle == Self::DEVICE_NAME_HANDLE => {
+                if value.len() > buf.len() {
+                    return Err(AttError::new(
+                        ErrorCode::InvalidAttributeValueLength,
+                        handle,
+                    ));
+                }
+                buf[..value.len()].copy_from_slice(value);
+                *len = value.len();
+                Ok(())
+            }
+            _ => Err(AttError::new(ErrorCode::WriteNotPermitted, handle)),
+        }
+    }
+}
+
+impl StaticAttributes for GapServiceAttrs<'_> {
+    const HANDLE_COUNT: u16 = 5;
+
+    fn for_static_attrs(
+        &self,
+        range: &HandleRange,
+        mut f: impl FnMut(Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for attr in attrs_in_range(&self.attributes, range) {
+            let value = if attr.handle == Self::APPEARANCE_HANDLE {
+                HexSlice(&self.appearance[..])
+            } else if attr.handle == Self::DEVICE_NAME_HANDLE {
+                HexSlice(self.device_name.as_bytes())
+            } else {
+                attr.value
+            };
+
+            f(Attribute {
+                att_type: attr.att_type,
+                handle: attr.handle,
+                value,
+            })?;
+        }
+        Ok(())
+    }
+}
 
-        for attr in attrs {
-            f(
-                self,
+/// An `AttributeProvider` for the *Device Information* service (`0x180A`).
+///
+/// Exposes the *Manufacturer Name String* and *Model Number String* characteristics, which clients
+/// often show to the user when listing devices. Both are read-only.
+pub struct DeviceInfoAttrs<'a> {
+    attributes: [Attribute<'a>; 5],
+}
+
+impl<'a> DeviceInfoAttrs<'a> {
+    /// Handle of the *Manufacturer Name String* characteristic value.
+    pub const MANUFACTURER_NAME_HANDLE: Handle = Handle::from_raw(0x0003);
+
+    /// Handle of the *Model Number String* characteristic value.
+    pub const MODEL_NUMBER_HANDLE: Handle = Handle::from_raw(0x0005);
+
+    /// Creates a Device Information service reporting the given manufacturer name and model
+    /// number.
+    pub fn new(manufacturer_name: &'a str, model_number: &'a str) -> Self {
+        Self {
+            attributes: [
+                Attribute {
+                    att_type: Uuid16::PRIMARY_SERVICE.into(),
+                    handle: Handle::from_raw(0x0001),
+                    value: HexSlice(&[0x0A, 0x18]), // "Device Information" = 0x180A
+                },
+                Attribute {
+                    att_type: Uuid16::CHARACTERISTIC.into(),
+                    handle: Handle::from_raw(0x0002),
+                    value: HexSlice(&[
+                        0x02, // 1 byte properties: READ = 0x02
+                        0x03, 0x00, // 2 bytes handle = 0x0003
+                        0x29, 0x2A, // 2 bytes UUID = 0x2A29 (Manufacturer Name String)
+                    ]),
+                },
+                // Characteristic value (Manufacturer Name String)
                 Attribute {
-                    att_type: attr.att_type,
-                    handle: attr.handle,
-                    value: attr.value,
+                    att_type: AttUuid::Uuid16(Uuid16::MANUFACTURER_NAME_STRING),
+                    handle: Self::MANUFACTURER_NAME_HANDLE,
+                    value: HexSlice(manufacturer_name.as_bytes()),
                 },
-            )?;
+                Attribute {
+                    att_type: Uuid16::CHARACTERISTIC.into(),
+                    handle: Handle::from_raw(0x0004),
+                    value: HexSlice(&[
+                        0x02, // 1 byte properties: READ = 0x02
+                        0x05, 0x00, // 2 bytes handle = 0x0005
+                        0x24, 0x2A, // 2 bytes UUID = 0x2A24 (Model Number String)
+                    ]),
+                },
+                // Characteristic value (Model Number String)
+                Attribute {
+                    att_type: AttUuid::Uuid16(Uuid16::MODEL_NUMBER_STRING),
+                    handle: Self::MODEL_NUMBER_HANDLE,
+                    value: HexSlice(model_number.as_bytes()),
+                },
+            ],
         }
-        Ok(())
+    }
+}
+
+impl AttributeProvider for DeviceInfoAttrs<'_> {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        composite::for_attrs_in_range(self, range, f)
     }
 
     fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
-        uuid == Uuid16(0x2800) // FIXME not characteristics?
+        uuid == Uuid16::PRIMARY_SERVICE
     }
 
-    fn group_end(&self, handle: Handle) -> Option<&Attribute<'_>> {
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
         match handle.as_u16() {
-            0x0001 => Some(&self.attributes[2]),
-            0x0002 => Some(&self.attributes[2]),
+            0x0001 => Some(self.attributes[4].handle),
             _ => None,
         }
     }
-}
 
-pub struct Attributes<'a> {
-    to_yield: slice::Iter<'a, Attribute<'a>>,
+    fn check_writable(&mut self, handle: Handle) -> Result<(), AttError> {
+        check_declared_writable(self, handle)
+    }
 }
 
-impl<'a> Iterator for Attributes<'a> {
-    type Item = Attribute<'a>;
+impl StaticAttributes for DeviceInfoAttrs<'_> {
+    const HANDLE_COUNT: u16 = 5;
 
-    fn next(&mut self) -> Option<Attribute<'a>> {
-        self.to_yield.next().map(|attr| Attribute {
-            att_type: attr.att_type,
-            handle: attr.handle,
-            value: attr.value,
-        })
+    fn for_static_attrs(
+        &self,
+        range: &HandleRange,
+        mut f: impl FnMut(Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for attr in attrs_in_range(&self.attributes, range) {
+            f(Attribute {
+                att_type: attr.att_type,
+                handle: attr.handle,
+                value: attr.value,
+            })?;
+        }
+        Ok(())
     }
 }
 
@@ -123,7 +574,7 @@ impl MidiServiceAttrs {
         Self {
             attributes: [
                 Attribute {
-                    att_type: Uuid16(0x2800).into(), // "Primary Service"
+                    att_type: Uuid16::PRIMARY_SERVICE.into(),
                     handle: Handle::from_raw(0x0001),
                     value: HexSlice(&[
                         0x00, 0xC7, 0xC4, 0x4E, 0xE3, 0x6C, /* - */
@@ -134,13 +585,13 @@ impl MidiServiceAttrs {
                     ]), // "Midi Service"
                 },
                 Attribute {
-                    att_type: Uuid16(0x2803).into(), // "Characteristic"
+                    att_type: Uuid16::CHARACTERISTIC.into(),
                     handle: Handle::from_raw(0x0002),
                     value: HexSlice(&[
                         0x02 | 0x08 | 0x04 | 0x10, // 1 byte properties: READ = 0x02, WRITE_REQ = 0x08, WRITE_CMD = 0x04, NOTIFICATION = 0x10
                         0x03,
                         0x00, // 2 bytes handle = 0x0003
-                        // the actual UUID
+                        // the actual UUID (little-endian, as transmitted)
                         0xF3,
                         0x6B,
                         0x10,
@@ -161,19 +612,15 @@ impl MidiServiceAttrs {
                 },
                 // Characteristic value (Empty Packet)
                 Attribute {
-                    att_type: AttUuid::Uuid128(Uuid::from_bytes([
-                        0xF3, 0x6B, 0x10, 0x9D, 0x66, 0xF2, /*-*/
-                        0xA9, 0xA1, /*-*/
-                        0x12, 0x41, /*-*/
-                        0x68, 0x38, /*-*/
-                        0xDB, 0xE5, 0x72, 0x77,
-                    ])),
+                    att_type: AttUuid::Uuid128(Uuid::from_u128_le(
+                        0x7772E5DB_3868_4112_A1A9_F2669D106BF3,
+                    )),
                     handle: Handle::from_raw(0x0003),
                     value: HexSlice(&[]),
                 },
                 // CCCD
                 Attribute {
-                    att_type: AttUuid::Uuid16(Uuid16(0x2902)),
+                    att_type: AttUuid::Uuid16(Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION),
                     handle: Handle::from_raw(0x0004),
                     value: HexSlice(&[0x00, 0x00]),
                 },
@@ -186,41 +633,577 @@ impl AttributeProvider for MidiServiceAttrs {
     fn for_attrs_in_range(
         &mut self,
         range: HandleRange,
-        mut f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+        f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
     ) -> Result<(), Error> {
-        let count = self.attributes.len();
-        let start = usize::from(range.start().as_u16() - 1); // handles start at 1, not 0
-        let end = usize::from(range.end().as_u16() - 1);
+        composite::for_attrs_in_range(self, range, f)
+    }
 
-        let attrs = if start >= count {
-            &[]
-        } else {
-            let end = cmp::min(count - 1, end);
-            &self.attributes[start..=end]
-        };
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        uuid == Uuid16::PRIMARY_SERVICE // FIXME not characteristics?
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
+        match handle.as_u16() {
+            0x0001 => Some(self.attributes[3].handle),
+            0x0002 => Some(self.attributes[3].handle),
+            _ => None,
+        }
+    }
+
+    fn check_writable(&mut self, handle: Handle) -> Result<(), AttError> {
+        check_declared_writable(self, handle)
+    }
+
+    fn write_attr(&mut self, _handle: Handle, _value: &[u8]) -> Result<(), AttError> {
+        // Written MIDI packets and CCCD values are dropped
+        Ok(())
+    }
+}
 
-        for attr in attrs {
-            f(
-                self,
+impl StaticAttributes for MidiServiceAttrs {
+    const HANDLE_COUNT: u16 = 4;
+
+    fn for_static_attrs(
+        &self,
+        range: &HandleRange,
+        mut f: impl FnMut(Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for attr in attrs_in_range(&self.attributes, range) {
+            f(Attribute {
+                att_type: attr.att_type,
+                handle: attr.handle,
+                value: attr.value,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Error returned by [`NordicUartAttrs::send_all`].
+///
+/// [`NordicUartAttrs::send_all`]: struct.NordicUartAttrs.html#method.send_all
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SendError {
+    /// The client has not enabled notifications on the TX characteristic.
+    NotSubscribed,
+
+    /// There is not enough space in the notification queue to hold all of the data.
+    ///
+    /// No data was enqueued.
+    QueueFull,
+}
+
+/// A demo `AttributeProvider` that will enumerate as a *Nordic UART Service* (NUS).
+///
+/// The service has an RX characteristic that the client writes to, and a TX characteristic that
+/// the server uses to send data to the client via notifications.
+pub struct NordicUartAttrs {
+    attributes: [Attribute<'static>; 6],
+    tx_subscribed: bool,
+    /// Data waiting to be sent via notifications.
+    ///
+    /// This fits 8 notifications with the default `ATT_MTU` of 23 Bytes, which leaves 20 Bytes for
+    /// the attribute value after the opcode and handle.
+    tx_queue: Queue<u8, U160>,
+}
+
+// Nordic UART Service (UUID: 6E400001-B5A3-F393-E0A9-E50E24DCCA9E)
+// RX Characteristic (UUID: 6E400002-B5A3-F393-E0A9-E50E24DCCA9E)
+// TX Characteristic (UUID: 6E400003-B5A3-F393-E0A9-E50E24DCCA9E)
+
+impl NordicUartAttrs {
+    /// Handle of the TX characteristic value, which is the target of notifications.
+    pub const TX_HANDLE: Handle = Handle::from_raw(0x0005);
+
+    /// Handle of the CCCD of the TX characteristic.
+    pub const TX_CCCD_HANDLE: Handle = Handle::from_raw(0x0006);
+
+    pub fn new() -> Self {
+        Self {
+            attributes: [
                 Attribute {
-                    att_type: attr.att_type,
-                    handle: attr.handle,
-                    value: attr.value,
+                    att_type: Uuid16::PRIMARY_SERVICE.into(),
+                    handle: Handle::from_raw(0x0001),
+                    value: HexSlice(&[
+                        0x9E, 0xCA, 0xDC, 0x24, 0x0E, 0xE5, /* - */
+                        0xA9, 0xE0, /* - */
+                        0x93, 0xF3, /* - */
+                        0xA3, 0xB5, /* - */
+                        0x01, 0x00, 0x40, 0x6E,
+                    ]), // "Nordic UART Service"
                 },
-            )?;
+                Attribute {
+                    att_type: Uuid16::CHARACTERISTIC.into(),
+                    handle: Handle::from_raw(0x0002),
+                    value: HexSlice(&[
+                        0x08 | 0x04, // 1 byte properties: WRITE_REQ = 0x08, WRITE_CMD = 0x04
+                        0x03,
+                        0x00, // 2 bytes handle = 0x0003
+                        // the actual UUID (little-endian, as transmitted)
+                        0x9E,
+                        0xCA,
+                        0xDC,
+                        0x24,
+                        0x0E,
+                        0xE5, /* - */
+                        0xA9,
+                        0xE0, /* - */
+                        0x93,
+                        0xF3, /* - */
+                        0xA3,
+                        0xB5, /* - */
+                        0x02,
+                        0x00,
+                        0x40,
+                        0x6E,
+                    ]),
+                },
+                // Characteristic value (RX)
+                Attribute {
+                    att_type: AttUuid::Uuid128(Uuid::from_u128_le(
+                        0x6E400002_B5A3_F393_E0A9_E50E24DCCA9E,
+                    )),
+                    handle: Handle::from_raw(0x0003),
+                    value: HexSlice(&[]),
+                },
+                Attribute {
+                    att_type: Uuid16::CHARACTERISTIC.into(),
+                    handle: Handle::from_raw(0x0004),
+                    value: HexSlice(&[
+                        0x10, // 1 byte properties: NOTIFICATION = 0x10
+                        0x05, 0x00, // 2 bytes handle = 0x0005
+                        // the actual UUID (little-endian, as transmitted)
+                        0x9E, 0xCA, 0xDC, 0x24, 0x0E, 0xE5, /* - */
+                        0xA9, 0xE0, /* - */
+                        0x93, 0xF3, /* - */
+                        0xA3, 0xB5, /* - */
+                        0x03, 0x00, 0x40, 0x6E,
+                    ]),
+                },
+                // Characteristic value (TX)
+                Attribute {
+                    att_type: AttUuid::Uuid128(Uuid::from_u128_le(
+                        0x6E400003_B5A3_F393_E0A9_E50E24DCCA9E,
+                    )),
+                    handle: Self::TX_HANDLE,
+                    value: HexSlice(&[]),
+                },
+                // CCCD of the TX characteristic
+                Attribute {
+                    att_type: AttUuid::Uuid16(Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION),
+                    handle: Handle::from_raw(0x0006),
+                    value: HexSlice(&[0x00, 0x00]),
+                },
+            ],
+            tx_subscribed: false,
+            tx_queue: Queue::new(),
+        }
+    }
+
+    /// Enables or disables notifications on the TX characteristic.
+    ///
+    /// This is equivalent to calling `set_cccd` on the TX CCCD. Client writes to the CCCD are
+    /// applied by the ATT server, but are only persisted for bonded clients when they are handled
+    /// with `ClientConfigs::write_cccd`.
+    pub fn set_tx_subscribed(&mut self, subscribed: bool) {
+        self.tx_subscribed = subscribed;
+        self.attributes[5].value = if subscribed {
+            HexSlice(&[0x01, 0x00])
+        } else {
+            HexSlice(&[0x00, 0x00])
+        };
+    }
+
+    /// Returns whether the client has enabled notifications on the TX characteristic.
+    pub fn tx_subscribed(&self) -> bool {
+        self.tx_subscribed
+    }
+
+    /// Enqueues `data` to be sent to the client over the TX characteristic.
+    ///
+    /// Either all of `data` is enqueued, or nothing is. Sending an empty `data` slice does nothing.
+    ///
+    /// The queued data is sent out by calling [`notify_next`] repeatedly, which splits it into as
+    /// many notifications as needed to fit into the `ATT_MTU`.
+    ///
+    /// [`notify_next`]: #method.notify_next
+    pub fn send_all(&mut self, data: &[u8]) -> Result<(), SendError> {
+        if data.is_empty() {
+            return Ok(());
         }
+
+        if !self.tx_subscribed {
+            return Err(SendError::NotSubscribed);
+        }
+
+        if self.tx_queue.capacity() - self.tx_queue.len() < data.len() {
+            return Err(SendError::QueueFull);
+        }
+
+        for &byte in data {
+            self.tx_queue.enqueue(byte).unwrap();
+        }
+
         Ok(())
     }
 
+    /// Sends the next queued TX notification using `att`.
+    ///
+    /// The notification carries as much of the queued data as fits into the negotiated `ATT_MTU`
+    /// (`ATT_MTU - 3` Bytes).
+    ///
+    /// Returns the `TxTicket` of the notification, which can be used to find out when the client
+    /// has received it (see `Connection::is_acknowledged`). This allows limiting the amount of data
+    /// in flight when streaming.
+    ///
+    /// Returns `None` if there was no notification to send, or if there is no connection (the data
+    /// stays queued then). If the client has unsubscribed in the meantime, all queued data is
+    /// dropped instead and `None` is returned as well.
+    pub fn notify_next(mut att: AttributeServerTx<'_, Self>) -> Option<TxTicket> {
+        if !att.is_connected() {
+            return None;
+        }
+
+        // Opcode and handle take up 3 Bytes of the PDU
+        let chunk_size = usize::from(att.mtu()) - 3;
+        let uart = att.provider();
+        if !uart.tx_subscribed {
+            while uart.tx_queue.dequeue().is_some() {}
+            return None;
+        }
+        if uart.tx_queue.is_empty() {
+            return None;
+        }
+
+        let mut chunk = Vec::<u8, U160>::new();
+        while let Some(byte) = uart.tx_queue.dequeue() {
+            chunk.push(byte).unwrap();
+            if chunk.len() == chunk_size {
+                break;
+            }
+        }
+        att.notify_raw(Self::TX_HANDLE, &chunk).ok()
+    }
+}
+
+impl Default for NordicUartAttrs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AttributeProvider for NordicUartAttrs {
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        composite::for_attrs_in_range(self, range, f)
+    }
+
     fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
-        uuid == Uuid16(0x2800) // FIXME not characteristics?
+        uuid == Uuid16::PRIMARY_SERVICE // FIXME not characteristics?
     }
 
-    fn group_end(&self, handle: Handle) -> Option<&Attribute<'_>> {
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
         match handle.as_u16() {
-            0x0001 => Some(&self.attributes[3]),
-            0x0002 => Some(&self.attributes[3]),
+            0x0001 => Some(self.attributes[5].handle),
+            0x0002 => Some(self.attributes[2].handle),
+            0x0004 => Some(self.attributes[5].handle),
             _ => None,
         }
     }
+
+    fn check_writable(&mut self, handle: Handle) -> Result<(), AttError> {
+        check_declared_writable(self, handle)
+    }
+
+    fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        if handle == Self::TX_CCCD_HANDLE {
+            if value.len() != 2 {
+                return Err(AttError::new(
+                    ErrorCode::InvalidAttributeValueLength,
+                    handle,
+                ));
+            }
+
+            self.set_cccd(handle, Cccd::from_value([value[0], value[1]]));
+        }
+
+        // Data written to the RX characteristic is dropped
+        Ok(())
+    }
+}
+
+impl StaticAttributes for NordicUartAttrs {
+    const HANDLE_COUNT: u16 = 6;
+
+    fn for_static_attrs(
+        &self,
+        range: &HandleRange,
+        mut f: impl FnMut(Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for attr in attrs_in_range(&self.attributes, range) {
+            f(Attribute {
+                att_type: attr.att_type,
+                handle: attr.handle,
+                value: attr.value,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl ClientConfigs for NordicUartAttrs {
+    fn cccd_handles(&self) -> &[Handle] {
+        &[Self::TX_CCCD_HANDLE]
+    }
+
+    fn cccd(&self, handle: Handle) -> Cccd {
+        match handle {
+            Self::TX_CCCD_HANDLE if self.tx_subscribed => Cccd::NOTIFY,
+            _ => Cccd::empty(),
+        }
+    }
+
+    fn set_cccd(&mut self, handle: Handle, value: Cccd) {
+        // The TX characteristic doesn't support indications
+        if handle == Self::TX_CCCD_HANDLE {
+            self.set_tx_subscribed(value.contains(Cccd::NOTIFY));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            att::check_attribute_table,
+            bytes::{ByteWriter, ToBytes},
+            l2cap::{self, BleChannelMap, Channel, L2CAPState},
+            link::{
+                mock::{MockKeyStore, MockQueue},
+                queue::{Consume, Consumer, PacketQueue},
+                AddressKind, DeviceAddress,
+            },
+            security::NoSecurity,
+        },
+    };
+
+    /// Sends the data queued in the UART service of `l2cap` using `notify_next`, and returns the
+    /// values of the notifications.
+    fn notified(
+        l2cap: &mut L2CAPState<BleChannelMap<NordicUartAttrs, NoSecurity>>,
+    ) -> std::vec::Vec<std::vec::Vec<u8>> {
+        let (mut tx, mut rx) = MockQueue::with_capacity(10).split();
+        let mut values = std::vec::Vec::new();
+        loop {
+            let ticket = {
+                let mut l2cap = l2cap.tx(&mut tx);
+                NordicUartAttrs::notify_next(l2cap.att().unwrap())
+            };
+            if ticket.is_none() {
+                return values;
+            }
+
+            let mut message = std::vec::Vec::new();
+            while let Ok(fragment) =
+                rx.consume_raw_with(|_, payload| Consume::always(Ok(payload.to_vec())))
+            {
+                message.extend_from_slice(&fragment);
+            }
+            // L2CAP header, opcode and handle
+            values.push(message.split_off(7));
+        }
+    }
+
+    #[test]
+    fn static_tables_valid() {
+        check_attribute_table(&BATTERY_SERVICE_ATTRS);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't point to the following attribute")]
+    fn static_table_wrong_value_handle() {
+        // The same check fails compilation when used with `assert_attribute_table!`
+        let mut attrs = BATTERY_SERVICE_ATTRS;
+        attrs[1].value = HexSlice(&[0x02, 0x04, 0x00, 0x19, 0x2A]);
+        check_attribute_table(&attrs);
+    }
+
+    #[test]
+    fn attrs_in_range_bounds() {
+        let range =
+            |start, end| HandleRange::new(Handle::from_raw(start), Handle::from_raw(end)).unwrap();
+        let handles = |attrs: &[Attribute<'_>]| {
+            attrs
+                .iter()
+                .map(|attr| attr.handle.as_u16())
+                .collect::<std::vec::Vec<_>>()
+        };
+
+        // An empty provider has no attributes in any range
+        assert!(attrs_in_range(&[], &HandleRange::FULL).is_empty());
+        assert!(attrs_in_range(&[], &range(1, 1)).is_empty());
+
+        let battery = BatteryServiceAttrs::new();
+        let attrs = &battery.attributes[..];
+        assert_eq!(
+            handles(attrs_in_range(attrs, &HandleRange::FULL)),
+            [1, 2, 3]
+        );
+        assert_eq!(handles(attrs_in_range(attrs, &range(2, 2))), [2]);
+        assert_eq!(handles(attrs_in_range(attrs, &range(3, 0xFFFF))), [3]);
+        assert!(attrs_in_range(attrs, &range(4, 0xFFFF)).is_empty());
+        assert!(attrs_in_range(attrs, &range(0xFFFF, 0xFFFF)).is_empty());
+    }
+
+    #[test]
+    fn battery_level_source() {
+        let level = core::cell::Cell::new(42);
+        let mut battery = BatteryServiceAttrs::with_level_source(|| {
+            let current = level.get();
+            level.set(current + 70);
+            current
+        });
+
+        let mut read = || {
+            let mut value = std::vec::Vec::new();
+            battery
+                .read_attr(BatteryServiceAttrs::BATTERY_LEVEL_HANDLE, |v| {
+                    value.extend_from_slice(v)
+                })
+                .unwrap();
+            value
+        };
+        assert_eq!(read(), [42]);
+        // 112% is clamped
+        assert_eq!(read(), [100]);
+    }
+
+    #[test]
+    fn uart_send_all_splits_by_mtu() {
+        let mut uart = NordicUartAttrs::new();
+        uart.set_tx_subscribed(true);
+
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(uart));
+        let lens = |l2cap: &mut L2CAPState<_>| {
+            let values = notified(l2cap);
+            assert!(values.iter().all(|v| v.iter().all(|b| *b == 0xAB)));
+            values.iter().map(|v| v.len()).collect::<std::vec::Vec<_>>()
+        };
+
+        // The default `ATT_MTU` fits 20 Bytes per notification
+        let data = [0xAB; 45];
+        l2cap.att_server().provider().send_all(&data).unwrap();
+        assert_eq!(lens(&mut l2cap), [20, 20, 5]);
+
+        // After an MTU exchange, larger notifications are sent
+        l2cap.att_server().set_max_mtu(30).unwrap();
+        l2cap::exchange(&mut l2cap, Channel::ATT, &[0x02, 100, 0]).unwrap();
+        l2cap.att_server().provider().send_all(&data).unwrap();
+        assert_eq!(lens(&mut l2cap), [27, 18]);
+    }
+
+    #[test]
+    fn uart_send_all_edge_cases() {
+        let mut uart = NordicUartAttrs::new();
+
+        // Empty sends are always fine, even when not subscribed
+        assert_eq!(uart.send_all(&[]), Ok(()));
+        assert_eq!(uart.send_all(&[1, 2, 3]), Err(SendError::NotSubscribed));
+
+        uart.set_tx_subscribed(true);
+        assert_eq!(uart.send_all(&[]), Ok(()));
+        assert!(uart.tx_queue.is_empty());
+
+        // 8 default-sized notifications fit, and a failed send doesn't enqueue anything
+        assert_eq!(uart.send_all(&[0; 8 * 20 + 1]), Err(SendError::QueueFull));
+        assert!(uart.tx_queue.is_empty());
+        assert_eq!(uart.send_all(&[0; 8 * 20]), Ok(()));
+        assert_eq!(uart.tx_queue.len(), 8 * 20);
+    }
+
+    #[test]
+    fn uart_value_types_match_declarations() {
+        let uart = NordicUartAttrs::new();
+        for &(decl, value) in &[(1, 2), (3, 4)] {
+            let mut uuid = [0; 16];
+            uart.attributes[value]
+                .att_type
+                .to_bytes(&mut ByteWriter::new(&mut uuid))
+                .unwrap();
+            assert_eq!(&uart.attributes[decl].value.0[3..], &uuid[..]);
+        }
+    }
+
+    #[test]
+    fn uart_cccd_write() {
+        let mut uart = NordicUartAttrs::new();
+        let cccd = NordicUartAttrs::TX_CCCD_HANDLE;
+
+        assert!(uart.write_attr(cccd, &[0x01, 0x00]).is_ok());
+        assert!(uart.tx_subscribed());
+        assert!(uart.write_attr(cccd, &[0x00]).is_err());
+        assert!(uart.tx_subscribed());
+        assert!(uart.write_attr(cccd, &[0x00, 0x00]).is_ok());
+        assert!(!uart.tx_subscribed());
+    }
+
+    #[test]
+    fn cccds_persist_for_bonded_clients() {
+        let bonded = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Public);
+        let cccd = NordicUartAttrs::TX_CCCD_HANDLE;
+        let mut keys = MockKeyStore::new(vec![]);
+        keys.bonded.push(bonded);
+
+        // A fresh connection starts out unsubscribed, the client subscribes
+        let mut uart = NordicUartAttrs::new();
+        uart.restore_cccds(&mut keys, &bonded);
+        assert!(!uart.tx_subscribed());
+        uart.write_cccd(&mut keys, &bonded, cccd, Cccd::NOTIFY);
+        assert!(uart.tx_subscribed());
+
+        // After reconnecting, the subscription is restored
+        let mut uart = NordicUartAttrs::new();
+        uart.restore_cccds(&mut keys, &bonded);
+        assert!(uart.tx_subscribed());
+        assert_eq!(uart.cccd(cccd), Cccd::NOTIFY);
+        assert_eq!(uart.attributes[5].value.0, &[0x01, 0x00][..]);
+
+        // Unsubscribing is persisted as well
+        uart.write_cccd(&mut keys, &bonded, cccd, Cccd::empty());
+        let mut uart = NordicUartAttrs::new();
+        uart.set_tx_subscribed(true);
+        uart.restore_cccds(&mut keys, &bonded);
+        assert!(!uart.tx_subscribed());
+    }
+
+    #[test]
+    fn cccds_reset_for_unbonded_clients() {
+        let unbonded = DeviceAddress::new([6, 5, 4, 3, 2, 1], AddressKind::Random);
+        let cccd = NordicUartAttrs::TX_CCCD_HANDLE;
+        let mut keys = MockKeyStore::new(vec![]);
+
+        let mut uart = NordicUartAttrs::new();
+        uart.write_cccd(&mut keys, &unbonded, cccd, Cccd::NOTIFY);
+        assert!(uart.tx_subscribed());
+        assert!(keys.cccds.is_empty());
+
+        // A stale subscription from the previous connection is cleared
+        uart.restore_cccds(&mut keys, &unbonded);
+        assert!(!uart.tx_subscribed());
+    }
+
+    #[test]
+    fn cccd_value_encoding() {
+        assert_eq!(Cccd::from_value([0x01, 0x00]), Cccd::NOTIFY);
+        assert_eq!(
+            Cccd::from_value([0x03, 0x00]),
+            Cccd::NOTIFY | Cccd::INDICATE
+        );
+        assert_eq!(Cccd::from_value([0xFC, 0xFF]), Cccd::empty());
+        assert_eq!(Cccd::INDICATE.to_value(), [0x02, 0x00]);
+    }
 }
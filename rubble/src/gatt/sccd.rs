@@ -0,0 +1,30 @@
+//! Server Characteristic Configuration Descriptors (SCCDs).
+//!
+//! Characteristics with the `BROADCAST` property have an SCCD, through which a client can ask the
+//! server to include the characteristic value in its advertising data (as *Service Data*). Unlike a
+//! CCCD, the SCCD value is shared by all clients, since advertisements aren't sent to any
+//! particular one.
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// Value of a Server Characteristic Configuration Descriptor.
+    pub struct Sccd: u16 {
+        /// The characteristic value should be broadcast while advertising.
+        const BROADCAST = 0x0001;
+    }
+}
+
+impl Sccd {
+    /// Decodes the 2-Byte little-endian attribute value of an SCCD.
+    ///
+    /// Reserved bits are ignored.
+    pub fn from_value(value: [u8; 2]) -> Self {
+        Self::from_bits_truncate(u16::from_le_bytes(value))
+    }
+
+    /// Encodes `self` as a 2-Byte little-endian attribute value.
+    pub fn to_value(self) -> [u8; 2] {
+        self.bits().to_le_bytes()
+    }
+}
@@ -0,0 +1,1249 @@
+//! A GATT server that dispatches reads and writes to callbacks.
+//!
+//! Instead of building an attribute table by hand and implementing `AttributeProvider` for it, a
+//! [`GattServer`] can be populated with services and characteristics. The server generates the
+//! service and characteristic declarations (as well as CCCDs for characteristics supporting
+//! notifications or indications, and SCCDs for broadcast characteristics), and forwards client
+//! reads and writes of a characteristic value to the callbacks registered for it. Descriptors with
+//! static values, such as the *Characteristic Presentation Format*, can be attached to the last
+//! added characteristic.
+//!
+//! The server can also host the *Database Hash* and *Client Supported Features* characteristics
+//! used for GATT caching, whose values are maintained by the server itself.
+//!
+//! Callbacks return `AttError`s to reject an operation, which are sent to the client.
+//!
+//! # Example
+//!
+//! A *Battery Service* whose *Battery Level* characteristic is read from a callback:
+//!
+//! ```
+//! use rubble::{
+//!     att::{AttError, Handle},
+//!     gatt::{characteristic::Properties, server::GattServer},
+//!     uuid::Uuid16,
+//! };
+//! use heapless::consts::U8;
+//!
+//! let mut battery_level = |buf: &mut [u8]| -> Result<usize, AttError> {
+//!     // Query the ADC here
+//!     buf[0] = 87;
+//!     Ok(1)
+//! };
+//!
+//! let mut server = GattServer::<U8>::new();
+//! server.add_service(Uuid16::BATTERY_SERVICE).unwrap();
+//! let level = server
+//!     .add_characteristic(
+//!         Uuid16::BATTERY_LEVEL,
+//!         Properties::READ,
+//!         Some(&mut battery_level),
+//!         None,
+//!     )
+//!     .unwrap();
+//! assert_eq!(level, Handle::from_raw(0x0003));
+//! ```
+//!
+//! [`GattServer`]: struct.GattServer.html
+
+use {
+    super::{
+        caching::{ClientFeatures, DatabaseHasher},
+        cccd::Cccd,
+        characteristic::{PresentationFormat, Properties, ValidRange},
+        sccd::Sccd,
+    },
+    crate::{
+        att::{
+            AttError, AttUuid, Attribute, AttributeProvider, ErrorCode, Handle, HandleRange,
+            MAX_VALUE_LEN,
+        },
+        bytes::{ByteWriter, ToBytes},
+        link::ad_structure::AdStructure,
+        utils::HexSlice,
+        uuid::Uuid16,
+        Error,
+    },
+    heapless::{consts::U8, ArrayLength, Vec},
+};
+
+/// Callback that reads the value of a characteristic.
+///
+/// The callback writes the value into the passed buffer and returns its length. The buffer is
+/// `MAX_VALUE_LEN` Bytes long.
+pub type ReadCallback<'a> = &'a mut dyn FnMut(&mut [u8]) -> Result<usize, AttError>;
+
+/// Callback that handles a client writing to a characteristic value.
+pub type WriteCallback<'a> = &'a mut dyn FnMut(&[u8]) -> Result<(), AttError>;
+
+/// An attribute hosted by a `GattServer`.
+pub struct GattAttribute<'a> {
+    handle: Handle,
+    kind: Kind<'a>,
+}
+
+enum Kind<'a> {
+    Service {
+        uuid: AttUuid,
+        /// The last attribute belonging to the service. Only the handle and type are used.
+        end: Attribute<'static>,
+    },
+    Declaration {
+        props: Properties,
+        value_handle: Handle,
+        uuid: AttUuid,
+    },
+    Value {
+        uuid: AttUuid,
+        read: Option<ReadCallback<'a>>,
+        write: Option<WriteCallback<'a>>,
+    },
+    Cccd(Cccd),
+    Sccd(Sccd),
+    PresentationFormat(PresentationFormat),
+    /// Handles of the referenced presentation format descriptors.
+    AggregateFormat(Vec<Handle, U8>),
+    ValidRange {
+        range: ValidRange,
+        /// Whether writes of values outside the range are rejected.
+        enforce: bool,
+    },
+    /// *Database Hash* characteristic value, updated whenever an attribute is added.
+    DatabaseHash([u8; 16]),
+    /// *Client Supported Features* characteristic value.
+    ClientFeatures(ClientFeatures),
+}
+
+impl GattAttribute<'_> {
+    fn att_type(&self) -> AttUuid {
+        match &self.kind {
+            Kind::Service { .. } => Uuid16::PRIMARY_SERVICE.into(),
+            Kind::Declaration { .. } => Uuid16::CHARACTERISTIC.into(),
+            Kind::Value { uuid, .. } => *uuid,
+            Kind::Cccd(_) => Uuid16::CLIENT_CHARACTERISTIC_CONFIGURATION.into(),
+            Kind::Sccd(_) => Uuid16::SERVER_CHARACTERISTIC_CONFIGURATION.into(),
+            Kind::PresentationFormat(_) => Uuid16::CHARACTERISTIC_PRESENTATION_FORMAT.into(),
+            Kind::AggregateFormat(_) => Uuid16::CHARACTERISTIC_AGGREGATE_FORMAT.into(),
+            Kind::ValidRange { .. } => Uuid16::VALID_RANGE.into(),
+            Kind::DatabaseHash(_) => Uuid16::DATABASE_HASH.into(),
+            Kind::ClientFeatures(_) => Uuid16::CLIENT_SUPPORTED_FEATURES.into(),
+        }
+    }
+
+    /// Writes the value of this attribute into `buf` and returns its length.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, AttError> {
+        let mut writer = ByteWriter::new(buf);
+        match &mut self.kind {
+            Kind::Service { uuid, .. } => uuid.to_bytes(&mut writer).unwrap(),
+            Kind::Declaration {
+                props,
+                value_handle,
+                uuid,
+            } => {
+                writer.write_u8(props.bits()).unwrap();
+                writer.write_u16_le(value_handle.as_u16()).unwrap();
+                uuid.to_bytes(&mut writer).unwrap();
+            }
+            Kind::Value { read, .. } => {
+                return match read {
+                    Some(read) => (*read)(writer.rest()),
+                    None => Err(AttError::new(ErrorCode::ReadNotPermitted, self.handle)),
+                };
+            }
+            Kind::Cccd(cccd) => writer.write_slice(&cccd.to_value()).unwrap(),
+            Kind::Sccd(sccd) => writer.write_slice(&sccd.to_value()).unwrap(),
+            Kind::PresentationFormat(format) => writer.write_slice(&format.to_value()).unwrap(),
+            Kind::AggregateFormat(formats) => {
+                for handle in formats.iter() {
+                    writer.write_u16_le(handle.as_u16()).unwrap();
+                }
+            }
+            Kind::ValidRange { range, .. } => range.to_bytes(&mut writer).unwrap(),
+            Kind::DatabaseHash(hash) => writer.write_slice(hash).unwrap(),
+            Kind::ClientFeatures(features) => writer.write_u8(features.bits()).unwrap(),
+        }
+        Ok(writer.bytes_written())
+    }
+
+    fn write(&mut self, value: &[u8]) -> Result<(), AttError> {
+        match &mut self.kind {
+            Kind::Value {
+                write: Some(write), ..
+            } => (*write)(value),
+            Kind::Cccd(cccd) => {
+                if value.len() != 2 {
+                    return Err(AttError::new(
+                        ErrorCode::InvalidAttributeValueLength,
+                        self.handle,
+                    ));
+                }
+
+                *cccd = Cccd::from_value([value[0], value[1]]);
+                Ok(())
+            }
+            Kind::Sccd(sccd) => {
+                if value.len() != 2 {
+                    return Err(AttError::new(
+                        ErrorCode::InvalidAttributeValueLength,
+                        self.handle,
+                    ));
+                }
+
+                *sccd = Sccd::from_value([value[0], value[1]]);
+                Ok(())
+            }
+            Kind::ClientFeatures(features) => features
+                .update(value)
+                .map_err(|code| AttError::new(code, self.handle)),
+            _ => Err(AttError::new(ErrorCode::WriteNotPermitted, self.handle)),
+        }
+    }
+}
+
+/// Error returned when the attribute table of a `GattServer` is full.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TableFull;
+
+/// An `AttributeProvider` that forwards reads and writes of characteristic values to callbacks.
+///
+/// `N` is the maximum number of attributes the server can hold. Every service takes up 1
+/// attribute, every characteristic 2, plus 1 for the CCCD of characteristics with the `NOTIFY` or
+/// `INDICATE` property, and 1 for the SCCD of characteristics with the `BROADCAST` property.
+/// Every added descriptor takes up 1 more attribute, and the GATT caching characteristics take up
+/// 2 each.
+///
+/// Attribute handles are assigned in the order services and characteristics are added, starting
+/// at `0x0001`.
+///
+/// Values are read into the buffer `B`, so that it doesn't have to be allocated on the stack for
+/// every request. `new` creates a server that owns a `MAX_VALUE_LEN`-Byte buffer, while
+/// `with_buffer` uses a buffer provided by the application (eg. a `&'static mut` array placed in a
+/// specific linker section).
+pub struct GattServer<'a, N: ArrayLength<GattAttribute<'a>>, B = [u8; MAX_VALUE_LEN]> {
+    attributes: Vec<GattAttribute<'a>, N>,
+    buf: B,
+}
+
+impl<'a, N: ArrayLength<GattAttribute<'a>>> GattServer<'a, N> {
+    /// Creates a server without any attributes.
+    pub fn new() -> Self {
+        Self::with_buffer([0; MAX_VALUE_LEN])
+    }
+}
+
+impl<'a, N: ArrayLength<GattAttribute<'a>>> Default for GattServer<'a, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, N: ArrayLength<GattAttribute<'a>>, B: AsRef<[u8]> + AsMut<[u8]>> GattServer<'a, N, B> {
+    /// Creates a server without any attributes that reads values into `buf`.
+    ///
+    /// `buf` should hold `MAX_VALUE_LEN` Bytes. Reading values that don't fit into it fails.
+    pub fn with_buffer(buf: B) -> Self {
+        Self {
+            attributes: Vec::new(),
+            buf,
+        }
+    }
+
+    /// Adds a primary service.
+    ///
+    /// All characteristics added after this belong to the service. Returns the handle of the
+    /// service declaration.
+    pub fn add_service(&mut self, uuid: impl Into<AttUuid>) -> Result<Handle, TableFull> {
+        let handle = self.next_handle(1)?;
+        self.push(GattAttribute {
+            handle,
+            kind: Kind::Service {
+                uuid: uuid.into(),
+                end: Attribute {
+                    att_type: Uuid16::PRIMARY_SERVICE.into(),
+                    handle,
+                    value: HexSlice(&[]),
+                },
+            },
+        });
+        Ok(handle)
+    }
+
+    /// Adds a characteristic to the last added service.
+    ///
+    /// `props` are reported to the client in the characteristic declaration, and should match the
+    /// callbacks provided: Reads of a value without a `read` callback are rejected with *Read Not
+    /// Permitted*, and writes without a `write` callback with *Write Not Permitted*. If `props`
+    /// contains `NOTIFY` or `INDICATE`, a CCCD is added after the value, which the client can
+    /// write to (see `subscription`). If `props` contains `BROADCAST`, an SCCD is added after that
+    /// (see `broadcast_data`).
+    ///
+    /// Returns the handle of the characteristic value, which is also the handle to use for
+    /// notifications and indications.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no service was added yet.
+    pub fn add_characteristic(
+        &mut self,
+        uuid: impl Into<AttUuid>,
+        props: Properties,
+        read: Option<ReadCallback<'a>>,
+        write: Option<WriteCallback<'a>>,
+    ) -> Result<Handle, TableFull> {
+        let has_cccd = props.intersects(Properties::NOTIFY | Properties::INDICATE);
+        let has_sccd = props.contains(Properties::BROADCAST);
+        let count = 2 + usize::from(has_cccd) + usize::from(has_sccd);
+        let decl_handle = self.next_handle(count)?;
+        let value_handle = Handle::from_raw(decl_handle.as_u16() + 1);
+        let uuid = uuid.into();
+
+        self.push(GattAttribute {
+            handle: decl_handle,
+            kind: Kind::Declaration {
+                props,
+                value_handle,
+                uuid,
+            },
+        });
+        self.push(GattAttribute {
+            handle: value_handle,
+            kind: Kind::Value { uuid, read, write },
+        });
+        if has_cccd {
+            self.push(GattAttribute {
+                handle: self.next_handle(1)?,
+                kind: Kind::Cccd(Cccd::empty()),
+            });
+        }
+        if has_sccd {
+            self.push(GattAttribute {
+                handle: self.next_handle(1)?,
+                kind: Kind::Sccd(Sccd::empty()),
+            });
+        }
+
+        Ok(value_handle)
+    }
+
+    /// Adds a read-only *Characteristic Presentation Format* descriptor to the last added
+    /// characteristic.
+    ///
+    /// Clients use the descriptor to display the characteristic value with the right unit and
+    /// scale. Returns the handle of the descriptor.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no characteristic was added to the last service yet.
+    pub fn add_presentation_format(
+        &mut self,
+        format: PresentationFormat,
+    ) -> Result<Handle, TableFull> {
+        self.add_descriptor(Kind::PresentationFormat(format))
+    }
+
+    /// Adds a *Characteristic Aggregate Format* descriptor to the last added characteristic.
+    ///
+    /// Characteristics whose value consists of several fields have one presentation format
+    /// descriptor per field. The aggregate format descriptor lists their handles in the order the
+    /// fields appear in the value, which is the order they were added in. It should be added after
+    /// all presentation formats of the characteristic. Returns the handle of the descriptor.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the last added characteristic has no presentation format descriptors, or more than
+    /// 8.
+    pub fn add_aggregate_format(&mut self) -> Result<Handle, TableFull> {
+        let mut formats = Vec::<Handle, U8>::new();
+        let descriptors = self.attributes.iter().rev().take_while(|attr| {
+            !matches!(
+                attr.kind,
+                Kind::Service { .. } | Kind::Declaration { .. } | Kind::Value { .. }
+            )
+        });
+        for attr in descriptors {
+            if let Kind::PresentationFormat(_) = attr.kind {
+                formats
+                    .push(attr.handle)
+                    .expect("more than 8 presentation formats to aggregate");
+            }
+        }
+        assert!(
+            !formats.is_empty(),
+            "aggregate format added without presentation formats"
+        );
+        formats.reverse();
+
+        self.add_descriptor(Kind::AggregateFormat(formats))
+    }
+
+    /// Adds a read-only *Valid Range* descriptor to the last added characteristic.
+    ///
+    /// The descriptor tells clients which values of a numeric characteristic are allowed. If
+    /// `enforce` is `true`, the server also rejects writes of values outside the range with an
+    /// *Out of Range* error, and writes of values whose length doesn't match the format of the
+    /// range with an *Invalid Attribute Value Length* error, before calling the write callback.
+    /// Returns the handle of the descriptor.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no characteristic was added to the last service yet.
+    pub fn add_valid_range(
+        &mut self,
+        range: ValidRange,
+        enforce: bool,
+    ) -> Result<Handle, TableFull> {
+        self.add_descriptor(Kind::ValidRange { range, enforce })
+    }
+
+    /// Adds a read-only *Database Hash* characteristic to the last added service.
+    ///
+    /// This should be added to the *Generic Attribute* service. Its value is the hash of the
+    /// attribute table (see `database_hash`), which is kept up to date as more attributes are
+    /// added. Returns the handle of the characteristic value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no service was added yet.
+    pub fn add_database_hash(&mut self) -> Result<Handle, TableFull> {
+        self.add_internal_characteristic(
+            Uuid16::DATABASE_HASH,
+            Properties::READ,
+            Kind::DatabaseHash([0; 16]),
+        )
+    }
+
+    /// Adds a *Client Supported Features* characteristic to the last added service.
+    ///
+    /// This should be added to the *Generic Attribute* service. The client writes the GATT
+    /// features it supports to the characteristic, which can be queried with `client_features`.
+    /// Returns the handle of the characteristic value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no service was added yet.
+    pub fn add_client_features(&mut self) -> Result<Handle, TableFull> {
+        self.add_internal_characteristic(
+            Uuid16::CLIENT_SUPPORTED_FEATURES,
+            Properties::READ | Properties::WRITE,
+            Kind::ClientFeatures(ClientFeatures::empty()),
+        )
+    }
+
+    /// Adds a characteristic whose value is stored by the server.
+    fn add_internal_characteristic(
+        &mut self,
+        uuid: Uuid16,
+        props: Properties,
+        kind: Kind<'a>,
+    ) -> Result<Handle, TableFull> {
+        let decl_handle = self.next_handle(2)?;
+        let value_handle = Handle::from_raw(decl_handle.as_u16() + 1);
+        self.push(GattAttribute {
+            handle: decl_handle,
+            kind: Kind::Declaration {
+                props,
+                value_handle,
+                uuid: uuid.into(),
+            },
+        });
+        self.push(GattAttribute {
+            handle: value_handle,
+            kind,
+        });
+        Ok(value_handle)
+    }
+
+    /// Returns the GATT features the client has enabled via the *Client Supported Features*
+    /// characteristic.
+    ///
+    /// Returns an empty `ClientFeatures` if the server doesn't host the characteristic.
+    pub fn client_features(&self) -> ClientFeatures {
+        self.attributes
+            .iter()
+            .find_map(|attr| match attr.kind {
+                Kind::ClientFeatures(features) => Some(features),
+                _ => None,
+            })
+            .unwrap_or(ClientFeatures::empty())
+    }
+
+    /// Computes the *Database Hash* of the attribute table.
+    ///
+    /// This is the value of the *Database Hash* characteristic, if one was added. The hash is
+    /// returned in the order it is transmitted in (least significant octet first).
+    pub fn database_hash(&mut self) -> [u8; 16] {
+        let mut hasher = DatabaseHasher::new();
+        // Declaration values are at most 19 Bytes long (with a 128-bit UUID)
+        let mut buf = [0; 19];
+        for attr in self.attributes.iter_mut() {
+            match attr.kind {
+                // Characteristic values never contribute to the hash
+                Kind::Value { .. } | Kind::DatabaseHash(_) | Kind::ClientFeatures(_) => continue,
+                _ => {}
+            }
+
+            let len = attr.read(&mut buf).unwrap();
+            hasher.add(&Attribute {
+                att_type: attr.att_type(),
+                handle: attr.handle,
+                value: HexSlice(&buf[..len]),
+            });
+        }
+        hasher.finish()
+    }
+
+    /// Appends a descriptor to the last added characteristic.
+    fn add_descriptor(&mut self, kind: Kind<'a>) -> Result<Handle, TableFull> {
+        match self.attributes.last() {
+            None
+            | Some(GattAttribute {
+                kind: Kind::Service { .. },
+                ..
+            }) => panic!("descriptor added before any characteristic"),
+            _ => {}
+        }
+
+        let handle = self.next_handle(1)?;
+        self.push(GattAttribute { handle, kind });
+        Ok(handle)
+    }
+
+    /// Returns the CCCD value of the characteristic whose value is at `value_handle`.
+    ///
+    /// Returns an empty `Cccd` if the characteristic doesn't exist or has no CCCD.
+    pub fn subscription(&self, value_handle: Handle) -> Cccd {
+        let cccd_handle = Handle::from_raw(value_handle.as_u16().wrapping_add(1));
+        match self.get(cccd_handle) {
+            Some(GattAttribute {
+                kind: Kind::Cccd(cccd),
+                ..
+            }) => *cccd,
+            _ => Cccd::empty(),
+        }
+    }
+
+    /// Returns the SCCD value of the characteristic whose value is at `value_handle`.
+    ///
+    /// Returns an empty `Sccd` if the characteristic doesn't exist or has no SCCD.
+    pub fn broadcast_config(&self, value_handle: Handle) -> Sccd {
+        let start = usize::from(value_handle.as_u16());
+        self.attributes
+            .iter()
+            .skip(start)
+            .take_while(|attr| matches!(attr.kind, Kind::Cccd(_) | Kind::Sccd(_)))
+            .find_map(|attr| match attr.kind {
+                Kind::Sccd(sccd) => Some(sccd),
+                _ => None,
+            })
+            .unwrap_or(Sccd::empty())
+    }
+
+    /// Returns the *Service Data* AD structure broadcasting the value of the characteristic at
+    /// `value_handle`.
+    ///
+    /// The value is read from the characteristic's read callback into `buf`, and the returned AD
+    /// structure should be included in the advertising data. Returns `None` if the client hasn't
+    /// enabled broadcasting via the SCCD, if the value can't be read, or if the containing service
+    /// doesn't have a 16-bit UUID (no other service data AD structures are supported).
+    pub fn broadcast_data<'b>(
+        &mut self,
+        value_handle: Handle,
+        buf: &'b mut [u8],
+    ) -> Option<AdStructure<'b>> {
+        if !self
+            .broadcast_config(value_handle)
+            .contains(Sccd::BROADCAST)
+        {
+            return None;
+        }
+
+        let index = usize::from(value_handle.as_u16()).checked_sub(1)?;
+        let service = self.attributes[..index]
+            .iter()
+            .rev()
+            .find_map(|attr| match attr.kind {
+                Kind::Service { uuid, .. } => Some(uuid),
+                _ => None,
+            })?;
+        let uuid = match service {
+            AttUuid::Uuid16(uuid) => uuid.0,
+            AttUuid::Uuid128(_) => return None,
+        };
+
+        let value = &mut self.attributes[index];
+        match value.kind {
+            Kind::Value { .. } => {}
+            _ => return None,
+        }
+        let len = value.read(buf).ok()?;
+        Some(AdStructure::ServiceData16 {
+            uuid,
+            data: &buf[..len],
+        })
+    }
+
+    /// Returns the handle of the next attribute, checking that `count` more attributes fit.
+    fn next_handle(&self, count: usize) -> Result<Handle, TableFull> {
+        if self.attributes.capacity() - self.attributes.len() < count {
+            return Err(TableFull);
+        }
+
+        Ok(Handle::from_raw(self.attributes.len() as u16 + 1))
+    }
+
+    /// Appends an attribute and extends the last service's group to include it.
+    fn push(&mut self, attr: GattAttribute<'a>) {
+        let (handle, att_type) = (attr.handle, attr.att_type());
+        match attr.kind {
+            Kind::Service { .. } => {}
+            _ => {
+                let end = self
+                    .attributes
+                    .iter_mut()
+                    .rev()
+                    .find_map(|attr| match &mut attr.kind {
+                        Kind::Service { end, .. } => Some(end),
+                        _ => None,
+                    })
+                    .expect("characteristic added before any service");
+                end.handle = handle;
+                end.att_type = att_type;
+            }
+        }
+
+        // `next_handle` made sure there's enough space
+        self.attributes.push(attr).ok().unwrap();
+
+        // The new attribute may have changed the *Database Hash*
+        let has_hash = self
+            .attributes
+            .iter()
+            .any(|attr| matches!(attr.kind, Kind::DatabaseHash(_)));
+        if has_hash {
+            let new_hash = self.database_hash();
+            for attr in self.attributes.iter_mut() {
+                if let Kind::DatabaseHash(hash) = &mut attr.kind {
+                    *hash = new_hash;
+                }
+            }
+        }
+    }
+
+    /// Returns the valid range to enforce on writes to the characteristic value at `handle`.
+    fn enforced_range(&self, handle: Handle) -> Option<ValidRange> {
+        match self.get(handle)?.kind {
+            Kind::Value { .. } => {}
+            _ => return None,
+        }
+
+        // The descriptors follow the value. `handle` is the index of the next attribute.
+        self.attributes[usize::from(handle.as_u16())..]
+            .iter()
+            .take_while(|attr| {
+                !matches!(
+                    attr.kind,
+                    Kind::Service { .. } | Kind::Declaration { .. } | Kind::Value { .. }
+                )
+            })
+            .find_map(|attr| match attr.kind {
+                Kind::ValidRange {
+                    range,
+                    enforce: true,
+                } => Some(range),
+                _ => None,
+            })
+    }
+
+    fn get(&self, handle: Handle) -> Option<&GattAttribute<'a>> {
+        let index = usize::from(handle.as_u16()).checked_sub(1)?;
+        self.attributes.get(index)
+    }
+
+    fn get_mut(&mut self, handle: Handle) -> Result<&mut GattAttribute<'a>, AttError> {
+        usize::from(handle.as_u16())
+            .checked_sub(1)
+            .and_then(move |index| self.attributes.get_mut(index))
+            .ok_or_else(|| AttError::new(ErrorCode::InvalidHandle, handle))
+    }
+}
+
+/// Characteristic values that can't be read (because there's no read callback, or because it
+/// returned an error) are reported with an empty value by `for_attrs_in_range`, so that they are
+/// still found during discovery. Reading them with `read_attr` returns the error.
+impl<'a, N, B> AttributeProvider for GattServer<'a, N, B>
+where
+    N: ArrayLength<GattAttribute<'a>>,
+    B: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn for_attrs_in_range(
+        &mut self,
+        range: HandleRange,
+        mut f: impl FnMut(&Self, Attribute<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for i in 0..self.attributes.len() {
+            let attr = &mut self.attributes[i];
+            if !range.contains(attr.handle) {
+                continue;
+            }
+
+            let (att_type, handle) = (attr.att_type(), attr.handle);
+            let len = attr.read(self.buf.as_mut()).unwrap_or(0);
+            let attr = Attribute {
+                att_type,
+                handle,
+                value: HexSlice(&self.buf.as_ref()[..len]),
+            };
+            f(self, attr)?;
+        }
+        Ok(())
+    }
+
+    fn is_grouping_attr(&self, uuid: AttUuid) -> bool {
+        uuid == Uuid16::PRIMARY_SERVICE
+    }
+
+    fn group_end(&self, handle: Handle) -> Option<Handle> {
+        match self.get(handle) {
+            Some(GattAttribute {
+                kind: Kind::Service { end, .. },
+                ..
+            }) => Some(end.handle),
+            _ => None,
+        }
+    }
+
+    fn read_attr(&mut self, handle: Handle, f: impl FnOnce(&[u8])) -> Result<(), AttError> {
+        let attributes = &mut self.attributes;
+        let attr = usize::from(handle.as_u16())
+            .checked_sub(1)
+            .and_then(|index| attributes.get_mut(index))
+            .ok_or_else(|| AttError::new(ErrorCode::InvalidHandle, handle))?;
+        let buf = self.buf.as_mut();
+        let len = attr.read(buf)?;
+        f(&buf[..len]);
+        Ok(())
+    }
+
+    fn check_writable(&mut self, handle: Handle) -> Result<(), AttError> {
+        match &self.get_mut(handle)?.kind {
+            Kind::Value { write: Some(_), .. }
+            | Kind::Cccd(_)
+            | Kind::Sccd(_)
+            | Kind::ClientFeatures(_) => Ok(()),
+            _ => Err(AttError::new(ErrorCode::WriteNotPermitted, handle)),
+        }
+    }
+
+    fn write_attr(&mut self, handle: Handle, value: &[u8]) -> Result<(), AttError> {
+        if let Some(range) = self.enforced_range(handle) {
+            if value.len() != range.value_len() {
+                return Err(AttError::new(
+                    ErrorCode::InvalidAttributeValueLength,
+                    handle,
+                ));
+            }
+            if !range.contains(value) {
+                return Err(AttError::new(ErrorCode::OutOfRange, handle));
+            }
+        }
+
+        self.get_mut(handle)?.write(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            gatt::characteristic::Format,
+            l2cap::{exchange, BleChannelMap, Channel, L2CAPState},
+            security::NoSecurity,
+            uuid::Uuid,
+        },
+        core::cell::Cell,
+        heapless::consts::U16,
+        std::vec::Vec,
+    };
+
+    /// Sends an ATT PDU to the server and returns the response PDU.
+    fn request<N: ArrayLength<GattAttribute<'static>>>(
+        l2cap: &mut L2CAPState<BleChannelMap<GattServer<'static, N>, NoSecurity>>,
+        pdu: &[u8],
+    ) -> Vec<u8> {
+        exchange(l2cap, Channel::ATT, pdu).expect("no response sent")
+    }
+
+    /// Leaks a callback, so it can be stored in a `GattServer<'static>`.
+    fn leak<T>(t: T) -> &'static mut T {
+        Box::leak(Box::new(t))
+    }
+
+    #[test]
+    fn battery_level_callback() {
+        let level: &'static Cell<u8> = leak(Cell::new(48));
+
+        let mut server = GattServer::<U8>::new();
+        assert_eq!(
+            server.add_service(Uuid16::BATTERY_SERVICE),
+            Ok(Handle::from_raw(1))
+        );
+        let handle = server
+            .add_characteristic(
+                Uuid16::BATTERY_LEVEL,
+                Properties::READ | Properties::NOTIFY,
+                Some(leak(move |buf: &mut [u8]| -> Result<usize, AttError> {
+                    buf[0] = level.get();
+                    Ok(1)
+                })),
+                None,
+            )
+            .unwrap();
+        assert_eq!(handle, Handle::from_raw(3));
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(server));
+
+        // The generated declarations can be discovered
+        assert_eq!(
+            request(&mut l2cap, &[0x10, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28]),
+            [0x11, 6, 1, 0, 4, 0, 0x0F, 0x18]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x08, 0x01, 0x00, 0xFF, 0xFF, 0x03, 0x28]),
+            [0x09, 7, 2, 0, 0x12, 3, 0, 0x19, 0x2A]
+        );
+
+        // Reads invoke the callback
+        assert_eq!(request(&mut l2cap, &[0x0A, 0x03, 0x00]), [0x0B, 48]);
+        level.set(47);
+        assert_eq!(request(&mut l2cap, &[0x0A, 0x03, 0x00]), [0x0B, 47]);
+
+        // The value isn't writable
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x03, 0x00, 50]),
+            [0x01, 0x12, 0x03, 0x00, 0x03]
+        );
+
+        // The client subscribes via the CCCD
+        assert_eq!(request(&mut l2cap, &[0x12, 0x04, 0x00, 0x01, 0x00]), [0x13]);
+        assert_eq!(
+            l2cap.att_server().provider().subscription(handle),
+            Cccd::NOTIFY
+        );
+        assert_eq!(request(&mut l2cap, &[0x0A, 0x04, 0x00]), [0x0B, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn discover_mixed_service_uuids() {
+        // Battery Service, Nordic UART Service and Device Information Service
+        let nus = Uuid::from_u128_le(0x6E400001_B5A3_F393_E0A9_E50E24DCCA9E);
+        let mut server = GattServer::<U16>::new();
+        server.add_service(Uuid16::BATTERY_SERVICE).unwrap();
+        server
+            .add_characteristic(Uuid16(0x2A19), Properties::READ, None, None)
+            .unwrap();
+        server.add_service(nus).unwrap();
+        server
+            .add_characteristic(nus, Properties::READ, None, None)
+            .unwrap();
+        server.add_service(Uuid16(0x180A)).unwrap();
+        server
+            .add_characteristic(Uuid16(0x2A29), Properties::READ, None, None)
+            .unwrap();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(server));
+
+        // Discovery stops before the 128-bit UUID instead of skipping over it...
+        assert_eq!(
+            request(&mut l2cap, &[0x10, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28]),
+            [0x11, 6, 1, 0, 3, 0, 0x0F, 0x18]
+        );
+
+        // ...so that the client finds every service when continuing after the last one
+        let mut nus_group = vec![0x11, 20, 4, 0, 6, 0];
+        nus_group.extend_from_slice(nus.as_bytes());
+        assert_eq!(
+            request(&mut l2cap, &[0x10, 0x04, 0x00, 0xFF, 0xFF, 0x00, 0x28]),
+            nus_group
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x10, 0x07, 0x00, 0xFF, 0xFF, 0x00, 0x28]),
+            [0x11, 6, 7, 0, 9, 0, 0x0A, 0x18]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x10, 0x0A, 0x00, 0xFF, 0xFF, 0x00, 0x28]),
+            [0x01, 0x10, 0x0A, 0x00, 0x0A]
+        );
+    }
+
+    #[test]
+    fn gatt_caching() {
+        let mut server = GattServer::<U16>::new();
+        server.add_service(Uuid16::GENERIC_ATTRIBUTE).unwrap();
+        let hash = server.add_database_hash().unwrap();
+        let features = server.add_client_features().unwrap();
+        assert_eq!((hash, features), (Handle::from_raw(3), Handle::from_raw(5)));
+        server.add_service(Uuid16::BATTERY_SERVICE).unwrap();
+        server
+            .add_characteristic(
+                Uuid16::BATTERY_LEVEL,
+                Properties::READ | Properties::NOTIFY,
+                None,
+                None,
+            )
+            .unwrap();
+
+        // AES-CMAC over the declarations and the CCCD, computed with an independent implementation
+        let expected = [
+            0x18, 0xf3, 0x25, 0x2c, 0xd8, 0x29, 0xf6, 0xee, 0x7a, 0xc0, 0x8f, 0x39, 0x7e, 0x1d,
+            0xbc, 0x00,
+        ];
+        assert_eq!(server.database_hash(), expected);
+        assert_eq!(crate::gatt::caching::database_hash(&mut server), expected);
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(server));
+
+        // The client reads the hash by its type, and enables robust caching
+        let mut response = vec![0x09, 18, 3, 0];
+        response.extend_from_slice(&expected);
+        assert_eq!(
+            request(&mut l2cap, &[0x08, 0x01, 0x00, 0xFF, 0xFF, 0x2A, 0x2B]),
+            response
+        );
+        assert_eq!(request(&mut l2cap, &[0x12, 0x05, 0x00, 0x01]), [0x13]);
+        assert_eq!(request(&mut l2cap, &[0x0A, 0x05, 0x00]), [0x0B, 0x01]);
+        assert_eq!(
+            l2cap.att_server().provider().client_features(),
+            ClientFeatures::ROBUST_CACHING
+        );
+
+        // Features can't be disabled, and the hash can't be written
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x05, 0x00, 0x00]),
+            [0x01, 0x12, 0x05, 0x00, 0x13]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x03, 0x00, 0x00]),
+            [0x01, 0x12, 0x03, 0x00, 0x03]
+        );
+
+        // Subscribing doesn't change the hash
+        assert_eq!(request(&mut l2cap, &[0x12, 0x09, 0x00, 0x01, 0x00]), [0x13]);
+        assert_eq!(l2cap.att_server().provider().database_hash(), expected);
+    }
+
+    #[test]
+    fn write_callback_errors() {
+        let written: &'static Cell<u8> = leak(Cell::new(0));
+
+        let mut server = GattServer::<U8>::new();
+        server.add_service(Uuid16(0x1234)).unwrap();
+        server
+            .add_characteristic(
+                Uuid16(0x5678),
+                Properties::WRITE,
+                None,
+                Some(leak(move |value: &[u8]| match value {
+                    [b] => {
+                        written.set(*b);
+                        Ok(())
+                    }
+                    _ => Err(AttError::new(
+                        ErrorCode::InvalidAttributeValueLength,
+                        Handle::from_raw(3),
+                    )),
+                })),
+            )
+            .unwrap();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(server));
+
+        assert_eq!(request(&mut l2cap, &[0x12, 0x03, 0x00, 42]), [0x13]);
+        assert_eq!(written.get(), 42);
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x03, 0x00, 1, 2]),
+            [0x01, 0x12, 0x03, 0x00, 0x0D]
+        );
+        assert_eq!(written.get(), 42);
+
+        // Values without a read callback can't be read, but are still found by discovery
+        assert_eq!(
+            request(&mut l2cap, &[0x0A, 0x03, 0x00]),
+            [0x01, 0x0A, 0x03, 0x00, 0x02]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x04, 0x01, 0x00, 0xFF, 0xFF]),
+            [0x05, 0x01, 1, 0, 0x00, 0x28, 2, 0, 0x03, 0x28, 3, 0, 0x78, 0x56]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x0A, 0x09, 0x00]),
+            [0x01, 0x0A, 0x09, 0x00, 0x01]
+        );
+
+        // Nonexistent attributes are reported before read-only ones
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x09, 0x00, 1, 2, 3]),
+            [0x01, 0x12, 0x09, 0x00, 0x01]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x02, 0x00, 1, 2, 3]),
+            [0x01, 0x12, 0x02, 0x00, 0x03]
+        );
+    }
+
+    #[test]
+    fn caller_provided_buffer() {
+        let mut buf = [0; 1];
+        {
+            let mut server = GattServer::<U8, _>::with_buffer(&mut buf);
+            server.add_service(Uuid16::BATTERY_SERVICE).unwrap();
+            let handle = server
+                .add_characteristic(
+                    Uuid16::BATTERY_LEVEL,
+                    Properties::READ,
+                    Some(leak(|buf: &mut [u8]| -> Result<usize, AttError> {
+                        buf[0] = 87;
+                        Ok(1)
+                    })),
+                    None,
+                )
+                .unwrap();
+
+            let mut value = Vec::new();
+            server
+                .read_attr(handle, |v| value.extend_from_slice(v))
+                .unwrap();
+            assert_eq!(value, [87]);
+        }
+
+        // The value was read into the provided buffer
+        assert_eq!(buf, [87]);
+    }
+
+    #[test]
+    fn broadcast() {
+        let mut server = GattServer::<U8>::new();
+        server.add_service(Uuid16::BATTERY_SERVICE).unwrap();
+        let handle = server
+            .add_characteristic(
+                Uuid16::BATTERY_LEVEL,
+                Properties::READ | Properties::NOTIFY | Properties::BROADCAST,
+                Some(leak(|buf: &mut [u8]| -> Result<usize, AttError> {
+                    buf[0] = 87;
+                    Ok(1)
+                })),
+                None,
+            )
+            .unwrap();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(server));
+
+        // Declaration includes the Broadcast property, SCCD follows the CCCD
+        assert_eq!(
+            request(&mut l2cap, &[0x08, 0x01, 0x00, 0xFF, 0xFF, 0x03, 0x28]),
+            [0x09, 7, 2, 0, 0x13, 3, 0, 0x19, 0x2A]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x08, 0x01, 0x00, 0xFF, 0xFF, 0x03, 0x29]),
+            [0x09, 4, 5, 0, 0x00, 0x00]
+        );
+
+        // Not broadcasting until the client enables it
+        let mut buf = [0; 4];
+        let server = l2cap.att_server().provider();
+        assert!(server.broadcast_data(handle, &mut buf).is_none());
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x05, 0x00, 0x01]),
+            [0x01, 0x12, 0x05, 0x00, 0x0D]
+        );
+        assert_eq!(request(&mut l2cap, &[0x12, 0x05, 0x00, 0x01, 0x00]), [0x13]);
+        assert_eq!(request(&mut l2cap, &[0x0A, 0x05, 0x00]), [0x0B, 0x01, 0x00]);
+
+        let server = l2cap.att_server().provider();
+        assert_eq!(server.broadcast_config(handle), Sccd::BROADCAST);
+        assert_eq!(server.subscription(handle), Cccd::empty());
+        match server.broadcast_data(handle, &mut buf) {
+            Some(AdStructure::ServiceData16 { uuid, data }) => {
+                assert_eq!(uuid, 0x180F);
+                assert_eq!(data, [87]);
+            }
+            ad => panic!("unexpected AD structure {:?}", ad),
+        }
+    }
+
+    #[test]
+    fn presentation_format() {
+        let mut server = GattServer::<U8>::new();
+        server.add_service(Uuid16::ENVIRONMENTAL_SENSING).unwrap();
+        server
+            .add_characteristic(
+                Uuid16::TEMPERATURE,
+                Properties::READ | Properties::NOTIFY,
+                Some(leak(|buf: &mut [u8]| -> Result<usize, AttError> {
+                    buf[..2].copy_from_slice(&2150i16.to_le_bytes());
+                    Ok(2)
+                })),
+                None,
+            )
+            .unwrap();
+        let format = PresentationFormat::new(Format::I16, -2, Uuid16::DEGREE_CELSIUS);
+        assert_eq!(
+            server.add_presentation_format(format),
+            Ok(Handle::from_raw(5))
+        );
+        assert_eq!(
+            server.group_end(Handle::from_raw(1)).unwrap(),
+            Handle::from_raw(5)
+        );
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(server));
+
+        // Find Information lists the descriptor after the CCCD
+        assert_eq!(
+            request(&mut l2cap, &[0x04, 0x04, 0x00, 0xFF, 0xFF]),
+            [0x05, 0x01, 0x04, 0x00, 0x02, 0x29, 0x05, 0x00, 0x04, 0x29]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x0A, 0x05, 0x00]),
+            [0x0B, 0x0E, 0xFE, 0x2F, 0x27, 0x01, 0x00, 0x00]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x05, 0x00, 0x00]),
+            [0x01, 0x12, 0x05, 0x00, 0x03]
+        );
+    }
+
+    #[test]
+    fn aggregate_format() {
+        let mut server = GattServer::<U8>::new();
+        server.add_service(Uuid16::ENVIRONMENTAL_SENSING).unwrap();
+        server
+            .add_characteristic(Uuid16(0xABCD), Properties::READ, None, None)
+            .unwrap();
+        // A temperature in 0.01 °C followed by a battery voltage in mV
+        let temp = server
+            .add_presentation_format(PresentationFormat::new(
+                Format::I16,
+                -2,
+                Uuid16::DEGREE_CELSIUS,
+            ))
+            .unwrap();
+        let voltage = server
+            .add_presentation_format(PresentationFormat::new(Format::U16, -3, Uuid16::VOLT))
+            .unwrap();
+        assert_eq!((temp, voltage), (Handle::from_raw(4), Handle::from_raw(5)));
+        assert_eq!(server.add_aggregate_format(), Ok(Handle::from_raw(6)));
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(server));
+
+        assert_eq!(
+            request(&mut l2cap, &[0x08, 0x01, 0x00, 0xFF, 0xFF, 0x05, 0x29]),
+            [0x09, 6, 6, 0, 4, 0, 5, 0]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x0A, 0x05, 0x00]),
+            [0x0B, 0x06, 0xFD, 0x28, 0x27, 0x01, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn valid_range() {
+        let written: &'static Cell<i16> = leak(Cell::new(0));
+
+        let mut server = GattServer::<U8>::new();
+        server.add_service(Uuid16(0x1234)).unwrap();
+        server
+            .add_characteristic(
+                Uuid16(0x5678),
+                Properties::WRITE,
+                None,
+                Some(leak(move |value: &[u8]| -> Result<(), AttError> {
+                    written.set(i16::from_le_bytes([value[0], value[1]]));
+                    Ok(())
+                })),
+            )
+            .unwrap();
+        let range = ValidRange::new(Format::I16, -400, 1250);
+        assert_eq!(server.add_valid_range(range, true), Ok(Handle::from_raw(4)));
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(server));
+
+        assert_eq!(
+            request(&mut l2cap, &[0x0A, 0x04, 0x00]),
+            [0x0B, 0x70, 0xFE, 0xE2, 0x04]
+        );
+
+        assert_eq!(request(&mut l2cap, &[0x12, 0x03, 0x00, 0x70, 0xFE]), [0x13]);
+        assert_eq!(written.get(), -400);
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x03, 0x00, 0x6F, 0xFE]),
+            [0x01, 0x12, 0x03, 0x00, 0xFF]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x03, 0x00, 0xE3, 0x04]),
+            [0x01, 0x12, 0x03, 0x00, 0xFF]
+        );
+        assert_eq!(
+            request(&mut l2cap, &[0x12, 0x03, 0x00, 0x00]),
+            [0x01, 0x12, 0x03, 0x00, 0x0D]
+        );
+        assert_eq!(written.get(), -400);
+    }
+
+    #[test]
+    fn valid_range_not_enforced() {
+        let mut server = GattServer::<U8>::new();
+        server.add_service(Uuid16(0x1234)).unwrap();
+        server
+            .add_characteristic(
+                Uuid16(0x5678),
+                Properties::WRITE,
+                None,
+                Some(leak(|_: &[u8]| -> Result<(), AttError> { Ok(()) })),
+            )
+            .unwrap();
+        let range = ValidRange::new(Format::U8, 10, 20);
+        server.add_valid_range(range, false).unwrap();
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(server));
+
+        assert_eq!(request(&mut l2cap, &[0x0A, 0x04, 0x00]), [0x0B, 10, 20]);
+        assert_eq!(request(&mut l2cap, &[0x12, 0x03, 0x00, 30]), [0x13]);
+    }
+
+    #[test]
+    #[should_panic(expected = "aggregate format added without presentation formats")]
+    fn aggregate_without_formats() {
+        let mut server = GattServer::<U8>::new();
+        server.add_service(Uuid16(0x1234)).unwrap();
+        server
+            .add_characteristic(Uuid16(0x5678), Properties::READ, None, None)
+            .unwrap();
+        let _ = server.add_aggregate_format();
+    }
+
+    #[test]
+    #[should_panic(expected = "descriptor added before any characteristic")]
+    fn descriptor_without_characteristic() {
+        let mut server = GattServer::<U8>::new();
+        server.add_service(Uuid16(0x1234)).unwrap();
+        let _ = server.add_presentation_format(PresentationFormat::new(
+            Format::U8,
+            0,
+            Uuid16::PERCENTAGE,
+        ));
+    }
+
+    #[test]
+    fn table_full() {
+        let mut server = GattServer::<U8>::new();
+        server.add_service(Uuid16(0x1234)).unwrap();
+        server
+            .add_characteristic(Uuid16(0x1), Properties::NOTIFY, None, None)
+            .unwrap();
+        server
+            .add_characteristic(Uuid16(0x2), Properties::READ, None, None)
+            .unwrap();
+        assert_eq!(
+            server.add_characteristic(Uuid16(0x3), Properties::NOTIFY, None, None),
+            Err(TableFull)
+        );
+        assert_eq!(server.attributes.len(), 6);
+        assert_eq!(server.add_service(Uuid16(0x5678)), Ok(Handle::from_raw(7)));
+        assert_eq!(
+            server.group_end(Handle::from_raw(1)).unwrap(),
+            Handle::from_raw(6)
+        );
+    }
+}
@@ -31,18 +31,44 @@ use {
         link::{
             data::Llid,
             queue::{Consume, Producer},
-            MIN_DATA_PAYLOAD_BUF,
+            ConnectionHandle, MIN_DATA_PAYLOAD_BUF,
         },
         security::{NoSecurity, SecurityLevel, SecurityManager},
         utils::HexSlice,
         Error,
     },
     core::{
-        fmt,
+        cmp, fmt,
         ops::{Deref, DerefMut},
     },
 };
 
+/// The largest L2CAP payload (eg. an ATT PDU) that can be sent or received over the link.
+///
+/// The Link-Layer doesn't support the Data Length Extension, so data channel PDUs carry at most 27
+/// Bytes of payload, 4 of which are taken up by the L2CAP header. Larger messages are fragmented
+/// into several data channel PDUs when sent, and reassembled when received, which requires buffer
+/// space in the `L2CAPState`.
+pub const MAX_PAYLOAD_LEN: u16 = 247;
+
+/// The size of the largest L2CAP message (header and payload) in Bytes.
+///
+/// This is the size of the message buffers owned by an `L2CAPState` created with
+/// `L2CAPState::new`.
+pub const MAX_MESSAGE_LEN: usize = MAX_PAYLOAD_LEN as usize + Header::SIZE as usize;
+
+/// Returns the largest L2CAP payload that can be sent through a TX queue holding `capacity` data
+/// channel PDUs.
+///
+/// All fragments of a message are enqueued at once, so the payload is limited by the queue
+/// capacity, as well as by `MAX_PAYLOAD_LEN`.
+pub(crate) fn max_payload_len(capacity: usize) -> u16 {
+    let len = capacity
+        .saturating_mul(MIN_DATA_PAYLOAD_BUF)
+        .saturating_sub(Header::SIZE.into());
+    cmp::min(len, MAX_PAYLOAD_LEN.into()) as u16
+}
+
 /// An L2CAP channel identifier (CID).
 ///
 /// Channels are basically like TCP ports. A `Protocol` can listen on a channel and is connected to
@@ -151,36 +177,49 @@ pub struct ChannelData<'a, P: ?Sized> {
     ///
     /// This is the number of bytes that must be available to the protocol in the TX buffer to
     /// guarantee that all of the protocol's PDUs will fit.
-    pdu: u8,
+    pdu: u16,
 }
 
 impl<'a> ChannelData<'a, dyn ProtocolObj + 'a> {
     /// Creates a `ChannelData` carrying a dynamically-dispatched `dyn ProtocolObj` from a concrete
     /// `Protocol` implementor `T`.
-    fn new_dyn<T: Protocol + 'a>(response_channel: Channel, protocol: &'a mut T) -> Self {
+    ///
+    /// This is used by `ChannelMapper` implementations to connect a protocol to a channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the protocol's `max_pdu_size` exceeds `MAX_PAYLOAD_LEN`.
+    pub fn new_dyn<T: Protocol + 'a>(response_channel: Channel, protocol: &'a mut T) -> Self {
+        let pdu = protocol.max_pdu_size();
         assert!(
-            usize::from(T::RSP_PDU_SIZE + Header::SIZE) <= MIN_DATA_PAYLOAD_BUF,
-            "protocol min PDU is smaller than data channel PDU (L2CAP reassembly NYI)"
+            pdu <= MAX_PAYLOAD_LEN,
+            "protocol PDU exceeds max. L2CAP payload"
         );
 
         ChannelData {
             response_channel,
-            pdu: T::RSP_PDU_SIZE,
+            pdu,
             protocol,
         }
     }
 }
 
 impl<'a, P: Protocol> ChannelData<'a, P> {
-    fn new(response_channel: Channel, protocol: &'a mut P) -> Self {
+    /// Creates a `ChannelData` for a statically known `Protocol` implementor `P`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the protocol's `max_pdu_size` exceeds `MAX_PAYLOAD_LEN`.
+    pub fn new(response_channel: Channel, protocol: &'a mut P) -> Self {
+        let pdu = protocol.max_pdu_size();
         assert!(
-            usize::from(P::RSP_PDU_SIZE + Header::SIZE) <= MIN_DATA_PAYLOAD_BUF,
-            "protocol min PDU is smaller than data channel PDU (L2CAP reassembly NYI)"
+            pdu <= MAX_PAYLOAD_LEN,
+            "protocol PDU exceeds max. L2CAP payload"
         );
 
         ChannelData {
             response_channel,
-            pdu: P::RSP_PDU_SIZE,
+            pdu,
             protocol,
         }
     }
@@ -200,7 +239,7 @@ impl<'a, P: ?Sized> ChannelData<'a, P> {
     ///
     /// The L2CAP implementation will not forward incoming PDUs to the protocol unless this amount
     /// of space is available in the TX buffer. This guarantees that the response will always fit.
-    pub fn pdu_size(&self) -> u8 {
+    pub fn pdu_size(&self) -> u16 {
         self.pdu
     }
 
@@ -257,6 +296,13 @@ impl<A: AttributeProvider> BleChannelMap<A, NoSecurity> {
     }
 }
 
+impl<A: AttributeProvider, S: SecurityLevel> BleChannelMap<A, S> {
+    /// Returns the security manager connected to channel `0x0006`.
+    pub fn security_manager(&mut self) -> &mut SecurityManager<S> {
+        &mut self.sm
+    }
+}
+
 impl<A: AttributeProvider, S: SecurityLevel> ChannelMapper for BleChannelMap<A, S> {
     type AttributeProvider = A;
 
@@ -281,7 +327,7 @@ pub trait ProtocolObj {
     /// Process a message sent to the protocol.
     ///
     /// The message is reassembled by L2CAP already, and the `responder` is guaranteed to fit a
-    /// protocol payload of at least `Protocol::RSP_PDU_SIZE` Bytes, as defined by the protocol.
+    /// protocol payload of `Protocol::max_pdu_size` Bytes, as defined by the protocol.
     ///
     /// # Errors
     ///
@@ -301,15 +347,25 @@ pub trait Protocol: ProtocolObj {
     /// Incoming PDUs will only be forwarded to the protocol if there is at least this much space in
     /// the TX buffer.
     const RSP_PDU_SIZE: u8;
+
+    /// Returns the size of the largest PDU the protocol may currently send.
+    ///
+    /// Protocols that negotiate a larger PDU size with the peer (like the ATT server after an MTU
+    /// exchange) can override this to return the negotiated size. It must not be smaller than
+    /// `RSP_PDU_SIZE` and not exceed `MAX_PAYLOAD_LEN`. PDUs exceeding a single data channel PDU
+    /// are fragmented by L2CAP.
+    fn max_pdu_size(&self) -> u16 {
+        u16::from(Self::RSP_PDU_SIZE)
+    }
 }
 
 /// Header used by *all* L2CAP PDUs.
 #[derive(Debug)]
-struct Header {
+pub(crate) struct Header {
     /// Length of the payload following the length and channel fields (after reassembly).
-    length: u16,
+    pub(crate) length: u16,
     /// Destination endpoint of the PDU.
-    channel: Channel,
+    pub(crate) channel: Channel,
 }
 
 impl Header {
@@ -333,19 +389,19 @@ impl ToBytes for Header {
     }
 }
 
-struct Message<P> {
-    header: Header,
-    payload: P,
+#[cfg(any(feature = "fuzz", feature = "testing"))]
+pub(crate) struct Message<P> {
+    pub(crate) header: Header,
+    pub(crate) payload: P,
 }
 
+#[cfg(any(feature = "fuzz", feature = "testing"))]
 impl<'a, P: FromBytes<'a>> FromBytes<'a> for Message<P> {
     fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
         let header = Header::from_bytes(bytes)?;
-        assert_eq!(
-            header.length as usize,
-            bytes.bytes_left(),
-            "L2CAP reassembly not yet implemented"
-        );
+        if usize::from(header.length) != bytes.bytes_left() {
+            return Err(Error::InvalidLength);
+        }
 
         Ok(Self {
             header,
@@ -354,6 +410,14 @@ impl<'a, P: FromBytes<'a>> FromBytes<'a> for Message<P> {
     }
 }
 
+/// Parses an unfragmented L2CAP message and returns the destination channel and the payload.
+#[cfg(any(feature = "fuzz", feature = "testing"))]
+pub(crate) fn parse_message(raw: &[u8]) -> Result<(Channel, &[u8]), Error> {
+    let msg = Message::<&[u8]>::from_bytes(&mut ByteReader::new(raw))?;
+    Ok((msg.header.channel, msg.payload))
+}
+
+#[cfg(any(feature = "fuzz", feature = "testing"))]
 impl<P: ToBytes> ToBytes for Message<P> {
     fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
         self.header.to_bytes(writer)?;
@@ -362,20 +426,181 @@ impl<P: ToBytes> ToBytes for Message<P> {
     }
 }
 
+/// Identifies a data channel PDU enqueued for transmission.
+///
+/// Tickets are handed out by `Sender` in the order PDUs are put into the TX queue. The Link-Layer
+/// counts how many PDUs from the TX queue were acknowledged by the master, which allows
+/// `Connection::is_acknowledged` to tell whether the PDU identified by a ticket was delivered.
+/// `LinkLayer::tx_status` additionally reports PDUs that were dropped because the connection was
+/// closed before they were acknowledged.
+///
+/// Tickets are only meaningful for the connection they were obtained on. Both counters start over
+/// when a connection is established: The Link-Layer's at zero, and the `L2CAPState`'s at the
+/// number of PDUs still waiting in the TX queue, which are sent over the new connection. The
+/// `Responder` takes care of the latter when the Link-Layer reports the new connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TxTicket(u32);
+
+impl TxTicket {
+    /// Returns whether the PDU identified by `self` is among the first `count` PDUs of the TX
+    /// queue (taking wraparound into account).
+    pub(crate) fn is_within(&self, count: u32) -> bool {
+        count.wrapping_sub(self.0) < (1 << 31)
+    }
+}
+
+/// Delivery status of the PDU identified by a `TxTicket`, as returned by `LinkLayer::tx_status`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TxStatus {
+    /// The PDU is still queued, or was transmitted but not yet acknowledged by the master.
+    Pending,
+
+    /// The master has acknowledged the PDU.
+    Acknowledged,
+
+    /// The connection was closed before the master acknowledged the PDU.
+    ///
+    /// The PDU will never be delivered.
+    Dropped,
+}
+
+/// An incoming L2CAP message that spans several data channel PDUs.
+struct Reassembly<B> {
+    /// Destination channel and total payload length of the message being reassembled, if any.
+    message: Option<(Channel, u16)>,
+    /// Number of payload Bytes received so far.
+    received: u16,
+    /// Buffer for the payload of the message.
+    buf: B,
+}
+
 /// L2CAP channel manager and responder.
-#[derive(Debug)]
-pub struct L2CAPState<M: ChannelMapper> {
+///
+/// An `L2CAPState` belongs to a single Link-Layer connection, identified by its
+/// `ConnectionHandle`.
+///
+/// Messages that don't fit into a single data channel PDU are reassembled and fragmented in two
+/// message buffers of type `B`. `L2CAPState::new` creates an instance owning buffers large enough
+/// for any message. `L2CAPState::with_buffers` lets the application pass in smaller buffers (eg.
+/// sized for its `Config::MAX_ATT_MTU`), or buffers it placed in a specific memory region.
+pub struct L2CAPState<M: ChannelMapper, B: AsMut<[u8]> = [u8; MAX_MESSAGE_LEN]> {
     mapper: M,
+    connection: ConnectionHandle,
+    /// Number of PDUs enqueued in the TX queue so far (wrapping).
+    sent: u32,
+    reassembly: Reassembly<B>,
+    /// Buffer for outgoing messages that have to be fragmented.
+    tx_buf: B,
+}
+
+impl<M: ChannelMapper + fmt::Debug, B: AsMut<[u8]>> fmt::Debug for L2CAPState<M, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("L2CAPState")
+            .field("mapper", &self.mapper)
+            .field("connection", &self.connection)
+            .field("sent", &self.sent)
+            .field("reassembling", &self.reassembly.message)
+            .finish()
+    }
 }
 
 impl<M: ChannelMapper> L2CAPState<M> {
     /// Creates a new L2CAP state using the given channel configuration.
+    ///
+    /// The state is used for the single connection supported by the Link-Layer
+    /// (`ConnectionHandle::SINGLE`). It owns message buffers of `MAX_MESSAGE_LEN` Bytes each.
     pub fn new(mapper: M) -> Self {
-        Self { mapper }
+        Self::with_connection(ConnectionHandle::SINGLE, mapper)
+    }
+
+    /// Creates a new L2CAP state for the connection identified by `connection`.
+    ///
+    /// Like `new`, this owns message buffers of `MAX_MESSAGE_LEN` Bytes each.
+    pub fn with_connection(connection: ConnectionHandle, mapper: M) -> Self {
+        Self::with_buffers(
+            connection,
+            mapper,
+            [0; MAX_MESSAGE_LEN],
+            [0; MAX_MESSAGE_LEN],
+        )
+    }
+}
+
+impl<M: ChannelMapper, B: AsMut<[u8]>> L2CAPState<M, B> {
+    /// Creates a new L2CAP state for `connection` that works in caller-provided message buffers.
+    ///
+    /// `rx_buf` receives the payload of incoming messages that span several data channel PDUs,
+    /// larger messages are dropped. `tx_buf` holds outgoing messages (including their 4-Byte L2CAP
+    /// header) that have to be fragmented, sending larger messages fails. Messages that fit into a
+    /// single data channel PDU (27 Bytes, eg. any ATT PDU with the default `ATT_MTU` of 23) don't
+    /// need any buffer space.
+    pub fn with_buffers(connection: ConnectionHandle, mapper: M, rx_buf: B, tx_buf: B) -> Self {
+        Self {
+            mapper,
+            connection,
+            sent: 0,
+            reassembly: Reassembly {
+                message: None,
+                received: 0,
+                buf: rx_buf,
+            },
+            tx_buf,
+        }
+    }
+
+    /// Returns the largest message payload that can be received and sent using the message
+    /// buffers.
+    ///
+    /// Messages that fit into a single data channel PDU don't need buffer space, so this is at
+    /// least 23, and at most `MAX_PAYLOAD_LEN`.
+    pub fn max_buffered_payload(&mut self) -> u16 {
+        let rx = self.reassembly.buf.as_mut().len();
+        let tx = self
+            .tx_buf
+            .as_mut()
+            .len()
+            .saturating_sub(Header::SIZE.into());
+        let len = cmp::max(
+            cmp::min(rx, tx),
+            MIN_DATA_PAYLOAD_BUF - usize::from(Header::SIZE),
+        );
+        cmp::min(len, MAX_PAYLOAD_LEN.into()) as u16
+    }
+
+    /// Returns the handle of the connection this L2CAP state belongs to.
+    pub fn connection_handle(&self) -> ConnectionHandle {
+        self.connection
+    }
+
+    /// Returns the ATT server listening on channel `0x0004`.
+    pub fn att_server(&mut self) -> &mut AttributeServer<M::AttributeProvider> {
+        self.mapper.att().into_protocol()
+    }
+
+    /// Returns a mutable reference to the `ChannelMapper`.
+    pub fn channel_mapper(&mut self) -> &mut M {
+        &mut self.mapper
+    }
+
+    /// Resets the state belonging to the previous connection when a new one is established.
+    ///
+    /// `queued` is the number of PDUs in the TX queue, which the Link-Layer sends over the new
+    /// connection. A partially received message is dropped.
+    pub(crate) fn reset_connection(&mut self, queued: u32) {
+        self.sent = queued;
+        self.reassembly.message = None;
+        self.reassembly.received = 0;
+    }
+
+    /// Records a PDU that was put into the TX queue without going through a `Sender`.
+    ///
+    /// This keeps the `TxTicket`s handed out afterwards in sync with the Link-Layer.
+    pub(crate) fn record_sent(&mut self) {
+        self.sent = self.sent.wrapping_add(1);
     }
 
     /// Gives this instance the ability to transmit packets.
-    pub fn tx<'a, P: Producer>(&'a mut self, tx: &'a mut P) -> L2CAPStateTx<'a, M, P> {
+    pub fn tx<'a, P: Producer>(&'a mut self, tx: &'a mut P) -> L2CAPStateTx<'a, M, P, B> {
         L2CAPStateTx { l2cap: self, tx }
     }
 }
@@ -386,25 +611,46 @@ impl<M: ChannelMapper> L2CAPState<M> {
 /// as a device-initiated packet (eg. an attribute notification).
 pub struct Sender<'a> {
     /// The protocol's max. outgoing PDU size.
-    pdu: u8,
+    pdu: u16,
+
+    /// Buffer holding messages that have to be fragmented.
+    buf: &'a mut [u8],
 
     /// Data PDU channel.
     tx: &'a mut dyn Producer,
 
     /// Channel to which the response will be addressed.
     channel: Channel,
+
+    /// Connection over which the message will be sent.
+    connection: ConnectionHandle,
+
+    /// Number of PDUs enqueued over this connection, counted by the `L2CAPState`.
+    sent: &'a mut u32,
 }
 
 impl<'a> Sender<'a> {
     /// Creates a `Sender` from a `Producer`, ensuring that sufficient free space is available to
     /// fit a PDU described by `chdata`.
     ///
-    /// If there is not enough space in `tx`, returns `None`.
-    fn new<T: ?Sized>(chdata: &ChannelData<'_, T>, tx: &'a mut dyn Producer) -> Option<Self> {
-        let free = tx.free_space();
-        let needed = chdata.pdu_size() + Header::SIZE;
-        if free < needed {
-            debug!("{} free bytes, need {}", free, needed);
+    /// If there is not enough space in `tx`, returns `None`. Messages that don't fit in a single
+    /// data channel PDU need a free slot in `tx` for every fragment.
+    fn new<T: ?Sized>(
+        chdata: &ChannelData<'_, T>,
+        connection: ConnectionHandle,
+        tx: &'a mut dyn Producer,
+        sent: &'a mut u32,
+        buf: &'a mut [u8],
+    ) -> Option<Self> {
+        let free = usize::from(tx.free_space());
+        let needed = usize::from(chdata.pdu_size()) + usize::from(Header::SIZE);
+        let fragments = needed.div_ceil(MIN_DATA_PAYLOAD_BUF);
+        let free_slots = tx.capacity() - tx.occupancy();
+        if free < needed.min(MIN_DATA_PAYLOAD_BUF) || free_slots < fragments {
+            debug!(
+                "{} free bytes in {} PDUs, need {} in {}",
+                free, free_slots, needed, fragments
+            );
             return None;
         }
 
@@ -412,11 +658,39 @@ impl<'a> Sender<'a> {
         let pdu = chdata.pdu_size();
         Some(Sender {
             pdu,
+            buf,
             tx,
             channel: resp_channel,
+            connection,
+            sent,
         })
     }
 
+    /// Returns the handle of the connection over which messages are sent.
+    ///
+    /// When responding to an incoming message, this is also the connection the message was
+    /// received on.
+    pub fn connection_handle(&self) -> ConnectionHandle {
+        self.connection
+    }
+
+    /// Returns the `TxTicket` of the last message enqueued over this connection.
+    ///
+    /// After a successful `send` or `send_with`, this identifies the message that was just sent.
+    pub fn last_ticket(&self) -> TxTicket {
+        TxTicket(*self.sent)
+    }
+
+    /// Returns whether the Link-Layer has taken the message identified by `ticket` out of the TX
+    /// queue.
+    ///
+    /// This is the case once the message was transmitted for the first time. It doesn't mean that
+    /// the message was acknowledged (see `Connection::is_acknowledged` for that).
+    pub fn is_dequeued(&self, ticket: TxTicket) -> bool {
+        let queued = self.tx.occupancy() as u32;
+        ticket.is_within(self.sent.wrapping_sub(queued))
+    }
+
     /// Enqueues an L2CAP message to be sent over the data connection.
     ///
     /// L2CAP header (including the destination endpoint's channel) and the data channel PDU header
@@ -432,8 +706,9 @@ impl<'a> Sender<'a> {
     /// L2CAP header and data channel PDU header will be added automatically. The closure `f` only
     /// has to write the protocol PDU to transmit over L2CAP.
     ///
-    /// The L2CAP implementation will ensure that there are exactly `Protocol::RSP_PDU_SIZE` Bytes
-    /// available in the `ByteWriter` passed to the closure.
+    /// The L2CAP implementation will ensure that there are exactly `Protocol::max_pdu_size` Bytes
+    /// available in the `ByteWriter` passed to the closure. If the message doesn't fit in a single
+    /// data channel PDU, it is fragmented automatically.
     pub fn send_with<T, E>(
         &mut self,
         f: impl FnOnce(&mut ByteWriter<'_>) -> Result<T, E>,
@@ -441,112 +716,229 @@ impl<'a> Sender<'a> {
     where
         E: From<Error>,
     {
-        // FIXME automatic fragmentation is not implemented
+        if usize::from(self.pdu + u16::from(Header::SIZE)) > MIN_DATA_PAYLOAD_BUF {
+            return self.send_fragmented(f);
+        }
 
         // The payload length goes into the header, so we have to skip that part and write it later
         let mut f = Some(f);
         let channel = self.channel;
         let pdu = self.pdu;
         let mut r = None;
-        let r2 = self.tx.produce_dyn(
-            pdu + Header::SIZE,
-            &mut |writer: &mut ByteWriter<'_>| -> Result<_, Error> {
-                let mut header_writer = writer.split_off(usize::from(Header::SIZE))?;
-
-                // The PDU size is determined based on how much space is left in `writer`, so we can't
-                // just `split_off` the protocol's PDU size.
-                assert!(writer.space_left() >= pdu.into());
-                let mut payload_writer = ByteWriter::new(&mut writer.rest()[..pdu.into()]);
-                let left = payload_writer.space_left();
-                let result = f.take().unwrap()(&mut payload_writer);
-                let is_ok = result.is_ok();
-                r = Some(result);
-                let used = left - payload_writer.space_left();
-                writer.skip(used).unwrap();
-
-                assert!(used < 0xFFFF);
-                Header {
-                    length: used as u16,
-                    channel,
-                }
-                .to_bytes(&mut header_writer)?;
-
-                assert_eq!(header_writer.space_left(), 0);
-
-                if is_ok {
-                    Ok(Llid::DataStart)
-                } else {
-                    // The error returned doesn't matter much. It just has to be an `Err` so the PDU
-                    // isn't actually sent.
-                    Err(Error::InvalidValue)
-                }
-            },
-        );
+        let r2 =
+            self.tx.produce_dyn(
+                pdu as u8 + Header::SIZE,
+                &mut |writer: &mut ByteWriter<'_>| -> Result<_, Error> {
+                    let mut header_writer = writer.split_off(usize::from(Header::SIZE))?;
+
+                    // The PDU size is determined based on how much space is left in `writer`, so we
+                    // can't just `split_off` the protocol's PDU size.
+                    assert!(writer.space_left() >= pdu.into());
+                    let mut payload_writer = ByteWriter::new(&mut writer.rest()[..pdu.into()]);
+                    let left = payload_writer.space_left();
+                    let result = f.take().unwrap()(&mut payload_writer);
+                    let is_ok = result.is_ok();
+                    r = Some(result);
+                    let used = left - payload_writer.space_left();
+                    writer.skip(used).unwrap();
+
+                    assert!(used < 0xFFFF);
+                    Header {
+                        length: used as u16,
+                        channel,
+                    }
+                    .to_bytes(&mut header_writer)?;
+
+                    assert_eq!(header_writer.space_left(), 0);
+
+                    if is_ok {
+                        Ok(Llid::DataStart)
+                    } else {
+                        // The error returned doesn't matter much. It just has to be an `Err` so the
+                        // PDU isn't actually sent.
+                        Err(Error::InvalidValue)
+                    }
+                },
+            );
 
-        if let Err(e) = r2 {
-            if e != Error::InvalidValue {
+        match r2 {
+            Ok(()) => *self.sent = self.sent.wrapping_add(1),
+            Err(e) if e != Error::InvalidValue => {
                 // Legitimate error
                 return Err(e.into());
             }
+            Err(_) => {}
         }
 
         r.unwrap()
     }
+
+    /// Encodes a message into the L2CAP TX buffer, then enqueues it as a start fragment followed by
+    /// as many continuation fragments as needed.
+    ///
+    /// If the TX buffer is smaller than the protocol's PDU size, the message is limited to the
+    /// space in the buffer, and writing more fails with `Error::Eof`.
+    fn send_fragmented<T, E>(
+        &mut self,
+        f: impl FnOnce(&mut ByteWriter<'_>) -> Result<T, E>,
+    ) -> Result<T, E>
+    where
+        E: From<Error>,
+    {
+        if self.buf.len() < usize::from(Header::SIZE) {
+            return Err(Error::Eof.into());
+        }
+        let (header, payload) = self.buf.split_at_mut(usize::from(Header::SIZE));
+        let len = cmp::min(usize::from(self.pdu), payload.len());
+        let mut payload_writer = ByteWriter::new(&mut payload[..len]);
+        let left = payload_writer.space_left();
+        let result = f(&mut payload_writer)?;
+        let used = left - payload_writer.space_left();
+        Header {
+            length: used as u16,
+            channel: self.channel,
+        }
+        .to_bytes(&mut ByteWriter::new(header))?;
+
+        // `Sender::new` made sure that there's a free slot in the queue for every fragment
+        let message = &self.buf[..usize::from(Header::SIZE) + used];
+        for (i, fragment) in message.chunks(MIN_DATA_PAYLOAD_BUF).enumerate() {
+            let llid = if i == 0 {
+                Llid::DataStart
+            } else {
+                Llid::DataCont
+            };
+            self.tx.produce_dyn(fragment.len() as u8, &mut |writer| {
+                writer.write_slice(fragment)?;
+                Ok(llid)
+            })?;
+            *self.sent = self.sent.wrapping_add(1);
+        }
+
+        Ok(result)
+    }
 }
 
 /// An `L2CAPState` with the ability to transmit packets.
 ///
 /// Derefs to the underlying `L2CAPState`.
-pub struct L2CAPStateTx<'a, M: ChannelMapper, P: Producer> {
-    l2cap: &'a mut L2CAPState<M>,
+pub struct L2CAPStateTx<'a, M: ChannelMapper, P: Producer, B: AsMut<[u8]> = [u8; MAX_MESSAGE_LEN]> {
+    l2cap: &'a mut L2CAPState<M, B>,
     tx: &'a mut P,
 }
 
-impl<'a, M: ChannelMapper, P: Producer> L2CAPStateTx<'a, M, P> {
+impl<'a, M: ChannelMapper, P: Producer, B: AsMut<[u8]>> L2CAPStateTx<'a, M, P, B> {
     /// Process the start of a new L2CAP message (or a complete, unfragmented message).
     ///
     /// If the incoming message is unfragmented, it will be forwarded to the protocol listening on
     /// the addressed channel, and a response may be sent.
+    ///
+    /// If the length in the L2CAP header is larger than the received payload, this is the start of
+    /// a fragmented message. It is buffered until the rest arrives via `process_cont`. Any message
+    /// that was being reassembled is discarded.
+    ///
+    /// If the declared length is smaller than the received payload, or exceeds `MAX_PAYLOAD_LEN` or
+    /// the size of the reassembly buffer, the message is dropped and `Error::InvalidLength` is
+    /// returned.
     pub fn process_start(&mut self, message: &[u8]) -> Consume<()> {
-        let msg = match Message::<&[u8]>::from_bytes(&mut ByteReader::new(message)) {
-            Ok(msg) => msg,
-            Err(e) => return Consume::always(Err(e)),
+        let mut bytes = ByteReader::new(message);
+        let header = match Header::from_bytes(&mut bytes) {
+            Ok(header) => header,
+            Err(e) => {
+                warn!("dropping malformed L2CAP message: {:?}", HexSlice(message));
+                return Consume::always(Err(e));
+            }
         };
 
-        if usize::from(msg.header.length) != msg.payload.len() {
-            // Lengths mismatch => Reassembly needed
-            unimplemented!("L2CAP reassembly");
+        if let Some((channel, _)) = self.l2cap.reassembly.message.take() {
+            warn!("discarding incomplete L2CAP message to {:?}", channel);
         }
 
-        self.dispatch(msg.header.channel, msg.payload)
+        let payload = bytes.read_rest();
+        let length = usize::from(header.length);
+        if length == payload.len() {
+            let l2cap = &mut *self.l2cap;
+            dispatch(
+                &mut l2cap.mapper,
+                l2cap.connection,
+                &mut l2cap.sent,
+                l2cap.tx_buf.as_mut(),
+                self.tx,
+                header.channel,
+                payload,
+            )
+        } else if length > payload.len()
+            && header.length <= MAX_PAYLOAD_LEN
+            && length <= self.l2cap.reassembly.buf.as_mut().len()
+        {
+            let reassembly = &mut self.l2cap.reassembly;
+            reassembly.buf.as_mut()[..payload.len()].copy_from_slice(payload);
+            reassembly.received = payload.len() as u16;
+            reassembly.message = Some((header.channel, header.length));
+            Consume::always(Ok(()))
+        } else {
+            warn!(
+                "dropping L2CAP message with invalid length: {:?}",
+                HexSlice(message)
+            );
+            Consume::always(Err(Error::InvalidLength))
+        }
     }
 
     /// Process continuation of an L2CAP message.
     ///
-    /// This is not yet implemented and will always panic.
-    pub fn process_cont(&mut self, _data: &[u8]) -> Consume<()> {
-        unimplemented!("reassembly")
-    }
-
-    /// Dispatches a fully reassembled L2CAP message to the protocol listening on the addressed
-    /// channel.
-    fn dispatch(&mut self, channel: Channel, payload: &[u8]) -> Consume<()> {
-        if let Some(mut chdata) = self.l2cap.mapper.lookup(channel) {
-            let sender = if let Some(sender) = Sender::new(&chdata, self.tx) {
-                sender
-            } else {
-                return Consume::never(Ok(()));
-            };
+    /// The data is appended to the message started by the last `process_start` call. Once the
+    /// message is complete, it is forwarded to the protocol listening on the addressed channel.
+    ///
+    /// Continuation fragments without a started message are dropped. If the fragment exceeds the
+    /// length declared in the L2CAP header, the whole message is dropped and
+    /// `Error::InvalidLength` is returned.
+    pub fn process_cont(&mut self, data: &[u8]) -> Consume<()> {
+        let l2cap = &mut *self.l2cap;
+        let (channel, length) = match l2cap.reassembly.message {
+            Some(message) => message,
+            None => {
+                warn!(
+                    "dropping unexpected L2CAP continuation: {:?}",
+                    HexSlice(data)
+                );
+                return Consume::always(Ok(()));
+            }
+        };
 
-            Consume::always(chdata.protocol().process_message(payload, sender))
-        } else {
+        let received = usize::from(l2cap.reassembly.received);
+        let end = received + data.len();
+        if end > usize::from(length) {
             warn!(
-                "ignoring message sent to unconnected channel {:?}: {:?}",
-                channel,
-                HexSlice(payload)
+                "dropping L2CAP message to {:?} exceeding its length",
+                channel
             );
-            Consume::always(Ok(()))
+            l2cap.reassembly.message = None;
+            return Consume::always(Err(Error::InvalidLength));
         }
+
+        l2cap.reassembly.buf.as_mut()[received..end].copy_from_slice(data);
+        if end < usize::from(length) {
+            l2cap.reassembly.received = end as u16;
+            return Consume::always(Ok(()));
+        }
+
+        let (consume, result) = dispatch(
+            &mut l2cap.mapper,
+            l2cap.connection,
+            &mut l2cap.sent,
+            l2cap.tx_buf.as_mut(),
+            self.tx,
+            channel,
+            &l2cap.reassembly.buf.as_mut()[..end],
+        )
+        .into_parts();
+
+        // If the message couldn't be processed yet, the last fragment is passed in again later
+        if consume {
+            l2cap.reassembly.message = None;
+        }
+        Consume::new(consume, result)
     }
 
     /// Prepares for sending data using the Attribute Protocol.
@@ -558,22 +950,379 @@ impl<'a, M: ChannelMapper, P: Producer> L2CAPStateTx<'a, M, P> {
     /// Returns `None` if there's not enough space in the TX packet queue to send an ATT PDU. If
     /// that happens, calling this method again at a later time (after the Link-Layer had time to
     /// transmit more packets) might succeed.
+    ///
+    /// The ATT PDU is sent over the connection this `L2CAPState` belongs to. This is a convenience
+    /// for the single-connection case, and is equivalent to calling `att_on` with
+    /// `connection_handle()`.
     pub fn att(&mut self) -> Option<att::AttributeServerTx<'_, M::AttributeProvider>> {
-        let att = self.l2cap.mapper.att();
-        Sender::new(&att, self.tx).map(move |sender| att.into_protocol().with_sender(sender))
+        let connection = self.l2cap.connection;
+        self.att_on(connection)
+    }
+
+    /// Prepares for sending data using the Attribute Protocol over the connection `connection`.
+    ///
+    /// Returns `None` if `connection` does not identify the connection this `L2CAPState` belongs
+    /// to, or if there's not enough space in the TX packet queue to send an ATT PDU.
+    pub fn att_on(
+        &mut self,
+        connection: ConnectionHandle,
+    ) -> Option<att::AttributeServerTx<'_, M::AttributeProvider>> {
+        if connection != self.l2cap.connection {
+            warn!("no L2CAP state for {:?}", connection);
+            return None;
+        }
+
+        let l2cap = &mut *self.l2cap;
+        let att = l2cap.mapper.att();
+        Sender::new(
+            &att,
+            connection,
+            self.tx,
+            &mut l2cap.sent,
+            l2cap.tx_buf.as_mut(),
+        )
+        .map(move |sender| att.into_protocol().with_sender(sender))
+    }
+}
+
+/// Dispatches a fully reassembled L2CAP message to the protocol listening on the addressed
+/// channel.
+fn dispatch<M: ChannelMapper>(
+    mapper: &mut M,
+    connection: ConnectionHandle,
+    sent: &mut u32,
+    tx_buf: &mut [u8],
+    tx: &mut dyn Producer,
+    channel: Channel,
+    payload: &[u8],
+) -> Consume<()> {
+    if let Some(mut chdata) = mapper.lookup(channel) {
+        let sender = if let Some(sender) = Sender::new(&chdata, connection, tx, sent, tx_buf) {
+            sender
+        } else {
+            return Consume::never(Ok(()));
+        };
+
+        Consume::always(chdata.protocol().process_message(payload, sender))
+    } else {
+        warn!(
+            "ignoring message sent to unconnected channel {:?}: {:?}",
+            channel,
+            HexSlice(payload)
+        );
+        Consume::always(Ok(()))
     }
 }
 
-impl<'a, M: ChannelMapper, P: Producer> Deref for L2CAPStateTx<'a, M, P> {
-    type Target = L2CAPState<M>;
+impl<'a, M: ChannelMapper, P: Producer, B: AsMut<[u8]>> Deref for L2CAPStateTx<'a, M, P, B> {
+    type Target = L2CAPState<M, B>;
 
     fn deref(&self) -> &Self::Target {
         &self.l2cap
     }
 }
 
-impl<'a, M: ChannelMapper, P: Producer> DerefMut for L2CAPStateTx<'a, M, P> {
+impl<'a, M: ChannelMapper, P: Producer, B: AsMut<[u8]>> DerefMut for L2CAPStateTx<'a, M, P, B> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.l2cap
     }
 }
+
+/// Builds an L2CAP message on `channel` whose header declares a payload of `length` Bytes.
+#[cfg(test)]
+pub(crate) fn message(length: u16, channel: Channel, payload: &[u8]) -> std::vec::Vec<u8> {
+    let mut message = std::vec::Vec::new();
+    message.extend_from_slice(&length.to_le_bytes());
+    message.extend_from_slice(&channel.as_raw().to_le_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+/// Passes a message carrying `payload` on `channel` to `l2cap`, and returns the payload of the
+/// response, if one was sent.
+///
+/// Panics if the response is sent on a different channel.
+#[cfg(test)]
+pub(crate) fn exchange<M: ChannelMapper>(
+    l2cap: &mut L2CAPState<M>,
+    channel: Channel,
+    payload: &[u8],
+) -> Option<std::vec::Vec<u8>> {
+    use crate::link::queue::{Consumer, PacketQueue, SimpleQueue};
+
+    let mut queue = SimpleQueue::new();
+    let (mut tx, mut rx) = (&mut queue).split();
+    let _ = l2cap
+        .tx(&mut tx)
+        .process_start(&message(payload.len() as u16, channel, payload));
+    rx.consume_raw_with(|_, response| {
+        assert_eq!(
+            response[2..4],
+            channel.as_raw().to_le_bytes(),
+            "response sent on wrong channel"
+        );
+        Consume::always(Ok(response[4..].to_vec()))
+    })
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            att::Handle,
+            link::{
+                data::Llid,
+                mock::MockQueue,
+                queue::{Consume, Consumer, PacketQueue, SimpleQueue},
+            },
+            security::NoSecurity,
+        },
+    };
+
+    /// Passes `message` to a fresh `L2CAPState` like the `Responder` does, and returns the result
+    /// and whether a response was sent.
+    fn process(message: &[u8]) -> (Result<(), Error>, bool) {
+        let mut incoming = SimpleQueue::new();
+        let (mut rx_prod, mut rx) = (&mut incoming).split();
+        rx_prod
+            .produce_with(message.len() as u8, |writer| -> Result<_, Error> {
+                writer.write_slice(message)?;
+                Ok(Llid::DataStart)
+            })
+            .unwrap();
+
+        let mut l2cap = L2CAPState::new(BleChannelMap::<NoAttributes, NoSecurity>::empty());
+        let mut outgoing = SimpleQueue::new();
+        let (mut tx, tx_cons) = (&mut outgoing).split();
+        let result = rx.consume_raw_with(|_, message| l2cap.tx(&mut tx).process_start(message));
+        (result, tx_cons.has_data())
+    }
+
+    #[test]
+    fn header_length_matches() {
+        // ATT Exchange MTU Request
+        let (result, responded) = process(&message(3, Channel::ATT, &[0x02, 23, 0]));
+        assert_eq!(result, Ok(()));
+        assert!(responded);
+    }
+
+    #[test]
+    fn header_length_too_long() {
+        // Start of a fragmented message, which is buffered
+        let (result, responded) = process(&message(4, Channel::ATT, &[0x02, 23, 0]));
+        assert_eq!(result, Ok(()));
+        assert!(!responded);
+
+        // Can't be reassembled
+        let (result, responded) = process(&message(MAX_PAYLOAD_LEN + 1, Channel::ATT, &[0x02]));
+        assert_eq!(result, Err(Error::InvalidLength));
+        assert!(!responded);
+
+        let (result, responded) = process(&message(0xFFFF, Channel::ATT, &[]));
+        assert_eq!(result, Err(Error::InvalidLength));
+        assert!(!responded);
+    }
+
+    #[test]
+    fn header_length_too_short() {
+        // Trailing Bytes after the declared payload are not ignored
+        let (result, responded) = process(&message(3, Channel::ATT, &[0x02, 23, 0, 0xFF]));
+        assert_eq!(result, Err(Error::InvalidLength));
+        assert!(!responded);
+
+        let (result, responded) = process(&message(0, Channel::ATT, &[0x02]));
+        assert_eq!(result, Err(Error::InvalidLength));
+        assert!(!responded);
+    }
+
+    #[test]
+    fn header_truncated() {
+        for len in 0..usize::from(Header::SIZE) {
+            let (result, responded) = process(&[0x00, 0x00, 0x04, 0x00][..len]);
+            assert_eq!(result, Err(Error::Eof));
+            assert!(!responded);
+        }
+    }
+
+    #[test]
+    fn fragmented_message_reassembled() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::<NoAttributes, NoSecurity>::empty());
+        let mut outgoing = SimpleQueue::new();
+        let (mut tx, tx_cons) = (&mut outgoing).split();
+
+        // ATT Exchange MTU Request, split into 3 fragments
+        let start = message(3, Channel::ATT, &[0x02]);
+        let consume = l2cap.tx(&mut tx).process_start(&start);
+        assert_eq!(consume.into_parts(), (true, Ok(())));
+        let consume = l2cap.tx(&mut tx).process_cont(&[23]);
+        assert_eq!(consume.into_parts(), (true, Ok(())));
+        assert!(!tx_cons.has_data());
+
+        let consume = l2cap.tx(&mut tx).process_cont(&[0]);
+        assert_eq!(consume.into_parts(), (true, Ok(())));
+        assert!(tx_cons.has_data());
+    }
+
+    #[test]
+    fn fragmented_message_invalid() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::<NoAttributes, NoSecurity>::empty());
+        let mut outgoing = SimpleQueue::new();
+        let (mut tx, tx_cons) = (&mut outgoing).split();
+
+        // Continuation without a start is dropped
+        let consume = l2cap.tx(&mut tx).process_cont(&[0x02, 23, 0]);
+        assert_eq!(consume.into_parts(), (true, Ok(())));
+
+        // Fragments exceeding the declared length drop the whole message
+        let start = message(3, Channel::ATT, &[0x02]);
+        let consume = l2cap.tx(&mut tx).process_start(&start);
+        assert_eq!(consume.into_parts(), (true, Ok(())));
+        let consume = l2cap.tx(&mut tx).process_cont(&[23, 0, 0]);
+        assert_eq!(consume.into_parts(), (true, Err(Error::InvalidLength)));
+        let consume = l2cap.tx(&mut tx).process_cont(&[0]);
+        assert_eq!(consume.into_parts(), (true, Ok(())));
+
+        // A new message discards the incomplete one
+        let consume = l2cap.tx(&mut tx).process_start(&start);
+        assert_eq!(consume.into_parts(), (true, Ok(())));
+        let consume = l2cap
+            .tx(&mut tx)
+            .process_start(&message(1, Channel::ATT, &[0x1E]));
+        assert_eq!(consume.into_parts(), (true, Ok(())));
+        let consume = l2cap.tx(&mut tx).process_cont(&[23, 0]);
+        assert_eq!(consume.into_parts(), (true, Ok(())));
+        assert!(!tx_cons.has_data());
+    }
+
+    #[test]
+    fn large_message_fragmented() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::<NoAttributes, NoSecurity>::empty());
+        l2cap.att_server().set_max_mtu(100).unwrap();
+        let (mut tx, mut tx_cons) = MockQueue::with_capacity(4).split();
+
+        // Exchange MTU Request/Response
+        let request = message(3, Channel::ATT, &[0x02, 100, 0]);
+        let consume = l2cap.tx(&mut tx).process_start(&request);
+        assert_eq!(consume.into_parts(), (true, Ok(())));
+        tx_cons
+            .consume_raw_with(|_, _| Consume::always(Ok(())))
+            .unwrap();
+
+        // The notification is sent in 3 fragments: 27 + 27 + 13 Bytes
+        let value = [0xAB; 60];
+        {
+            let mut l2cap = l2cap.tx(&mut tx);
+            let att = l2cap.att().unwrap();
+            att.notify_raw(Handle::from_raw(0x0003), &value).unwrap();
+        }
+        let mut fragments = std::vec::Vec::new();
+        while let Ok(fragment) = tx_cons.consume_raw_with(|header, payload| {
+            Consume::always(Ok((header.llid(), payload.to_vec())))
+        }) {
+            fragments.push(fragment);
+        }
+
+        let llids = fragments
+            .iter()
+            .map(|(llid, _)| *llid)
+            .collect::<std::vec::Vec<_>>();
+        assert_eq!(llids, [Llid::DataStart, Llid::DataCont, Llid::DataCont]);
+        let sent = fragments
+            .into_iter()
+            .flat_map(|(_, payload)| payload)
+            .collect::<std::vec::Vec<_>>();
+        let mut pdu = std::vec![0x1B, 0x03, 0x00];
+        pdu.extend_from_slice(&value);
+        assert_eq!(sent, message(63, Channel::ATT, &pdu));
+        assert_eq!(l2cap.sent, 4);
+    }
+
+    #[test]
+    fn caller_provided_buffers() {
+        let (mut rx_buf, mut tx_buf) = ([0; 35], [0; 35]);
+        let mut l2cap = L2CAPState::with_buffers(
+            ConnectionHandle::SINGLE,
+            BleChannelMap::<NoAttributes, NoSecurity>::empty(),
+            &mut rx_buf[..],
+            &mut tx_buf[..],
+        );
+        l2cap.att_server().set_max_mtu(100).unwrap();
+        let (mut tx, mut tx_cons) = MockQueue::with_capacity(4).split();
+
+        // Messages that don't fit into the RX buffer can't be reassembled
+        let request = message(36, Channel::ATT, &[0x02]);
+        let consume = l2cap.tx(&mut tx).process_start(&request);
+        assert_eq!(consume.into_parts(), (true, Err(Error::InvalidLength)));
+        let request = message(35, Channel::ATT, &[0x02]);
+        let consume = l2cap.tx(&mut tx).process_start(&request);
+        assert_eq!(consume.into_parts(), (true, Ok(())));
+
+        // Exchange MTU Request/Response
+        let request = message(3, Channel::ATT, &[0x02, 100, 0]);
+        let _ = l2cap.tx(&mut tx).process_start(&request);
+        tx_cons
+            .consume_raw_with(|_, _| Consume::always(Ok(())))
+            .unwrap();
+
+        // Notifications are fragmented in the TX buffer, and truncated to fit into it
+        {
+            let mut l2cap = l2cap.tx(&mut tx);
+            let att = l2cap.att().unwrap();
+            att.notify_raw(Handle::from_raw(0x0003), &[0xAB; 60])
+                .unwrap();
+        }
+        let mut lengths = std::vec::Vec::new();
+        while let Ok(len) =
+            tx_cons.consume_raw_with(|_, payload| Consume::always(Ok(payload.len())))
+        {
+            lengths.push(len);
+        }
+        assert_eq!(lengths, [27, 8]);
+    }
+
+    #[test]
+    fn fragmented_pdu_needs_queue_space() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::<NoAttributes, NoSecurity>::empty());
+        l2cap.att_server().set_max_mtu(100).unwrap();
+        let (mut tx, mut tx_cons) = MockQueue::with_capacity(4).split();
+        let request = message(3, Channel::ATT, &[0x02, 100, 0]);
+        let _ = l2cap.tx(&mut tx).process_start(&request);
+
+        // An ATT_MTU of 100 needs 4 free PDUs
+        assert!(l2cap.tx(&mut tx).att().is_none());
+        tx_cons
+            .consume_raw_with(|_, _| Consume::always(Ok(())))
+            .unwrap();
+        assert!(l2cap.tx(&mut tx).att().is_some());
+    }
+
+    #[test]
+    fn smp_channel_routing() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::<NoAttributes, NoSecurity>::empty());
+        let mut outgoing = SimpleQueue::new();
+        let (mut tx, mut tx_cons) = (&mut outgoing).split();
+
+        // A Pairing Request on the Security Manager channel reaches the security manager...
+        let request = [0x01, 0x03, 0x00, 0x01, 0x10, 0x00, 0x01];
+        let _ =
+            l2cap
+                .tx(&mut tx)
+                .process_start(&message(7, Channel::LE_SECURITY_MANAGER, &request));
+        assert!(l2cap
+            .channel_mapper()
+            .security_manager()
+            .take_pairing_failure()
+            .is_some());
+
+        // ...and its response is sent back on the same channel
+        let response = tx_cons
+            .consume_raw_with(|_, payload| Consume::always(Ok(payload.to_vec())))
+            .unwrap();
+        assert_eq!(
+            response,
+            message(2, Channel::LE_SECURITY_MANAGER, &[0x05, 0x05])
+        );
+    }
+}
@@ -1,8 +1,12 @@
 //! L2CAP Signaling channel PDUs and functions (`0x0005`).
+//!
+//! Rubble only supports the fixed L2CAP channels, so the signaling channel is only used to answer
+//! requests sent by the other device. Requests that aren't supported are answered with a
+//! *Command Reject* response.
 
 use {
-    super::{Protocol, ProtocolObj, Sender},
-    crate::Error,
+    super::{Channel, Protocol, ProtocolObj, Sender},
+    crate::{bytes::*, utils::HexSlice, Error},
 };
 
 enum_with_unknown! {
@@ -12,6 +16,8 @@ enum_with_unknown! {
         CommandReject = 0x01,
         DisconnectionReq = 0x06,
         DisconnectionRsp = 0x07,
+        EchoReq = 0x08,
+        EchoRsp = 0x09,
         ConnectionParameterUpdateReq = 0x12,
         ConnectionParameterUpdateRsp = 0x13,
         CreditBasedConnectionReq = 0x14,
@@ -30,6 +36,135 @@ enum_with_unknown! {
     }
 }
 
+/// A signaling PDU, containing a single command.
+#[derive(Debug, Copy, Clone)]
+struct Pdu<'a> {
+    /// Identifier used to match responses with their request.
+    ///
+    /// Responses must use the same identifier as the request they respond to.
+    identifier: u8,
+    command: Command<'a>,
+}
+
+impl<'a> FromBytes<'a> for Pdu<'a> {
+    fn from_bytes(bytes: &mut ByteReader<'a>) -> Result<Self, Error> {
+        let code = Code::from(bytes.read_u8()?);
+        let identifier = bytes.read_u8()?;
+        let length = bytes.read_u16_le()?;
+        let data = &mut ByteReader::new(bytes.read_slice(usize::from(length))?);
+
+        let command = match code {
+            Code::CommandReject => Command::Reject {
+                reason: RejectReason::from(data.read_u16_le()?),
+                data: data.read_rest(),
+            },
+            Code::DisconnectionReq => Command::DisconnectionReq {
+                dcid: Channel::from_bytes(data)?,
+                scid: Channel::from_bytes(data)?,
+            },
+            Code::DisconnectionRsp => Command::DisconnectionRsp {
+                dcid: Channel::from_bytes(data)?,
+                scid: Channel::from_bytes(data)?,
+            },
+            Code::EchoReq => Command::EchoReq {
+                data: data.read_rest(),
+            },
+            Code::EchoRsp => Command::EchoRsp {
+                data: data.read_rest(),
+            },
+            _ => Command::Unknown {
+                code,
+                data: data.read_rest(),
+            },
+        };
+
+        Ok(Self {
+            identifier,
+            command,
+        })
+    }
+}
+
+impl ToBytes for Pdu<'_> {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(self.command.code().into())?;
+        writer.write_u8(self.identifier)?;
+
+        // The length of the command data is written once the data is encoded
+        let mut length = writer.split_off(2)?;
+        let left = writer.space_left();
+        match self.command {
+            Command::Reject { reason, data } => {
+                writer.write_u16_le(reason.into())?;
+                writer.write_slice(data)?;
+            }
+            Command::DisconnectionReq { dcid, scid } | Command::DisconnectionRsp { dcid, scid } => {
+                dcid.to_bytes(writer)?;
+                scid.to_bytes(writer)?;
+            }
+            Command::EchoReq { data }
+            | Command::EchoRsp { data }
+            | Command::Unknown { data, .. } => {
+                writer.write_slice(data)?;
+            }
+        }
+        length.write_u16_le((left - writer.space_left()) as u16)?;
+
+        Ok(())
+    }
+}
+
+/// A signaling command.
+#[derive(Debug, Copy, Clone)]
+enum Command<'a> {
+    /// `0x01` Command Reject.
+    ///
+    /// Sent in response to a request that is not supported or invalid.
+    Reject {
+        reason: RejectReason,
+        /// Reason-specific data.
+        data: &'a [u8],
+    },
+
+    /// `0x06` Disconnection Request.
+    ///
+    /// Requests termination of the connection-oriented channel `dcid`.
+    DisconnectionReq {
+        /// The channel to disconnect, on the device receiving the request.
+        dcid: Channel,
+        /// The channel to disconnect, on the device sending the request.
+        scid: Channel,
+    },
+
+    /// `0x07` Disconnection Response.
+    DisconnectionRsp { dcid: Channel, scid: Channel },
+
+    /// `0x08` Echo Request.
+    EchoReq {
+        /// Optional payload, which is sent back in the response.
+        data: &'a [u8],
+    },
+
+    /// `0x09` Echo Response.
+    EchoRsp { data: &'a [u8] },
+
+    /// A command that isn't supported by Rubble.
+    Unknown { code: Code, data: &'a [u8] },
+}
+
+impl Command<'_> {
+    fn code(&self) -> Code {
+        match self {
+            Command::Reject { .. } => Code::CommandReject,
+            Command::DisconnectionReq { .. } => Code::DisconnectionReq,
+            Command::DisconnectionRsp { .. } => Code::DisconnectionRsp,
+            Command::EchoReq { .. } => Code::EchoReq,
+            Command::EchoRsp { .. } => Code::EchoRsp,
+            Command::Unknown { code, .. } => *code,
+        }
+    }
+}
+
 /// The `Protocol` implementor listening on the LE Signaling Channel `0x0005`.
 pub struct SignalingState {}
 
@@ -40,12 +175,119 @@ impl SignalingState {
 }
 
 impl ProtocolObj for SignalingState {
-    fn process_message(&mut self, message: &[u8], responder: Sender<'_>) -> Result<(), Error> {
-        let _ = (message, responder);
-        unimplemented!();
+    fn process_message(&mut self, message: &[u8], mut responder: Sender<'_>) -> Result<(), Error> {
+        let pdu = Pdu::from_bytes(&mut ByteReader::new(message))?;
+        trace!("signaling: {:?}", pdu);
+
+        if pdu.identifier == 0 {
+            // Identifier 0 is illegal and must not be used by any command
+            warn!("ignoring signaling command with identifier 0: {:?}", pdu);
+            return Ok(());
+        }
+
+        let mut cids = [0; 4];
+        let response = match pdu.command {
+            Command::EchoReq { data } => Command::EchoRsp { data },
+            Command::DisconnectionReq { dcid, scid } => {
+                // Only fixed channels are supported, and those can't be disconnected. The reject
+                // contains the local and remote CID from the request.
+                cids[..2].copy_from_slice(&dcid.as_raw().to_le_bytes());
+                cids[2..].copy_from_slice(&scid.as_raw().to_le_bytes());
+                Command::Reject {
+                    reason: RejectReason::InvalidCid,
+                    data: &cids,
+                }
+            }
+            Command::Reject { .. }
+            | Command::DisconnectionRsp { .. }
+            | Command::EchoRsp { .. }
+            | Command::Unknown {
+                code: Code::ConnectionParameterUpdateRsp,
+                ..
+            }
+            | Command::Unknown {
+                code: Code::CreditBasedConnectionRsp,
+                ..
+            } => {
+                // We never send requests, so there's nothing to do with a response
+                debug!("ignoring unsolicited signaling response {:?}", pdu);
+                return Ok(());
+            }
+            Command::Unknown { code, data } => {
+                warn!(
+                    "rejecting unsupported signaling command {:?}: {:?}",
+                    code,
+                    HexSlice(data)
+                );
+                Command::Reject {
+                    reason: RejectReason::CommandNotUnderstood,
+                    data: &[],
+                }
+            }
+        };
+
+        responder.send(Pdu {
+            identifier: pdu.identifier,
+            command: response,
+        })
     }
 }
 
 impl Protocol for SignalingState {
     const RSP_PDU_SIZE: u8 = 23;
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        crate::l2cap::{exchange, BleChannelMap, Channel, L2CAPState},
+        std::vec::Vec,
+    };
+
+    /// Sends a signaling PDU and returns the response PDU, if one was sent.
+    fn request(pdu: &[u8]) -> Option<Vec<u8>> {
+        let mut l2cap = L2CAPState::new(BleChannelMap::empty());
+        exchange(&mut l2cap, Channel::LE_SIGNALING, pdu)
+    }
+
+    #[test]
+    fn echo() {
+        assert_eq!(
+            request(&[0x08, 0x11, 0x03, 0x00, 0xAA, 0xBB, 0xCC]).unwrap(),
+            [0x09, 0x11, 0x03, 0x00, 0xAA, 0xBB, 0xCC]
+        );
+        assert_eq!(
+            request(&[0x08, 0x12, 0x00, 0x00]).unwrap(),
+            [0x09, 0x12, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn reject_unknown_command() {
+        assert_eq!(
+            request(&[0x42, 0x07, 0x02, 0x00, 0x01, 0x02]).unwrap(),
+            [0x01, 0x07, 0x02, 0x00, 0x00, 0x00]
+        );
+
+        // Connection Parameter Update Requests are only sent to the master
+        assert_eq!(
+            request(&[0x12, 0x08, 0x08, 0x00, 6, 0, 6, 0, 0, 0, 0x80, 0x0C]).unwrap(),
+            [0x01, 0x08, 0x02, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn reject_disconnection() {
+        assert_eq!(
+            request(&[0x06, 0x09, 0x04, 0x00, 0x40, 0x00, 0x41, 0x00]).unwrap(),
+            [0x01, 0x09, 0x06, 0x00, 0x02, 0x00, 0x40, 0x00, 0x41, 0x00]
+        );
+    }
+
+    #[test]
+    fn ignore_responses() {
+        assert_eq!(request(&[0x01, 0x0A, 0x02, 0x00, 0x00, 0x00]), None);
+        assert_eq!(request(&[0x09, 0x0B, 0x00, 0x00]), None);
+        assert_eq!(request(&[0x08, 0x00, 0x00, 0x00]), None);
+    }
+}
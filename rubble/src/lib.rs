@@ -27,21 +27,32 @@
 // The claims of this lint are dubious, disable it
 #![allow(clippy::trivially_copy_pass_by_ref)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 #[macro_use]
 mod log;
 #[macro_use]
 mod utils;
+#[cfg(feature = "async")]
+pub mod asynch;
 pub mod att;
 pub mod beacon;
 pub mod bytes;
 pub mod config;
 mod crc;
+pub mod crypto;
 mod error;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
 pub mod gatt;
 pub mod l2cap;
 pub mod link;
 pub mod phy;
+pub mod rng;
 pub mod security;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod time;
 pub mod uuid;
 
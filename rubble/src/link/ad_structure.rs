@@ -5,16 +5,30 @@
 //! Also see the [assigned numbers document][gap] hosted by the SIG.
 //!
 //! [gap]: https://www.bluetooth.com/specifications/assigned-numbers/generic-access-profile
+//!
+//! Advertising and scan response data can hold at most [`MAX_DATA_LEN`] Bytes of AD structures.
+//! [`AdDataBuilder`] can be used to assemble a list of AD structures while checking that it fits.
+//! The length of an existing list can be computed with [`encoded_len`].
+//!
+//! [`MAX_DATA_LEN`]: constant.MAX_DATA_LEN.html
+//! [`AdDataBuilder`]: struct.AdDataBuilder.html
+//! [`encoded_len`]: fn.encoded_len.html
 
 use {
     crate::{
         bytes::*,
+        link::CompanyId,
         uuid::{IsUuid, Uuid, Uuid16, Uuid32, UuidKind},
         Error,
     },
     bitflags::bitflags,
+    core::ops::Deref,
+    heapless::{consts::U15, Vec},
 };
 
+/// Maximum number of Bytes of advertising data or scan response data in an advertising PDU.
+pub const MAX_DATA_LEN: usize = 31;
+
 /// A list of AD structures can be sent along with an advertising packet or scan response.
 ///
 /// This mechanism allows a scanner to, for example, receive the device's name without having to
@@ -55,6 +69,20 @@ pub enum AdStructure<'a> {
     /// Sets the shortened device name.
     ShortenedLocalName(&'a str),
 
+    /// The external appearance of the device, as a 16-bit value from the assigned numbers list.
+    ///
+    /// This is the same value as in the GAP service's *Appearance* characteristic, and is used by
+    /// scanners to pick an icon for the device.
+    Appearance(u16),
+
+    /// Manufacturer-specific data, prefixed with the manufacturer's company identifier.
+    ManufacturerSpecificData {
+        /// The SIG-assigned company identifier of the manufacturer.
+        company_identifier: CompanyId,
+        /// Data whose format is defined by the manufacturer.
+        payload: &'a [u8],
+    },
+
     /// An unknown or unimplemented AD structure stored as raw bytes.
     Unknown {
         /// Type byte.
@@ -67,6 +95,97 @@ pub enum AdStructure<'a> {
     __Nonexhaustive,
 }
 
+impl AdStructure<'_> {
+    /// Returns the number of Bytes this AD structure takes up when encoded.
+    ///
+    /// This includes the length and type Bytes.
+    pub fn encoded_len(&self) -> usize {
+        let data_len = match self {
+            AdStructure::Flags(_) => 1,
+            AdStructure::ServiceUuids16(uuids) => uuids.iter().count() * 2,
+            AdStructure::ServiceUuids32(uuids) => uuids.iter().count() * 4,
+            AdStructure::ServiceUuids128(uuids) => uuids.iter().count() * 16,
+            AdStructure::ServiceData16 { data, .. } => 2 + data.len(),
+            AdStructure::CompleteLocalName(name) | AdStructure::ShortenedLocalName(name) => {
+                name.len()
+            }
+            AdStructure::Appearance(_) => 2,
+            AdStructure::ManufacturerSpecificData { payload, .. } => 2 + payload.len(),
+            AdStructure::Unknown { data, .. } => data.len(),
+            AdStructure::__Nonexhaustive => unreachable!(),
+        };
+
+        2 + data_len
+    }
+}
+
+/// Returns the number of Bytes the AD structures in `structures` take up when encoded.
+///
+/// The list fits into advertising or scan response data if this is at most [`MAX_DATA_LEN`]. An
+/// empty list has a length of 0. Note that `PduBuf::discoverable` and `PduBuf::beacon` add a
+/// 3-Byte `Flags` AD structure to the advertising data on their own.
+///
+/// [`MAX_DATA_LEN`]: constant.MAX_DATA_LEN.html
+pub fn encoded_len(structures: &[AdStructure<'_>]) -> usize {
+    structures.iter().map(AdStructure::encoded_len).sum()
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for AdStructure<'_> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        match self {
+            AdStructure::Flags(flags) => defmt::write!(f, "Flags({=u8:#04x})", flags.bits()),
+            AdStructure::ServiceUuids16(uuids) => {
+                defmt::write!(f, "ServiceUuids16(complete={=bool}", uuids.is_complete());
+                for uuid in uuids.iter() {
+                    defmt::write!(f, ", {}", uuid);
+                }
+                defmt::write!(f, ")");
+            }
+            AdStructure::ServiceUuids32(uuids) => {
+                defmt::write!(f, "ServiceUuids32(complete={=bool}", uuids.is_complete());
+                for uuid in uuids.iter() {
+                    defmt::write!(f, ", {}", uuid);
+                }
+                defmt::write!(f, ")");
+            }
+            AdStructure::ServiceUuids128(uuids) => {
+                defmt::write!(f, "ServiceUuids128(complete={=bool}", uuids.is_complete());
+                for uuid in uuids.iter() {
+                    defmt::write!(f, ", ");
+                    crate::uuid::format_uuid(f, &uuid);
+                }
+                defmt::write!(f, ")");
+            }
+            AdStructure::ServiceData16 { uuid, data } => {
+                defmt::write!(f, "ServiceData16({=u16:04x}, {=[u8]:x})", uuid, data)
+            }
+            AdStructure::CompleteLocalName(name) => {
+                defmt::write!(f, "CompleteLocalName({=str})", name)
+            }
+            AdStructure::ShortenedLocalName(name) => {
+                defmt::write!(f, "ShortenedLocalName({=str})", name)
+            }
+            AdStructure::Appearance(appearance) => {
+                defmt::write!(f, "Appearance({=u16:#06x})", appearance)
+            }
+            AdStructure::ManufacturerSpecificData {
+                company_identifier,
+                payload,
+            } => defmt::write!(
+                f,
+                "ManufacturerSpecificData({=u16:#x}, {=[u8]:x})",
+                company_identifier.as_u16(),
+                payload
+            ),
+            AdStructure::Unknown { ty, data } => {
+                defmt::write!(f, "Unknown({=u8:#04x}, {=[u8]:x})", ty, data)
+            }
+            AdStructure::__Nonexhaustive => unreachable!(),
+        }
+    }
+}
+
 impl<'a> ToBytes for AdStructure<'a> {
     /// Lowers this AD structure into a Byte buffer.
     ///
@@ -102,6 +221,18 @@ impl<'a> ToBytes for AdStructure<'a> {
                 buf.write_u8(Type::SHORTENED_LOCAL_NAME)?;
                 buf.write_slice(name.as_bytes())?;
             }
+            AdStructure::Appearance(appearance) => {
+                buf.write_u8(Type::APPEARANCE)?;
+                buf.write_u16_le(*appearance)?;
+            }
+            AdStructure::ManufacturerSpecificData {
+                company_identifier,
+                payload,
+            } => {
+                buf.write_u8(Type::MANUFACTURER_SPECIFIC_DATA)?;
+                buf.write_u16_le(company_identifier.as_u16())?;
+                buf.write_slice(payload)?;
+            }
             AdStructure::Unknown { ty, data } => {
                 buf.write_u8(*ty)?;
                 buf.write_slice(data)?;
@@ -139,6 +270,23 @@ impl<'a> FromBytes<'a> for AdStructure<'a> {
                 let flags = Flags::from_bits_truncate(bits);
                 AdStructure::Flags(flags)
             }
+            Type::APPEARANCE => {
+                if data.len() != 2 {
+                    return Err(Error::InvalidLength);
+                }
+
+                AdStructure::Appearance(u16::from_le_bytes([data[0], data[1]]))
+            }
+            Type::MANUFACTURER_SPECIFIC_DATA => {
+                if data.len() < 2 {
+                    return Err(Error::InvalidLength);
+                }
+
+                AdStructure::ManufacturerSpecificData {
+                    company_identifier: CompanyId::from_raw(u16::from_le_bytes([data[0], data[1]])),
+                    payload: &data[2..],
+                }
+            }
             Type::COMPLETE_LIST_OF_16BIT_SERVICE_UUIDS
             | Type::INCOMPLETE_LIST_OF_16BIT_SERVICE_UUIDS => {
                 let uuids = ServiceUuids::<Uuid16>::from_bytes(&mut ByteReader::new(ty_and_data))?;
@@ -258,11 +406,23 @@ impl Flags {
     /// Returns flags suitable for discoverable devices that want to establish a connection.
     ///
     /// The created `Flags` value specifies that this device is not BR/EDR (classic Bluetooth)
-    /// capable and is in General Discoverable mode.
+    /// capable and is in General Discoverable mode (`0x06`). This is also the `Default`.
+    ///
+    /// Devices in General Discoverable mode are discoverable for an unlimited amount of time.
     pub fn discoverable() -> Flags {
         Self::BR_EDR_NOT_SUPPORTED | Self::LE_GENERAL_DISCOVERABLE
     }
 
+    /// Returns flags for LE-only devices that are discoverable for a limited time only.
+    ///
+    /// The created `Flags` value specifies that this device is not BR/EDR (classic Bluetooth)
+    /// capable and is in Limited Discoverable mode (`0x05`). Devices should only use this mode
+    /// for a short time after a user interaction (at most 180 seconds), and advertise at a higher
+    /// rate while in it.
+    pub fn limited_discoverable() -> Flags {
+        Self::BR_EDR_NOT_SUPPORTED | Self::LE_LIMITED_DISCOVERABLE
+    }
+
     /// Returns flags suitable for non-connectable devices that just broadcast advertising packets.
     ///
     /// Creates a `Flags` value that specifies that BR/EDR (classic Bluetooth) is not supported and
@@ -279,7 +439,7 @@ impl Flags {
     /// Returns a boolean indicating whether the device that sent this `Flags` value supports BR/EDR
     /// (aka "Classic Bluetooth").
     pub fn supports_classic_bluetooth(&self) -> bool {
-        self.contains(Self::BR_EDR_NOT_SUPPORTED)
+        !self.contains(Self::BR_EDR_NOT_SUPPORTED)
     }
 
     /// Device operating in LE Limited Discoverable mode.
@@ -301,12 +461,162 @@ impl Flags {
     }
 }
 
+/// Returns `Flags::discoverable()`, the right choice for a connectable LE-only peripheral.
+impl Default for Flags {
+    fn default() -> Self {
+        Self::discoverable()
+    }
+}
+
 impl<'a> From<Flags> for AdStructure<'a> {
     fn from(flags: Flags) -> Self {
         AdStructure::Flags(flags)
     }
 }
 
+/// Error returned by [`AdDataBuilder::build`].
+///
+/// [`AdDataBuilder::build`]: struct.AdDataBuilder.html#method.build
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AdDataError {
+    /// The AD structures don't fit into the `MAX_DATA_LEN` Bytes available.
+    TooLong {
+        /// Total encoded length of all added AD structures.
+        len: usize,
+    },
+
+    /// A `Flags` AD structure was added to scan response data.
+    FlagsInScanResponse,
+}
+
+/// Builder for advertising data and scan response data.
+///
+/// AD structures are added with [`push`], and [`build`] checks that their combined length fits
+/// into an advertising PDU:
+///
+/// ```
+/// use rubble::link::ad_structure::{AdDataBuilder, AdDataError, AdStructure, Flags};
+///
+/// let data = AdDataBuilder::advertising()
+///     .push(Flags::discoverable())
+///     .push(AdStructure::CompleteLocalName("rubble"))
+///     .build()
+///     .unwrap();
+/// assert_eq!(data.encoded_len(), 3 + 8);
+///
+/// let too_long = AdDataBuilder::scan_response()
+///     .push(AdStructure::CompleteLocalName("a very long name that doesn't fit"))
+///     .build();
+/// assert_eq!(too_long.unwrap_err(), AdDataError::TooLong { len: 35 });
+/// ```
+///
+/// Note that `PduBuf::discoverable` and `PduBuf::beacon` add a `Flags` AD structure on their own,
+/// which takes up 3 more Bytes.
+///
+/// [`push`]: #method.push
+/// [`build`]: #method.build
+#[derive(Debug, Clone)]
+pub struct AdDataBuilder<'a> {
+    structures: Vec<AdStructure<'a>, U15>,
+    /// Encoded length of all added structures, including those that didn't fit into `structures`.
+    len: usize,
+    scan_response: bool,
+    has_flags: bool,
+}
+
+impl<'a> AdDataBuilder<'a> {
+    /// Creates a builder for the advertising data of an advertising PDU.
+    pub fn advertising() -> Self {
+        Self::new(false)
+    }
+
+    /// Creates a builder for the scan response data sent in a `SCAN_RSP` PDU.
+    ///
+    /// Scan response data must not contain a `Flags` AD structure.
+    pub fn scan_response() -> Self {
+        Self::new(true)
+    }
+
+    fn new(scan_response: bool) -> Self {
+        Self {
+            structures: Vec::new(),
+            len: 0,
+            scan_response,
+            has_flags: false,
+        }
+    }
+
+    /// Adds an AD structure.
+    ///
+    /// Errors are reported when calling `build`.
+    pub fn push(mut self, ad: impl Into<AdStructure<'a>>) -> Self {
+        let ad = ad.into();
+        if let AdStructure::Flags(_) = ad {
+            self.has_flags = true;
+        }
+
+        self.len += ad.encoded_len();
+        if self.len <= MAX_DATA_LEN {
+            // Every AD structure takes at least 2 Bytes, so 15 of them always fit
+            self.structures.push(ad).unwrap();
+        }
+        self
+    }
+
+    /// Returns the total encoded length of the AD structures added so far.
+    pub fn encoded_len(&self) -> usize {
+        self.len
+    }
+
+    /// Finishes building the data.
+    ///
+    /// Returns an error if the AD structures don't fit into `MAX_DATA_LEN` Bytes, or if a
+    /// `Flags` AD structure was added to scan response data.
+    pub fn build(self) -> Result<AdData<'a>, AdDataError> {
+        if self.scan_response && self.has_flags {
+            return Err(AdDataError::FlagsInScanResponse);
+        }
+
+        if self.len > MAX_DATA_LEN {
+            return Err(AdDataError::TooLong { len: self.len });
+        }
+
+        Ok(AdData {
+            structures: self.structures,
+            len: self.len,
+        })
+    }
+}
+
+/// A list of AD structures that is known to fit into an advertising PDU.
+///
+/// Created by an [`AdDataBuilder`]. Dereferences to a slice of `AdStructure`s, so it can be passed
+/// to any function that takes advertising or scan response data.
+///
+/// [`AdDataBuilder`]: struct.AdDataBuilder.html
+#[derive(Debug, Clone)]
+pub struct AdData<'a> {
+    structures: Vec<AdStructure<'a>, U15>,
+    len: usize,
+}
+
+impl<'a> AdData<'a> {
+    /// Returns the total encoded length of the AD structures.
+    ///
+    /// This is at most `MAX_DATA_LEN`.
+    pub fn encoded_len(&self) -> usize {
+        self.len
+    }
+}
+
+impl<'a> Deref for AdData<'a> {
+    type Target = [AdStructure<'a>];
+
+    fn deref(&self) -> &[AdStructure<'a>] {
+        &self.structures
+    }
+}
+
 /// Data Type constants.
 ///
 /// https://www.bluetooth.com/specifications/assigned-numbers/generic-access-profile
@@ -362,3 +672,194 @@ impl Type {
     const _3D_INFORMATION_DATA: u8 = 0x3D;
     const MANUFACTURER_SPECIFIC_DATA: u8 = 0xFF;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode<'a>(ad: AdStructure<'_>, buf: &'a mut [u8]) -> &'a [u8] {
+        let mut writer = ByteWriter::new(buf);
+        ad.to_bytes(&mut writer).unwrap();
+        let len = writer.bytes_written();
+        &buf[..len]
+    }
+
+    #[test]
+    fn structure_encoded_len() {
+        let uuids = [Uuid16(0x180F), Uuid16(0x180A)];
+        let structures = [
+            AdStructure::Flags(Flags::discoverable()),
+            AdStructure::ServiceUuids16(ServiceUuids::from_uuids(true, &uuids)),
+            AdStructure::ServiceData16 {
+                uuid: 0x180F,
+                data: &[100],
+            },
+            AdStructure::CompleteLocalName("rubble"),
+            AdStructure::ShortenedLocalName("rb"),
+            AdStructure::Appearance(0x03C1),
+            AdStructure::ManufacturerSpecificData {
+                company_identifier: CompanyId::from_raw(0x0059),
+                payload: &[1, 2, 3],
+            },
+            AdStructure::Unknown {
+                ty: 0x0A,
+                data: &[],
+            },
+        ];
+
+        for ad in &structures {
+            let mut buf = [0; 31];
+            assert_eq!(ad.encoded_len(), encode(*ad, &mut buf).len(), "{:?}", ad);
+        }
+    }
+
+    #[test]
+    fn list_encoded_len() {
+        assert_eq!(encoded_len(&[]), 0);
+
+        // Exactly 31 Bytes
+        let name = "abcdefghijklmnopqrstuvwxyz";
+        let mut structures = [
+            AdStructure::Flags(Flags::discoverable()),
+            AdStructure::CompleteLocalName(name),
+        ];
+        assert_eq!(encoded_len(&structures), MAX_DATA_LEN);
+
+        // One Byte too many
+        structures[1] = AdStructure::CompleteLocalName("abcdefghijklmnopqrstuvwxyz0");
+        assert_eq!(encoded_len(&structures), MAX_DATA_LEN + 1);
+    }
+
+    #[test]
+    fn builder() {
+        let data = AdDataBuilder::advertising()
+            .push(Flags::discoverable())
+            .push(AdStructure::CompleteLocalName("x"))
+            .build()
+            .unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data.encoded_len(), 3 + 3);
+
+        // Exactly 31 Bytes fit
+        let name = "abcdefghijklmnopqrstuvwxyz";
+        let data = AdDataBuilder::advertising()
+            .push(Flags::discoverable())
+            .push(AdStructure::CompleteLocalName(name))
+            .build()
+            .unwrap();
+        assert_eq!(data.encoded_len(), MAX_DATA_LEN);
+
+        let builder = AdDataBuilder::advertising()
+            .push(Flags::discoverable())
+            .push(AdStructure::CompleteLocalName(name))
+            .push(AdStructure::Appearance(0));
+        assert_eq!(builder.encoded_len(), 35);
+        assert_eq!(
+            builder.build().unwrap_err(),
+            AdDataError::TooLong { len: 35 }
+        );
+
+        // Overflowing the number of structures is also reported as a length error
+        let mut builder = AdDataBuilder::advertising();
+        for _ in 0..16 {
+            builder = builder.push(AdStructure::Unknown { ty: 0, data: &[] });
+        }
+        assert_eq!(
+            builder.build().unwrap_err(),
+            AdDataError::TooLong { len: 32 }
+        );
+    }
+
+    #[test]
+    fn scan_response_builder() {
+        let data = AdDataBuilder::scan_response()
+            .push(AdStructure::ShortenedLocalName("rubble"))
+            .build()
+            .unwrap();
+        assert_eq!(data.encoded_len(), 8);
+
+        let err = AdDataBuilder::scan_response()
+            .push(Flags::discoverable())
+            .build()
+            .unwrap_err();
+        assert_eq!(err, AdDataError::FlagsInScanResponse);
+    }
+
+    #[test]
+    fn default_flags() {
+        let flags = Flags::default();
+        assert_eq!(flags.to_u8(), 0x06);
+        assert!(flags.le_general_discoverable());
+        assert!(!flags.le_limited_discoverable());
+        assert!(!flags.supports_classic_bluetooth());
+
+        let mut buf = [0; 31];
+        let bytes = encode(flags.into(), &mut buf);
+        assert_eq!(bytes, [0x02, 0x01, 0x06]);
+    }
+
+    #[test]
+    fn flags_round_trip() {
+        let all = [
+            Flags::discoverable(),
+            Flags::limited_discoverable(),
+            Flags::broadcast(),
+            Flags::empty(),
+            Flags::all(),
+        ];
+        for &flags in &all {
+            let mut buf = [0; 31];
+            let bytes = encode(AdStructure::Flags(flags), &mut buf);
+            match AdStructure::from_bytes(&mut ByteReader::new(bytes)).unwrap() {
+                AdStructure::Flags(decoded) => assert_eq!(decoded, flags),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+
+        assert_eq!(Flags::limited_discoverable().to_u8(), 0x05);
+
+        // Reserved bits are ignored
+        let bytes = [0x02, 0x01, 0xE6];
+        match AdStructure::from_bytes(&mut ByteReader::new(&bytes)).unwrap() {
+            AdStructure::Flags(decoded) => assert_eq!(decoded, Flags::discoverable()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn appearance() {
+        let mut buf = [0; 31];
+        // 0x03C1 = Keyboard
+        let bytes = encode(AdStructure::Appearance(0x03C1), &mut buf);
+        assert_eq!(bytes, [0x03, 0x19, 0xC1, 0x03]);
+
+        match AdStructure::from_bytes(&mut ByteReader::new(bytes)).unwrap() {
+            AdStructure::Appearance(0x03C1) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn manufacturer_specific_data() {
+        let mut buf = [0; 31];
+        let bytes = encode(
+            AdStructure::ManufacturerSpecificData {
+                company_identifier: CompanyId::from_raw(0x0059),
+                payload: &[1, 2, 3],
+            },
+            &mut buf,
+        );
+        assert_eq!(bytes, [0x06, 0xFF, 0x59, 0x00, 1, 2, 3]);
+
+        match AdStructure::from_bytes(&mut ByteReader::new(bytes)).unwrap() {
+            AdStructure::ManufacturerSpecificData {
+                company_identifier,
+                payload,
+            } => {
+                assert_eq!(company_identifier, CompanyId::from_raw(0x0059));
+                assert_eq!(payload, [1, 2, 3]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -13,7 +13,7 @@ use {
         link::{
             ad_structure::{AdStructure, Flags},
             channel_map::ChannelMap,
-            AddressKind, DeviceAddress,
+            AddressKind, DeviceAddress, TransmitWindow,
         },
         time::Duration,
         utils::{Hex, HexSlice},
@@ -389,7 +389,20 @@ impl ConnectRequestData {
     /// Returns the end of the transmit window from reception of the `CONNECT_REQ` containing
     /// `self`.
     pub fn end_of_tx_window(&self) -> Duration {
-        self.win_offset + self.win_size + Duration::from_micros(1250)
+        TransmitWindow::DELAY + self.win_offset + self.win_size
+    }
+
+    /// Returns the transmit window offset (`transmitWindowOffset`).
+    ///
+    /// This is the time between the earliest possible start of the transmit window (1.25 ms after
+    /// the end of the `CONNECT_REQ`) and the actual start of the transmit window.
+    pub fn transmit_window_offset(&self) -> Duration {
+        self.win_offset
+    }
+
+    /// Returns the transmit window size (`transmitWindowSize`).
+    pub fn transmit_window_size(&self) -> Duration {
+        self.win_size
     }
 
     /// Returns the connection event interval in µs.
@@ -409,6 +422,11 @@ impl ConnectRequestData {
     pub fn supervision_timeout(&self) -> Duration {
         self.timeout
     }
+
+    /// Returns the accuracy of the master's sleep clock.
+    pub fn sleep_clock_accuracy(&self) -> SleepClockAccuracy {
+        self.sca
+    }
 }
 
 impl FromBytes<'_> for ConnectRequestData {
@@ -422,11 +440,11 @@ impl FromBytes<'_> for ConnectRequestData {
                 Hex(u32::from_le_bytes(le_bytes))
             },
             // transmitWindowSize in 1.25 ms steps
-            win_size: Duration::from_micros(u32::from(bytes.read_u8()?) * 1250),
+            win_size: Duration::from_1250us_units(bytes.read_u8()?.into()),
             // transmitWindowOffset in 1.25 ms steps
-            win_offset: Duration::from_micros(u32::from(bytes.read_u16_le()?) * 1250),
+            win_offset: Duration::from_1250us_units(bytes.read_u16_le()?),
             // connInterval in 1.25 ms steps
-            interval: Duration::from_micros(u32::from(bytes.read_u16_le()?) * 1250),
+            interval: Duration::from_1250us_units(bytes.read_u16_le()?),
             // connSlaveLatency in no. of events
             latency: bytes.read_u16_le()?,
             // supervision timeout in 10 ms steps
@@ -455,11 +473,190 @@ impl FromBytes<'_> for ConnectRequestData {
     }
 }
 
+/// Error returned by `ConnectTimingBuilder::build` when a timing parameter is invalid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectTimingError {
+    /// The connection interval is not a multiple of 1.25 ms between 7.5 ms and 4 s.
+    Interval,
+
+    /// The transmit window offset or size is not a multiple of 1.25 ms, the size is not between
+    /// 1.25 ms and 10 ms (or is not shorter than the connection interval), or the offset is longer
+    /// than the connection interval.
+    TransmitWindow,
+
+    /// The slave latency is larger than 499, or too large to fit in the supervision timeout.
+    SlaveLatency,
+
+    /// The supervision timeout is not a multiple of 10 ms between 100 ms and 32 s, or isn't longer
+    /// than twice the connection interval.
+    SupervisionTimeout,
+}
+
+/// Builder for the timing fields of a `CONNECT_REQ`.
+///
+/// The fields are encoded in units of 1.25 ms (transmit window and connection interval) and 10 ms
+/// (supervision timeout). The builder takes `Duration`s instead, and `build` checks that they can
+/// be encoded exactly and are within the ranges allowed by the specification.
+///
+/// ```
+/// use rubble::link::advertising::{ConnectTimingBuilder, ConnectTimingError};
+/// use rubble::time::Duration;
+///
+/// let timing = ConnectTimingBuilder::new(Duration::from_micros(7_500), Duration::from_secs(1))
+///     .slave_latency(4)
+///     .build()
+///     .unwrap();
+/// assert_eq!(timing.interval(), 6);
+/// assert_eq!(timing.supervision_timeout(), 100);
+///
+/// let too_fast = ConnectTimingBuilder::new(Duration::from_micros(6_250), Duration::from_secs(1));
+/// assert_eq!(too_fast.build().unwrap_err(), ConnectTimingError::Interval);
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct ConnectTimingBuilder {
+    win_size: Duration,
+    win_offset: Duration,
+    interval: Duration,
+    latency: u16,
+    timeout: Duration,
+}
+
+impl ConnectTimingBuilder {
+    /// Creates a builder for a connection with the given connection interval and supervision
+    /// timeout.
+    ///
+    /// The transmit window starts right away (with an offset of 0) and is 1.25 ms long, and the
+    /// slave latency is 0.
+    pub fn new(interval: Duration, supervision_timeout: Duration) -> Self {
+        Self {
+            win_size: Duration::from_1250us_units(1),
+            win_offset: Duration::from_micros(0),
+            interval,
+            latency: 0,
+            timeout: supervision_timeout,
+        }
+    }
+
+    /// Sets the offset and size of the transmit window in which the first packet is sent.
+    pub fn transmit_window(mut self, offset: Duration, size: Duration) -> Self {
+        self.win_offset = offset;
+        self.win_size = size;
+        self
+    }
+
+    /// Sets the slave latency (the number of connection events the slave may skip).
+    pub fn slave_latency(mut self, latency: u16) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Checks and encodes the timing parameters.
+    pub fn build(self) -> Result<ConnectTiming, ConnectTimingError> {
+        let interval = to_1250us_units(self.interval)
+            .filter(|units| (6..=3200).contains(units))
+            .ok_or(ConnectTimingError::Interval)?;
+
+        let win_size = to_1250us_units(self.win_size)
+            .filter(|&units| (1..=8).contains(&units) && units < interval)
+            .ok_or(ConnectTimingError::TransmitWindow)?;
+        let win_offset = to_1250us_units(self.win_offset)
+            .filter(|&units| units <= interval)
+            .ok_or(ConnectTimingError::TransmitWindow)?;
+
+        let timeout_micros = self.timeout.as_micros();
+        let timeout = timeout_micros / 10_000;
+        if !timeout_micros.is_multiple_of(10_000)
+            || !(10..=3200).contains(&timeout)
+            || timeout_micros <= 2 * self.interval.as_micros()
+        {
+            return Err(ConnectTimingError::SupervisionTimeout);
+        }
+
+        // The timeout has to be longer than `(1 + latency) * interval * 2`
+        let max_events = u64::from(self.latency) + 1;
+        if self.latency > 499
+            || u64::from(timeout_micros) <= max_events * 2 * u64::from(self.interval.as_micros())
+        {
+            return Err(ConnectTimingError::SlaveLatency);
+        }
+
+        Ok(ConnectTiming {
+            win_size: win_size as u8,
+            win_offset,
+            interval,
+            latency: self.latency,
+            timeout: timeout as u16,
+        })
+    }
+}
+
+/// Converts `duration` to a number of 1.25 ms units, if it is an exact multiple that fits in a
+/// `u16`.
+fn to_1250us_units(duration: Duration) -> Option<u16> {
+    if duration.as_micros().is_multiple_of(1_250) {
+        let units = duration.whole_1250us_units();
+        if units <= u32::from(u16::MAX) {
+            return Some(units as u16);
+        }
+    }
+    None
+}
+
+/// Encoded timing fields of a `CONNECT_REQ`, created by a `ConnectTimingBuilder`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConnectTiming {
+    win_size: u8,
+    win_offset: u16,
+    interval: u16,
+    latency: u16,
+    timeout: u16,
+}
+
+impl ConnectTiming {
+    /// Returns the `WinSize` field (in units of 1.25 ms).
+    pub fn transmit_window_size(&self) -> u8 {
+        self.win_size
+    }
+
+    /// Returns the `WinOffset` field (in units of 1.25 ms).
+    pub fn transmit_window_offset(&self) -> u16 {
+        self.win_offset
+    }
+
+    /// Returns the `Interval` field (in units of 1.25 ms).
+    pub fn interval(&self) -> u16 {
+        self.interval
+    }
+
+    /// Returns the `Latency` field (in connection events).
+    pub fn slave_latency(&self) -> u16 {
+        self.latency
+    }
+
+    /// Returns the `Timeout` field (in units of 10 ms).
+    pub fn supervision_timeout(&self) -> u16 {
+        self.timeout
+    }
+}
+
+/// Writes the fields in the order they appear in the `LLData` of a `CONNECT_REQ` (starting at
+/// `WinSize`).
+impl ToBytes for ConnectTiming {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        writer.write_u8(self.win_size)?;
+        writer.write_u16_le(self.win_offset)?;
+        writer.write_u16_le(self.interval)?;
+        writer.write_u16_le(self.latency)?;
+        writer.write_u16_le(self.timeout)?;
+        Ok(())
+    }
+}
+
 /// Indicates the master's sleep clock accuracy (SCA) in ppm (parts per
 /// million).
 ///
 /// The lower the PPM, the higher the accuracy.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum SleepClockAccuracy {
     Ppm251To500,
     Ppm151To250,
@@ -471,6 +668,23 @@ pub enum SleepClockAccuracy {
     Ppm0To20,
 }
 
+impl SleepClockAccuracy {
+    /// Returns the worst-case clock drift in this accuracy range, in ppm.
+    pub fn max_ppm(&self) -> u16 {
+        use self::SleepClockAccuracy::*;
+        match self {
+            Ppm251To500 => 500,
+            Ppm151To250 => 250,
+            Ppm101To150 => 150,
+            Ppm76To100 => 100,
+            Ppm51To75 => 75,
+            Ppm31To50 => 50,
+            Ppm21To30 => 30,
+            Ppm0To20 => 20,
+        }
+    }
+}
+
 /// Stores an advertising channel PDU.
 ///
 /// This is an owned version of `Pdu` and should be used when *creating* a PDU
@@ -484,31 +698,47 @@ pub struct PduBuf {
 }
 
 impl PduBuf {
-    /// Builds a PDU buffer containing advertiser address and data.
-    fn adv(
+    /// Builds a PDU buffer from the addresses of sender and receiver, followed by data written by
+    /// `f`.
+    ///
+    /// The `TxAdd` and `RxAdd` header bits are derived from the address kinds.
+    fn build(
         ty: PduType,
-        adv: DeviceAddress,
-        adv_data: &mut dyn Iterator<Item = &AdStructure<'_>>,
+        sender: DeviceAddress,
+        receiver: Option<DeviceAddress>,
+        f: impl FnOnce(&mut ByteWriter<'_>) -> Result<(), Error>,
     ) -> Result<Self, Error> {
         let mut payload = [0; MAX_PAYLOAD_SIZE];
         let mut buf = ByteWriter::new(&mut payload[..]);
-        buf.write_slice(adv.raw()).unwrap();
-        for ad in adv_data {
-            ad.to_bytes(&mut buf)?;
+        buf.write_slice(sender.raw())?;
+        if let Some(receiver) = &receiver {
+            buf.write_slice(receiver.raw())?;
         }
+        f(&mut buf)?;
 
-        let left = buf.space_left();
-        let used = payload.len() - left;
-        let mut header = Header::new(ty);
+        let used = buf.bytes_written();
+        let mut header = Header::with_addresses(ty, &sender, receiver.as_ref());
         header.set_payload_length(used as u8);
-        header.set_tx_add(adv.is_random());
-        header.set_rx_add(false);
         Ok(Self {
             header,
             payload_buf: payload,
         })
     }
 
+    /// Builds a PDU buffer containing advertiser address and data.
+    fn adv(
+        ty: PduType,
+        adv: DeviceAddress,
+        adv_data: &mut dyn Iterator<Item = &AdStructure<'_>>,
+    ) -> Result<Self, Error> {
+        Self::build(ty, adv, None, |buf| {
+            for ad in adv_data {
+                ad.to_bytes(buf)?;
+            }
+            Ok(())
+        })
+    }
+
     /// Creates a connectable undirected advertising PDU (`ADV_IND`).
     ///
     /// # Parameters
@@ -532,19 +762,14 @@ impl PduBuf {
         advertiser_addr: DeviceAddress,
         initiator_addr: DeviceAddress,
     ) -> Self {
-        let mut payload = [0; 37];
-        payload[0..6].copy_from_slice(advertiser_addr.raw());
-        payload[6..12].copy_from_slice(initiator_addr.raw());
-
-        let mut header = Header::new(PduType::AdvDirectInd);
-        header.set_payload_length(6 + 6);
-        header.set_tx_add(advertiser_addr.is_random());
-        header.set_rx_add(initiator_addr.is_random());
-
-        Self {
-            header,
-            payload_buf: payload,
-        }
+        // 12 Bytes of payload always fit
+        Self::build(
+            PduType::AdvDirectInd,
+            advertiser_addr,
+            Some(initiator_addr),
+            |_| Ok(()),
+        )
+        .unwrap()
     }
 
     /// Creates a non-connectable undirected advertising PDU
@@ -609,7 +834,6 @@ impl PduBuf {
         advertiser_addr: DeviceAddress,
         advertiser_data: &[AdStructure<'_>],
     ) -> Result<Self, Error> {
-        // TODO what's the difference between "general" and "limited" discoverability?
         Self::adv(
             PduType::AdvInd,
             advertiser_addr,
@@ -627,8 +851,8 @@ impl PduBuf {
     ///   the request).
     /// * `adv`: Device address of the advertising device that this scan request
     ///   is directed towards.
-    pub fn scan_request(_scanner: DeviceAddress, _adv: DeviceAddress) -> Result<Self, Error> {
-        unimplemented!()
+    pub fn scan_request(scanner: DeviceAddress, adv: DeviceAddress) -> Result<Self, Error> {
+        Self::build(PduType::ScanReq, scanner, Some(adv), |_| Ok(()))
     }
 
     /// Creates a scan response PDU.
@@ -638,23 +862,7 @@ impl PduBuf {
         advertiser_addr: DeviceAddress,
         scan_data: &[AdStructure<'_>],
     ) -> Result<Self, Error> {
-        let mut payload = [0; MAX_PAYLOAD_SIZE];
-        let mut buf = ByteWriter::new(&mut payload[..]);
-        buf.write_slice(advertiser_addr.raw()).unwrap();
-        for ad in scan_data {
-            ad.to_bytes(&mut buf)?;
-        }
-
-        let left = buf.space_left();
-        let used = payload.len() - left;
-        let mut header = Header::new(PduType::ScanRsp);
-        header.set_payload_length(used as u8);
-        header.set_tx_add(advertiser_addr.is_random());
-        header.set_rx_add(false);
-        Ok(Self {
-            header,
-            payload_buf: payload,
-        })
+        Self::adv(PduType::ScanRsp, advertiser_addr, &mut scan_data.iter())
     }
 
     pub fn header(&self) -> Header {
@@ -702,6 +910,23 @@ impl Header {
         Header(u16::from(u8::from(ty)))
     }
 
+    /// Creates a header for a PDU of type `ty` that is sent by `sender` and, if the PDU type has a
+    /// receiver address field, addressed to `receiver`.
+    ///
+    /// The `TxAdd` and `RxAdd` bits are set according to whether the respective address is random.
+    /// A wrong bit causes the peer to look for a different address than ours, so PDUs should
+    /// always be created with this instead of setting the bits by hand.
+    pub fn with_addresses(
+        ty: PduType,
+        sender: &DeviceAddress,
+        receiver: Option<&DeviceAddress>,
+    ) -> Self {
+        let mut header = Self::new(ty);
+        header.set_tx_add(sender.is_random());
+        header.set_rx_add(receiver.is_some_and(DeviceAddress::is_random));
+        header
+    }
+
     pub fn parse(raw: &[u8]) -> Self {
         Header(LittleEndian::read_u16(&raw))
     }
@@ -854,3 +1079,145 @@ impl PduType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLIC: DeviceAddress = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Public);
+    const RANDOM: DeviceAddress = DeviceAddress::new([1, 2, 3, 4, 5, 0xC6], AddressKind::Random);
+
+    #[test]
+    fn tx_add_from_advertiser() {
+        for &(addr, random) in &[(PUBLIC, false), (RANDOM, true)] {
+            let pdus = [
+                PduBuf::connectable_undirected(addr, &[]).unwrap(),
+                PduBuf::nonconnectable_undirected(addr, &[]).unwrap(),
+                PduBuf::scannable_undirected(addr, &[]).unwrap(),
+                PduBuf::beacon(addr, &[]).unwrap(),
+                PduBuf::discoverable(addr, &[]).unwrap(),
+                PduBuf::scan_response(addr, &[]).unwrap(),
+            ];
+            for pdu in &pdus {
+                assert_eq!(pdu.header().tx_add(), random, "{:?}", pdu);
+                assert!(!pdu.header().rx_add(), "{:?}", pdu);
+                assert_eq!(pdu.header().to_u16() & TXADD_MASK != 0, random);
+            }
+        }
+    }
+
+    #[test]
+    fn rx_add_from_receiver() {
+        let pdu = PduBuf::connectable_directed(PUBLIC, RANDOM);
+        assert!(!pdu.header().tx_add());
+        assert!(pdu.header().rx_add());
+        assert_eq!(pdu.payload(), [1, 2, 3, 4, 5, 6, 1, 2, 3, 4, 5, 0xC6]);
+
+        let pdu = PduBuf::connectable_directed(RANDOM, PUBLIC);
+        assert!(pdu.header().tx_add());
+        assert!(!pdu.header().rx_add());
+
+        let pdu = PduBuf::scan_request(RANDOM, RANDOM).unwrap();
+        assert_eq!(pdu.header().type_(), PduType::ScanReq);
+        assert!(pdu.header().tx_add());
+        assert!(pdu.header().rx_add());
+    }
+
+    #[test]
+    fn address_kind_roundtrip() {
+        let pdu = PduBuf::connectable_directed(RANDOM, PUBLIC);
+        let parsed =
+            Pdu::from_header_and_payload(pdu.header(), &mut ByteReader::new(pdu.payload()))
+                .unwrap();
+        assert!(*parsed.sender() == RANDOM);
+        assert!(*parsed.receiver().unwrap() == PUBLIC);
+    }
+
+    #[test]
+    fn connect_timing_boundaries() {
+        let ms = |ms| Duration::from_millis(ms);
+        let us = Duration::from_micros;
+        let build = |interval, timeout| ConnectTimingBuilder::new(interval, timeout).build();
+
+        // Connection interval: 7.5 ms to 4 s in steps of 1.25 ms
+        assert_eq!(build(us(7_500), ms(100)).unwrap().interval(), 6);
+        assert_eq!(build(us(6_250), ms(100)), Err(ConnectTimingError::Interval));
+        assert_eq!(build(us(8_000), ms(100)), Err(ConnectTimingError::Interval));
+        assert_eq!(
+            build(ms(4_000), Duration::from_secs(32))
+                .unwrap()
+                .interval(),
+            3200
+        );
+        assert_eq!(
+            build(ms(4_000) + us(1_250), Duration::from_secs(32)),
+            Err(ConnectTimingError::Interval)
+        );
+
+        // Supervision timeout: 100 ms to 32 s in steps of 10 ms
+        let timing = build(us(7_500), Duration::from_secs(32)).unwrap();
+        assert_eq!(timing.supervision_timeout(), 3200);
+        for &timeout in &[ms(90), ms(105), Duration::from_secs(32) + ms(10)] {
+            assert_eq!(
+                build(us(7_500), timeout),
+                Err(ConnectTimingError::SupervisionTimeout)
+            );
+        }
+        assert_eq!(
+            build(ms(100), ms(200)),
+            Err(ConnectTimingError::SupervisionTimeout)
+        );
+
+        // The timeout must exceed `(1 + latency) * interval * 2`
+        let latency = |latency| {
+            ConnectTimingBuilder::new(us(7_500), ms(100))
+                .slave_latency(latency)
+                .build()
+        };
+        assert_eq!(latency(5).unwrap().slave_latency(), 5);
+        assert_eq!(latency(6), Err(ConnectTimingError::SlaveLatency));
+        let long = ConnectTimingBuilder::new(us(7_500), Duration::from_secs(32));
+        assert!(long.slave_latency(499).build().is_ok());
+        assert_eq!(
+            long.slave_latency(500).build(),
+            Err(ConnectTimingError::SlaveLatency)
+        );
+
+        // Transmit window: Up to 10 ms and shorter than the interval
+        let window = |interval, offset, size| {
+            ConnectTimingBuilder::new(interval, Duration::from_secs(1))
+                .transmit_window(offset, size)
+                .build()
+        };
+        assert_eq!(
+            window(ms(20), ms(20), ms(10))
+                .unwrap()
+                .transmit_window_size(),
+            8
+        );
+        for &(interval, offset, size) in &[
+            (ms(20), ms(0), us(11_250)),
+            (ms(20), ms(0), ms(0)),
+            (us(7_500), ms(0), us(7_500)),
+            (ms(20), us(21_250), ms(5)),
+            (ms(20), ms(1), ms(5)),
+        ] {
+            assert_eq!(
+                window(interval, offset, size),
+                Err(ConnectTimingError::TransmitWindow)
+            );
+        }
+    }
+
+    #[test]
+    fn connect_timing_encoding() {
+        let timing = ConnectTimingBuilder::new(Duration::from_millis(30), Duration::from_secs(4))
+            .transmit_window(Duration::from_micros(3_750), Duration::from_micros(2_500))
+            .slave_latency(3)
+            .build()
+            .unwrap();
+        let mut buf = [0; 9];
+        timing.to_bytes(&mut ByteWriter::new(&mut buf)).unwrap();
+        assert_eq!(buf, [2, 3, 0, 24, 0, 3, 0, 144, 1]);
+    }
+}
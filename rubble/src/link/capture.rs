@@ -0,0 +1,202 @@
+//! Raw packet capture.
+//!
+//! In capture mode, the radio receives packets without removing data whitening and without
+//! checking the CRC, and hands every received buffer to Rubble unmodified. This turns a device into
+//! a basic sniffer: Packets with bad CRCs or unexpected contents are reported just like valid ones.
+//!
+//! The radio driver has to be configured to match the Access Address returned by
+//! [`RawCapture::access_address`] on the frequency returned by [`RawCapture::channel`], with
+//! whitening and CRC checking disabled. Every received buffer (starting after the Access Address)
+//! is then passed to [`RawCapture::process`], which invokes the user callback. The
+//! [`RawPacket`] passed to the callback can de-whiten and check the packet afterwards.
+//!
+//! [`RawCapture::access_address`]: struct.RawCapture.html#method.access_address
+//! [`RawCapture::channel`]: struct.RawCapture.html#method.channel
+//! [`RawCapture::process`]: struct.RawCapture.html#method.process
+//! [`RawPacket`]: struct.RawPacket.html
+
+use {
+    crate::{
+        crc::ble_crc24,
+        link::advertising,
+        phy::{self, AdvertisingChannel, DataChannel},
+        time::Instant,
+        utils::HexSlice,
+        Error,
+    },
+    byteorder::{ByteOrder, LittleEndian},
+    core::fmt,
+};
+
+/// A channel to capture packets on.
+#[derive(Copy, Clone, Debug)]
+pub enum CaptureChannel {
+    Advertising(AdvertisingChannel),
+    Data(DataChannel),
+}
+
+impl CaptureChannel {
+    /// Returns the center frequency of the channel in MHz.
+    pub fn freq(&self) -> u16 {
+        match self {
+            CaptureChannel::Advertising(ch) => ch.freq(),
+            CaptureChannel::Data(ch) => ch.freq(),
+        }
+    }
+
+    /// Returns the initial value of the whitening LFSR used on this channel.
+    pub fn whitening_iv(&self) -> u8 {
+        match self {
+            CaptureChannel::Advertising(ch) => ch.whitening_iv(),
+            CaptureChannel::Data(ch) => ch.whitening_iv(),
+        }
+    }
+}
+
+/// A raw packet received in capture mode.
+pub struct RawPacket<'a> {
+    /// The channel the packet was received on.
+    pub channel: CaptureChannel,
+
+    /// The Access Address the radio matched on.
+    pub access_address: u32,
+
+    /// The time at which the packet was fully received.
+    pub timestamp: Instant,
+
+    /// The raw received bytes following the Access Address (usually PDU and CRC, still whitened).
+    pub data: &'a [u8],
+}
+
+impl<'a> RawPacket<'a> {
+    /// Copies the packet data into `buf` and removes the data whitening.
+    ///
+    /// Returns the de-whitened data, or `Error::Eof` if `buf` is too small to hold it.
+    pub fn dewhiten<'b>(&self, buf: &'b mut [u8]) -> Result<&'b mut [u8], Error> {
+        let buf = buf.get_mut(..self.data.len()).ok_or(Error::Eof)?;
+        buf.copy_from_slice(self.data);
+        phy::whiten(buf, self.channel.whitening_iv());
+        Ok(buf)
+    }
+}
+
+impl fmt::Debug for RawPacket<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawPacket")
+            .field("channel", &self.channel)
+            .field(
+                "access_address",
+                &format_args!("{:#010x}", self.access_address),
+            )
+            .field("timestamp", &self.timestamp)
+            .field("data", &HexSlice(self.data))
+            .finish()
+    }
+}
+
+/// Checks the CRC of a de-whitened packet consisting of a PDU followed by the 3-octet CRC.
+///
+/// Returns `false` if `packet` is too short to contain a CRC.
+pub fn crc_ok(packet: &[u8], crc_init: u32) -> bool {
+    if packet.len() < 3 {
+        return false;
+    }
+
+    let (pdu, crc) = packet.split_at(packet.len() - 3);
+    ble_crc24(pdu, crc_init) == LittleEndian::read_u24(crc)
+}
+
+/// Raw packet capture state.
+///
+/// Forwards every received buffer to a user-provided callback, along with the channel and Access
+/// Address it was received with.
+pub struct RawCapture<F: FnMut(RawPacket<'_>)> {
+    channel: CaptureChannel,
+    access_address: u32,
+    callback: F,
+}
+
+impl<F: FnMut(RawPacket<'_>)> RawCapture<F> {
+    /// Creates a capture configuration listening for packets with `access_address` on `channel`.
+    pub fn new(channel: CaptureChannel, access_address: u32, callback: F) -> Self {
+        Self {
+            channel,
+            access_address,
+            callback,
+        }
+    }
+
+    /// Creates a capture configuration listening for advertising channel packets.
+    pub fn advertising(channel: AdvertisingChannel, callback: F) -> Self {
+        Self::new(
+            CaptureChannel::Advertising(channel),
+            advertising::ACCESS_ADDRESS,
+            callback,
+        )
+    }
+
+    /// Returns the channel the radio needs to listen on.
+    pub fn channel(&self) -> CaptureChannel {
+        self.channel
+    }
+
+    /// Returns the Access Address the radio has to match.
+    pub fn access_address(&self) -> u32 {
+        self.access_address
+    }
+
+    /// Changes the channel to capture packets on.
+    ///
+    /// The radio has to be reconfigured afterwards.
+    pub fn set_channel(&mut self, channel: CaptureChannel) {
+        self.channel = channel;
+    }
+
+    /// Reports a buffer received by the radio in capture mode.
+    ///
+    /// `data` should contain all bytes following the Access Address, exactly as received.
+    pub fn process(&mut self, timestamp: Instant, data: &[u8]) {
+        (self.callback)(RawPacket {
+            channel: self.channel,
+            access_address: self.access_address,
+            timestamp,
+            data,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_and_decode() {
+        // ADV_NONCONN_IND with an empty AdvData
+        let mut packet = [0x02, 0x06, 1, 2, 3, 4, 5, 6, 0, 0, 0];
+        let crc = ble_crc24(&packet[..8], advertising::CRC_PRESET);
+        LittleEndian::write_u24(&mut packet[8..], crc);
+        let plain = packet;
+
+        let channel = AdvertisingChannel::first();
+        phy::whiten(&mut packet, channel.whitening_iv());
+        assert_ne!(packet, plain);
+
+        let mut captured = 0;
+        let mut capture = RawCapture::advertising(channel, |raw| {
+            captured += 1;
+            assert_eq!(raw.access_address, advertising::ACCESS_ADDRESS);
+            assert_eq!(raw.channel.freq(), 2402);
+
+            // Still whitened, so the CRC doesn't match
+            assert!(!crc_ok(raw.data, advertising::CRC_PRESET));
+
+            let mut buf = [0; 39];
+            let decoded = raw.dewhiten(&mut buf).unwrap();
+            assert_eq!(decoded, &plain[..]);
+            assert!(crc_ok(decoded, advertising::CRC_PRESET));
+        });
+        capture.process(Instant::from_raw_micros(0), &packet);
+        capture.process(Instant::from_raw_micros(1000), &packet);
+        assert_eq!(captured, 2);
+    }
+}
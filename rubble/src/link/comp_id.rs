@@ -3,7 +3,7 @@
 use core::fmt;
 
 /// Company identifier for use in link layer Control PDUs.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct CompanyId(u16);
 
 impl fmt::Debug for CompanyId {
@@ -14,7 +14,7 @@ impl fmt::Debug for CompanyId {
 
 impl CompanyId {
     /// Create a company ID from a raw `u16`.
-    pub fn from_raw(raw: u16) -> Self {
+    pub const fn from_raw(raw: u16) -> Self {
         Self(raw)
     }
 
@@ -0,0 +1,50 @@
+//! Connection handles.
+
+use core::fmt;
+
+/// Identifies a Link-Layer connection.
+///
+/// Rubble currently supports only a single connection at a time, which always uses the handle
+/// `ConnectionHandle::SINGLE`. Still, the upper layers (L2CAP, ATT, GATT) are told which connection
+/// they are operating on, so that they don't have to assume there is only one.
+///
+/// Like HCI connection handles, valid values lie in the range `0x0000..=0x0EFF`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ConnectionHandle(u16);
+
+impl ConnectionHandle {
+    /// The handle of the single connection supported by the Link-Layer.
+    pub const SINGLE: Self = ConnectionHandle(0x0000);
+
+    /// The largest valid connection handle.
+    const MAX: u16 = 0x0EFF;
+
+    /// Creates a connection handle from a raw `u16`.
+    ///
+    /// Returns `None` if `raw` is not a valid connection handle (larger than `0x0EFF`).
+    pub fn from_raw(raw: u16) -> Option<Self> {
+        if raw <= Self::MAX {
+            Some(ConnectionHandle(raw))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the raw `u16` representing this connection handle.
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+}
+
+/// Returns `ConnectionHandle::SINGLE`.
+impl Default for ConnectionHandle {
+    fn default() -> Self {
+        Self::SINGLE
+    }
+}
+
+impl fmt::Debug for ConnectionHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ConnectionHandle(0x{:04X})", self.0)
+    }
+}
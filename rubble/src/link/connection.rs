@@ -4,22 +4,43 @@ use {
     crate::{
         bytes::*,
         config::Config,
+        l2cap::TxTicket,
         link::{
-            advertising::ConnectRequestData,
+            advertising::{ConnectRequestData, SleepClockAccuracy},
             channel_map::ChannelMap,
             data::{self, Header, Llid, Pdu},
-            llcp::{ConnectionUpdateData, ControlPdu},
+            encryption::{ConnectionSecurity, KeyStore, Session, ERROR_KEY_MISSING, MIC_LEN},
+            llcp::{ConnectionUpdateData, ControlOpcode, ControlPdu},
+            privacy::PeerAddress,
             queue::{Consume, Consumer, Producer},
-            Cmd, CompanyId, FeatureSet, NextUpdate, RadioCmd, SeqNum, Transmitter,
+            responder::LinkReport,
+            trace::{self, Direction, EventKind},
+            Cmd, ConnectionHandle, DeviceAddress, FeatureSet, NextUpdate, RadioCmd, SeqNum,
+            Transmitter, MIN_DATA_PAYLOAD_BUF,
         },
-        phy::DataChannel,
+        phy::{DataChannel, Phy},
+        rng::Rng,
         time::{Duration, Instant, Timer},
         utils::{Hex, HexSlice},
         Error, BLUETOOTH_VERSION,
     },
-    core::{marker::PhantomData, num::Wrapping},
+    core::{cmp, marker::PhantomData, mem, num::Wrapping},
 };
 
+/// Minimum time between two connection event times reported to the `Responder`.
+///
+/// The `Responder` uses the reported time to check the ATT server's indication confirmation
+/// timeout, which lasts 30 seconds, so a coarse resolution suffices.
+const TIME_REPORT_INTERVAL: Duration = Duration::from_micros(1_000_000);
+
+/// Time to keep listening after the end of the widened receive window of a connection event.
+///
+/// A packet starting right at the end of the window still has to be received in full. The longest
+/// data channel packet on the LE 1M PHY (preamble, Access Address, 2-Byte header, 27-Byte payload,
+/// 4-Byte MIC and CRC) takes 328 µs on air; the rest is slack for radio ramp-up and the latency of
+/// the timer interrupt.
+const RX_END_MARGIN: Duration = Duration::from_micros(500);
+
 /// Connection state and parameters.
 pub struct Connection<C: Config> {
     access_address: u32,
@@ -29,8 +50,8 @@ pub struct Connection<C: Config> {
     /// Number of (unmapped) channels to hop between each connection event.
     hop: u8,
 
-    /// Connection event interval (duration between the start of 2 subsequent connection events).
-    conn_interval: Duration,
+    /// Currently applied connection interval, slave latency and supervision timeout.
+    params: ConnectionParams,
 
     /// Connection event counter (`connEventCount(er)` in the spec).
     conn_event_count: Wrapping<u16>,
@@ -54,14 +75,95 @@ pub struct Connection<C: Config> {
     /// Whether we have ever received a data packet in this connection.
     received_packet: bool,
 
+    /// Number of times the last transmitted packet was retransmitted.
+    retransmissions: u16,
+
+    /// End of the first packet received in the ongoing connection event, or `None` between
+    /// connection events.
+    ///
+    /// A connection event lasts for more than one exchange of PDUs while either side sets the `MD`
+    /// bit.
+    event_start: Option<Instant>,
+
+    /// Number of PDUs received from the master in the ongoing connection event.
+    event_pdus: u8,
+
+    /// Accuracy of the master's sleep clock, as sent in the `CONNECT_REQ`.
+    master_sca: SleepClockAccuracy,
+
+    /// Instant at which the last packet from the master was received.
+    ///
+    /// Our timing is synchronized to the master's at this point, and starts drifting afterwards.
+    last_sync: Instant,
+
+    /// Latest expected start of the connection event we listen for next.
+    next_anchor: Instant,
+
+    /// Window widening applied to the next connection event.
+    window_widening: Duration,
+
+    /// Transmit window in which the master has to send its first packet.
+    ///
+    /// This is set when the connection is established or a connection update is applied, and is
+    /// cleared when the first packet in the window is received (which establishes the anchor).
+    tx_window: Option<TransmitWindow>,
+
+    /// The PHY used to exchange packets.
+    ///
+    /// The PHY update procedure is not supported, so this is always `Le1M`.
+    phy: Phy,
+
     tx: C::PacketConsumer,
     rx: C::PacketProducer,
 
+    /// Whether the last transmitted PDU was taken from the `tx` queue.
+    last_from_queue: bool,
+
+    /// Number of PDUs taken from the `tx` queue that were acknowledged by the master (wrapping).
+    tx_acknowledged: u32,
+
     /// LLCP connection update data received in a previous LL Control PDU.
     ///
     /// Contains the *instant* at which it should be applied to the Link Layer state.
     update_data: Option<LlcpUpdate>,
 
+    /// LL Control PDU to send at the next opportunity, before any queued data.
+    ///
+    /// Used by procedures that require sending more than one PDU in response to the master.
+    pending_llcp: Option<ControlPdu<'static>>,
+
+    /// Progress of the encryption start procedure and the session key in use.
+    encryption: Encryption,
+
+    /// Whether the `Responder` was told about the new connection.
+    connected_reported: bool,
+
+    /// New security level to report to the `Responder` once there's space in the RX queue.
+    security_update: Option<ConnectionSecurity>,
+
+    /// Start of the connection event whose time was last reported to the `Responder`.
+    last_time_report: Instant,
+
+    /// Maximum time between two authenticated (encrypted) packets from the master, or `None` if
+    /// the authenticated payload timer is disabled.
+    authenticated_payload_timeout: Option<Duration>,
+
+    /// Instant at which the last authenticated packet from the master was received.
+    last_authenticated: Instant,
+
+    /// Whether we have sent an `LL_PING_REQ` since the last authenticated packet.
+    ping_sent: bool,
+
+    /// Address of the master.
+    peer: PeerAddress,
+
+    stats: ConnectionStats,
+
+    /// Exponentially weighted moving average of the RSSI of received packets, in 1/16 dBm.
+    ///
+    /// `None` until the radio reported the first sample.
+    rssi: Option<i16>,
+
     _p: PhantomData<C>,
 }
 
@@ -73,11 +175,14 @@ impl<C: Config> Connection<C> {
     ///
     /// # Parameters
     ///
+    /// * **`peer`**: Address of the master that sent the `CONNECT_REQ`, resolved using the
+    ///   Link-Layer's resolving list.
     /// * **`lldata`**: Data contained in the `CONNECT_REQ` advertising PDU.
     /// * **`rx_end`**: Instant at which the `CONNECT_REQ` PDU was fully received.
     /// * **`tx`**: Channel for packets to transmit.
     /// * **`rx`**: Channel for received packets.
     pub(crate) fn create(
+        peer: PeerAddress,
         lldata: &ConnectRequestData,
         rx_end: Instant,
         tx: C::PacketConsumer,
@@ -88,7 +193,7 @@ impl<C: Config> Connection<C> {
             crc_init: lldata.crc_init(),
             channel_map: *lldata.channel_map(),
             hop: lldata.hop(),
-            conn_interval: lldata.interval(),
+            params: ConnectionParams::from_connect_request(lldata),
             conn_event_count: Wrapping(0),
 
             unmapped_channel: DataChannel::new(0),
@@ -98,10 +203,32 @@ impl<C: Config> Connection<C> {
             next_expected_seq_num: SeqNum::ZERO,
             last_header: Header::new(Llid::DataCont),
             received_packet: false,
+            retransmissions: 0,
+            event_start: None,
+            event_pdus: 0,
+            master_sca: lldata.sleep_clock_accuracy(),
+            last_sync: rx_end,
+            next_anchor: rx_end,
+            window_widening: Duration::from_micros(0),
+            tx_window: Some(TransmitWindow::after_connect_request(rx_end, lldata)),
+            phy: Phy::Le1M,
 
             tx,
             rx,
+            last_from_queue: false,
+            tx_acknowledged: 0,
             update_data: None,
+            pending_llcp: None,
+            encryption: Encryption::Off,
+            connected_reported: false,
+            security_update: None,
+            last_time_report: rx_end,
+            authenticated_payload_timeout: None,
+            last_authenticated: rx_end,
+            ping_sent: false,
+            peer,
+            stats: ConnectionStats::default(),
+            rssi: None,
 
             _p: PhantomData,
         };
@@ -109,16 +236,21 @@ impl<C: Config> Connection<C> {
         // Calculate the first channel to use
         this.hop_channel();
 
+        // Let the ATT server know that notifications can be sent now. If the RX queue is full,
+        // this is retried when packets are received.
+        this.connected_reported = this.report(LinkReport::Connected);
+
+        // Listen until the transmit window ends. The master's first packet must start inside the
+        // window, so we wait for a little longer to account for its air time.
         let cmd = Cmd {
-            next_update: NextUpdate::At(
-                rx_end + lldata.end_of_tx_window() + Duration::from_micros(500),
-            ),
+            next_update: this.listen_until(this.tx_window.unwrap().end()),
             radio: RadioCmd::ListenData {
                 channel: this.channel,
                 access_address: this.access_address,
                 crc_init: this.crc_init,
             },
-            queued_work: false,
+            queued_work: this.connected_reported,
+            turnaround: None,
         };
 
         (this, cmd)
@@ -126,16 +258,41 @@ impl<C: Config> Connection<C> {
 
     /// Called by the `LinkLayer` when a data channel packet is received.
     ///
-    /// Returns `Err(())` when the connection is ended (not necessarily due to an error condition).
+    /// `keys` contains the key store and RNG to use for the encryption start procedure, or `None`
+    /// if encryption is not supported.
+    ///
+    /// Returns an error when the connection is ended (not necessarily due to an error condition).
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn process_data_packet(
         &mut self,
         rx_end: Instant,
         tx: &mut C::Transmitter,
-        timer: &mut C::Timer,
+        keys: Option<&mut (C::KeyStore, C::Rng)>,
         header: data::Header,
         payload: &[u8],
         crc_ok: bool,
-    ) -> Result<Cmd, ()> {
+    ) -> Result<Cmd, DisconnectReason> {
+        // The length field is under the peer's control, and the radio only receives as many Bytes
+        // as fit into its buffer. A PDU whose length doesn't match the received payload, or that
+        // is longer than we can receive, is dropped like a PDU with a CRC error: It is neither
+        // processed nor acknowledged.
+        let max_len = match self.encryption.rx_session() {
+            Some(_) => MIN_DATA_PAYLOAD_BUF + MIC_LEN,
+            None => MIN_DATA_PAYLOAD_BUF,
+        };
+        let declared_len = usize::from(header.payload_length());
+        let crc_ok = if crc_ok && (declared_len != payload.len() || declared_len > max_len) {
+            warn!(
+                "dropping data PDU with invalid length {} ({} Bytes received)",
+                declared_len,
+                payload.len()
+            );
+            self.stats.invalid_length = self.stats.invalid_length.wrapping_add(1);
+            false
+        } else {
+            crc_ok
+        };
+
         // If the sequence number of the packet is the same as our next expected sequence number,
         // the packet contains new data that we should try to process. However, if the CRC is bad,
         // we'll never try to process the data and instead request a retransmission.
@@ -149,11 +306,71 @@ impl<C: Config> Connection<C> {
 
         let is_empty = header.llid() == Llid::DataCont && payload.is_empty();
 
+        // The first packet of a connection event also determines when the next event starts
+        let event_start = match self.event_start {
+            Some(start) => start,
+            None => {
+                let anchor = rx_end - packet_air_time(self.phy, header.payload_length());
+                trace::event_started::<C>(EventKind::Connection, anchor);
+                self.last_sync = rx_end;
+                self.event_start = Some(rx_end);
+                self.event_pdus = 0;
+                rx_end
+            }
+        };
+        self.event_pdus = self.event_pdus.saturating_add(1);
+
+        if let Some(window) = self.tx_window.take() {
+            // This is the first packet sent by the master after connection setup (or an update),
+            // its start marks the anchor point of the connection.
+            let anchor = rx_end - packet_air_time(self.phy, header.payload_length());
+            if !window.contains(anchor) {
+                warn!("anchor {} outside of transmit window {:?}", anchor, window);
+            }
+        }
+
         if acknowledged {
             self.received_packet = true;
+            self.retransmissions = 0;
             self.transmit_seq_num += SeqNum::ONE;
+            self.encryption.acknowledged();
+            if mem::replace(&mut self.last_from_queue, false) {
+                self.tx_acknowledged = self.tx_acknowledged.wrapping_add(1);
+            }
+        }
+
+        // Once the master has started encrypting, new non-empty PDUs have to be decrypted. The
+        // radio's RX buffer is read-only, so the plaintext is stored in a local buffer.
+        let mut header = header;
+        let mut plaintext = [0; MIN_DATA_PAYLOAD_BUF + MIC_LEN];
+        let mut decrypted = false;
+        let payload = match self.encryption.rx_session() {
+            Some(session) if is_new && !payload.is_empty() => {
+                // The length was checked against `plaintext.len()` above
+                let buf = &mut plaintext[..payload.len()];
+                buf.copy_from_slice(payload);
+                match session.decrypt(header, buf) {
+                    Ok(len) => {
+                        header.set_payload_length(len as u8);
+                        decrypted = true;
+                        &buf[..len]
+                    }
+                    Err(e) => {
+                        // MIC failure, the connection must be terminated immediately
+                        error!("failed to decrypt PDU ({:?}), closing connection", e);
+                        return Err(DisconnectReason::MicFailure);
+                    }
+                }
+            }
+            _ => payload,
+        };
+
+        if is_new {
+            trace::trace_data::<C>(Direction::Inbound, header.llid(), payload);
         }
 
+        let expected_seq_num = self.next_expected_seq_num;
+
         // Whether we've already sent a response packet.
         let mut responded = false;
         // Whether we've pushed more work into the RX queue.
@@ -167,25 +384,34 @@ impl<C: Config> Connection<C> {
                 // LLCP message, try to process it immediately. Certain LLCPDUs might be put in the
                 // channel instead and answered by the non-real-time part.
 
-                if let Ok(pdu) = ControlPdu::from_bytes(&mut ByteReader::new(payload)) {
+                let pdu = match ControlPdu::from_bytes(&mut ByteReader::new(payload)) {
+                    Ok(pdu) => Some(pdu),
+                    // The CRC was valid, so a PDU we can't parse has invalid `CtrData` for its
+                    // opcode. It is answered like an unknown opcode, with `LL_UNKNOWN_RSP`.
+                    Err(_) => payload
+                        .split_first()
+                        .map(|(opcode, ctr_data)| ControlPdu::Unknown {
+                            opcode: ControlOpcode::from(*opcode),
+                            ctr_data,
+                        }),
+                };
+
+                if let Some(pdu) = pdu {
                     // Some LLCPDUs don't need a response, those can always be processed and
                     // ACKed. For those that do, the other device must have ACKed the last
                     // packet we sent, because we'll directly use the radio's TX buffer to send
                     // back the LLCP response.
 
-                    match self.process_control_pdu(pdu, acknowledged) {
+                    match self.process_control_pdu(pdu, acknowledged, keys) {
                         Ok(Some(response)) => {
                             self.next_expected_seq_num += SeqNum::ONE;
 
                             let rsp = Pdu::from(&response);
-                            let mut payload_writer = ByteWriter::new(tx.tx_payload_buf());
-                            let left = payload_writer.space_left();
-                            rsp.to_bytes(&mut payload_writer).unwrap();
+                            let pl_len = rsp.to_bytes_into(tx.tx_payload_buf()).unwrap() as u8;
 
                             let mut header = Header::new(Llid::Control);
-                            let pl_len = (left - payload_writer.space_left()) as u8;
                             header.set_payload_length(pl_len);
-                            self.send(header, tx);
+                            self.send(header, tx, rx_end);
                             responded = true;
 
                             info!("LLCP<- {:?}", pdu);
@@ -197,16 +423,24 @@ impl<C: Config> Connection<C> {
                             info!("LLCP<- {:?}", pdu);
                             info!("LLCP-> (no response)");
                         }
-                        Err(LlcpError::ConnectionLost) => {
-                            return Err(());
+                        Err(LlcpError::ConnectionLost(reason)) => {
+                            return Err(reason);
                         }
                         Err(LlcpError::NoSpace) => {
                             // Do not acknowledge the PDU
                         }
                     }
                 } else {
-                    // Couldn't parse control PDU. CRC might be invalid. NACK
+                    // There's no opcode to put in an `LL_UNKNOWN_RSP`, so the PDU is dropped.
+                    // It has to be acknowledged, or the master would resend it forever.
+                    warn!("dropping empty LL Control PDU");
+                    self.next_expected_seq_num += SeqNum::ONE;
                 }
+            } else if payload.is_empty() {
+                // A start fragment without any data carries nothing L2CAP could parse. Acknowledge
+                // it like an empty PDU instead of forwarding it.
+                warn!("dropping empty L2CAP start fragment");
+                self.next_expected_seq_num += SeqNum::ONE;
             } else {
                 // Try to buffer the packet. If it fails, we don't acknowledge it, so it will be
                 // resent until we have space.
@@ -228,26 +462,94 @@ impl<C: Config> Connection<C> {
             }
         }
 
+        if !self.connected_reported && self.report(LinkReport::Connected) {
+            self.connected_reported = true;
+            queued_work = true;
+        }
+
+        if let Some(level) = self.security_update {
+            // Tell the `Responder`, so the ATT server can enforce attribute permissions
+            if self.report(LinkReport::Security(level)) {
+                self.security_update = None;
+                queued_work = true;
+            }
+        }
+
+        if rx_end.duration_since(self.last_time_report) >= TIME_REPORT_INTERVAL {
+            // Tell the `Responder` the current time, so the ATT server can time out indications
+            if self.report(LinkReport::Time(rx_end)) {
+                self.last_time_report = rx_end;
+                queued_work = true;
+            }
+        }
+
+        if decrypted && self.next_expected_seq_num != expected_seq_num {
+            // The decrypted PDU was accepted, the next one will use a new packet counter
+            if let Some(session) = self.encryption.rx_session() {
+                session.advance_rx_counter();
+            }
+
+            // It also carried a valid MIC, so the master is still present
+            self.last_authenticated = rx_end;
+            self.ping_sent = false;
+        }
+
+        self.check_authenticated_payload(rx_end);
+
         if acknowledged {
             if !responded {
                 // Send a new data packet.
 
-                // Try to acquire PDU from the tx queue, fall back to an empty PDU.
-                let mut payload_writer = ByteWriter::new(tx.tx_payload_buf());
-                let header = match self.tx.consume_raw_with(|header, pl| {
-                    payload_writer.write_slice(pl).expect("TX buf out of space");
-                    Consume::always(Ok(header))
-                }) {
-                    Ok(h) => h,
-                    Err(_) => Header::new(Llid::DataCont),
+                let header = if let Some(pdu) = self.pending_llcp.take() {
+                    // Finish a multi-PDU procedure before sending anything else
+                    let pl_len = Pdu::from(&pdu).to_bytes_into(tx.tx_payload_buf()).unwrap() as u8;
+                    if let ControlPdu::StartEncReq = pdu {
+                        self.encryption.start_req_sent();
+                    }
+                    info!("LLCP-> {:?}", pdu);
+
+                    let mut header = Header::new(Llid::Control);
+                    header.set_payload_length(pl_len);
+                    header
+                } else if self.encryption.is_starting() {
+                    // No data may be sent while encryption is being started
+                    let pdu = Pdu::empty();
+                    pdu.to_bytes(&mut ByteWriter::new(tx.tx_payload_buf()))
+                        .unwrap();
+                    Header::new(pdu.llid())
+                } else {
+                    // Try to acquire PDU from the tx queue, fall back to an empty PDU.
+                    let mut payload_writer = ByteWriter::new(tx.tx_payload_buf());
+                    match self.tx.consume_raw_with(|header, pl| {
+                        payload_writer.write_slice(pl).expect("TX buf out of space");
+                        Consume::always(Ok(header))
+                    }) {
+                        Ok(h) => {
+                            self.last_from_queue = true;
+                            h
+                        }
+                        Err(_) => Header::new(Llid::DataCont),
+                    }
                 };
 
-                self.send(header, tx);
+                self.send(header, tx, rx_end);
             }
         } else {
             // Last packet not acknowledged, resend.
             // If CRC is bad, this bit could be flipped, so we always retransmit in that case.
             if self.received_packet {
+                if C::MAX_RETRANSMISSIONS == Some(self.retransmissions) {
+                    warn!(
+                        "no ACK after {} retransmissions, giving up",
+                        self.retransmissions
+                    );
+                    return Err(DisconnectReason::RetransmissionLimit);
+                }
+                self.retransmissions = self.retransmissions.saturating_add(1);
+                self.stats.retransmissions = self.stats.retransmissions.wrapping_add(1);
+
+                // The queue may have changed since the PDU was first sent, so `MD` is updated
+                self.last_header.set_md(self.has_more_data(rx_end));
                 self.last_header.set_nesn(self.next_expected_seq_num);
                 tx.transmit_data(
                     self.access_address,
@@ -265,37 +567,44 @@ impl<C: Config> Connection<C> {
                 let pdu = Pdu::empty();
                 let mut payload_writer = ByteWriter::new(tx.tx_payload_buf());
                 pdu.to_bytes(&mut payload_writer).unwrap();
-                self.send(Header::new(pdu.llid()), tx);
+                self.send(Header::new(pdu.llid()), tx, rx_end);
             }
         }
 
-        let last_channel = self.channel;
-
-        // FIXME: Don't hop if one of the MD bits is set to true (also don't log then)
-        {
-            // Connection event closes
-            self.conn_event_count += Wrapping(1);
-
-            if let Some(update) = self.update_data.take() {
-                if update.instant() == self.conn_event_count.0 {
-                    // Next conn event will the the first one with these parameters.
-                    let result = self.apply_llcp_update(update, rx_end);
-                    info!("LLCP patch applied: {:?} -> {:?}", update, result);
-                    if let Some(mut cmd) = result {
-                        cmd.queued_work = queued_work;
-                        return Ok(cmd);
-                    }
-                } else {
-                    // Put it back
-                    self.update_data = Some(update);
-                }
-            }
+        // The connection event continues as long as either device sets the `MD` bit, and there's
+        // enough time left for another exchange. After a CRC error, the master's `MD` bit can't be
+        // trusted, so the event is closed.
+        let more_data = crc_ok && (header.md() || self.last_header.md());
+        if more_data && self.event_has_time(rx_end) {
+            trace!(
+                "#{} DATA({})<- MD, {:?}, {:?}",
+                self.conn_event_count,
+                self.channel.index(),
+                header,
+                HexSlice(payload)
+            );
 
-            // Hop channels after applying LLCP update because it might change the channel map used
-            // by the next event
-            self.hop_channel();
+            // Wait for the master's next PDU, which follows our response after `T_IFS`. If it
+            // doesn't arrive, the master has closed the event, which is handled in `timer_update`.
+            let max_pdu = packet_air_time(self.phy, (MIN_DATA_PAYLOAD_BUF + MIC_LEN) as u8);
+            let timeout = Duration::from_micros(
+                2 * (Duration::T_IFS.as_micros() + max_pdu.as_micros()) + 100,
+            );
+            return Ok(Cmd {
+                next_update: NextUpdate::At(rx_end + timeout),
+                radio: RadioCmd::ListenData {
+                    channel: self.channel,
+                    access_address: self.access_address,
+                    crc_init: self.crc_init,
+                },
+                queued_work,
+                turnaround: Some(rx_end + Duration::T_IFS),
+            });
         }
 
+        let last_channel = self.channel;
+        let mut cmd = self.close_event(event_start, rx_end);
+
         trace!(
             "#{} DATA({}->{})<- {}{:?}, {:?}",
             self.conn_event_count,
@@ -306,24 +615,152 @@ impl<C: Config> Connection<C> {
             HexSlice(payload)
         );
 
-        Ok(Cmd {
-            next_update: NextUpdate::At(timer.now() + self.conn_event_timeout()),
+        cmd.queued_work = queued_work;
+        // A response is sent to every packet
+        cmd.turnaround = Some(rx_end + Duration::T_IFS);
+        Ok(cmd)
+    }
+
+    /// Runs the authenticated payload timer (*LE Ping*).
+    ///
+    /// On an encrypted connection, the master has to send an authenticated packet at least once
+    /// per `authenticated_payload_timeout`. When half of the timeout has elapsed without one, we
+    /// send an `LL_PING_REQ`, whose response is authenticated. If the timeout expires anyway, it
+    /// is recorded in the `ConnectionStats` and the timer restarts.
+    fn check_authenticated_payload(&mut self, now: Instant) {
+        let timeout = match self.authenticated_payload_timeout {
+            Some(timeout) if self.is_encrypted() => timeout,
+            _ => return,
+        };
+
+        let elapsed = now.duration_since(self.last_authenticated);
+        if elapsed >= timeout {
+            warn!("authenticated payload timeout expired");
+            self.stats.authenticated_payload_timeouts =
+                self.stats.authenticated_payload_timeouts.wrapping_add(1);
+            self.last_authenticated = now;
+            self.ping_sent = false;
+        } else if elapsed.as_micros() >= timeout.as_micros() / 2
+            && !self.ping_sent
+            && self.pending_llcp.is_none()
+        {
+            self.pending_llcp = Some(ControlPdu::PingReq);
+            self.ping_sent = true;
+        }
+    }
+
+    /// Ends the ongoing connection event that started at `event_start` at the instant `now`.
+    ///
+    /// This advances the connection event counter and hops to the channel of the next event we
+    /// listen on, skipping events as permitted by the slave latency. Returns the `Cmd` to listen
+    /// for that event, or for the transmit window if a connection update takes effect.
+    fn close_event(&mut self, event_start: Instant, now: Instant) -> Cmd {
+        trace::event_ended::<C>(EventKind::Connection, now);
+
+        // This has to be decided before an update is applied, so that the event at the instant is
+        // always attended
+        let skip = self.events_to_skip();
+
+        self.event_start = None;
+        self.conn_event_count += Wrapping(1);
+
+        if let Some(update) = self.update_data.take() {
+            if update.instant() == self.conn_event_count.0 {
+                // Next conn event will the the first one with these parameters.
+                let result = self.apply_llcp_update(update, event_start);
+                info!("LLCP patch applied: {:?} -> {:?}", update, result);
+                if let Some(cmd) = result {
+                    return cmd;
+                }
+            } else {
+                // Put it back
+                self.update_data = Some(update);
+            }
+        }
+
+        // Hop channels after applying LLCP update because it might change the channel map used by
+        // the next event
+        self.hop_channel();
+        for _ in 0..skip {
+            self.conn_event_count += Wrapping(1);
+            self.hop_channel();
+        }
+        if skip != 0 {
+            trace!("slave latency: skipping {} conn events", skip);
+        }
+
+        let interval = self.params.interval.as_micros() * (u32::from(skip) + 1);
+        Cmd {
+            next_update: self.listen_until(event_start + Duration::from_micros(interval)),
             radio: RadioCmd::ListenData {
                 channel: self.channel,
                 access_address: self.access_address,
                 crc_init: self.crc_init,
             },
-            queued_work,
-        })
+            queued_work: false,
+            turnaround: None,
+        }
+    }
+
+    /// Returns the number of connection events to skip after the ongoing one is closed.
+    ///
+    /// The slave may skip up to `slave_latency` events as long as it has nothing to send. It never
+    /// skips events while a procedure is in progress: Until the instant of a pending update has
+    /// passed, and while the master waits for an LL Control PDU or the acknowledgement of our last
+    /// non-empty PDU. To avoid a supervision timeout, it also listens at least once per half
+    /// `connSupervisionTimeout`.
+    ///
+    /// Data queued by the application while events are skipped is sent at the next attended event.
+    fn events_to_skip(&self) -> u16 {
+        let idle = self.update_data.is_none()
+            && self.tx_window.is_none()
+            && self.pending_llcp.is_none()
+            && !self.encryption.is_starting()
+            && !self.tx.has_data()
+            && self.last_header.payload_length() == 0;
+        if !idle {
+            return 0;
+        }
+
+        let interval = self.params.interval.as_micros();
+        let max_skip =
+            (self.params.supervision_timeout.as_micros() / 2 / interval).saturating_sub(1);
+        cmp::min(u32::from(self.params.slave_latency), max_skip) as u16
+    }
+
+    /// Called by the `LinkLayer` when the radio missed the turnaround deadline of the last `Cmd`.
+    ///
+    /// The master didn't receive our response, so it will retransmit its packet without
+    /// acknowledging ours. This is handled like any other lost packet, so the only thing to do
+    /// here is to record it.
+    pub(crate) fn turnaround_missed(&mut self) {
+        self.stats.missed_ifs = self.stats.missed_ifs.wrapping_add(1);
+        trace!("missed T_IFS in conn event #{}", self.conn_event_count);
+    }
+
+    /// Adds an RSSI sample (in dBm) to the moving average.
+    ///
+    /// Each sample is weighted with 1/8, so the average follows changes of the signal strength
+    /// within a few dozen packets while smoothing out fading of individual packets.
+    pub(crate) fn record_rssi(&mut self, rssi: i8) {
+        let sample = i16::from(rssi) * 16;
+        self.rssi = Some(match self.rssi {
+            Some(avg) => avg + (sample - avg) / 8,
+            None => sample,
+        });
     }
 
     /// Called by the `LinkLayer` when the configured timer expires (according to a `Cmd` returned
     /// earlier).
     ///
-    /// Returns `Err(())` when the connection is closed or lost. In that case, the Link-Layer will
+    /// Returns an error when the connection is closed or lost. In that case, the Link-Layer will
     /// return to standby state.
-    pub(crate) fn timer_update(&mut self, timer: &mut C::Timer) -> Result<Cmd, ()> {
-        if self.received_packet {
+    pub(crate) fn timer_update(&mut self, timer: &mut C::Timer) -> Result<Cmd, DisconnectReason> {
+        if let Some(event_start) = self.event_start {
+            // The master didn't send another PDU, so it has closed the connection event
+            trace!("conn event #{} closed by master", self.conn_event_count);
+            Ok(self.close_event(event_start, timer.now()))
+        } else if self.received_packet {
             // No packet from master, skip this connection event and listen on the next channel
 
             let last_channel = self.channel;
@@ -337,13 +774,14 @@ impl<C: Config> Connection<C> {
             );
 
             Ok(Cmd {
-                next_update: NextUpdate::At(timer.now() + self.conn_event_timeout()),
+                next_update: self.listen_until(self.next_anchor + self.params.interval),
                 radio: RadioCmd::ListenData {
                     channel: self.channel,
                     access_address: self.access_address,
                     crc_init: self.crc_init,
                 },
                 queued_work: false,
+                turnaround: None,
             })
         } else {
             // Master did not transmit the first packet during this transmit window.
@@ -353,21 +791,68 @@ impl<C: Config> Connection<C> {
 
             self.conn_event_count += Wrapping(1);
             trace!("missed transmit window");
-            Err(())
+            Err(DisconnectReason::FailedToEstablish)
         }
     }
 
-    fn conn_event_timeout(&self) -> Duration {
-        // Time out ~500µs after the anchor point of the next conn event.
-        self.conn_interval + Duration::from_micros(500)
+    /// Returns the `NextUpdate` ending the connection event expected to start at `anchor`.
+    ///
+    /// The master's PDU can arrive early or late by the combined drift of both sleep clocks since
+    /// we last received a packet. The radio keeps listening from the end of the previous event, so
+    /// only the end of the receive window needs to be widened. The event is considered missed
+    /// `RX_END_MARGIN` after that.
+    fn listen_until(&mut self, anchor: Instant) -> NextUpdate {
+        let since_sync = anchor.duration_since(self.last_sync);
+        let ppm = u64::from(self.master_sca.max_ppm()) + u64::from(C::SLEEP_CLOCK_ACCURACY);
+        let drift = u64::from(since_sync.as_micros()) * ppm / 1_000_000;
+
+        // Active clocks may additionally jitter by up to 16 µs
+        self.window_widening = Duration::from_micros(drift as u32 + 16);
+        self.next_anchor = anchor;
+        NextUpdate::At(anchor + self.window_widening + RX_END_MARGIN)
     }
 
     /// Whether we want to send more data during this connection event.
     ///
     /// Note that this *has to* change to `false` eventually, even if there's more data to be sent,
     /// because the connection event must close at least `T_IFS` before the next one occurs.
-    fn has_more_data(&self) -> bool {
-        false
+    fn has_more_data(&self, now: Instant) -> bool {
+        let queued =
+            self.pending_llcp.is_some() || (!self.encryption.is_starting() && self.tx.has_data());
+        queued && self.event_has_time(now)
+    }
+
+    /// Whether there's enough time left in the connection event for another exchange of PDUs
+    /// after the master's PDU that ended at `now`.
+    ///
+    /// An exchange consists of our response, the master's next PDU and our response to it, all of
+    /// which may have the maximum length. The event has to end `T_IFS` before the next anchor
+    /// point, and must not exceed the limits in `Config::MAX_EVENT_PDUS` and
+    /// `Config::MAX_EVENT_LENGTH`.
+    fn event_has_time(&self, now: Instant) -> bool {
+        let event_start = match self.event_start {
+            Some(start) => start,
+            None => return false,
+        };
+
+        if let Some(max_pdus) = C::MAX_EVENT_PDUS {
+            if self.event_pdus >= max_pdus {
+                return false;
+            }
+        }
+
+        let max_pdu = packet_air_time(self.phy, (MIN_DATA_PAYLOAD_BUF + MIC_LEN) as u8);
+        let needed =
+            Duration::from_micros(4 * Duration::T_IFS.as_micros() + 3 * max_pdu.as_micros());
+        if let Some(max_length) = C::MAX_EVENT_LENGTH {
+            if (now - event_start) + needed > max_length {
+                return false;
+            }
+        }
+
+        // The next anchor point is less than one interval after `event_start`, so one max. PDU is
+        // accounted for to be safe
+        (now - event_start) + needed + max_pdu <= self.params.interval
     }
 
     /// Advances the `unmapped_channel` and `channel` fields to the next data channel on which a
@@ -388,8 +873,19 @@ impl<C: Config> Connection<C> {
     }
 
     /// Sends a new PDU to the connected device (ie. a non-retransmitted PDU).
-    fn send(&mut self, mut header: Header, tx: &mut C::Transmitter) {
-        header.set_md(self.has_more_data());
+    ///
+    /// If encryption is enabled, the payload in the radio's TX buffer is encrypted in place.
+    ///
+    /// `now` is the end of the master's PDU we're responding to.
+    fn send(&mut self, mut header: Header, tx: &mut C::Transmitter, now: Instant) {
+        let pl = &tx.tx_payload_buf()[..usize::from(header.payload_length())];
+        trace::trace_data::<C>(Direction::Outbound, header.llid(), pl);
+
+        if let Encryption::On(session) = &mut self.encryption {
+            session.encrypt(&mut header, tx.tx_payload_buf());
+        }
+
+        header.set_md(self.has_more_data(now));
         header.set_nesn(self.next_expected_seq_num);
         header.set_sn(self.transmit_seq_num);
         self.last_header = header;
@@ -402,7 +898,7 @@ impl<C: Config> Connection<C> {
 
     /// Tries to process and acknowledge an LL Control PDU.
     ///
-    /// Returns `Err(())` when the connection is closed or lost.
+    /// Returns `Err(LlcpError::ConnectionLost)` when the connection is closed or lost.
     ///
     /// Note this this function is on a time-critical path and thus can not use logging since that's
     /// currently way too slow. Critical errors can still be logged, since they abort the connection
@@ -414,11 +910,18 @@ impl<C: Config> Connection<C> {
     /// * **`can_respond`**: Whether the radio's TX buffer may be overwritten to send a response. If
     ///   this is `false`, this method may choose not to acknowledge the PDU and wait for a
     ///   retransmission instead.
+    /// * **`keys`**: Key store and RNG for the encryption start procedure, if supported.
     fn process_control_pdu(
         &mut self,
         pdu: ControlPdu<'_>,
         can_respond: bool,
+        keys: Option<&mut (C::KeyStore, C::Rng)>,
     ) -> Result<Option<ControlPdu<'static>>, LlcpError> {
+        let mut features = FeatureSet::supported();
+        if keys.is_some() {
+            features |= FeatureSet::LE_ENCRYPTION | FeatureSet::LE_PING;
+        }
+
         let response = match pdu {
             ControlPdu::ConnectionUpdateReq(data) => {
                 self.prepare_llcp_update(LlcpUpdate::ConnUpdate(data))?;
@@ -433,20 +936,83 @@ impl<C: Config> Connection<C> {
                     "closing connection due to termination request: code {:?}",
                     error_code
                 );
-                return Err(LlcpError::ConnectionLost);
+                return Err(LlcpError::ConnectionLost(DisconnectReason::Terminated(
+                    error_code.0,
+                )));
             }
             ControlPdu::FeatureReq { features_master } => ControlPdu::FeatureRsp {
-                features_used: features_master & FeatureSet::supported(),
+                features_used: features_master & features,
             },
+            ControlPdu::EncReq {
+                rand,
+                ediv,
+                skdm,
+                ivm,
+            } if keys.is_some() && self.encryption.is_off() && self.pending_llcp.is_none() => {
+                // Only start the procedure if we can answer right away, since it consumes
+                // randomness and may already prepare the session
+                if !can_respond {
+                    return Err(LlcpError::NoSpace);
+                }
+
+                let (key_store, rng) = keys.unwrap();
+                let mut random = [0; 12];
+                rng.fill_bytes(&mut random);
+                let mut skds = [0; 8];
+                skds.copy_from_slice(&random[..8]);
+                let skds = u64::from_le_bytes(skds);
+                let ivs = u32::from_le_bytes([random[8], random[9], random[10], random[11]]);
+
+                match key_store.lookup_ltk(ediv.0, rand.0) {
+                    Some(ltk) => {
+                        let session = Session::new(&ltk, skdm.0, skds, ivm.0, ivs);
+                        self.encryption = Encryption::StartReqPending {
+                            session,
+                            sent: false,
+                        };
+                        self.pending_llcp = Some(ControlPdu::StartEncReq);
+                    }
+                    None => {
+                        // Tell the master to pair again
+                        self.pending_llcp = Some(ControlPdu::RejectInd {
+                            error_code: Hex(ERROR_KEY_MISSING),
+                        });
+                    }
+                }
+
+                ControlPdu::EncRsp {
+                    skds: Hex(skds),
+                    ivs: Hex(ivs),
+                }
+            }
+            ControlPdu::StartEncRsp if self.encryption.is_rx_encrypted() => {
+                if !can_respond {
+                    return Err(LlcpError::NoSpace);
+                }
+
+                // Our response is the first encrypted PDU we send
+                self.encryption.enable_tx();
+                self.security_update = Some(self.security_level());
+                ControlPdu::StartEncRsp
+            }
+            ControlPdu::UnknownRsp { unknown_type } => {
+                // The master doesn't support a procedure we started. Answering this with another
+                // `LL_UNKNOWN_RSP` could make both sides bounce responses back and forth forever.
+                info!("master doesn't support {:?}", unknown_type);
+                return Ok(None);
+            }
+            ControlPdu::PingReq => ControlPdu::PingRsp,
+            ControlPdu::PingRsp => {
+                // Receiving the response is all that matters for the authenticated payload timer
+                return Ok(None);
+            }
             ControlPdu::VersionInd { .. } => {
-                // FIXME this should be something real, and defined somewhere else
-                let comp_id = 0xFFFF;
                 // FIXME this should correlate with the Cargo package version
                 let sub_vers_nr = 0x0000;
 
                 ControlPdu::VersionInd {
                     vers_nr: BLUETOOTH_VERSION,
-                    comp_id: CompanyId::from_raw(comp_id),
+                    comp_id: C::COMPANY_ID,
                     sub_vers_nr: Hex(sub_vers_nr),
                 }
             }
@@ -472,7 +1038,9 @@ impl<C: Config> Connection<C> {
                 "got update data {:?} while update {:?} is already queued",
                 update, data
             );
-            Err(LlcpError::ConnectionLost)
+            Err(LlcpError::ConnectionLost(
+                DisconnectReason::ProcedureCollision,
+            ))
         } else {
             self.update_data = Some(update);
             Ok(())
@@ -486,16 +1054,22 @@ impl<C: Config> Connection<C> {
     fn apply_llcp_update(&mut self, update: LlcpUpdate, rx_end: Instant) -> Option<Cmd> {
         match update {
             LlcpUpdate::ConnUpdate(data) => {
-                let old_conn_interval = self.conn_interval;
-                self.conn_interval = data.interval();
+                let old_conn_interval = self.params.interval;
+                self.params.apply_update(&data);
+
+                // The transmit window is relative to the (old) anchor point of the connection
+                // event at the instant, for which `rx_end` is a decent approximation.
+                let window_start = rx_end + old_conn_interval + data.win_offset();
+                self.tx_window = Some(TransmitWindow {
+                    start: window_start,
+                    end: window_start + data.win_size(),
+                });
 
                 self.hop_channel();
 
                 Some(Cmd {
                     // Next update after the tx window ends (= missed it)
-                    next_update: NextUpdate::At(
-                        rx_end + old_conn_interval + data.win_offset() + data.win_size(),
-                    ),
+                    next_update: self.listen_until(window_start + data.win_size()),
                     // Listen for the transmit window
                     radio: RadioCmd::ListenData {
                         channel: self.channel,
@@ -504,6 +1078,7 @@ impl<C: Config> Connection<C> {
                     },
                     // This function never queues work, but the caller might change this to `true`
                     queued_work: false,
+                    turnaround: None,
                 })
             }
             LlcpUpdate::ChannelMap { map, .. } => {
@@ -526,7 +1101,400 @@ impl<C: Config> Connection<C> {
     /// message, or by using the Link Layer control procedure for requesting new connection
     /// parameters.
     pub fn connection_interval(&self) -> Duration {
-        self.conn_interval
+        self.params.interval
+    }
+
+    /// Returns the window widening applied when listening for the next connection event.
+    ///
+    /// This is the time by which the master's packet may be late, because both devices' sleep
+    /// clocks drifted since the last packet was received from the master. It is computed from
+    /// `Config::SLEEP_CLOCK_ACCURACY` and the master's sleep clock accuracy from the `CONNECT_REQ`,
+    /// and grows with every connection event that is skipped or missed.
+    pub fn window_widening(&self) -> Duration {
+        self.window_widening
+    }
+
+    /// Returns the accuracy of the master's sleep clock, as sent in the `CONNECT_REQ`.
+    ///
+    /// The window widening uses the worst-case drift of this accuracy range instead of assuming
+    /// the worst accuracy allowed by the specification, so a precise master clock allows listening
+    /// for a shorter time.
+    pub fn master_sleep_clock_accuracy(&self) -> SleepClockAccuracy {
+        self.master_sca
+    }
+
+    /// Returns the connection parameters currently in effect.
+    ///
+    /// These are the parameters sent in the `CONNECT_REQ`, or the ones negotiated by the last
+    /// connection update procedure (once it has taken effect).
+    pub fn connection_params(&self) -> ConnectionParams {
+        self.params
+    }
+
+    /// Returns the Access Address of the connection's data channel packets.
+    ///
+    /// This is chosen by the master and sent in the `CONNECT_REQ`. Together with `crc_init` and
+    /// `channel_map`, it is needed to follow the connection with a sniffer.
+    pub fn access_address(&self) -> u32 {
+        self.access_address
+    }
+
+    /// Returns the initialization value of the CRC calculation for data channel packets.
+    ///
+    /// Only the least significant 24 bits are used.
+    pub fn crc_init(&self) -> u32 {
+        self.crc_init
+    }
+
+    /// Returns the data channels currently used for hopping.
+    ///
+    /// The initial map is sent in the `CONNECT_REQ`, and the master can change it later using the
+    /// channel map update procedure (the new map is returned once it has taken effect).
+    pub fn channel_map(&self) -> ChannelMap {
+        self.channel_map
+    }
+
+    /// Returns the PHY used for packets in this connection.
+    ///
+    /// The radio must be configured to use this PHY when sending and receiving data channel PDUs.
+    pub fn phy(&self) -> Phy {
+        self.phy
+    }
+
+    /// Returns the handle identifying this connection.
+    ///
+    /// Only a single connection is supported, so this is always `ConnectionHandle::SINGLE`.
+    pub fn handle(&self) -> ConnectionHandle {
+        ConnectionHandle::SINGLE
+    }
+
+    /// Returns the identity address of the connected master.
+    ///
+    /// If the master connected using a Resolvable Private Address that could be resolved using the
+    /// Link-Layer's resolving list, this is the master's identity address. Otherwise, it is the
+    /// address the master used to connect.
+    pub fn peer_identity(&self) -> DeviceAddress {
+        self.peer.identity().unwrap_or_else(|| self.peer.address())
+    }
+
+    /// Returns the address of the connected master.
+    ///
+    /// The returned `PeerAddress` contains both the address the master used to connect, and its
+    /// identity address if it is known. Use `PeerAddress::is_unresolved_private` to detect masters
+    /// using a Resolvable Private Address the Link-Layer could not resolve.
+    pub fn peer_address(&self) -> PeerAddress {
+        self.peer
+    }
+
+    /// Returns the authenticated payload timeout of this connection.
+    ///
+    /// See `LinkLayer::set_authenticated_payload_timeout`.
+    pub fn authenticated_payload_timeout(&self) -> Option<Duration> {
+        self.authenticated_payload_timeout
+    }
+
+    /// Sets the authenticated payload timeout, or disables it with `None`.
+    pub(crate) fn set_authenticated_payload_timeout(&mut self, timeout: Option<Duration>) {
+        self.authenticated_payload_timeout = timeout;
+        self.ping_sent = false;
+    }
+
+    /// Returns statistics about this connection.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats
+    }
+
+    /// Returns whether the PDU identified by `ticket` was acknowledged by the master.
+    ///
+    /// Tickets are obtained when enqueueing a PDU, eg. from `AttributeServerTx::notify_raw`. A
+    /// PDU is acknowledged once the master has received it, so this can be used to find out when
+    /// a notification was delivered. If the connection is closed before that, the PDU is dropped,
+    /// which `LinkLayer::tx_status` reports.
+    pub fn is_acknowledged(&self, ticket: TxTicket) -> bool {
+        ticket.is_within(self.tx_acknowledged)
+    }
+
+    /// Tells the `Responder` that the connection was closed.
+    ///
+    /// This is called by the `LinkLayer` before dropping the connection. Returns whether the
+    /// report was put into the RX queue, which fails if the queue is full.
+    pub(crate) fn report_closed(&mut self) -> bool {
+        let reported = self.report(LinkReport::Disconnected);
+        if !reported {
+            warn!("RX queue full, can't report closed connection");
+        }
+        reported
+    }
+
+    /// Passes `report` to the `Responder` through the RX queue.
+    ///
+    /// Returns `false` if the RX queue is full.
+    fn report(&mut self, report: LinkReport) -> bool {
+        let result: Result<(), Error> = self.rx.produce_with(report.encoded_size(), |writer| {
+            report.to_bytes(writer)?;
+            Ok(Llid::Reserved)
+        });
+        result.is_ok()
+    }
+
+    /// Returns the number of PDUs from the TX queue that were acknowledged by the master.
+    pub(crate) fn tx_acknowledged(&self) -> u32 {
+        self.tx_acknowledged
+    }
+
+    /// Returns the smoothed RSSI of packets received from the master, in dBm.
+    ///
+    /// Returns `None` if the radio hasn't reported any RSSI samples (see
+    /// `LinkLayer::record_rssi`).
+    pub fn rssi(&self) -> Option<i8> {
+        // Round to the nearest dBm (`>>` rounds towards negative infinity)
+        self.rssi.map(|avg| ((avg + 8) >> 4) as i8)
+    }
+
+    /// Returns whether the connection is encrypted.
+    ///
+    /// This becomes `true` once the encryption start procedure has completed.
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self.encryption, Encryption::On(_))
+    }
+
+    /// Returns the current security level of the connection.
+    ///
+    /// This is `ConnectionSecurity::Unencrypted` until the encryption start procedure has
+    /// completed. After that, it depends on whether the Long Term Key was generated by
+    /// authenticated pairing.
+    ///
+    /// Changes of the security level are reported to the `Responder` through the RX queue, which
+    /// passes them on to the ATT server (see `AttributeServer::set_security_level`).
+    pub fn security_level(&self) -> ConnectionSecurity {
+        match &self.encryption {
+            Encryption::On(session) => session.security_level(),
+            _ => ConnectionSecurity::Unencrypted,
+        }
+    }
+}
+
+/// Statistics collected over the lifetime of a connection.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    missed_ifs: u32,
+    invalid_length: u32,
+    retransmissions: u32,
+    authenticated_payload_timeouts: u32,
+}
+
+impl ConnectionStats {
+    /// Returns how often the radio missed the `T_IFS` turnaround deadline for a response.
+    ///
+    /// This is the number of times `LinkLayer::turnaround_missed` was called during the
+    /// connection. The counter wraps around on overflow.
+    pub fn missed_ifs(&self) -> u32 {
+        self.missed_ifs
+    }
+
+    /// Returns how many received data channel PDUs were dropped because of an invalid length.
+    ///
+    /// A PDU is invalid if its length field doesn't match the number of Bytes received, or if it
+    /// exceeds the maximum payload size the Link-Layer can receive. The counter wraps around on
+    /// overflow.
+    pub fn invalid_length(&self) -> u32 {
+        self.invalid_length
+    }
+
+    /// Returns how often a data channel PDU had to be retransmitted because the master didn't
+    /// acknowledge it.
+    ///
+    /// The number of consecutive retransmissions can be limited with `Config::MAX_RETRANSMISSIONS`.
+    /// The counter wraps around on overflow.
+    pub fn retransmissions(&self) -> u32 {
+        self.retransmissions
+    }
+
+    /// Returns how often the authenticated payload timeout expired because the master didn't send
+    /// an authenticated packet in time.
+    ///
+    /// The counter wraps around on overflow.
+    pub fn authenticated_payload_timeouts(&self) -> u32 {
+        self.authenticated_payload_timeouts
+    }
+}
+
+/// Timing parameters of an established connection.
+///
+/// The parameters are initially set by the master in its `CONNECT_REQ`, and can be changed by the
+/// master at any time using the connection update procedure.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConnectionParams {
+    interval: Duration,
+    slave_latency: u16,
+    supervision_timeout: Duration,
+}
+
+impl ConnectionParams {
+    /// Extracts the initial connection parameters from a `CONNECT_REQ`'s `LLData`.
+    fn from_connect_request(lldata: &ConnectRequestData) -> Self {
+        Self {
+            interval: lldata.interval(),
+            slave_latency: lldata.slave_latency(),
+            supervision_timeout: lldata.supervision_timeout(),
+        }
+    }
+
+    /// Replaces the parameters with those from an `LL_CONNECTION_UPDATE_REQ`.
+    fn apply_update(&mut self, update: &ConnectionUpdateData) {
+        self.interval = update.interval();
+        self.slave_latency = update.latency();
+        self.supervision_timeout = update.timeout();
+    }
+
+    /// Returns the connection event interval (duration between the start of 2 subsequent
+    /// connection events).
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the slave latency: The number of consecutive connection events the slave may skip.
+    pub fn slave_latency(&self) -> u16 {
+        self.slave_latency
+    }
+
+    /// Returns the connection supervision timeout (`connSupervisionTimeout`).
+    ///
+    /// If no valid packet is received for this long, the connection is considered lost.
+    pub fn supervision_timeout(&self) -> Duration {
+        self.supervision_timeout
+    }
+}
+
+/// The time window in which the master transmits the first packet of a connection.
+///
+/// The start of the master's first packet inside the transmit window defines the first *anchor
+/// point* of the connection. All following connection events are timed relative to this anchor.
+///
+/// A transmit window is opened after receiving a `CONNECT_REQ` and after applying a connection
+/// update.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TransmitWindow {
+    start: Instant,
+    end: Instant,
+}
+
+impl TransmitWindow {
+    /// Fixed delay between the end of a `CONNECT_REQ` PDU and the earliest possible start of the
+    /// transmit window (`transmitWindowDelay`).
+    pub const DELAY: Duration = Duration::from_micros(1250);
+
+    /// Computes the transmit window opened by a `CONNECT_REQ`.
+    ///
+    /// # Parameters
+    ///
+    /// * **`rx_end`**: Instant at which the `CONNECT_REQ` PDU was fully received.
+    /// * **`lldata`**: Data contained in the `CONNECT_REQ` PDU.
+    pub fn after_connect_request(rx_end: Instant, lldata: &ConnectRequestData) -> Self {
+        let start = rx_end + Self::DELAY + lldata.transmit_window_offset();
+        Self {
+            start,
+            end: start + lldata.transmit_window_size(),
+        }
+    }
+
+    /// Returns the instant at which the transmit window opens.
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+
+    /// Returns the instant at which the transmit window closes.
+    ///
+    /// The master's first packet must *start* before this instant, but might end after it.
+    pub fn end(&self) -> Instant {
+        self.end
+    }
+
+    /// Returns whether `anchor` lies inside of this transmit window.
+    pub fn contains(&self, anchor: Instant) -> bool {
+        // Compare raw offsets to handle wraparound correctly, `duration_since` would complain about
+        // `anchor` lying before `start`.
+        let size = self.end.raw_micros().wrapping_sub(self.start.raw_micros());
+        let offset = anchor.raw_micros().wrapping_sub(self.start.raw_micros());
+        offset <= size
+    }
+}
+
+/// Returns the on-air duration of a data channel packet carrying `payload_len` octets on `phy`.
+///
+/// This includes preamble, access address, header and CRC.
+fn packet_air_time(phy: Phy, payload_len: u8) -> Duration {
+    phy.packet_duration(2 + u16::from(payload_len))
+}
+
+/// State of the encryption start procedure.
+enum Encryption {
+    /// The connection is not encrypted.
+    Off,
+
+    /// An `LL_START_ENC_REQ` is queued (`sent = false`) or was sent, but not yet acknowledged.
+    ///
+    /// PDUs are still exchanged in plain text.
+    StartReqPending { session: Session, sent: bool },
+
+    /// The master has received our `LL_START_ENC_REQ` and encrypts its PDUs. We keep sending
+    /// unencrypted PDUs until the master's `LL_START_ENC_RSP` arrives.
+    RxEncrypted(Session),
+
+    /// Encryption is enabled in both directions.
+    On(Session),
+}
+
+impl Encryption {
+    fn is_off(&self) -> bool {
+        matches!(self, Encryption::Off)
+    }
+
+    fn is_rx_encrypted(&self) -> bool {
+        matches!(self, Encryption::RxEncrypted(_))
+    }
+
+    /// Returns whether the encryption start procedure is in progress (during which no data PDUs
+    /// may be sent).
+    fn is_starting(&self) -> bool {
+        match self {
+            Encryption::StartReqPending { .. } | Encryption::RxEncrypted(_) => true,
+            Encryption::Off | Encryption::On(_) => false,
+        }
+    }
+
+    /// Returns the session to decrypt received PDUs with, if the master encrypts them.
+    fn rx_session(&mut self) -> Option<&mut Session> {
+        match self {
+            Encryption::RxEncrypted(session) | Encryption::On(session) => Some(session),
+            Encryption::Off | Encryption::StartReqPending { .. } => None,
+        }
+    }
+
+    /// Called when the `LL_START_ENC_REQ` was handed to the radio.
+    fn start_req_sent(&mut self) {
+        if let Encryption::StartReqPending { sent, .. } = self {
+            *sent = true;
+        }
+    }
+
+    /// Called when the master has acknowledged the last PDU we sent.
+    fn acknowledged(&mut self) {
+        *self = match mem::replace(self, Encryption::Off) {
+            Encryption::StartReqPending {
+                session,
+                sent: true,
+            } => Encryption::RxEncrypted(session),
+            other => other,
+        };
+    }
+
+    /// Called when the master's `LL_START_ENC_RSP` was received.
+    fn enable_tx(&mut self) {
+        *self = match mem::replace(self, Encryption::Off) {
+            Encryption::RxEncrypted(session) => Encryption::On(session),
+            other => other,
+        };
     }
 }
 
@@ -536,7 +1504,44 @@ enum LlcpError {
     NoSpace,
 
     /// Consider the connection lost due to a critical error or timeout.
-    ConnectionLost,
+    ConnectionLost(DisconnectReason),
+}
+
+/// Why a connection was closed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DisconnectReason {
+    /// The master closed the connection with an `LL_TERMINATE_IND` containing this error code.
+    Terminated(u8),
+
+    /// A received PDU failed the MIC check, so it was either tampered with or the devices no
+    /// longer agree on the packet counters. The connection is closed without notifying the master.
+    MicFailure,
+
+    /// The master didn't send a packet in the transmit window after the `CONNECT_REQ`.
+    FailedToEstablish,
+
+    /// The master started a procedure that conflicts with one that is still in progress.
+    ProcedureCollision,
+
+    /// The master didn't acknowledge a PDU within `Config::MAX_RETRANSMISSIONS` retransmissions.
+    RetransmissionLimit,
+}
+
+impl DisconnectReason {
+    /// Returns the HCI error code describing this reason.
+    ///
+    /// This is the reason a Host Controller Interface reports in its *Disconnection Complete*
+    /// event.
+    pub fn error_code(&self) -> u8 {
+        match *self {
+            DisconnectReason::Terminated(code) => code,
+            DisconnectReason::MicFailure => 0x3D,
+            DisconnectReason::FailedToEstablish => 0x3E,
+            DisconnectReason::ProcedureCollision => 0x23,
+            DisconnectReason::RetransmissionLimit => 0x08,
+        }
+    }
 }
 
 /// A Link-Layer state update that may be applied with a delay.
@@ -567,3 +1572,1037 @@ impl LlcpUpdate {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            att::{Handle, NoAttributes},
+            crypto::{self, Ccm},
+            l2cap::{BleChannelMap, L2CAPState},
+            link::{encryption::LongTermKey, mock::*, trace::PduKind, AddressKind},
+            security::NoSecurity,
+        },
+    };
+
+    /// Returns parsed `CONNECT_REQ` LLData with the given transmit window offset, size, and
+    /// connection interval (all in units of 1.25 ms, as transmitted).
+    fn lldata(win_size: u8, win_offset: u16, interval: u16) -> ConnectRequestData {
+        lldata_with_sca(win_size, win_offset, interval, 0)
+    }
+
+    /// Like `lldata`, but with the raw 3-bit SCA field set to `sca`.
+    fn lldata_with_sca(
+        win_size: u8,
+        win_offset: u16,
+        interval: u16,
+        sca: u8,
+    ) -> ConnectRequestData {
+        let mut raw = [0; 22];
+        let mut writer = ByteWriter::new(&mut raw[..]);
+        writer.write_u32_le(0x5065_9A2B).unwrap(); // access address
+        writer.write_slice(&[0x12, 0x34, 0x56]).unwrap(); // CRC init
+        writer.write_u8(win_size).unwrap();
+        writer.write_u16_le(win_offset).unwrap();
+        writer.write_u16_le(interval).unwrap();
+        writer.write_u16_le(0).unwrap(); // latency
+        writer.write_u16_le(100).unwrap(); // timeout
+        writer.write_slice(&[0xff, 0xff, 0xff, 0xff, 0x1f]).unwrap(); // channel map
+        writer.write_u8(7 | (sca << 5)).unwrap(); // hop + SCA
+        assert_eq!(writer.space_left(), 0);
+
+        ConnectRequestData::from_bytes(&mut ByteReader::new(&raw)).unwrap()
+    }
+
+    #[test]
+    fn params_after_update() {
+        let mut params = ConnectionParams::from_connect_request(&lldata(2, 4, 24));
+        assert_eq!(params.interval(), Duration::from_millis(30));
+        assert_eq!(params.slave_latency(), 0);
+        assert_eq!(params.supervision_timeout(), Duration::from_secs(1));
+
+        // LL_CONNECTION_UPDATE_REQ: WinSize=1, WinOffset=0, Interval=6 (7.5 ms), Latency=4,
+        // Timeout=200 (2 s), Instant=10
+        let raw = [0x00, 1, 0, 0, 6, 0, 4, 0, 200, 0, 10, 0];
+        let update = match ControlPdu::from_bytes(&mut ByteReader::new(&raw)).unwrap() {
+            ControlPdu::ConnectionUpdateReq(data) => data,
+            pdu => panic!("unexpected PDU {:?}", pdu),
+        };
+        params.apply_update(&update);
+        assert_eq!(params.interval(), Duration::from_micros(7_500));
+        assert_eq!(params.slave_latency(), 4);
+        assert_eq!(params.supervision_timeout(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn first_transmit_window() {
+        // 2.5 ms window, starting 5 ms after the earliest possible point, 30 ms interval
+        let lldata = lldata(2, 4, 24);
+        let rx_end = Instant::from_raw_micros(1_000);
+        let window = TransmitWindow::after_connect_request(rx_end, &lldata);
+
+        assert_eq!(
+            window.start(),
+            Instant::from_raw_micros(1_000 + 1_250 + 5_000)
+        );
+        assert_eq!(
+            window.end(),
+            Instant::from_raw_micros(1_000 + 1_250 + 5_000 + 2_500)
+        );
+        assert_eq!(
+            rx_end + lldata.end_of_tx_window(),
+            window.end(),
+            "window end disagrees with `end_of_tx_window`"
+        );
+
+        assert!(window.contains(window.start()));
+        assert!(window.contains(window.end()));
+        assert!(!window.contains(window.start() - Duration::from_micros(1)));
+        assert!(!window.contains(window.end() + Duration::from_micros(1)));
+    }
+
+    #[test]
+    fn first_anchor_in_window() {
+        let lldata = lldata(1, 0, 6);
+        let rx_end = Instant::from_raw_micros(u32::max_value() - 500);
+        let window = TransmitWindow::after_connect_request(rx_end, &lldata);
+
+        // Empty PDU starting right at the start of the window (with wraparound)
+        let pdu_end = window.start() + packet_air_time(Phy::Le1M, 0);
+        let anchor = pdu_end - packet_air_time(Phy::Le1M, 0);
+        assert_eq!(packet_air_time(Phy::Le1M, 0), Duration::from_micros(80));
+        assert_eq!(anchor, window.start());
+        assert!(window.contains(anchor));
+
+        // A packet *ending* after the window might still start inside of it
+        let pdu_end = window.end() + Duration::from_micros(40);
+        assert!(window.contains(pdu_end - packet_air_time(Phy::Le1M, 0)));
+    }
+
+    #[test]
+    fn link_identifiers() {
+        let master = Master::connect(vec![]);
+        assert_eq!(master.conn.access_address(), 0x5065_9A2B);
+        assert_eq!(master.conn.crc_init(), 0x0056_3412);
+        assert_eq!(
+            master.conn.channel_map(),
+            ChannelMap::from_raw([0xff, 0xff, 0xff, 0xff, 0x1f])
+        );
+    }
+
+    #[test]
+    fn rssi_average() {
+        let mut master = Master::connect(vec![]);
+        assert_eq!(master.conn.rssi(), None);
+
+        master.conn.record_rssi(-60);
+        assert_eq!(master.conn.rssi(), Some(-60));
+
+        // A single weak packet only has a small effect
+        master.conn.record_rssi(-92);
+        assert_eq!(master.conn.rssi(), Some(-64));
+
+        // A sustained drop is followed
+        for _ in 0..40 {
+            master.conn.record_rssi(-90);
+        }
+        assert_eq!(master.conn.rssi(), Some(-90));
+    }
+
+    /// `MockConfig` that gives up after 3 retransmissions.
+    enum RetransmitConfig {}
+
+    impl Config for RetransmitConfig {
+        type Timer = MockTimer;
+        type Transmitter = MockTransmitter;
+        type Rng = MockRng;
+        type KeyStore = MockKeyStore;
+        type ChannelMapper = <MockConfig as Config>::ChannelMapper;
+
+        type PacketQueue = <MockConfig as Config>::PacketQueue;
+        type PacketProducer = <MockConfig as Config>::PacketProducer;
+        type PacketConsumer = <MockConfig as Config>::PacketConsumer;
+        type L2CAPBuffer = <MockConfig as Config>::L2CAPBuffer;
+
+        const MAX_RETRANSMISSIONS: Option<u16> = Some(3);
+    }
+
+    #[test]
+    fn retransmission_limit() {
+        let ((_, tx), (rx, _)) = queues();
+        let (mut conn, _) = Connection::<RetransmitConfig>::create(
+            PEER.into(),
+            &lldata(1, 0, 6),
+            Instant::from_raw_micros(0),
+            tx,
+            rx,
+        );
+        let mut radio = MockTransmitter::new();
+        let mut process = |sn, nesn| {
+            let mut header = Header::new(Llid::DataCont);
+            header.set_sn(sn);
+            header.set_nesn(nesn);
+            conn.process_data_packet(
+                Instant::from_raw_micros(0),
+                &mut radio,
+                None,
+                header,
+                &[],
+                true,
+            )
+            .map(|_| conn.stats().retransmissions())
+        };
+
+        // The master never receives the slave's PDUs, so it keeps sending its first packet
+        assert_eq!(process(SeqNum::ZERO, SeqNum::ZERO), Ok(0));
+        assert_eq!(process(SeqNum::ZERO, SeqNum::ZERO), Ok(1));
+        assert_eq!(process(SeqNum::ZERO, SeqNum::ZERO), Ok(2));
+
+        // An acknowledgement resets the limit, but not the statistics
+        assert_eq!(process(SeqNum::ONE, SeqNum::ONE), Ok(2));
+        for retransmissions in 3..6 {
+            assert_eq!(process(SeqNum::ONE, SeqNum::ONE), Ok(retransmissions));
+        }
+
+        // No ACK after 3 retransmissions, the connection is lost
+        assert_eq!(
+            process(SeqNum::ONE, SeqNum::ONE),
+            Err(DisconnectReason::RetransmissionLimit)
+        );
+    }
+
+    #[test]
+    fn late_turnaround() {
+        let mut master = Master::connect(vec![]);
+        assert_eq!(master.conn.stats().missed_ifs(), 0);
+
+        let mut header = Header::new(Llid::DataCont);
+        header.set_sn(master.sn);
+        header.set_nesn(master.nesn);
+        let rx_end = master.timer.now();
+
+        // Processing the packet takes longer than `T_IFS`
+        master.timer.advance(Duration::from_micros(200));
+        let cmd = master
+            .conn
+            .process_data_packet(rx_end, &mut master.radio, None, header, &[], true)
+            .unwrap();
+        let turnaround = cmd.turnaround.unwrap();
+        assert_eq!(turnaround, rx_end + Duration::T_IFS);
+        let missed = match master.radio.sent.last().unwrap() {
+            Sent::Data { header, .. } => *header,
+            _ => unreachable!(),
+        };
+
+        // The mock radio can't transmit in time, drops the response and reports it
+        assert!(master.radio.turnaround_missed(&cmd, &master.timer));
+        master.conn.turnaround_missed();
+        assert_eq!(master.conn.stats().missed_ifs(), 1);
+
+        // The master didn't get a response, so it retransmits its packet. The slave answers with
+        // the PDU that was never sent.
+        let rx_end = master.timer.now();
+        let cmd = master
+            .conn
+            .process_data_packet(rx_end, &mut master.radio, None, header, &[], true)
+            .unwrap();
+        assert_eq!(cmd.turnaround, Some(rx_end + Duration::T_IFS));
+        assert!(!master.radio.turnaround_missed(&cmd, &master.timer));
+        match master.radio.sent.last().unwrap() {
+            Sent::Data { header, .. } => {
+                assert_eq!(header.sn(), missed.sn());
+                assert_eq!(header.nesn(), missed.nesn());
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(master.conn.stats().missed_ifs(), 1);
+    }
+
+    #[test]
+    fn invalid_length_dropped() {
+        let mut master = Master::connect(vec![]);
+
+        // The length field claims more data than the radio received
+        let mut header = Header::new(Llid::DataStart);
+        header.set_payload_length(200);
+        header.set_sn(master.sn);
+        header.set_nesn(master.nesn);
+        let now = master.timer.now();
+        let _cmd = master
+            .conn
+            .process_data_packet(
+                now,
+                &mut master.radio,
+                None,
+                header,
+                &[0x03, 0x00, 0x04, 0x00, 0x0A, 0x01, 0x00],
+                true,
+            )
+            .unwrap();
+        assert_eq!(master.conn.stats().invalid_length(), 1);
+
+        // The PDU was not acknowledged, so the master has to retransmit it
+        match master.radio.sent.last().unwrap() {
+            Sent::Data { header, .. } => assert_eq!(header.nesn(), master.sn),
+            _ => unreachable!(),
+        }
+
+        // Consistent, but longer than the slave can receive without encryption
+        let (header, _) = master.send(Llid::DataStart, &[0; 31]).unwrap();
+        // `send` assumes the PDU was acknowledged, but the slave still expects the previous `SN`
+        assert_eq!(header.nesn(), master.sn + SeqNum::ONE);
+        assert_eq!(master.conn.stats().invalid_length(), 2);
+
+        // Bad CRCs aren't counted, since the length can't be trusted anyway
+        let mut header = Header::new(Llid::DataCont);
+        header.set_payload_length(5);
+        let now = master.timer.now();
+        let _cmd = master
+            .conn
+            .process_data_packet(now, &mut master.radio, None, header, &[], false)
+            .unwrap();
+        assert_eq!(master.conn.stats().invalid_length(), 2);
+    }
+
+    #[test]
+    fn empty_pdus_not_forwarded() {
+        let mut master = Master::connect(vec![]);
+
+        // Empty PDUs and empty start fragments are acknowledged, but not passed to L2CAP
+        for &llid in &[Llid::DataCont, Llid::DataStart] {
+            let (header, _) = master.send(llid, &[]).unwrap();
+            assert_eq!(header.nesn(), master.sn);
+            assert!(!master.rx.has_data());
+        }
+
+        // Data is forwarded
+        let (header, _) = master
+            .send(Llid::DataStart, &[0x00, 0x00, 0x04, 0x00])
+            .unwrap();
+        assert_eq!(header.nesn(), master.sn);
+        master
+            .rx
+            .consume_raw_with(|header, payload| {
+                assert_eq!(header.llid(), Llid::DataStart);
+                assert_eq!(payload, [0x00, 0x00, 0x04, 0x00]);
+                Consume::always(Ok(()))
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn more_data_in_one_event() {
+        let mut master = Master::connect_with_queues(vec![], 5);
+        let (event, channel) = (master.conn.conn_event_count, master.conn.channel);
+
+        for i in 0..5 {
+            master
+                .tx
+                .produce_with(4, |writer| -> Result<_, Error> {
+                    writer.write_slice(&[i; 4])?;
+                    Ok(Llid::DataStart)
+                })
+                .unwrap();
+        }
+
+        // All 5 PDUs are sent in a single connection event, with `MD` set on all but the last
+        for i in 0..5 {
+            let (header, payload) = master.send(Llid::DataCont, &[]).unwrap();
+            assert_eq!(payload, [i; 4]);
+            assert_eq!(header.md(), i < 4);
+            if i < 4 {
+                assert_eq!(master.conn.conn_event_count, event);
+                assert_eq!(master.conn.channel, channel);
+            }
+        }
+
+        // Neither side has more data, so the event was closed after the last exchange
+        assert_eq!(master.conn.conn_event_count, event + Wrapping(1));
+        assert_ne!(master.conn.channel, channel);
+        let (header, payload) = master.send(Llid::DataCont, &[]).unwrap();
+        assert!(payload.is_empty());
+        assert!(!header.md());
+        assert_eq!(master.conn.conn_event_count, event + Wrapping(2));
+    }
+
+    #[test]
+    fn bidirectional_data_in_one_event() {
+        let mut master = Master::connect_with_queues(vec![], 3);
+        let event = master.conn.conn_event_count;
+
+        for i in 0..3 {
+            master
+                .tx
+                .produce_with(4, |writer| -> Result<_, Error> {
+                    writer.write_slice(&[i; 4])?;
+                    Ok(Llid::DataStart)
+                })
+                .unwrap();
+        }
+
+        // Every exchange carries a PDU in both directions, and the event stays open until both
+        // sides have cleared `MD`
+        for i in 0..3 {
+            let (header, payload) = master
+                .send_md(Llid::DataStart, &[0x10 + i; 4], i < 2)
+                .unwrap();
+            assert_eq!(payload, [i; 4]);
+            assert_eq!(header.md(), i < 2);
+            let expected = if i < 2 { event } else { event + Wrapping(1) };
+            assert_eq!(master.conn.conn_event_count, expected);
+        }
+
+        for i in 0..3 {
+            master
+                .rx
+                .consume_raw_with(|_, payload| {
+                    assert_eq!(payload, [0x10 + i; 4]);
+                    Consume::always(Ok(()))
+                })
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn retransmission_updates_md() {
+        let mut master = Master::connect(vec![]);
+        let queue = |master: &mut Master, i| {
+            master
+                .tx
+                .produce_with(4, |writer| -> Result<_, Error> {
+                    writer.write_slice(&[i; 4])?;
+                    Ok(Llid::DataStart)
+                })
+                .unwrap();
+        };
+
+        queue(&mut master, 0);
+        let (header, payload) = master.send_md(Llid::DataCont, &[], true).unwrap();
+        assert_eq!(payload, [0; 4]);
+        assert!(!header.md());
+
+        // More data is queued before the master NACKs the PDU, so the retransmission sets `MD`
+        queue(&mut master, 1);
+        master.nesn += SeqNum::ONE;
+        let (header, payload) = master.send_md(Llid::DataCont, &[], true).unwrap();
+        assert_eq!(payload, [0; 4]);
+        assert!(header.md());
+
+        let (header, payload) = master.send(Llid::DataCont, &[]).unwrap();
+        assert_eq!(payload, [1; 4]);
+        assert!(!header.md());
+    }
+
+    /// `MockConfig` that sends at most 2 PDUs per connection event.
+    enum EventLengthConfig {}
+
+    impl Config for EventLengthConfig {
+        type Timer = MockTimer;
+        type Transmitter = MockTransmitter;
+        type Rng = MockRng;
+        type KeyStore = MockKeyStore;
+        type ChannelMapper = <MockConfig as Config>::ChannelMapper;
+
+        type PacketQueue = <MockConfig as Config>::PacketQueue;
+        type PacketProducer = <MockConfig as Config>::PacketProducer;
+        type PacketConsumer = <MockConfig as Config>::PacketConsumer;
+        type L2CAPBuffer = <MockConfig as Config>::L2CAPBuffer;
+
+        const MAX_EVENT_PDUS: Option<u8> = Some(2);
+    }
+
+    #[test]
+    fn event_pdu_limit() {
+        let ((mut producer, tx), (rx, _)) = queues_with_capacity(5);
+        let (mut conn, _) = Connection::<EventLengthConfig>::create(
+            PEER.into(),
+            &lldata(1, 0, 6),
+            Instant::from_raw_micros(0),
+            tx,
+            rx,
+        );
+        let mut radio = MockTransmitter::new();
+        let mut seq = SeqNum::ZERO;
+        let mut exchange = |conn: &mut Connection<EventLengthConfig>, md| {
+            let mut header = Header::new(Llid::DataCont);
+            header.set_md(md);
+            header.set_sn(seq);
+            header.set_nesn(seq);
+            seq += SeqNum::ONE;
+            let _cmd = conn
+                .process_data_packet(
+                    Instant::from_raw_micros(0),
+                    &mut radio,
+                    None,
+                    header,
+                    &[],
+                    true,
+                )
+                .unwrap();
+            match radio.sent.last().unwrap() {
+                Sent::Data {
+                    header, payload, ..
+                } => (*header, payload.clone()),
+                _ => unreachable!(),
+            }
+        };
+        exchange(&mut conn, false);
+
+        for i in 0..5 {
+            producer
+                .produce_with(4, |writer| -> Result<_, Error> {
+                    writer.write_slice(&[i; 4])?;
+                    Ok(Llid::DataStart)
+                })
+                .unwrap();
+        }
+
+        // A burst of 5 PDUs is split into events of 2 PDUs each, even though the master keeps
+        // setting `MD`
+        let mut events = Vec::new();
+        for i in 0..5 {
+            let event = conn.conn_event_count;
+            let (header, payload) = exchange(&mut conn, true);
+            assert_eq!(payload, [i; 4]);
+            assert_eq!(header.md(), i % 2 == 0 && i < 4);
+            events.push(event.0);
+        }
+        let first = events[0];
+        assert_eq!(events, [first, first, first + 1, first + 1, first + 2]);
+    }
+
+    #[test]
+    fn master_closes_event() {
+        let mut master = Master::connect(vec![]);
+        let (event, channel) = (master.conn.conn_event_count, master.conn.channel);
+
+        // The master has more data, so the slave keeps listening on the same channel
+        let (header, _) = master.send_md(Llid::DataCont, &[], true).unwrap();
+        assert!(!header.md());
+        assert_eq!(master.conn.conn_event_count, event);
+        assert_eq!(master.conn.channel, channel);
+
+        // ...but then doesn't send anything, which closes the event
+        let cmd = master.conn.timer_update(&mut master.timer).unwrap();
+        assert_eq!(master.conn.conn_event_count, event + Wrapping(1));
+        assert_ne!(master.conn.channel, channel);
+        match cmd.radio {
+            RadioCmd::ListenData { channel, .. } => assert_eq!(channel, master.conn.channel),
+            _ => unreachable!(),
+        }
+
+        // The next packet starts a new event
+        master.send(Llid::DataCont, &[]).unwrap();
+        assert_eq!(master.conn.conn_event_count, event + Wrapping(2));
+    }
+
+    #[test]
+    fn notification_acknowledged() {
+        let mut master = Master::connect(vec![]);
+        let mut l2cap = L2CAPState::new(BleChannelMap::<NoAttributes, NoSecurity>::empty());
+        let ticket = l2cap
+            .tx(&mut master.tx)
+            .att()
+            .unwrap()
+            .notify_raw(Handle::from_raw(0x0003), &[1, 2])
+            .unwrap();
+        assert!(!master.conn.is_acknowledged(ticket));
+
+        // The notification is sent in response to the master's next packet
+        let mut header = Header::new(Llid::DataCont);
+        header.set_sn(master.sn);
+        header.set_nesn(master.nesn);
+        for _ in 0..2 {
+            let now = master.timer.now();
+            let _cmd = master
+                .conn
+                .process_data_packet(now, &mut master.radio, None, header, &[], true)
+                .unwrap();
+            match master.radio.sent.last().unwrap() {
+                Sent::Data { payload, .. } => {
+                    assert_eq!(payload[..], [5, 0, 4, 0, 0x1B, 0x03, 0x00, 1, 2])
+                }
+                _ => unreachable!(),
+            }
+
+            // It isn't delivered until the master acknowledges it. The second iteration is a
+            // retransmission of the master's packet, which makes the slave retransmit as well.
+            assert!(!master.conn.is_acknowledged(ticket));
+        }
+
+        master.sn += SeqNum::ONE;
+        master.nesn += SeqNum::ONE;
+        let (_, payload) = master.send(Llid::DataCont, &[]).unwrap();
+        assert!(payload.is_empty());
+        assert!(master.conn.is_acknowledged(ticket));
+
+        // Acknowledged empty PDUs are not counted
+        let ticket = l2cap
+            .tx(&mut master.tx)
+            .att()
+            .unwrap()
+            .notify_raw(Handle::from_raw(0x0003), &[3])
+            .unwrap();
+        assert!(!master.conn.is_acknowledged(ticket));
+        master.send(Llid::DataCont, &[]).unwrap();
+        assert!(!master.conn.is_acknowledged(ticket));
+        master.send(Llid::DataCont, &[]).unwrap();
+        assert!(master.conn.is_acknowledged(ticket));
+    }
+
+    #[test]
+    fn window_widening_grows() {
+        let mut master = Master::connect(vec![]);
+        let anchor = master.timer.now();
+
+        // 550 ppm combined drift, 7.5 ms interval
+        assert_eq!(master.conn.window_widening(), Duration::from_micros(4 + 16));
+
+        // Missed events widen the window further
+        for events in 2..5 {
+            let cmd = master.conn.timer_update(&mut master.timer).unwrap();
+            let widening = Duration::from_micros(events * 7_500 * 550 / 1_000_000 + 16);
+            assert_eq!(master.conn.window_widening(), widening);
+            match cmd.next_update {
+                NextUpdate::At(at) => assert_eq!(
+                    at,
+                    anchor + Duration::from_micros(events * 7_500) + RX_END_MARGIN + widening
+                ),
+                _ => unreachable!(),
+            }
+        }
+
+        // Receiving a packet resynchronizes
+        master.timer.advance(Duration::from_micros(4 * 7_500));
+        master.send(Llid::DataCont, &[]).unwrap();
+        assert_eq!(master.conn.window_widening(), Duration::from_micros(4 + 16));
+    }
+
+    #[test]
+    fn window_widening_uses_master_sca() {
+        // The transmit window ends 1.25 ms + 100 ms + 1.25 ms after the CONNECT_REQ
+        let create = |sca| {
+            let ((_, tx), (rx, _)) = queues();
+            let (conn, _) = Connection::<MockConfig>::create(
+                PEER.into(),
+                &lldata_with_sca(1, 80, 6, sca),
+                Instant::from_raw_micros(0),
+                tx,
+                rx,
+            );
+            conn
+        };
+
+        let conn = create(0);
+        assert_eq!(
+            conn.master_sleep_clock_accuracy(),
+            SleepClockAccuracy::Ppm251To500
+        );
+        // 500 ppm for the master plus 50 ppm for the slave, plus jitter
+        assert_eq!(
+            conn.window_widening(),
+            Duration::from_micros(102_500 * 550 / 1_000_000 + 16)
+        );
+
+        let conn = create(7);
+        assert_eq!(
+            conn.master_sleep_clock_accuracy(),
+            SleepClockAccuracy::Ppm0To20
+        );
+        assert_eq!(
+            conn.window_widening(),
+            Duration::from_micros(102_500 * 70 / 1_000_000 + 16)
+        );
+    }
+
+    #[test]
+    fn latency_attends_update_instant() {
+        let mut master = Master::connect(vec![]);
+        master.conn.params.slave_latency = 4;
+
+        // With nothing to send, the slave skips 4 events and wakes up for the 5th
+        let event = master.conn.conn_event_count;
+        let mut header = Header::new(Llid::DataCont);
+        header.set_sn(master.sn);
+        header.set_nesn(master.nesn);
+        let rx_end = master.timer.now();
+        let cmd = master
+            .conn
+            .process_data_packet(rx_end, &mut master.radio, None, header, &[], true)
+            .unwrap();
+        master.sn += SeqNum::ONE;
+        master.nesn += SeqNum::ONE;
+        assert_eq!(master.conn.conn_event_count, event + Wrapping(5));
+        // Master SCA of 500 ppm plus 50 ppm for the slave, over 5 intervals, plus jitter
+        let widening = Duration::from_micros(5 * 7_500 * 550 / 1_000_000 + 16);
+        assert_eq!(master.conn.window_widening(), widening);
+        match cmd.next_update {
+            NextUpdate::At(at) => assert_eq!(
+                at,
+                rx_end + Duration::from_micros(5 * 7_500) + RX_END_MARGIN + widening
+            ),
+            _ => unreachable!(),
+        }
+
+        // While a channel map update is pending, no event is skipped
+        let instant = master.conn.conn_event_count + Wrapping(3);
+        let mut buf = [0; 27];
+        let map = ChannelMap::from_raw([0xff, 0xff, 0xff, 0xff, 0x0f]);
+        let len = ControlPdu::ChannelMapReq {
+            map,
+            instant: instant.0,
+        }
+        .to_bytes_into(&mut buf)
+        .unwrap();
+        master.send(Llid::Control, &buf[..len]).unwrap();
+        assert_eq!(master.conn.conn_event_count, instant - Wrapping(2));
+        master.send(Llid::DataCont, &[]).unwrap();
+        assert_eq!(master.conn.conn_event_count, instant - Wrapping(1));
+
+        // The event at the instant is attended, too
+        master.send(Llid::DataCont, &[]).unwrap();
+        assert_eq!(master.conn.conn_event_count, instant);
+        assert_eq!(master.conn.channel_map, map);
+
+        // Afterwards, the slave goes back to skipping events
+        master.send(Llid::DataCont, &[]).unwrap();
+        assert_eq!(master.conn.conn_event_count, instant + Wrapping(5));
+    }
+
+    /// Long Term Key from the encryption sample data (Vol. 6, Part C, 1).
+    const LTK: [u8; 16] = [
+        0x4c, 0x68, 0x38, 0x41, 0x39, 0xf5, 0x74, 0xd8, 0x36, 0xbc, 0xf3, 0x4e, 0x9d, 0xfb, 0x01,
+        0xbf,
+    ];
+    const EDIV: u16 = 0x2474;
+    const RAND: u64 = 0xABCD_EF12_3456_7890;
+    const SKDM: u64 = 0xACBD_CEDF_E0F1_0213;
+    const IVM: u32 = 0xBADC_AB24;
+    const PEER: DeviceAddress = DeviceAddress::new([6, 5, 4, 3, 2, 1], AddressKind::Public);
+
+    struct Master {
+        conn: Connection<MockConfig>,
+        radio: MockTransmitter,
+        timer: MockTimer,
+        keys: (MockKeyStore, MockRng),
+        /// Queue of PDUs for the slave to send.
+        tx: MockProducer,
+        /// Queue of PDUs the slave received.
+        rx: MockConsumer,
+        sn: SeqNum,
+        nesn: SeqNum,
+    }
+
+    impl Master {
+        fn connect(keys: Vec<(u16, u64, LongTermKey)>) -> Self {
+            Self::connect_with_queues(keys, 1)
+        }
+
+        /// Like `connect`, but the packet queues hold up to `capacity` PDUs.
+        fn connect_with_queues(keys: Vec<(u16, u64, LongTermKey)>, capacity: usize) -> Self {
+            let ((producer, tx), (rx, consumer)) = queues_with_capacity(capacity);
+            let (conn, _) = Connection::create(
+                PEER.into(),
+                &lldata(1, 0, 6),
+                Instant::from_raw_micros(0),
+                tx,
+                rx,
+            );
+            let mut master = Self {
+                conn,
+                radio: MockTransmitter::new(),
+                timer: MockTimer::new(),
+                keys: (MockKeyStore::new(keys), MockRng::new()),
+                tx: producer,
+                rx: consumer,
+                sn: SeqNum::ZERO,
+                nesn: SeqNum::ZERO,
+            };
+
+            // The `Responder` is told about the new connection
+            assert_eq!(master.report(), Some(LinkReport::Connected));
+
+            // The first exchange consists of empty PDUs
+            let response = master.send(Llid::DataCont, &[]).unwrap();
+            assert!(response.1.is_empty());
+            master
+        }
+
+        /// Takes the next `LinkReport` for the `Responder` out of the RX queue.
+        ///
+        /// Returns `None` if the next packet isn't a report.
+        fn report(&mut self) -> Option<LinkReport> {
+            self.rx
+                .consume_raw_with(|header, payload| {
+                    if header.llid() == Llid::Reserved {
+                        Consume::always(LinkReport::from_bytes(&mut ByteReader::new(payload)))
+                    } else {
+                        Consume::never(Err(Error::InvalidValue))
+                    }
+                })
+                .ok()
+        }
+
+        /// Sends a new PDU to the slave, acknowledging its last PDU, and returns its response.
+        fn send(
+            &mut self,
+            llid: Llid,
+            payload: &[u8],
+        ) -> Result<(Header, Vec<u8>), DisconnectReason> {
+            self.send_md(llid, payload, false)
+        }
+
+        /// Like `send`, but sets the `MD` bit to `md`.
+        fn send_md(
+            &mut self,
+            llid: Llid,
+            payload: &[u8],
+            md: bool,
+        ) -> Result<(Header, Vec<u8>), DisconnectReason> {
+            let mut header = Header::new(llid);
+            header.set_payload_length(payload.len() as u8);
+            header.set_md(md);
+            header.set_sn(self.sn);
+            header.set_nesn(self.nesn);
+
+            let now = self.timer.now();
+            let _cmd = self.conn.process_data_packet(
+                now,
+                &mut self.radio,
+                Some(&mut self.keys),
+                header,
+                payload,
+                true,
+            )?;
+
+            self.sn += SeqNum::ONE;
+            self.nesn += SeqNum::ONE;
+            match self.radio.sent.last().unwrap() {
+                Sent::Data {
+                    header, payload, ..
+                } => Ok((*header, payload.clone())),
+                _ => unreachable!(),
+            }
+        }
+
+        fn send_llcp(&mut self, pdu: ControlPdu<'_>) -> ControlPdu<'static> {
+            let mut buf = [0; 27];
+            let len = pdu.to_bytes_into(&mut buf).unwrap();
+            let (header, payload) = self.send(Llid::Control, &buf[..len]).unwrap();
+            assert_eq!(header.llid(), Llid::Control);
+            parse_llcp(&payload)
+        }
+    }
+
+    fn parse_llcp(payload: &[u8]) -> ControlPdu<'static> {
+        let payload: &'static [u8] = Box::leak(payload.to_vec().into_boxed_slice());
+        ControlPdu::from_bytes(&mut ByteReader::new(payload)).unwrap()
+    }
+
+    fn enc_req() -> ControlPdu<'static> {
+        ControlPdu::EncReq {
+            rand: Hex(RAND),
+            ediv: Hex(EDIV),
+            skdm: Hex(SKDM),
+            ivm: Hex(IVM),
+        }
+    }
+
+    /// Builds the CCM instance and nonce the master uses for its PDU number `counter`.
+    fn master_ccm(skds: u64, ivs: u32, counter: u8, from_master: bool) -> (Ccm, [u8; 13]) {
+        let mut skd = [0; 16];
+        skd[..8].copy_from_slice(&skds.to_be_bytes());
+        skd[8..].copy_from_slice(&SKDM.to_be_bytes());
+        let ccm = Ccm::new(&crypto::e(&LTK, &skd));
+
+        let mut nonce = [0; 13];
+        nonce[0] = counter;
+        nonce[4] = if from_master { 0x80 } else { 0 };
+        nonce[5..9].copy_from_slice(&IVM.to_le_bytes());
+        nonce[9..].copy_from_slice(&ivs.to_le_bytes());
+        (ccm, nonce)
+    }
+
+    #[test]
+    fn encryption_restart_with_stored_ltk() {
+        let mut master = Master::connect(vec![(EDIV, RAND, LongTermKey::from_raw(LTK))]);
+
+        let (skds, ivs) = match master.send_llcp(enc_req()) {
+            ControlPdu::EncRsp { skds, ivs } => (skds.0, ivs.0),
+            pdu => panic!("expected LL_ENC_RSP, got {:?}", pdu),
+        };
+
+        // The slave follows up with an unencrypted LL_START_ENC_REQ
+        let (header, payload) = master.send(Llid::DataCont, &[]).unwrap();
+        assert_eq!(header.llid(), Llid::Control);
+        match parse_llcp(&payload) {
+            ControlPdu::StartEncReq => {}
+            pdu => panic!("expected LL_START_ENC_REQ, got {:?}", pdu),
+        }
+        assert!(!master.conn.is_encrypted());
+        assert_eq!(
+            master.conn.security_level(),
+            ConnectionSecurity::Unencrypted
+        );
+
+        // The master answers with an encrypted LL_START_ENC_RSP
+        let (ccm, nonce) = master_ccm(skds, ivs, 0, true);
+        let mut pdu = [0x06, 0, 0, 0, 0];
+        let mic = ccm.encrypt(&nonce, Llid::Control as u8, &mut pdu[..1]);
+        pdu[1..].copy_from_slice(&mic);
+        let (header, mut payload) = master.send(Llid::Control, &pdu).unwrap();
+
+        // ...and so does the slave, encrypted with its own packet counter and direction
+        assert!(master.conn.is_encrypted());
+        assert_eq!(
+            master.conn.security_level(),
+            ConnectionSecurity::Unauthenticated
+        );
+
+        // The new security level is reported to the `Responder`
+        assert_eq!(
+            master.report(),
+            Some(LinkReport::Security(ConnectionSecurity::Unauthenticated))
+        );
+        assert_eq!(header.llid(), Llid::Control);
+        assert_eq!(payload.len(), 5);
+        let (ccm, nonce) = master_ccm(skds, ivs, 0, false);
+        let (data, mic) = payload.split_at_mut(1);
+        assert!(ccm.decrypt(
+            &nonce,
+            Llid::Control as u8,
+            data,
+            &[mic[0], mic[1], mic[2], mic[3]]
+        ));
+        assert_eq!(data[0], 0x06);
+
+        // PDUs are traced in plain text
+        let trace = take_trace();
+        assert!(trace.contains(&(PduKind::LinkControl, Direction::Inbound, vec![0x06])));
+        assert!(trace.contains(&(PduKind::LinkControl, Direction::Outbound, vec![0x06])));
+
+        // A PDU with a bad MIC terminates the connection
+        let (ccm, nonce) = master_ccm(skds, ivs, 1, true);
+        let mut pdu = [0x12, 0, 0, 0, 0];
+        let mic = ccm.encrypt(&nonce, Llid::Control as u8, &mut pdu[..1]);
+        pdu[1..].copy_from_slice(&mic);
+        pdu[4] ^= 0x01;
+        let reason = master.send(Llid::Control, &pdu).unwrap_err();
+        assert_eq!(reason, DisconnectReason::MicFailure);
+        assert_eq!(reason.error_code(), 0x3D);
+    }
+
+    #[test]
+    fn ping_answered() {
+        let mut master = Master::connect(vec![]);
+        match master.send_llcp(ControlPdu::PingReq) {
+            ControlPdu::PingRsp => {}
+            pdu => panic!("expected LL_PING_RSP, got {:?}", pdu),
+        }
+
+        // A response to our own ping isn't answered
+        let (header, payload) = master.send(Llid::Control, &[0x13]).unwrap();
+        assert_eq!(header.llid(), Llid::DataCont);
+        assert!(payload.is_empty());
+    }
+
+    #[test]
+    fn unknown_opcode_answered() {
+        let mut master = Master::connect(vec![]);
+        let (header, payload) = master.send(Llid::Control, &[0xF0, 0x01, 0x02]).unwrap();
+        assert_eq!(header.llid(), Llid::Control);
+        assert_eq!(payload, [0x07, 0xF0]);
+
+        // Known opcodes with invalid `CtrData` are answered the same way
+        let (header, payload) = master.send(Llid::Control, &[0x08, 0x01]).unwrap();
+        assert_eq!(header.llid(), Llid::Control);
+        assert_eq!(payload, [0x07, 0x08]);
+
+        // `LL_UNKNOWN_RSP` itself is never answered
+        let (header, payload) = master.send(Llid::Control, &[0x07, 0x12]).unwrap();
+        assert_eq!(header.llid(), Llid::DataCont);
+        assert!(payload.is_empty());
+    }
+
+    #[test]
+    fn connection_event_time_reported() {
+        let mut master = Master::connect(Vec::new());
+
+        // Nothing is reported before `TIME_REPORT_INTERVAL` has passed
+        master
+            .timer
+            .advance(TIME_REPORT_INTERVAL - Duration::from_micros(1));
+        master.send(Llid::DataCont, &[]).unwrap();
+        assert!(!master.rx.has_data());
+
+        master.timer.advance(Duration::from_micros(1));
+        let now = master.timer.now();
+        master.send(Llid::DataCont, &[]).unwrap();
+        assert_eq!(master.report(), Some(LinkReport::Time(now)));
+    }
+
+    #[test]
+    fn ping_before_authenticated_payload_timeout() {
+        let mut master = Master::connect(vec![(EDIV, RAND, LongTermKey::from_raw(LTK))]);
+        let (skds, ivs) = match master.send_llcp(enc_req()) {
+            ControlPdu::EncRsp { skds, ivs } => (skds.0, ivs.0),
+            pdu => panic!("expected LL_ENC_RSP, got {:?}", pdu),
+        };
+        master.send(Llid::DataCont, &[]).unwrap();
+        let (ccm, nonce) = master_ccm(skds, ivs, 0, true);
+        let mut pdu = [0x06, 0, 0, 0, 0];
+        let mic = ccm.encrypt(&nonce, Llid::Control as u8, &mut pdu[..1]);
+        pdu[1..].copy_from_slice(&mic);
+        master.send(Llid::Control, &pdu).unwrap();
+        assert!(master.conn.is_encrypted());
+
+        master
+            .conn
+            .set_authenticated_payload_timeout(Some(Duration::from_millis(1_000)));
+        let (_, payload) = master.send(Llid::DataCont, &[]).unwrap();
+        assert!(payload.is_empty());
+
+        // The master only sends empty PDUs, so the slave pings it after half the timeout
+        master.timer.advance(Duration::from_millis(600));
+        let (header, mut payload) = master.send(Llid::DataCont, &[]).unwrap();
+        assert_eq!(header.llid(), Llid::Control);
+        assert_eq!(payload.len(), 5);
+        let (ccm, nonce) = master_ccm(skds, ivs, 1, false);
+        let (data, mic) = payload.split_at_mut(1);
+        assert!(ccm.decrypt(
+            &nonce,
+            Llid::Control as u8,
+            data,
+            &[mic[0], mic[1], mic[2], mic[3]]
+        ));
+        assert_eq!(data[0], 0x12);
+
+        // Only one ping is sent, and the timeout expires without a response
+        master.timer.advance(Duration::from_millis(300));
+        let (_, payload) = master.send(Llid::DataCont, &[]).unwrap();
+        assert!(payload.is_empty());
+        assert_eq!(master.conn.stats().authenticated_payload_timeouts(), 0);
+        master.timer.advance(Duration::from_millis(200));
+        let (_, payload) = master.send(Llid::DataCont, &[]).unwrap();
+        assert!(payload.is_empty());
+        assert_eq!(master.conn.stats().authenticated_payload_timeouts(), 1);
+    }
+
+    #[test]
+    fn encryption_rejected_without_ltk() {
+        let mut master = Master::connect(vec![(EDIV, RAND ^ 1, LongTermKey::from_raw(LTK))]);
+
+        match master.send_llcp(enc_req()) {
+            ControlPdu::EncRsp { .. } => {}
+            pdu => panic!("expected LL_ENC_RSP, got {:?}", pdu),
+        }
+
+        let (_, payload) = master.send(Llid::DataCont, &[]).unwrap();
+        match parse_llcp(&payload) {
+            ControlPdu::RejectInd { error_code } => assert_eq!(error_code.0, ERROR_KEY_MISSING),
+            pdu => panic!("expected LL_REJECT_IND, got {:?}", pdu),
+        }
+        assert!(!master.conn.is_encrypted());
+    }
+}
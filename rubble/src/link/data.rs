@@ -183,6 +183,10 @@ impl fmt::Debug for Header {
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Llid {
     /// Reserved for future use.
+    ///
+    /// PDUs with this LLID are never sent over the air. The Link-Layer uses it to report events to
+    /// the `Responder` through the RX queue (a changed security level, or the time of a connection
+    /// event).
     Reserved = 0b00,
 
     /// Continuation of L2CAP message, or empty PDU.
@@ -215,6 +219,24 @@ impl<'a> Pdu<'a, &'a [u8]> {
     pub fn empty() -> Self {
         Pdu::DataCont { message: &[] }
     }
+
+    /// Parses a PDU consisting of the 2-Byte header and the (unencrypted) payload.
+    ///
+    /// Returns the header along with the parsed PDU.
+    #[cfg(any(feature = "fuzz", feature = "testing"))]
+    pub(crate) fn from_raw(raw: &'a [u8]) -> Result<(Header, Self), Error> {
+        if raw.len() < 2 {
+            return Err(Error::Eof);
+        }
+
+        let (header, payload) = raw.split_at(2);
+        let header = Header::parse(header);
+        if usize::from(header.payload_length()) != payload.len() {
+            return Err(Error::InvalidLength);
+        }
+
+        Ok((header, Self::parse(header, payload)?))
+    }
 }
 
 impl<'a, L> Pdu<'a, L> {
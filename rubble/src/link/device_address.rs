@@ -1,4 +1,4 @@
-use core::fmt;
+use {crate::rng::Rng, core::fmt};
 
 /// Specifies whether a device address is randomly generated or a LAN MAC address.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -20,10 +20,29 @@ impl DeviceAddress {
     /// Create a new device address from 6 raw Bytes and an address kind specifier.
     ///
     /// The `raw` array contains the address Bytes as they are sent over the air (LSB first).
-    pub fn new(bytes: [u8; 6], kind: AddressKind) -> Self {
+    pub const fn new(bytes: [u8; 6], kind: AddressKind) -> Self {
         DeviceAddress { bytes, kind }
     }
 
+    /// Generates a new Random Static Address using `rng`.
+    ///
+    /// Random Static Addresses have their 2 most significant bits set to `0b11`. The remaining 46
+    /// random bits must not be all 0 or all 1. A device may generate a new static address when it
+    /// first boots, but must keep it for as long as it is powered (typically, it is stored in
+    /// non-volatile memory and used forever).
+    pub fn new_random_static<R: Rng>(rng: &mut R) -> Self {
+        loop {
+            let mut bytes = [0; 6];
+            rng.fill_bytes(&mut bytes);
+            bytes[5] |= 0b1100_0000;
+
+            let addr = DeviceAddress::new(bytes, AddressKind::Random);
+            if addr.is_random_static() {
+                return addr;
+            }
+        }
+    }
+
     /// Returns the address kind.
     pub fn kind(&self) -> AddressKind {
         self.kind
@@ -34,6 +53,28 @@ impl DeviceAddress {
         self.kind == AddressKind::Random
     }
 
+    /// Returns whether this address is a Resolvable Private Address (RPA).
+    ///
+    /// RPAs are random addresses whose 2 most significant bits are `0b01`. They can be resolved to
+    /// the identity address of the device using its Identity Resolving Key.
+    pub fn is_resolvable_private(&self) -> bool {
+        self.is_random() && self.bytes[5] >> 6 == 0b01
+    }
+
+    /// Returns whether this address is a valid Random Static Address.
+    ///
+    /// Random Static Addresses are random addresses whose 2 most significant bits are `0b11`, and
+    /// whose remaining 46 bits are neither all 0 nor all 1.
+    pub fn is_random_static(&self) -> bool {
+        let rest = &self.bytes[..5];
+        let top = self.bytes[5] & 0b0011_1111;
+
+        self.is_random()
+            && self.bytes[5] >> 6 == 0b11
+            && !(top == 0 && rest.iter().all(|b| *b == 0))
+            && !(top == 0b0011_1111 && rest.iter().all(|b| *b == 0xFF))
+    }
+
     /// Returns the raw bytes making up this address.
     pub fn raw(&self) -> &[u8; 6] {
         &self.bytes
@@ -54,3 +95,56 @@ impl fmt::Debug for DeviceAddress {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::link::mock::MockRng};
+
+    /// Returns a fixed sequence of 6-Byte values.
+    struct SequenceRng(Vec<[u8; 6]>);
+
+    impl Rng for SequenceRng {
+        fn fill_bytes(&mut self, buf: &mut [u8]) {
+            buf.copy_from_slice(&self.0.remove(0));
+        }
+    }
+
+    #[test]
+    fn random_static() {
+        let mut rng = MockRng::new();
+        for _ in 0..100 {
+            let addr = DeviceAddress::new_random_static(&mut rng);
+            assert_eq!(addr.kind(), AddressKind::Random);
+            assert_eq!(addr.raw()[5] >> 6, 0b11);
+            assert!(addr.is_random_static());
+            assert!(!addr.is_resolvable_private());
+        }
+    }
+
+    #[test]
+    fn random_static_validity() {
+        let valid = DeviceAddress::new([1, 2, 3, 4, 5, 0xC6], AddressKind::Random);
+        assert!(valid.is_random_static());
+
+        let public = DeviceAddress::new([1, 2, 3, 4, 5, 0xC6], AddressKind::Public);
+        assert!(!public.is_random_static());
+
+        let rpa = DeviceAddress::new([1, 2, 3, 4, 5, 0x46], AddressKind::Random);
+        assert!(!rpa.is_random_static());
+
+        let zeros = DeviceAddress::new([0, 0, 0, 0, 0, 0xC0], AddressKind::Random);
+        assert!(!zeros.is_random_static());
+
+        let ones = DeviceAddress::new([0xFF; 6], AddressKind::Random);
+        assert!(!ones.is_random_static());
+    }
+
+    #[test]
+    fn random_static_retries() {
+        // The all-ones address is invalid, so the RNG has to be invoked again
+        let mut rng = SequenceRng(vec![[0xFF; 6], [0x42; 6]]);
+        let addr = DeviceAddress::new_random_static(&mut rng);
+        assert_eq!(addr.raw(), &[0x42, 0x42, 0x42, 0x42, 0x42, 0xC2]);
+        assert!(addr.is_random_static());
+    }
+}
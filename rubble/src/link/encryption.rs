@@ -0,0 +1,394 @@
+//! Link-Layer encryption using keys from a previous bonding.
+//!
+//! When a bonded master reconnects, it starts the *Encryption Start* procedure by sending an
+//! `LL_ENC_REQ` containing the `EDIV` and `Rand` values that were distributed together with the
+//! *Long Term Key* (LTK) during bonding. The slave looks up the matching LTK in its `KeyStore` and,
+//! if it finds one, both devices derive a session key from it. Encryption is resumed without
+//! having to pair again.
+//!
+//! If no matching LTK is found, the procedure is rejected with the error code *PIN or Key Missing*,
+//! which tells the master to pair again.
+//!
+//! Once encryption is enabled, every non-empty data channel PDU is encrypted using AES-CCM and
+//! carries a 4-octet Message Integrity Check (MIC). Receiving a PDU with an invalid MIC terminates
+//! the connection.
+//!
+//! Besides keys, the `KeyStore` also persists other per-bond state that must survive reconnections,
+//! such as the *Client Characteristic Configuration Descriptor* values of bonded clients.
+
+use {
+    crate::{
+        att::Handle,
+        crypto::{self, Ccm},
+        gatt::cccd::Cccd,
+        link::{data::Header, DeviceAddress},
+        Error,
+    },
+    core::fmt,
+};
+
+/// Error code sent in `LL_REJECT_IND` when no Long Term Key is available (*PIN or Key Missing*).
+pub(crate) const ERROR_KEY_MISSING: u8 = 0x06;
+
+/// Length of the Message Integrity Check appended to encrypted PDUs.
+pub const MIC_LEN: usize = 4;
+
+/// Security level of a connection (*LE Security Mode 1*).
+///
+/// The level is determined by whether the connection is encrypted, and whether the key used for
+/// encryption was generated by a pairing method with MITM protection. Attributes can require a
+/// minimum security level to be accessed (see `AttributeProvider::required_security`).
+///
+/// Levels are ordered, so a level is sufficient if it compares greater than or equal to the
+/// required level.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConnectionSecurity {
+    /// No encryption and no authentication (*Security Level 1*).
+    Unencrypted,
+
+    /// Encrypted using a key from pairing without MITM protection, eg. *Just Works*
+    /// (*Security Level 2*).
+    Unauthenticated,
+
+    /// Encrypted using a key from authenticated pairing with MITM protection (*Security Level 3*).
+    Authenticated,
+}
+
+impl ConnectionSecurity {
+    /// Returns the raw value used to report the level to the `Responder` via the RX queue.
+    pub(crate) fn to_raw(self) -> u8 {
+        self as u8
+    }
+
+    /// Parses a raw value returned by `to_raw`.
+    pub(crate) fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(ConnectionSecurity::Unencrypted),
+            1 => Some(ConnectionSecurity::Unauthenticated),
+            2 => Some(ConnectionSecurity::Authenticated),
+            _ => None,
+        }
+    }
+}
+
+/// Returns `ConnectionSecurity::Unencrypted`.
+impl Default for ConnectionSecurity {
+    fn default() -> Self {
+        ConnectionSecurity::Unencrypted
+    }
+}
+
+/// A 128-bit Long Term Key (LTK).
+///
+/// LTKs are distributed during bonding and used to derive the session key whenever encryption is
+/// started.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct LongTermKey {
+    raw: [u8; 16],
+    authenticated: bool,
+}
+
+impl LongTermKey {
+    /// Creates an LTK from its raw bytes, most significant octet first.
+    ///
+    /// The key is assumed to be the result of unauthenticated pairing. Use `from_raw_authenticated`
+    /// for keys generated by pairing with MITM protection.
+    ///
+    /// Note that the Security Manager transmits keys least significant octet first.
+    pub fn from_raw(raw: [u8; 16]) -> Self {
+        Self {
+            raw,
+            authenticated: false,
+        }
+    }
+
+    /// Creates an LTK that was generated by authenticated pairing (with MITM protection).
+    ///
+    /// Connections encrypted with this key have the security level `Authenticated`.
+    pub fn from_raw_authenticated(raw: [u8; 16]) -> Self {
+        Self {
+            raw,
+            authenticated: true,
+        }
+    }
+
+    /// Returns the raw bytes of this LTK, most significant octet first.
+    pub fn raw(&self) -> &[u8; 16] {
+        &self.raw
+    }
+
+    /// Returns whether this key was generated by authenticated pairing.
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// Returns the security level of a connection encrypted with this key.
+    pub fn security_level(&self) -> ConnectionSecurity {
+        if self.authenticated {
+            ConnectionSecurity::Authenticated
+        } else {
+            ConnectionSecurity::Unauthenticated
+        }
+    }
+}
+
+/// Does not print the key itself.
+impl fmt::Debug for LongTermKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LongTermKey(..)")
+    }
+}
+
+/// Trait for persistent storage of bonding keys.
+///
+/// Implementations are queried by the real-time part of the Link-Layer, so lookups should be fast
+/// (eg. by keeping an in-memory copy of the keys stored in flash).
+pub trait KeyStore {
+    /// Looks up the Long Term Key that was distributed along with `ediv` and `rand`.
+    ///
+    /// Returns `None` if no matching key is stored, in which case the master is asked to pair
+    /// again.
+    fn lookup_ltk(&mut self, ediv: u16, rand: u64) -> Option<LongTermKey>;
+
+    /// Loads the value of the CCCD at `handle` that was stored for the bonded device `peer`.
+    ///
+    /// `peer` is the identity address of the device. Returns `None` if `peer` is not bonded or no
+    /// value was stored for `handle`. The default implementation does not store any values.
+    fn load_cccd(&mut self, _peer: &DeviceAddress, _handle: Handle) -> Option<Cccd> {
+        None
+    }
+
+    /// Stores the value of the CCCD at `handle` after it was changed by `peer`.
+    ///
+    /// Implementations should persist the value if `peer` is bonded, and ignore it otherwise.
+    /// Values must also be stored when all bits are cleared, so that a client that unsubscribes
+    /// stays unsubscribed when it reconnects. The default implementation does nothing.
+    fn store_cccd(&mut self, _peer: &DeviceAddress, _handle: Handle, _value: Cccd) {}
+}
+
+impl<K: KeyStore + ?Sized> KeyStore for &'_ mut K {
+    fn lookup_ltk(&mut self, ediv: u16, rand: u64) -> Option<LongTermKey> {
+        (**self).lookup_ltk(ediv, rand)
+    }
+
+    fn load_cccd(&mut self, peer: &DeviceAddress, handle: Handle) -> Option<Cccd> {
+        (**self).load_cccd(peer, handle)
+    }
+
+    fn store_cccd(&mut self, peer: &DeviceAddress, handle: Handle, value: Cccd) {
+        (**self).store_cccd(peer, handle, value)
+    }
+}
+
+/// A `KeyStore` without any keys.
+///
+/// Every attempt to start encryption is rejected.
+#[derive(Debug)]
+pub struct NoKeys;
+
+impl KeyStore for NoKeys {
+    fn lookup_ltk(&mut self, _ediv: u16, _rand: u64) -> Option<LongTermKey> {
+        None
+    }
+}
+
+/// Encryption state of a connection after a session key was derived.
+#[derive(Clone)]
+pub(crate) struct Session {
+    ccm: Ccm,
+    /// Security level provided by the LTK the session key was derived from.
+    level: ConnectionSecurity,
+    /// The initialization vector `IVm || IVs`, in transmission order.
+    iv: [u8; 8],
+    /// Counter of encrypted PDUs sent by us.
+    tx_counter: u64,
+    /// Counter of encrypted PDUs received from the master.
+    rx_counter: u64,
+}
+
+impl Session {
+    /// Derives the session key from `ltk` and the diversifiers and IVs exchanged in `LL_ENC_REQ`
+    /// and `LL_ENC_RSP`.
+    pub fn new(ltk: &LongTermKey, skdm: u64, skds: u64, ivm: u32, ivs: u32) -> Self {
+        // SKD = SKDs || SKDm, with SKDm in the least significant octets
+        let mut skd = [0; 16];
+        skd[..8].copy_from_slice(&skds.to_be_bytes());
+        skd[8..].copy_from_slice(&skdm.to_be_bytes());
+        let sk = crypto::e(ltk.raw(), &skd);
+
+        let mut iv = [0; 8];
+        iv[..4].copy_from_slice(&ivm.to_le_bytes());
+        iv[4..].copy_from_slice(&ivs.to_le_bytes());
+
+        Self {
+            ccm: Ccm::new(&sk),
+            level: ltk.security_level(),
+            iv,
+            tx_counter: 0,
+            rx_counter: 0,
+        }
+    }
+
+    /// Returns the security level of a connection encrypted with this session.
+    pub fn security_level(&self) -> ConnectionSecurity {
+        self.level
+    }
+
+    /// Encrypts the payload of an outgoing PDU in place and appends the MIC.
+    ///
+    /// `buf` must contain the plaintext payload of `header` followed by at least `MIC_LEN` free
+    /// Bytes. The payload length in `header` is updated to include the MIC. Empty PDUs are not
+    /// encrypted.
+    pub fn encrypt(&mut self, header: &mut Header, buf: &mut [u8]) {
+        let len = usize::from(header.payload_length());
+        if len == 0 {
+            return;
+        }
+
+        let nonce = self.nonce(self.tx_counter, false);
+        let mic = self.ccm.encrypt(&nonce, aad(*header), &mut buf[..len]);
+        buf[len..len + MIC_LEN].copy_from_slice(&mic);
+        header.set_payload_length((len + MIC_LEN) as u8);
+        self.tx_counter += 1;
+    }
+
+    /// Decrypts the payload of a new (not retransmitted), non-empty PDU sent by the master.
+    ///
+    /// `payload` contains the encrypted payload followed by the MIC and is decrypted in place.
+    /// Returns the length of the plaintext payload.
+    ///
+    /// Returns `Error::InvalidValue` if the MIC is wrong, which requires the connection to be
+    /// terminated.
+    ///
+    /// The packet counter is not advanced by this method, since the PDU might not be acknowledged
+    /// (in which case the master retransmits it with the same counter). Call `advance_rx_counter`
+    /// once the PDU was accepted.
+    pub fn decrypt(&self, header: Header, payload: &mut [u8]) -> Result<usize, Error> {
+        if payload.len() < MIC_LEN {
+            return Err(Error::InvalidLength);
+        }
+
+        let len = payload.len() - MIC_LEN;
+        let (data, mic) = payload.split_at_mut(len);
+        let mut mic_buf = [0; MIC_LEN];
+        mic_buf.copy_from_slice(mic);
+
+        let nonce = self.nonce(self.rx_counter, true);
+        if self.ccm.decrypt(&nonce, aad(header), data, &mic_buf) {
+            Ok(len)
+        } else {
+            Err(Error::InvalidValue)
+        }
+    }
+
+    /// Advances the packet counter of received PDUs after a decrypted PDU was acknowledged.
+    pub fn advance_rx_counter(&mut self) {
+        self.rx_counter += 1;
+    }
+
+    /// Builds the CCM nonce for the 39-bit packet counter `counter`.
+    fn nonce(&self, counter: u64, from_master: bool) -> [u8; 13] {
+        let mut nonce = [0; 13];
+        nonce[..5].copy_from_slice(&counter.to_le_bytes()[..5]);
+        nonce[4] &= 0x7F;
+        if from_master {
+            nonce[4] |= 0x80;
+        }
+        nonce[5..].copy_from_slice(&self.iv);
+        nonce
+    }
+}
+
+/// Returns the additional authenticated data for a PDU: Its first header octet with `NESN`, `SN`
+/// and `MD` masked out.
+fn aad(header: Header) -> u8 {
+    (header.to_u16() as u8) & 0b1110_0011
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::data::Llid;
+
+    /// Bluetooth Core Specification v5.0, Vol. 6, Part C, 1: Encryption sample data.
+    fn sample_session() -> Session {
+        let ltk = LongTermKey::from_raw([
+            0x4c, 0x68, 0x38, 0x41, 0x39, 0xf5, 0x74, 0xd8, 0x36, 0xbc, 0xf3, 0x4e, 0x9d, 0xfb,
+            0x01, 0xbf,
+        ]);
+        Session::new(
+            &ltk,
+            0xACBD_CEDF_E0F1_0213,
+            0x0213_2435_4657_6879,
+            0xBADC_AB24,
+            0xDEAF_BABE,
+        )
+    }
+
+    #[test]
+    fn start_enc_rsp_sample_data() {
+        let mut session = sample_session();
+
+        // LL_START_ENC_RSP sent by the master
+        let header = Header::parse(&[0x0f, 0x05]);
+        let mut payload = [0x9f, 0xcd, 0xa7, 0xf4, 0x48];
+        assert_eq!(session.decrypt(header, &mut payload), Ok(1));
+        assert_eq!(payload[0], 0x06);
+
+        // LL_START_ENC_RSP sent by the slave
+        let mut header = Header::parse(&[0x07, 0x01]);
+        let mut buf = [0x06, 0, 0, 0, 0];
+        session.encrypt(&mut header, &mut buf);
+        assert_eq!(header.payload_length(), 5);
+        assert_eq!(buf, [0xa3, 0x4c, 0x13, 0xa4, 0x15]);
+    }
+
+    #[test]
+    fn mic_failure() {
+        let session = sample_session();
+        let header = Header::parse(&[0x0f, 0x05]);
+        let mut payload = [0x9f, 0xcd, 0xa7, 0xf4, 0x49];
+        assert_eq!(
+            session.decrypt(header, &mut payload),
+            Err(Error::InvalidValue)
+        );
+
+        // The packet counter was not advanced, so the correct PDU is still accepted
+        let mut payload = [0x9f, 0xcd, 0xa7, 0xf4, 0x48];
+        assert_eq!(session.decrypt(header, &mut payload), Ok(1));
+    }
+
+    #[test]
+    fn security_levels() {
+        let key = [0x42; 16];
+        assert_eq!(
+            LongTermKey::from_raw(key).security_level(),
+            ConnectionSecurity::Unauthenticated
+        );
+        assert_eq!(
+            LongTermKey::from_raw_authenticated(key).security_level(),
+            ConnectionSecurity::Authenticated
+        );
+        assert_ne!(
+            LongTermKey::from_raw(key),
+            LongTermKey::from_raw_authenticated(key)
+        );
+
+        assert!(ConnectionSecurity::Unencrypted < ConnectionSecurity::Unauthenticated);
+        assert!(ConnectionSecurity::Unauthenticated < ConnectionSecurity::Authenticated);
+        assert_eq!(
+            ConnectionSecurity::default(),
+            ConnectionSecurity::Unencrypted
+        );
+    }
+
+    #[test]
+    fn empty_pdus_are_not_encrypted() {
+        let mut session = sample_session();
+        let mut header = Header::new(Llid::DataCont);
+        let mut buf = [0; MIC_LEN];
+        session.encrypt(&mut header, &mut buf);
+        assert_eq!(header.payload_length(), 0);
+        assert_eq!(session.tx_counter, 0);
+    }
+}
@@ -2,7 +2,9 @@
 
 use {
     super::DeviceAddress,
+    crate::Error,
     core::{iter, slice},
+    heapless::{consts::U8, Vec},
 };
 
 pub trait AddressFilter {
@@ -69,6 +71,90 @@ impl<I: Iterator<Item = DeviceAddress> + Clone> AddressFilter for WhitelistFilte
     }
 }
 
+/// A fixed-capacity list of device addresses that may interact with this device.
+///
+/// This is what the Bluetooth specification calls the *White List* (or *Filter Accept List* in
+/// newer versions). Unlike a [`WhitelistFilter`], an `AllowList` owns its entries and can be
+/// modified at runtime, which makes it suitable for storing in the Link-Layer.
+///
+/// Both public and random device addresses can be stored. An address only matches an entry when
+/// its kind (public or random) matches as well.
+///
+/// [`WhitelistFilter`]: struct.WhitelistFilter.html
+#[derive(Debug, Clone, Default)]
+pub struct AllowList {
+    addresses: Vec<DeviceAddress, U8>,
+}
+
+impl AllowList {
+    /// The maximum number of addresses an `AllowList` can hold.
+    pub const CAPACITY: usize = 8;
+
+    /// Creates an empty allow list.
+    ///
+    /// An empty allow list does not match any device.
+    pub fn new() -> Self {
+        Self {
+            addresses: Vec::new(),
+        }
+    }
+
+    /// Adds `address` to the list.
+    ///
+    /// Adding an address that is already on the list has no effect. Returns `Error::Eof` when the
+    /// list is full.
+    pub fn add(&mut self, address: DeviceAddress) -> Result<(), Error> {
+        if self.contains(address) {
+            return Ok(());
+        }
+
+        self.addresses.push(address).map_err(|_| Error::Eof)
+    }
+
+    /// Removes `address` from the list.
+    ///
+    /// Returns whether `address` was on the list.
+    pub fn remove(&mut self, address: DeviceAddress) -> bool {
+        if let Some(index) = self.addresses.iter().position(|a| *a == address) {
+            self.addresses.swap_remove(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Removes all addresses from the list.
+    pub fn clear(&mut self) {
+        self.addresses.clear();
+    }
+
+    /// Returns whether `address` is on the list.
+    pub fn contains(&self, address: DeviceAddress) -> bool {
+        self.addresses.contains(&address)
+    }
+
+    /// Returns an iterator over all addresses on the list.
+    pub fn iter(&self) -> impl Iterator<Item = DeviceAddress> + '_ {
+        self.addresses.iter().cloned()
+    }
+
+    /// Returns the number of addresses on the list.
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// Returns whether the list is empty.
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+}
+
+impl AddressFilter for AllowList {
+    fn matches(&self, address: DeviceAddress) -> bool {
+        self.contains(address)
+    }
+}
+
 /// Advertising filter policy. Governs which devices may scan and connect to an advertising device.
 pub struct AdvFilter<S: AddressFilter, C: AddressFilter> {
     scan: S,
@@ -113,3 +199,45 @@ impl<S: AddressFilter> ScanFilter<S> {
         self.scan.matches(device)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::AddressKind;
+
+    #[test]
+    fn allow_list_address_kinds() {
+        let public = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Public);
+        let random = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let other = DeviceAddress::new([6, 5, 4, 3, 2, 1], AddressKind::Random);
+
+        let mut list = AllowList::new();
+        assert!(!list.matches(public));
+        list.add(public).unwrap();
+        assert!(list.matches(public));
+        assert!(!list.matches(random), "address kind must match");
+
+        list.add(other).unwrap();
+        list.add(other).unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(list.matches(other));
+
+        assert!(list.remove(public));
+        assert!(!list.remove(public));
+        assert!(!list.matches(public));
+        assert!(list.matches(other));
+    }
+
+    #[test]
+    fn allow_list_full() {
+        let mut list = AllowList::new();
+        for i in 0..AllowList::CAPACITY {
+            list.add(DeviceAddress::new([i as u8; 6], AddressKind::Public))
+                .unwrap();
+        }
+
+        let extra = DeviceAddress::new([0xff; 6], AddressKind::Public);
+        assert_eq!(list.add(extra), Err(Error::Eof));
+        assert!(!list.matches(extra));
+    }
+}
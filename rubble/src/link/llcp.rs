@@ -62,8 +62,8 @@ impl ConnectionParamRequest {
         assert!(min <= max);
 
         // Convert and round to units of 1.25 ms.
-        let max = max.as_micros() / 1_250;
-        let min = min.as_micros() / 1_250;
+        let max = max.whole_1250us_units();
+        let min = min.whole_1250us_units();
 
         // Clamp to valid range of 6..=3200
         let min = cmp::min(cmp::max(min, 6), 3200);
@@ -75,12 +75,12 @@ impl ConnectionParamRequest {
 
     /// Returns the minimum requested connection interval.
     pub fn min_conn_interval(&self) -> Duration {
-        Duration::from_micros(u32::from(self.interval_min) * 1_250)
+        Duration::from_1250us_units(self.interval_min)
     }
 
     /// Returns the maximum requested connection interval.
     pub fn max_conn_interval(&self) -> Duration {
-        Duration::from_micros(u32::from(self.interval_max) * 1_250)
+        Duration::from_1250us_units(self.interval_max)
     }
 
     /// Returns the slave latency in number of connection events.
@@ -145,17 +145,17 @@ pub struct ConnectionUpdateData {
 impl ConnectionUpdateData {
     /// Returns the size of the transmit window for the first PDU of the connection.
     pub fn win_size(&self) -> Duration {
-        Duration::from_micros(u32::from(self.win_size) * 1_250)
+        Duration::from_1250us_units(self.win_size.into())
     }
 
     /// Returns the offset of the transmit window, as a duration since the `instant`.
     pub fn win_offset(&self) -> Duration {
-        Duration::from_micros(u32::from(self.win_offset) * 1_250)
+        Duration::from_1250us_units(self.win_offset)
     }
 
     /// Returns the duration between connection events.
     pub fn interval(&self) -> Duration {
-        Duration::from_micros(u32::from(self.interval) * 1_250)
+        Duration::from_1250us_units(self.interval)
     }
 
     /// Returns the slave latency.
@@ -197,6 +197,36 @@ pub enum ControlPdu<'a> {
         error_code: Hex<u8>,
     },
 
+    /// `0x03`/`LL_ENC_REQ` - Start the encryption of the connection.
+    ///
+    /// Sent by the master. The slave responds with `LL_ENC_RSP`, followed by either
+    /// `LL_START_ENC_REQ` or, if it has no Long Term Key for `rand` and `ediv`, `LL_REJECT_IND`.
+    EncReq {
+        /// Random number identifying the Long Term Key.
+        rand: Hex<u64>,
+        /// Encrypted diversifier identifying the Long Term Key.
+        ediv: Hex<u16>,
+        /// Master's part of the session key diversifier.
+        skdm: Hex<u64>,
+        /// Master's part of the initialization vector.
+        ivm: Hex<u32>,
+    },
+
+    /// `0x04`/`LL_ENC_RSP` - Slave's response to `LL_ENC_REQ`.
+    EncRsp {
+        /// Slave's part of the session key diversifier.
+        skds: Hex<u64>,
+        /// Slave's part of the initialization vector.
+        ivs: Hex<u32>,
+    },
+
+    /// `0x05`/`LL_START_ENC_REQ` - Sent unencrypted by the slave once it has the Long Term Key.
+    StartEncReq,
+
+    /// `0x06`/`LL_START_ENC_RSP` - Sent encrypted by both devices to complete the encryption start
+    /// procedure.
+    StartEncRsp,
+
     /// `0x07`/`LL_UNKNOWN_RSP` - Response to unknown/unsupported LL Control PDUs.
     ///
     /// This is returned as a response to an incoming LL Control PDU when the opcode is
@@ -229,9 +259,24 @@ pub enum ControlPdu<'a> {
         sub_vers_nr: Hex<u16>,
     },
 
+    /// `0x0D`/`LL_REJECT_IND` - Rejects a procedure initiated by the other device.
+    RejectInd {
+        error_code: Hex<u8>,
+    },
+
     ConnectionParamReq(ConnectionParamRequest),
     ConnectionParamRsp(ConnectionParamRequest),
 
+    /// `0x12`/`LL_PING_REQ` - Checks that the other device is still present (*LE Ping*).
+    ///
+    /// Can be sent by master or slave. The other device answers with `LL_PING_RSP`. On an
+    /// encrypted connection, the response is an authenticated packet, which restarts the
+    /// authenticated payload timer.
+    PingReq,
+
+    /// `0x13`/`LL_PING_RSP` - Response to `LL_PING_REQ`.
+    PingRsp,
+
     /// Catch-all variant for unsupported opcodes.
     Unknown {
         /// The opcode we don't support. This can also be the `Unknown` variant.
@@ -249,12 +294,19 @@ impl ControlPdu<'_> {
             ControlPdu::ConnectionUpdateReq { .. } => ControlOpcode::ConnectionUpdateReq,
             ControlPdu::ChannelMapReq { .. } => ControlOpcode::ChannelMapReq,
             ControlPdu::TerminateInd { .. } => ControlOpcode::TerminateInd,
+            ControlPdu::EncReq { .. } => ControlOpcode::EncReq,
+            ControlPdu::EncRsp { .. } => ControlOpcode::EncRsp,
+            ControlPdu::StartEncReq => ControlOpcode::StartEncReq,
+            ControlPdu::StartEncRsp => ControlOpcode::StartEncRsp,
             ControlPdu::UnknownRsp { .. } => ControlOpcode::UnknownRsp,
             ControlPdu::FeatureReq { .. } => ControlOpcode::FeatureReq,
             ControlPdu::FeatureRsp { .. } => ControlOpcode::FeatureRsp,
             ControlPdu::VersionInd { .. } => ControlOpcode::VersionInd,
+            ControlPdu::RejectInd { .. } => ControlOpcode::RejectInd,
             ControlPdu::ConnectionParamReq(_) => ControlOpcode::ConnectionParamReq,
             ControlPdu::ConnectionParamRsp(_) => ControlOpcode::ConnectionParamRsp,
+            ControlPdu::PingReq => ControlOpcode::PingReq,
+            ControlPdu::PingRsp => ControlOpcode::PingRsp,
             ControlPdu::Unknown { opcode, .. } => *opcode,
         }
     }
@@ -322,6 +374,18 @@ impl<'a> FromBytes<'a> for ControlPdu<'a> {
             ControlOpcode::TerminateInd => ControlPdu::TerminateInd {
                 error_code: Hex(bytes.read_u8()?),
             },
+            ControlOpcode::EncReq => ControlPdu::EncReq {
+                rand: Hex(bytes.read_u64_le()?),
+                ediv: Hex(bytes.read_u16_le()?),
+                skdm: Hex(bytes.read_u64_le()?),
+                ivm: Hex(bytes.read_u32_le()?),
+            },
+            ControlOpcode::EncRsp => ControlPdu::EncRsp {
+                skds: Hex(bytes.read_u64_le()?),
+                ivs: Hex(bytes.read_u32_le()?),
+            },
+            ControlOpcode::StartEncReq => ControlPdu::StartEncReq,
+            ControlOpcode::StartEncRsp => ControlPdu::StartEncRsp,
             ControlOpcode::UnknownRsp => ControlPdu::UnknownRsp {
                 unknown_type: ControlOpcode::from(bytes.read_u8()?),
             },
@@ -336,6 +400,11 @@ impl<'a> FromBytes<'a> for ControlPdu<'a> {
                 comp_id: CompanyId::from_raw(bytes.read_u16_le()?),
                 sub_vers_nr: Hex(bytes.read_u16_le()?),
             },
+            ControlOpcode::RejectInd => ControlPdu::RejectInd {
+                error_code: Hex(bytes.read_u8()?),
+            },
+            ControlOpcode::PingReq => ControlPdu::PingReq,
+            ControlOpcode::PingRsp => ControlPdu::PingRsp,
             _ => ControlPdu::Unknown {
                 opcode,
                 ctr_data: bytes.read_rest(),
@@ -366,6 +435,27 @@ impl<'a> ToBytes for ControlPdu<'a> {
                 buffer.write_u8(error_code.0)?;
                 Ok(())
             }
+            ControlPdu::EncReq {
+                rand,
+                ediv,
+                skdm,
+                ivm,
+            } => {
+                buffer.write_u64_le(rand.0)?;
+                buffer.write_u16_le(ediv.0)?;
+                buffer.write_u64_le(skdm.0)?;
+                buffer.write_u32_le(ivm.0)?;
+                Ok(())
+            }
+            ControlPdu::EncRsp { skds, ivs } => {
+                buffer.write_u64_le(skds.0)?;
+                buffer.write_u32_le(ivs.0)?;
+                Ok(())
+            }
+            ControlPdu::StartEncReq
+            | ControlPdu::StartEncRsp
+            | ControlPdu::PingReq
+            | ControlPdu::PingRsp => Ok(()),
             ControlPdu::UnknownRsp { unknown_type } => {
                 buffer.write_u8(u8::from(*unknown_type))?;
                 Ok(())
@@ -382,6 +472,10 @@ impl<'a> ToBytes for ControlPdu<'a> {
                 buffer.write_u16_le(sub_vers_nr.0)?;
                 Ok(())
             }
+            ControlPdu::RejectInd { error_code } => {
+                buffer.write_u8(error_code.0)?;
+                Ok(())
+            }
             ControlPdu::ConnectionParamReq(data) | ControlPdu::ConnectionParamRsp(data) => {
                 data.to_bytes(buffer)
             }
@@ -473,6 +567,58 @@ mod tests {
         assert_eq!(max, Duration::from_micros(7_500));
     }
 
+    #[test]
+    fn to_bytes_into_returns_wire_size() {
+        let pdus = [
+            ControlPdu::TerminateInd {
+                error_code: Hex(0x13),
+            },
+            ControlPdu::UnknownRsp {
+                unknown_type: ControlOpcode::EncReq,
+            },
+            ControlPdu::FeatureRsp {
+                features_used: FeatureSet::empty(),
+            },
+            ControlPdu::VersionInd {
+                vers_nr: VersionNumber::V4_2,
+                comp_id: CompanyId::from_raw(0xFFFF),
+                sub_vers_nr: Hex(0),
+            },
+            ControlPdu::ChannelMapReq {
+                map: ChannelMap::with_all_channels(),
+                instant: 100,
+            },
+            ControlPdu::EncReq {
+                rand: Hex(0xABCD_EF12_3456_7890),
+                ediv: Hex(0x2474),
+                skdm: Hex(0xACBD_CEDF_E0F1_0213),
+                ivm: Hex(0xBADC_AB24),
+            },
+            ControlPdu::EncRsp {
+                skds: Hex(0x0213_2435_4657_6879),
+                ivs: Hex(0xDEAF_BABE),
+            },
+            ControlPdu::StartEncReq,
+            ControlPdu::StartEncRsp,
+            ControlPdu::RejectInd {
+                error_code: Hex(0x06),
+            },
+            ControlPdu::Unknown {
+                opcode: ControlOpcode::Unknown(0xF0),
+                ctr_data: &[1, 2, 3],
+            },
+        ];
+
+        for pdu in &pdus {
+            let mut buf = [0; 27];
+            let len = pdu.to_bytes_into(&mut buf).unwrap();
+            assert_eq!(len, usize::from(pdu.encoded_size()), "{:?}", pdu);
+
+            let parsed = ControlPdu::from_bytes(&mut ByteReader::new(&buf[..len])).unwrap();
+            assert_eq!(parsed.opcode(), pdu.opcode());
+        }
+    }
+
     #[test]
     #[should_panic(expected = "min <= max")]
     fn update_req_set_conn_interval_minmax() {
@@ -0,0 +1,379 @@
+//! Mock hardware interfaces for testing the Link-Layer on the host.
+
+// Not every test uses every part of the mock
+#![allow(dead_code)]
+
+use {
+    crate::{
+        att::{Handle, NoAttributes},
+        bytes::ByteWriter,
+        config::Config,
+        gatt::cccd::Cccd,
+        l2cap::{BleChannelMap, MAX_MESSAGE_LEN},
+        link::{
+            advertising,
+            data::{self, Llid},
+            encryption::{KeyStore, LongTermKey},
+            queue::*,
+            trace::{Direction, EventKind, PduKind},
+            Cmd, DeviceAddress, Transmitter, MIN_DATA_PAYLOAD_BUF, MIN_PAYLOAD_BUF,
+        },
+        phy::{AdvertisingChannel, DataChannel},
+        rng::Rng,
+        security::NoSecurity,
+        time::{Duration, Instant, Timer},
+        Error,
+    },
+    std::{cell::RefCell, collections::VecDeque, rc::Rc, vec::Vec},
+};
+
+/// A `Timer` whose time only changes when told to.
+pub struct MockTimer {
+    now: Instant,
+}
+
+impl MockTimer {
+    pub fn new() -> Self {
+        Self {
+            now: Instant::from_raw_micros(0),
+        }
+    }
+
+    pub fn set(&mut self, now: Instant) {
+        self.now = now;
+    }
+
+    pub fn advance(&mut self, by: Duration) {
+        self.now += by;
+    }
+}
+
+impl Timer for MockTimer {
+    fn now(&self) -> Instant {
+        self.now
+    }
+}
+
+/// A deterministic xorshift `Rng`.
+pub struct MockRng {
+    state: u32,
+}
+
+impl MockRng {
+    pub fn new() -> Self {
+        Self { state: 0xDEAD_BEEF }
+    }
+}
+
+impl Rng for MockRng {
+    fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for b in buf {
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 17;
+            self.state ^= self.state << 5;
+            *b = self.state as u8;
+        }
+    }
+}
+
+/// A `KeyStore` holding a list of `(EDIV, Rand, LTK)` entries.
+///
+/// CCCD values are only stored for the devices in `bonded`.
+pub struct MockKeyStore {
+    pub keys: Vec<(u16, u64, LongTermKey)>,
+    pub bonded: Vec<DeviceAddress>,
+    pub cccds: Vec<(DeviceAddress, Handle, Cccd)>,
+}
+
+impl MockKeyStore {
+    pub fn new(keys: Vec<(u16, u64, LongTermKey)>) -> Self {
+        Self {
+            keys,
+            bonded: Vec::new(),
+            cccds: Vec::new(),
+        }
+    }
+}
+
+impl KeyStore for MockKeyStore {
+    fn lookup_ltk(&mut self, ediv: u16, rand: u64) -> Option<LongTermKey> {
+        self.keys
+            .iter()
+            .find(|(e, r, _)| *e == ediv && *r == rand)
+            .map(|(_, _, ltk)| *ltk)
+    }
+
+    fn load_cccd(&mut self, peer: &DeviceAddress, handle: Handle) -> Option<Cccd> {
+        self.cccds
+            .iter()
+            .find(|(p, h, _)| p == peer && *h == handle)
+            .map(|(_, _, value)| *value)
+    }
+
+    fn store_cccd(&mut self, peer: &DeviceAddress, handle: Handle, value: Cccd) {
+        if !self.bonded.contains(peer) {
+            return;
+        }
+
+        self.cccds.retain(|(p, h, _)| !(p == peer && *h == handle));
+        self.cccds.push((*peer, handle, value));
+    }
+}
+
+/// A packet sent through a `MockTransmitter`.
+#[derive(Debug, Clone)]
+pub enum Sent {
+    Advertising {
+        header: advertising::Header,
+        payload: Vec<u8>,
+        channel: AdvertisingChannel,
+    },
+    Data {
+        access_address: u32,
+        header: data::Header,
+        payload: Vec<u8>,
+        channel: DataChannel,
+    },
+}
+
+/// A `Transmitter` that records all packets instead of sending them.
+pub struct MockTransmitter {
+    buf: [u8; MIN_PAYLOAD_BUF],
+    pub sent: Vec<Sent>,
+}
+
+impl MockTransmitter {
+    pub fn new() -> Self {
+        Self {
+            buf: [0; MIN_PAYLOAD_BUF],
+            sent: Vec::new(),
+        }
+    }
+
+    /// Simulates the radio starting the transmission requested by `cmd` at the time of `timer`.
+    ///
+    /// Returns `true` if the `turnaround` deadline of `cmd` has already passed. Like a real driver,
+    /// the mock then drops the packet instead of sending it late, and the caller has to report the
+    /// missed turnaround to the Link-Layer.
+    pub fn turnaround_missed(&mut self, cmd: &Cmd, timer: &MockTimer) -> bool {
+        match cmd.turnaround {
+            // The deadline may lie past a wraparound of the timer
+            Some(deadline)
+                if (deadline.raw_micros().wrapping_sub(timer.now().raw_micros()) as i32) < 0 =>
+            {
+                self.sent.pop();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Transmitter for MockTransmitter {
+    fn tx_payload_buf(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    fn transmit_advertising(&mut self, header: advertising::Header, channel: AdvertisingChannel) {
+        let payload = self.buf[..usize::from(header.payload_length())].to_vec();
+        self.sent.push(Sent::Advertising {
+            header,
+            payload,
+            channel,
+        });
+    }
+
+    fn transmit_data(
+        &mut self,
+        access_address: u32,
+        _crc_iv: u32,
+        header: data::Header,
+        channel: DataChannel,
+    ) {
+        let payload = self.buf[..usize::from(header.payload_length())].to_vec();
+        self.sent.push(Sent::Data {
+            access_address,
+            header,
+            payload,
+            channel,
+        });
+    }
+}
+
+/// Stack configuration using the mock hardware.
+pub enum MockConfig {}
+
+impl Config for MockConfig {
+    type Timer = MockTimer;
+    type Transmitter = MockTransmitter;
+    type Rng = MockRng;
+    type KeyStore = MockKeyStore;
+    type ChannelMapper = BleChannelMap<NoAttributes, NoSecurity>;
+
+    type PacketQueue = MockQueue;
+    type PacketProducer = MockProducer;
+    type PacketConsumer = MockConsumer;
+    type L2CAPBuffer = [u8; MAX_MESSAGE_LEN];
+
+    fn trace_pdu(kind: PduKind, direction: Direction, pdu: &[u8]) {
+        TRACE.with(|trace| trace.borrow_mut().push((kind, direction, pdu.to_vec())));
+    }
+
+    fn event_started(kind: EventKind, at: Instant) {
+        EVENTS.with(|events| events.borrow_mut().push((kind, true, at)));
+    }
+
+    fn event_ended(kind: EventKind, at: Instant) {
+        EVENTS.with(|events| events.borrow_mut().push((kind, false, at)));
+    }
+}
+
+std::thread_local! {
+    static TRACE: RefCell<Vec<(PduKind, Direction, Vec<u8>)>> = RefCell::new(Vec::new());
+    static EVENTS: RefCell<Vec<(EventKind, bool, Instant)>> = RefCell::new(Vec::new());
+}
+
+/// Returns and clears the PDUs traced by `MockConfig` on the current thread.
+pub fn take_trace() -> Vec<(PduKind, Direction, Vec<u8>)> {
+    TRACE.with(|trace| trace.replace(Vec::new()))
+}
+
+/// Returns and clears the starts (`true`) and ends (`false`) of radio events reported to
+/// `MockConfig` on the current thread.
+///
+/// Events are only reported with the `profiling` feature.
+pub fn take_events() -> Vec<(EventKind, bool, Instant)> {
+    EVENTS.with(|events| events.replace(Vec::new()))
+}
+
+/// A `PacketQueue` holding up to a fixed number of packets.
+///
+/// With a capacity of 1, this behaves like a `SimpleQueue`. Larger queues allow testing how the
+/// Link-Layer sends several queued PDUs.
+pub struct MockQueue {
+    capacity: usize,
+}
+
+impl MockQueue {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { capacity }
+    }
+}
+
+type Packets = Rc<RefCell<VecDeque<(data::Header, Vec<u8>)>>>;
+
+impl PacketQueue for MockQueue {
+    type Producer = MockProducer;
+    type Consumer = MockConsumer;
+
+    fn split(self) -> (MockProducer, MockConsumer) {
+        let packets = Packets::default();
+        let producer = MockProducer {
+            packets: packets.clone(),
+            capacity: self.capacity,
+        };
+        let consumer = MockConsumer {
+            packets,
+            capacity: self.capacity,
+        };
+        (producer, consumer)
+    }
+}
+
+/// Producer half of a `MockQueue`.
+pub struct MockProducer {
+    packets: Packets,
+    capacity: usize,
+}
+
+impl Producer for MockProducer {
+    fn free_space(&self) -> u8 {
+        if self.occupancy() < self.capacity {
+            MIN_DATA_PAYLOAD_BUF as u8
+        } else {
+            0
+        }
+    }
+
+    fn occupancy(&self) -> usize {
+        self.packets.borrow().len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn produce_dyn(
+        &mut self,
+        payload_bytes: u8,
+        f: &mut dyn FnMut(&mut ByteWriter<'_>) -> Result<Llid, Error>,
+    ) -> Result<(), Error> {
+        assert!(usize::from(payload_bytes) <= MIN_DATA_PAYLOAD_BUF);
+
+        if self.free_space() == 0 {
+            return Err(Error::Eof);
+        }
+
+        let mut buf = [0; MIN_DATA_PAYLOAD_BUF];
+        let mut writer = ByteWriter::new(&mut buf);
+        let llid = f(&mut writer)?;
+        let used = MIN_DATA_PAYLOAD_BUF - writer.space_left();
+
+        let mut header = data::Header::new(llid);
+        header.set_payload_length(used as u8);
+        self.packets
+            .borrow_mut()
+            .push_back((header, buf[..used].to_vec()));
+        Ok(())
+    }
+}
+
+/// Consumer half of a `MockQueue`.
+pub struct MockConsumer {
+    packets: Packets,
+    capacity: usize,
+}
+
+impl Consumer for MockConsumer {
+    fn has_data(&self) -> bool {
+        !self.packets.borrow().is_empty()
+    }
+
+    fn occupancy(&self) -> usize {
+        self.packets.borrow().len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn consume_raw_with<R>(
+        &mut self,
+        f: impl FnOnce(data::Header, &[u8]) -> Consume<R>,
+    ) -> Result<R, Error> {
+        let (consume, result) = match self.packets.borrow().front() {
+            Some((header, payload)) => f(*header, payload).into_parts(),
+            None => return Err(Error::Eof),
+        };
+
+        if consume {
+            self.packets.borrow_mut().pop_front();
+        }
+        result
+    }
+}
+
+/// Creates a new pair of single-packet queues for use with `MockConfig`.
+pub fn queues() -> ((MockProducer, MockConsumer), (MockProducer, MockConsumer)) {
+    queues_with_capacity(1)
+}
+
+/// Creates a new pair of packet queues holding up to `capacity` packets each.
+pub fn queues_with_capacity(
+    capacity: usize,
+) -> ((MockProducer, MockConsumer), (MockProducer, MockConsumer)) {
+    (
+        MockQueue::with_capacity(capacity).split(),
+        MockQueue::with_capacity(capacity).split(),
+    )
+}
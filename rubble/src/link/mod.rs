@@ -119,20 +119,31 @@
 
 pub mod ad_structure;
 pub mod advertising;
+pub mod capture;
 mod channel_map;
 mod comp_id;
+mod conn_handle;
 mod connection;
 pub mod data;
 mod device_address;
+pub mod encryption;
 mod features;
 pub mod filter;
 pub mod llcp;
+#[cfg(test)]
+pub(crate) mod mock;
+pub mod privacy;
 pub mod queue;
 mod responder;
 mod seq_num;
+pub mod trace;
 
+pub use self::channel_map::ChannelMap;
 pub use self::comp_id::*;
-pub use self::connection::Connection;
+pub use self::conn_handle::*;
+pub use self::connection::{
+    Connection, ConnectionParams, ConnectionStats, DisconnectReason, TransmitWindow,
+};
 pub use self::device_address::*;
 pub use self::features::*;
 pub use self::responder::*;
@@ -141,14 +152,19 @@ use {
     self::{
         ad_structure::AdStructure,
         advertising::{Pdu, PduBuf},
+        encryption::ConnectionSecurity,
+        filter::{AddressFilter, AllowList},
+        privacy::{ResolvingFilter, ResolvingList},
         seq_num::SeqNum,
+        trace::{Direction, EventKind, PduKind},
     },
     crate::{
         bytes::ByteReader,
         config::Config,
         crc::ble_crc24,
-        phy::{AdvertisingChannel, DataChannel, Radio},
-        time::{Duration, Instant, Timer},
+        l2cap::{TxStatus, TxTicket},
+        phy::{AdvertisingChannel, DataChannel, Phy, Radio},
+        time::{self, Duration, Instant, Timer},
         utils::HexSlice,
         Error,
     },
@@ -224,17 +240,71 @@ enum State<C: Config> {
         /// Precomputed PDU payload to copy into the transmitter's buffer.
         pdu: advertising::PduBuf,
 
+        /// Precomputed response to scan requests.
+        scan_rsp: advertising::PduBuf,
+
+        /// Advertising and scan response PDUs to switch to at the next advertising event.
+        pending: Option<(advertising::PduBuf, advertising::PduBuf)>,
+
         /// Next advertising channel to use for a message.
         // FIXME: spec check; no idea what order or change delay
         channel: AdvertisingChannel,
 
         data_queues: Option<(C::PacketConsumer, C::PacketProducer)>,
+
+        /// Whether an advertising event has been reported to the profiling hooks, but not ended.
+        event_open: bool,
     },
 
     /// Connected with another device.
     Connection(Connection<C>),
 }
 
+/// The state of a `LinkLayer`, as returned by `LinkLayer::state`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LinkState {
+    /// Neither advertising nor connected.
+    ///
+    /// This is the initial state, and the state after a connection was closed.
+    Standby,
+
+    /// Sending advertising PDUs and waiting for a connection request.
+    Advertising,
+
+    /// Connected with another device, in the given role.
+    Connected(Role),
+}
+
+impl LinkState {
+    /// Returns our role in the connection, or `NotConnected` if there is no connection.
+    ///
+    /// Notifications and other data put into the packet queues are only transmitted while
+    /// connected. Applications should call this before sending data, since the L2CAP and ATT
+    /// layers don't know about the Link-Layer state.
+    pub fn ensure_connected(self) -> Result<Role, NotConnected> {
+        match self {
+            LinkState::Connected(role) => Ok(role),
+            LinkState::Standby | LinkState::Advertising => Err(NotConnected),
+        }
+    }
+}
+
+/// The role of a device in a connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+    /// The device initiated the connection.
+    Master,
+
+    /// The device accepted a connection request while advertising.
+    ///
+    /// This is the only role supported by Rubble.
+    Slave,
+}
+
+/// Error returned when an operation requires a connection, but the Link-Layer isn't connected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NotConnected;
+
 /// Implementation of the real-time BLE Link-Layer logic.
 ///
 /// Users of this struct must provide an interface to the platform's hardware by implementing
@@ -243,6 +313,31 @@ pub struct LinkLayer<C: Config> {
     dev_addr: DeviceAddress,
     state: State<C>,
     timer: C::Timer,
+
+    /// Devices allowed to scan or connect to us while advertising (`None` allows all devices).
+    allow_list: Option<AllowList>,
+
+    /// IRKs of bonded peers, used to resolve their private addresses.
+    resolving_list: ResolvingList,
+
+    /// Key store and RNG used to start encryption (`None` if encryption is not supported).
+    keys: Option<(C::KeyStore, C::Rng)>,
+
+    /// Radio configuration requested by the last returned `Cmd`.
+    radio: RadioCmd,
+
+    /// When the timer should expire next, according to the returned `Cmd`s (`None` if disabled).
+    deadline: Option<Instant>,
+
+    /// Why the last connection was closed.
+    disconnect_reason: Option<DisconnectReason>,
+
+    /// Number of TX queue PDUs the master acknowledged before the last connection was closed.
+    closed_tx_acknowledged: u32,
+
+    /// Events to signal to `async` application code.
+    #[cfg(feature = "async")]
+    events: Option<&'static crate::asynch::LinkEvents>,
 }
 
 impl<C: Config> LinkLayer<C> {
@@ -260,14 +355,102 @@ impl<C: Config> LinkLayer<C> {
             dev_addr,
             state: State::Standby,
             timer,
+            allow_list: None,
+            resolving_list: ResolvingList::new(),
+            keys: None,
+            radio: RadioCmd::Off,
+            deadline: None,
+            disconnect_reason: None,
+            closed_tx_acknowledged: 0,
+            #[cfg(feature = "async")]
+            events: None,
         }
     }
 
+    /// Creates a new Link-Layer using a freshly generated Random Static Address.
+    ///
+    /// The address is generated by [`DeviceAddress::new_random_static`] using `rng`, and can be
+    /// queried with `device_address`. Since a device must keep its static address for as long as
+    /// it is powered, only use this once per boot.
+    ///
+    /// [`DeviceAddress::new_random_static`]: struct.DeviceAddress.html#method.new_random_static
+    pub fn with_random_static_address(rng: &mut C::Rng, timer: C::Timer) -> Self {
+        Self::new(DeviceAddress::new_random_static(rng), timer)
+    }
+
     /// Returns a reference to the timer instance used by the Link-Layer.
     pub fn timer(&mut self) -> &mut C::Timer {
         &mut self.timer
     }
 
+    /// Returns the address this device advertises and connects with.
+    pub fn device_address(&self) -> DeviceAddress {
+        self.dev_addr
+    }
+
+    /// Restricts the devices that may scan or connect to this device while advertising.
+    ///
+    /// When an allow list is set, `SCAN_REQ` and `CONNECT_REQ` PDUs sent by devices that aren't on
+    /// the list are ignored. Passing `None` allows all devices to scan and connect (the default).
+    ///
+    /// The allow list is retained when advertising is restarted.
+    pub fn set_allow_list(&mut self, allow_list: Option<AllowList>) {
+        self.allow_list = allow_list;
+    }
+
+    /// Returns the allow list used while advertising, if any.
+    pub fn allow_list(&self) -> Option<&AllowList> {
+        self.allow_list.as_ref()
+    }
+
+    /// Sets the list of peer IRKs used to resolve Resolvable Private Addresses.
+    ///
+    /// When checking a peer against the allow list, its address is first resolved to its identity
+    /// address using this list. This allows placing the identity addresses of bonded devices on the
+    /// allow list, even if they use privacy.
+    pub fn set_resolving_list(&mut self, resolving_list: ResolvingList) {
+        self.resolving_list = resolving_list;
+    }
+
+    /// Returns the list of peer IRKs used to resolve Resolvable Private Addresses.
+    pub fn resolving_list(&self) -> &ResolvingList {
+        &self.resolving_list
+    }
+
+    /// Enables Link-Layer encryption using the Long Term Keys in `key_store`.
+    ///
+    /// When a bonded master reconnects and requests encryption, the LTK matching the `EDIV` and
+    /// `Rand` values in its request is looked up in `key_store` and encryption is resumed. If no
+    /// matching key is found, the request is rejected so that the master can pair again. `rng` is
+    /// used to generate the slave's part of the session key diversifier and IV.
+    ///
+    /// This also makes the Link-Layer report support for the `LE_ENCRYPTION` feature.
+    pub fn enable_encryption(&mut self, key_store: C::KeyStore, rng: C::Rng) {
+        self.keys = Some((key_store, rng));
+    }
+
+    /// Returns a reference to the key store, if encryption was enabled.
+    pub fn key_store(&mut self) -> Option<&mut C::KeyStore> {
+        self.keys.as_mut().map(|(key_store, _)| key_store)
+    }
+
+    /// Signals connection events to `async` code waiting on `events`.
+    ///
+    /// See the [`asynch`] module for details.
+    ///
+    /// [`asynch`]: ../asynch/index.html
+    #[cfg(feature = "async")]
+    pub fn set_events(&mut self, events: &'static crate::asynch::LinkEvents) {
+        self.events = Some(events);
+    }
+
+    /// Returns whether the device with the address `peer` may scan or connect to us.
+    fn is_peer_allowed(&self, peer: DeviceAddress) -> bool {
+        self.allow_list
+            .as_ref()
+            .is_none_or(|list| ResolvingFilter::new(list, &self.resolving_list).matches(peer))
+    }
+
     /// Starts advertising this device, optionally sending data along with the advertising PDU.
     pub fn start_advertise(
         &mut self,
@@ -280,25 +463,65 @@ impl<C: Config> LinkLayer<C> {
         // TODO tear down existing connection?
 
         let pdu = PduBuf::discoverable(self.dev_addr, data)?;
+        let scan_rsp = PduBuf::scan_response(self.dev_addr, &[])?;
         debug!("start_advertise: adv_data = {:?}", data);
         debug!("start_advertise: PDU = {:?}", pdu);
+        let now = self.timer().now();
+        if let State::Advertising {
+            event_open: true, ..
+        } = self.state
+        {
+            trace::event_ended::<C>(EventKind::Advertising, now);
+        }
         self.state = State::Advertising {
-            next_adv: self.timer().now(),
+            next_adv: now,
             interval,
             pdu,
+            scan_rsp,
+            pending: None,
             channel: AdvertisingChannel::first(),
             data_queues: Some((tx, rx)),
+            event_open: false,
         };
         Ok(self.update_timer(transmitter).next_update)
     }
 
+    /// Replaces the advertising data and scan response data while advertising.
+    ///
+    /// The new data is sent starting with the next advertising event, so a packet is never sent
+    /// with a mix of old and new data, and scan requests are answered with the scan response data
+    /// that belongs to the advertisement the scanner received. This can be used to update
+    /// advertised sensor readings without restarting advertising.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `data` or `scan_data` don't fit in a single PDU, and
+    /// `Error::InvalidValue` if the Link-Layer isn't advertising.
+    pub fn set_advertising_data(
+        &mut self,
+        data: &[AdStructure<'_>],
+        scan_data: &[AdStructure<'_>],
+    ) -> Result<(), Error> {
+        let adv = PduBuf::discoverable(self.dev_addr, data)?;
+        let scan_rsp = PduBuf::scan_response(self.dev_addr, scan_data)?;
+        match &mut self.state {
+            State::Advertising { pending, .. } => {
+                debug!("set_advertising_data: PDU = {:?}", adv);
+                *pending = Some((adv, scan_rsp));
+                Ok(())
+            }
+            _ => Err(Error::InvalidValue),
+        }
+    }
+
     /// Process an incoming packet from an advertising channel.
     ///
     /// The access address of the packet must be `ADVERTISING_ADDRESS`.
     ///
     /// # Parameters
     ///
-    /// * **`rx_end`**: A timestamp indicating when the packet was fully received.
+    /// * **`rx_end`**: A timestamp indicating when the packet was fully received. This may lag
+    ///   behind the actual end of the packet by `Config::RADIO_RX_DELAY`.
     /// * **`tx`**: A packet transmitter.
     /// * **`header`**: The header of the received packet.
     /// * **`payload`**: The packet payload following the header.
@@ -311,31 +534,65 @@ impl<C: Config> LinkLayer<C> {
         payload: &[u8],
         crc_ok: bool,
     ) -> Cmd {
+        let rx_end = rx_end - C::RADIO_RX_DELAY;
+        let cmd = self.handle_adv_packet(rx_end, tx, header, payload, crc_ok);
+        self.track(cmd)
+    }
+
+    fn handle_adv_packet(
+        &mut self,
+        rx_end: Instant,
+        tx: &mut C::Transmitter,
+        header: advertising::Header,
+        payload: &[u8],
+        crc_ok: bool,
+    ) -> Cmd {
+        if crc_ok {
+            C::trace_pdu(PduKind::Advertising, Direction::Inbound, payload);
+        }
+
         let pdu = advertising::Pdu::from_header_and_payload(header, &mut ByteReader::new(payload));
 
+        let mut turnaround = None;
         if let Ok(pdu) = pdu {
+            let peer_allowed = self.is_peer_allowed(*pdu.sender());
             if let State::Advertising {
                 channel,
                 data_queues,
+                scan_rsp,
                 ..
             } = &mut self.state
             {
-                if crc_ok && pdu.receiver() == Some(&self.dev_addr) {
-                    // Got a packet addressed at us, can be a scan or connect request
+                if crc_ok && pdu.receiver() == Some(&self.dev_addr) && peer_allowed {
+                    // Got a packet addressed at us from an allowed device, can be a scan or
+                    // connect request
                     match pdu {
                         Pdu::ScanRequest { .. } => {
-                            let scan_data = &[]; // TODO make this configurable
-                            let response = PduBuf::scan_response(self.dev_addr, scan_data).unwrap();
-                            tx.transmit_advertising(response.header(), *channel);
+                            let payload = scan_rsp.payload();
+                            tx.tx_payload_buf()[..payload.len()].copy_from_slice(payload);
+                            tx.transmit_advertising(scan_rsp.header(), *channel);
+                            C::trace_pdu(PduKind::Advertising, Direction::Outbound, payload);
+
+                            turnaround = Some(rx_end + Duration::T_IFS);
 
                             // Log after responding to meet timing
-                            debug!("-> SCAN RESP: {:?}", response);
+                            debug!("-> SCAN RESP: {:?}", scan_rsp);
+                        }
+                        Pdu::ConnectRequest { lldata, .. }
+                            if lldata.access_address() == advertising::ACCESS_ADDRESS =>
+                        {
+                            // Data packets would be indistinguishable from advertising packets,
+                            // so the spec forbids this. Keep advertising.
+                            warn!("ignoring CONNECT_REQ using the advertising access address");
                         }
                         Pdu::ConnectRequest { lldata, .. } => {
                             trace!("ADV<- CONN! {:?}", pdu);
 
+                            let initiator = *pdu.sender();
+                            let peer = self.resolving_list.resolve_peer(initiator);
                             let (tx, rx) = data_queues.take().unwrap();
-                            let (conn, cmd) = Connection::create(&lldata, rx_end, tx, rx);
+                            trace::event_ended::<C>(EventKind::Advertising, rx_end);
+                            let (conn, cmd) = Connection::create(peer, &lldata, rx_end, tx, rx);
                             self.state = State::Connection(conn);
                             return cmd;
                         }
@@ -358,16 +615,22 @@ impl<C: Config> LinkLayer<C> {
             State::Connection { .. } => unreachable!("process_adv_packet called while connected"),
             State::Advertising { channel, .. } => {
                 Cmd {
-                    radio: RadioCmd::ListenAdvertising { channel },
+                    radio: RadioCmd::ListenAdvertising {
+                        channel,
+                        phy: Phy::Le1M,
+                    },
                     // no change
                     next_update: NextUpdate::Keep,
                     queued_work: false,
+                    turnaround,
                 }
             }
         }
     }
 
     /// Process an incoming data channel packet.
+    ///
+    /// The parameters are the same as for `process_adv_packet`.
     pub fn process_data_packet(
         &mut self,
         rx_end: Instant,
@@ -376,23 +639,31 @@ impl<C: Config> LinkLayer<C> {
         payload: &[u8],
         crc_ok: bool,
     ) -> Cmd {
-        if let State::Connection(conn) = &mut self.state {
-            match conn.process_data_packet(rx_end, tx, &mut self.timer, header, payload, crc_ok) {
+        let rx_end = rx_end - C::RADIO_RX_DELAY;
+        let cmd = if let State::Connection(conn) = &mut self.state {
+            let keys = self.keys.as_mut();
+            match conn.process_data_packet(rx_end, tx, keys, header, payload, crc_ok) {
                 Ok(cmd) => cmd,
-                Err(()) => {
-                    debug!("connection ended, standby");
+                Err(reason) => {
+                    trace::event_ended::<C>(EventKind::Connection, rx_end);
+                    debug!("connection ended ({:?}), standby", reason);
+                    self.closed_tx_acknowledged = conn.tx_acknowledged();
+                    let reported = conn.report_closed();
                     self.state = State::Standby;
+                    self.disconnect_reason = Some(reason);
                     Cmd {
                         next_update: NextUpdate::Disable,
                         radio: RadioCmd::Off,
-                        // FIXME(#70) this might need to be changed to `true`
-                        queued_work: false,
+                        // The `Responder` has to process the report
+                        queued_work: reported,
+                        turnaround: None,
                     }
                 }
             }
         } else {
             unreachable!("received data channel PDU while not in connected state");
-        }
+        };
+        self.track(cmd)
     }
 
     /// Update the Link-Layer state after the timer expires.
@@ -403,14 +674,35 @@ impl<C: Config> LinkLayer<C> {
     ///
     /// * `tx`: A `Transmitter` for sending packets.
     pub fn update_timer(&mut self, tx: &mut C::Transmitter) -> Cmd {
+        let cmd = self.timer_expired(tx);
+        self.track(cmd)
+    }
+
+    fn timer_expired(&mut self, tx: &mut C::Transmitter) -> Cmd {
         match &mut self.state {
             State::Advertising {
                 next_adv,
                 interval,
                 pdu,
+                scan_rsp,
+                pending,
                 channel,
+                event_open,
                 ..
             } => {
+                let now = self.timer.now();
+                if *event_open {
+                    trace::event_ended::<C>(EventKind::Advertising, now);
+                }
+                trace::event_started::<C>(EventKind::Advertising, now);
+                *event_open = true;
+
+                // Switch to new data between advertising events
+                if let Some((new_pdu, new_scan_rsp)) = pending.take() {
+                    *pdu = new_pdu;
+                    *scan_rsp = new_scan_rsp;
+                }
+
                 *channel = channel.cycle();
                 let payload = pdu.payload();
                 let buf = tx.tx_payload_buf();
@@ -419,25 +711,42 @@ impl<C: Config> LinkLayer<C> {
                 // FIXME According to the spec, this has to broadcast on all advertising channels
 
                 tx.transmit_advertising(pdu.header(), *channel);
+                C::trace_pdu(PduKind::Advertising, Direction::Outbound, payload);
 
                 *next_adv += *interval;
 
                 Cmd {
-                    radio: RadioCmd::ListenAdvertising { channel: *channel },
+                    radio: RadioCmd::ListenAdvertising {
+                        channel: *channel,
+                        phy: Phy::Le1M,
+                    },
                     next_update: NextUpdate::At(*next_adv),
                     queued_work: false,
+                    turnaround: None,
                 }
             }
             State::Connection(conn) => match conn.timer_update(&mut self.timer) {
-                Ok(cmd) => cmd,
-                Err(()) => {
-                    debug!("connection ended (timer), standby");
+                Ok(cmd) => {
+                    #[cfg(feature = "async")]
+                    {
+                        if let Some(events) = self.events {
+                            events.connection_event().signal();
+                        }
+                    }
+                    cmd
+                }
+                Err(reason) => {
+                    debug!("connection ended ({:?}, timer), standby", reason);
+                    self.closed_tx_acknowledged = conn.tx_acknowledged();
+                    let reported = conn.report_closed();
                     self.state = State::Standby;
+                    self.disconnect_reason = Some(reason);
                     Cmd {
                         next_update: NextUpdate::Disable,
                         radio: RadioCmd::Off,
-                        // FIXME(#70) this might need to be changed to `true`
-                        queued_work: false,
+                        // The `Responder` has to process the report
+                        queued_work: reported,
+                        turnaround: None,
                     }
                 }
             },
@@ -445,6 +754,90 @@ impl<C: Config> LinkLayer<C> {
         }
     }
 
+    /// Drives the Link-Layer from a cooperative main loop or timer interrupt.
+    ///
+    /// This is an alternative to `update_timer` for applications that would rather not keep track
+    /// of the `Cmd`s returned by the Link-Layer: It remembers the radio configuration and timer
+    /// deadline requested by every `Cmd` it returns. If that deadline has been reached at `now`,
+    /// this does the work of `update_timer`, passing any packet that is due to `tx`. Calling `poll`
+    /// before the deadline is cheap and has no effect.
+    ///
+    /// Unlike the other methods, the returned `Cmd` always describes the complete desired state, so
+    /// it can be applied as-is:
+    ///
+    /// * A packet was passed to `tx` if it was due. `turnaround` is always `None`, since packets
+    ///   sent on timer expiry are not responses and can be transmitted right away.
+    /// * `radio` says whether to listen afterwards (`ListenAdvertising` or `ListenData`) or to turn
+    ///   the radio off.
+    /// * `next_update` is `NextUpdate::At` the instant `poll` has to be called again, or
+    ///   `NextUpdate::Disable` if the Link-Layer is in standby and only needs to be polled after
+    ///   it is restarted. It is never `NextUpdate::Keep`.
+    ///
+    /// Received packets must still be passed to `process_adv_packet` and `process_data_packet`.
+    ///
+    /// `now` should be the current time of the Link-Layer's timer.
+    pub fn poll(&mut self, now: Instant, tx: &mut C::Transmitter) -> Cmd {
+        let mut cmd = match self.deadline {
+            Some(deadline) if !time::is_before(now, deadline) => self.update_timer(tx),
+            _ => Cmd {
+                radio: self.radio.clone(),
+                next_update: NextUpdate::Keep,
+                queued_work: false,
+                turnaround: None,
+            },
+        };
+        cmd.next_update = match self.deadline {
+            Some(deadline) => NextUpdate::At(deadline),
+            None => NextUpdate::Disable,
+        };
+        cmd
+    }
+
+    /// Records the radio configuration and timer deadline requested by `cmd` for `poll`.
+    ///
+    /// Also moves the `turnaround` forward by the radio's TX ramp-up time.
+    fn track(&mut self, mut cmd: Cmd) -> Cmd {
+        cmd.turnaround = cmd.turnaround.map(|at| at - C::RADIO_TX_DELAY);
+        self.radio = cmd.radio.clone();
+        self.deadline = cmd.next_update.apply(self.deadline);
+        cmd
+    }
+
+    /// Reports that the radio could not start transmitting at the `turnaround` instant of the last
+    /// `Cmd`.
+    ///
+    /// The packet must not be transmitted late. Since the master did not receive a response, it
+    /// will retransmit its packet, which the Link-Layer answers by retransmitting the missed
+    /// response. Missed turnarounds are counted in the `ConnectionStats` of the connection.
+    ///
+    /// Slow MCUs or radio drivers can regularly miss the `T_IFS` deadline, which makes the
+    /// connection very slow and can eventually cause it to time out.
+    pub fn turnaround_missed(&mut self) {
+        if let State::Connection(conn) = &mut self.state {
+            conn.turnaround_missed();
+        }
+    }
+
+    /// Records the RSSI of a received data channel packet, in dBm.
+    ///
+    /// Radio drivers that can measure the signal strength should call this for every data channel
+    /// packet passed to `process_data_packet`. The samples are averaged to obtain the
+    /// `connection_rssi`. Calls while not in a connection are ignored.
+    pub fn record_rssi(&mut self, rssi: i8) {
+        if let State::Connection(conn) = &mut self.state {
+            conn.record_rssi(rssi);
+        }
+    }
+
+    /// Returns the smoothed RSSI of packets received in the current connection, in dBm.
+    ///
+    /// This can be used to estimate the link quality or the distance to the master. Returns `None`
+    /// if the Link Layer is not currently in a connection, or if the radio driver hasn't reported
+    /// any samples via `record_rssi` yet.
+    pub fn connection_rssi(&self) -> Option<i8> {
+        self.connection().and_then(|conn| conn.rssi())
+    }
+
     /// Returns a reference to the connection state.
     ///
     /// If the Link Layer is not currently in a connection, returns `None`.
@@ -456,6 +849,81 @@ impl<C: Config> LinkLayer<C> {
         }
     }
 
+    /// Returns the delivery status of the PDU identified by `ticket`.
+    ///
+    /// While connected, this reports whether the master has acknowledged the PDU yet (like
+    /// `Connection::is_acknowledged`). Once the connection is closed, PDUs that weren't
+    /// acknowledged are reported as `TxStatus::Dropped`.
+    ///
+    /// Like the ticket itself, the status is only meaningful until the next connection is
+    /// established.
+    pub fn tx_status(&self, ticket: TxTicket) -> TxStatus {
+        match &self.state {
+            State::Connection(conn) if conn.is_acknowledged(ticket) => TxStatus::Acknowledged,
+            State::Connection(_) => TxStatus::Pending,
+            _ if ticket.is_within(self.closed_tx_acknowledged) => TxStatus::Acknowledged,
+            State::Standby | State::Advertising { .. } => TxStatus::Dropped,
+        }
+    }
+
+    /// Returns the parameters of the current connection.
+    ///
+    /// The returned values reflect the parameters currently in effect, including any changes made
+    /// by connection update procedures. If the Link Layer is not currently in a connection, returns
+    /// `None`.
+    pub fn connection_params(&self) -> Option<ConnectionParams> {
+        self.connection().map(|conn| conn.connection_params())
+    }
+
+    /// Returns the security level of the current connection.
+    ///
+    /// Returns `ConnectionSecurity::Unencrypted` if the Link Layer is not currently in a
+    /// connection.
+    pub fn security_level(&self) -> ConnectionSecurity {
+        self.connection()
+            .map_or(ConnectionSecurity::Unencrypted, |conn| {
+                conn.security_level()
+            })
+    }
+
+    /// Returns why the last connection was closed.
+    ///
+    /// Returns `None` if no connection was closed yet.
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        self.disconnect_reason
+    }
+
+    /// Sets the authenticated payload timeout of the current connection (*LE Ping*).
+    ///
+    /// While the connection is encrypted, the master has to send a packet with a valid MIC at
+    /// least once per `timeout`. If it only sends empty PDUs for half of the timeout, the
+    /// Link-Layer sends an `LL_PING_REQ` to elicit an authenticated response. Expired timeouts are
+    /// counted in `ConnectionStats::authenticated_payload_timeouts`. The timeout should be longer
+    /// than `connInterval * (1 + connSlaveLatency)`, the specification's default is 30 seconds.
+    ///
+    /// The timer is disabled (`None`) when a connection is established.
+    pub fn set_authenticated_payload_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<(), NotConnected> {
+        match &mut self.state {
+            State::Connection(conn) => {
+                conn.set_authenticated_payload_timeout(timeout);
+                Ok(())
+            }
+            State::Standby | State::Advertising { .. } => Err(NotConnected),
+        }
+    }
+
+    /// Returns the current state of the Link-Layer.
+    pub fn state(&self) -> LinkState {
+        match self.state {
+            State::Standby => LinkState::Standby,
+            State::Advertising { .. } => LinkState::Advertising,
+            State::Connection(_) => LinkState::Connected(Role::Slave),
+        }
+    }
+
     /// Returns whether the Link-Layer is currently broadcasting advertisement packets.
     pub fn is_advertising(&self) -> bool {
         if let State::Advertising { .. } = self.state {
@@ -478,6 +946,26 @@ impl<C: Config> LinkLayer<C> {
 /// Command returned by the Link-Layer to the user.
 ///
 /// Specifies how the radio should be configured and when/if to call `LinkLayer::update` again.
+///
+/// Any packet the Link-Layer needs to send is passed to the `Transmitter` before the `Cmd` is
+/// returned, so a radio driver only ever has to do this:
+///
+/// * Configure the radio according to `radio`. [`RadioCmd::rx_params`] returns everything needed
+///   to listen, or `None` if the radio should be turned off.
+/// * Reschedule the timer according to `next_update`. [`NextUpdate::apply`] computes the new
+///   deadline from the previously scheduled one.
+///
+/// Alternatively, [`Cmd::action`] combines all of this into a single [`RadioAction`], which tells
+/// the driver whether to listen until a deadline, or to transmit a response first.
+///
+/// Applications driving the Link-Layer from a main loop can use [`LinkLayer::poll`] instead, which
+/// returns `Cmd`s that don't depend on previously returned ones.
+///
+/// [`LinkLayer::poll`]: struct.LinkLayer.html#method.poll
+/// [`RadioCmd::rx_params`]: enum.RadioCmd.html#method.rx_params
+/// [`NextUpdate::apply`]: enum.NextUpdate.html#method.apply
+/// [`Cmd::action`]: #method.action
+/// [`RadioAction`]: enum.RadioAction.html
 #[must_use]
 #[derive(Debug, Clone)]
 pub struct Cmd {
@@ -496,6 +984,72 @@ pub struct Cmd {
     /// calling the `Responder`. The apps idle loop might unconditionally do that, in which case
     /// checking this flag is not necessary.
     pub queued_work: bool,
+
+    /// The instant at which the transmission of a response packet must start.
+    ///
+    /// If the Link-Layer passed a packet to the `Transmitter` in response to a received packet,
+    /// this is the end of the received packet plus the inter frame space `T_IFS` (150 µs), minus
+    /// the radio's ramp-up time (`Config::RADIO_TX_DELAY`). The radio must start transmitting
+    /// exactly at this instant (the spec allows a deviation of 2 µs).
+    /// If it can't, it must not transmit the packet, and has to report the missed turnaround by
+    /// calling `LinkLayer::turnaround_missed`.
+    ///
+    /// This is `None` if nothing was passed to the `Transmitter`, or if the packet isn't a
+    /// response (eg. an advertising PDU sent when the timer expired).
+    pub turnaround: Option<Instant>,
+}
+
+impl Cmd {
+    /// Returns the radio action required to carry out this command.
+    ///
+    /// `scheduled` is the time at which `update` was supposed to be called before this `Cmd` was
+    /// returned, or `None` if the timer was disabled (see `NextUpdate::apply`).
+    pub fn action(&self, scheduled: Option<Instant>) -> RadioAction {
+        let until = self.next_update.apply(scheduled);
+        match (self.turnaround, self.radio.rx_params()) {
+            (None, None) => RadioAction::Off,
+            (None, Some(rx)) => RadioAction::Listen { rx, until },
+            (Some(at), None) => RadioAction::Transmit { at },
+            (Some(at), Some(rx)) => RadioAction::TransmitThenListen { at, rx, until },
+        }
+    }
+}
+
+/// The action a radio driver has to perform, as returned by `Cmd::action`.
+///
+/// In all cases, the timer has to be configured to call `LinkLayer::update` at `until`, or
+/// disabled if it is `None`. When the radio is listening at that time, it should be turned off.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RadioAction {
+    /// Turn the radio off.
+    Off,
+
+    /// Listen with the configuration `rx` until `until`.
+    ///
+    /// Any packet the Link-Layer wanted to send right away was already passed to the
+    /// `Transmitter`.
+    Listen {
+        rx: RxParams,
+        until: Option<Instant>,
+    },
+
+    /// Start transmitting the response passed to the `Transmitter` exactly at `at`, then turn the
+    /// radio off.
+    ///
+    /// If the radio can't start in time, the response must not be sent, and the driver has to
+    /// call `LinkLayer::turnaround_missed`.
+    Transmit { at: Instant },
+
+    /// Start transmitting the response passed to the `Transmitter` exactly at `at`, then listen
+    /// with the configuration `rx` until `until`.
+    ///
+    /// If the radio can't start in time, the response must not be sent, and the driver has to
+    /// call `LinkLayer::turnaround_missed`.
+    TransmitThenListen {
+        at: Instant,
+        rx: RxParams,
+        until: Option<Instant>,
+    },
 }
 
 /// Specifies when the Link Layer's `update` method should be called the next time.
@@ -513,6 +1067,21 @@ pub enum NextUpdate {
     At(Instant),
 }
 
+impl NextUpdate {
+    /// Applies this `NextUpdate` to the currently scheduled update time.
+    ///
+    /// `scheduled` is the time at which `update` was supposed to be called before this
+    /// `NextUpdate` was returned, or `None` if the timer was disabled. Returns the time at which
+    /// `update` should now be called, or `None` if the timer should be disabled.
+    pub fn apply(&self, scheduled: Option<Instant>) -> Option<Instant> {
+        match self {
+            NextUpdate::Disable => None,
+            NextUpdate::Keep => scheduled,
+            NextUpdate::At(instant) => Some(*instant),
+        }
+    }
+}
+
 /// Specifies if and how the radio should listen for transmissions.
 ///
 /// Returned by the Link-Layer update and processing methods to reconfigure the radio as needed.
@@ -528,6 +1097,12 @@ pub enum RadioCmd {
     ListenAdvertising {
         /// The advertising channel to listen on.
         channel: AdvertisingChannel,
+
+        /// The PHY to listen on.
+        ///
+        /// This is always `Phy::Le1M` for the `LinkLayer`. A `BeaconScanner` can also be
+        /// configured to scan on one of the coded PHYs.
+        phy: Phy,
     },
 
     /// Listen on a data channel. If a matching packet is received, pass it to
@@ -549,6 +1124,58 @@ pub enum RadioCmd {
     },
 }
 
+impl RadioCmd {
+    /// Returns the radio configuration needed to carry out this command.
+    ///
+    /// This covers both advertising and data channels, so radio drivers don't have to handle them
+    /// separately. Returns `None` if the radio should be turned off.
+    pub fn rx_params(&self) -> Option<RxParams> {
+        match *self {
+            RadioCmd::Off => None,
+            RadioCmd::ListenAdvertising { channel, phy } => Some(RxParams {
+                phy,
+                freq: channel.freq(),
+                whitening_iv: channel.whitening_iv(),
+                access_address: advertising::ACCESS_ADDRESS,
+                crc_init: advertising::CRC_PRESET,
+            }),
+            RadioCmd::ListenData {
+                channel,
+                access_address,
+                crc_init,
+            } => Some(RxParams {
+                // Connections always use the LE 1M PHY
+                phy: Phy::Le1M,
+                freq: channel.freq(),
+                whitening_iv: channel.whitening_iv(),
+                access_address,
+                crc_init,
+            }),
+        }
+    }
+}
+
+/// Radio configuration for receiving packets, returned by [`RadioCmd::rx_params`].
+///
+/// [`RadioCmd::rx_params`]: enum.RadioCmd.html#method.rx_params
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RxParams {
+    /// The PHY to configure the radio for.
+    pub phy: Phy,
+
+    /// The center frequency of the channel to listen on, in MHz.
+    pub freq: u16,
+
+    /// The initial value of the data whitening LFSR for the channel.
+    pub whitening_iv: u8,
+
+    /// The Access Address to listen for.
+    pub access_address: u32,
+
+    /// Initialization value of the CRC-24 calculation (only the lower 24 bits are relevant).
+    pub crc_init: u32,
+}
+
 /// Trait for Link Layer packet transmission.
 ///
 /// The specifics of sending a Link-Layer packet depend on the underlying hardware. The `link`
@@ -682,3 +1309,587 @@ impl<R: Radio> Transmitter for RawTransmitter<R> {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{mock::*, *},
+        crate::{
+            att::{Handle, NoAttributes},
+            l2cap::{BleChannelMap, L2CAPState},
+            link::advertising::Header,
+            security::NoSecurity,
+        },
+        std::vec::Vec,
+    };
+
+    /// A minimal radio driver that only uses the generic `Cmd` accessors.
+    struct MockDriver {
+        rx: Option<RxParams>,
+        wakeup: Option<Instant>,
+        /// Instant at which the last response was transmitted.
+        transmitted: Option<Instant>,
+    }
+
+    impl MockDriver {
+        fn apply(&mut self, cmd: Cmd) {
+            let (rx, wakeup) = match cmd.action(self.wakeup) {
+                RadioAction::Off => (None, self.wakeup),
+                RadioAction::Listen { rx, until } => (Some(rx), until),
+                RadioAction::Transmit { at } => {
+                    self.transmitted = Some(at);
+                    (None, self.wakeup)
+                }
+                RadioAction::TransmitThenListen { at, rx, until } => {
+                    self.transmitted = Some(at);
+                    (Some(rx), until)
+                }
+            };
+            self.rx = rx;
+            self.wakeup = wakeup;
+        }
+    }
+
+    #[test]
+    fn mock_driver_follows_cmds() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let mut ll = LinkLayer::<MockConfig>::new(addr, MockTimer::new());
+        let mut radio = MockTransmitter::new();
+        let mut driver = MockDriver {
+            rx: None,
+            wakeup: None,
+            transmitted: None,
+        };
+        let ((tx, _), (_, rx)) = queues();
+        let interval = Duration::from_millis(100);
+
+        let next = ll
+            .start_advertise(interval, &[], &mut radio, rx, tx)
+            .unwrap();
+        driver.wakeup = next.apply(driver.wakeup);
+        assert_eq!(driver.wakeup, Some(Instant::from_raw_micros(0) + interval));
+        assert_eq!(radio.sent.len(), 1);
+
+        // The timer fires: Send the next advertisement and listen on the same channel
+        ll.timer().set(driver.wakeup.unwrap());
+        driver.apply(ll.update_timer(&mut radio));
+        let channel = match radio.sent.last().unwrap() {
+            Sent::Advertising { channel, .. } => *channel,
+            _ => unreachable!(),
+        };
+        let rx = driver.rx.unwrap();
+        assert_eq!(rx.freq, channel.freq());
+        assert_eq!(rx.access_address, advertising::ACCESS_ADDRESS);
+        assert_eq!(rx.crc_init, advertising::CRC_PRESET);
+        assert_eq!(
+            driver.wakeup,
+            Some(Instant::from_raw_micros(0) + interval + interval)
+        );
+
+        assert_eq!(driver.transmitted, None);
+
+        // An unrelated packet keeps the current timer and radio configuration
+        let header = Header::new(advertising::PduType::AdvInd);
+        driver.apply(ll.process_adv_packet(ll.timer.now(), &mut radio, header, &[], false));
+        assert_eq!(driver.rx, Some(rx));
+        assert_eq!(
+            driver.wakeup,
+            Some(Instant::from_raw_micros(0) + interval + interval)
+        );
+        assert_eq!(driver.transmitted, None);
+
+        // A scan request is answered after `T_IFS`, then the radio keeps listening
+        let scanner = DeviceAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF], AddressKind::Public);
+        let mut scan_req = scanner.raw().to_vec();
+        scan_req.extend_from_slice(addr.raw());
+        let mut header =
+            Header::with_addresses(advertising::PduType::ScanReq, &scanner, Some(&addr));
+        header.set_payload_length(12);
+        let rx_end = ll.timer.now();
+        driver.apply(ll.process_adv_packet(rx_end, &mut radio, header, &scan_req, true));
+        assert_eq!(driver.transmitted, Some(rx_end + Duration::T_IFS));
+        assert_eq!(driver.rx, Some(rx));
+        assert_eq!(
+            driver.wakeup,
+            Some(Instant::from_raw_micros(0) + interval + interval)
+        );
+    }
+
+    #[test]
+    fn poll_loop() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let mut ll = LinkLayer::<MockConfig>::new(addr, MockTimer::new());
+        let mut radio = MockTransmitter::new();
+        let start = ll.timer.now();
+        let interval = Duration::from_millis(100);
+
+        // Nothing to do in standby
+        let cmd = ll.poll(start, &mut radio);
+        assert_eq!(cmd.radio.rx_params(), None);
+        assert_eq!(cmd.next_update.apply(Some(start)), None);
+
+        let ((tx, _), (_, rx)) = queues();
+        let _ = ll
+            .start_advertise(interval, &[], &mut radio, rx, tx)
+            .unwrap();
+        assert_eq!(radio.sent.len(), 1);
+
+        // Polling early repeats the current state without transmitting
+        let cmd = ll.poll(start + Duration::from_millis(50), &mut radio);
+        assert_eq!(radio.sent.len(), 1);
+        let listen = cmd.radio.rx_params().unwrap();
+        assert_eq!(listen.access_address, advertising::ACCESS_ADDRESS);
+        assert_eq!(cmd.next_update.apply(None), Some(start + interval));
+
+        // At the deadline, the next advertisement is sent and the deadline moves on
+        ll.timer().set(start + interval);
+        let cmd = ll.poll(start + interval, &mut radio);
+        assert_eq!(radio.sent.len(), 2);
+        assert_eq!(cmd.turnaround, None);
+        assert_ne!(cmd.radio.rx_params(), Some(listen));
+        assert_eq!(
+            cmd.next_update.apply(None),
+            Some(start + interval + interval)
+        );
+
+        // A received packet that changes nothing doesn't affect the deadline either
+        let header = Header::new(advertising::PduType::AdvInd);
+        let _ = ll.process_adv_packet(ll.timer.now(), &mut radio, header, &[], false);
+        let cmd = ll.poll(start + interval, &mut radio);
+        assert_eq!(radio.sent.len(), 2);
+        assert_eq!(
+            cmd.next_update.apply(None),
+            Some(start + interval + interval)
+        );
+    }
+
+    #[test]
+    fn update_advertising_data() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let scanner = DeviceAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF], AddressKind::Public);
+        let mut ll = LinkLayer::<MockConfig>::new(addr, MockTimer::new());
+        let mut radio = MockTransmitter::new();
+        let ((tx, _), (_, rx)) = queues();
+        let interval = Duration::from_millis(100);
+        ll.start_advertise(
+            interval,
+            &[AdStructure::CompleteLocalName("old")],
+            &mut radio,
+            rx,
+            tx,
+        )
+        .unwrap();
+
+        let last_payload = |radio: &MockTransmitter| match radio.sent.last().unwrap() {
+            Sent::Advertising { payload, .. } => payload[6..].to_vec(),
+            _ => unreachable!(),
+        };
+        let scan = |ll: &mut LinkLayer<MockConfig>, radio: &mut MockTransmitter| {
+            let mut scan_req = scanner.raw().to_vec();
+            scan_req.extend_from_slice(addr.raw());
+            let mut header =
+                Header::with_addresses(advertising::PduType::ScanReq, &scanner, Some(&addr));
+            header.set_payload_length(12);
+            let _ = ll.process_adv_packet(ll.timer.now(), radio, header, &scan_req, true);
+            last_payload(radio)
+        };
+        assert!(last_payload(&radio).ends_with(b"\x04\x09old"));
+        assert_eq!(scan(&mut ll, &mut radio), b"");
+
+        // The ongoing event still uses the old data...
+        ll.set_advertising_data(
+            &[AdStructure::CompleteLocalName("new")],
+            &[AdStructure::ShortenedLocalName("n")],
+        )
+        .unwrap();
+        assert_eq!(scan(&mut ll, &mut radio), b"");
+
+        // ...and the next one switches to the new data
+        ll.timer().set(Instant::from_raw_micros(0) + interval);
+        let _ = ll.update_timer(&mut radio);
+        assert!(last_payload(&radio).ends_with(b"\x04\x09new"));
+        assert_eq!(scan(&mut ll, &mut radio), b"\x02\x08n");
+
+        // Data that doesn't fit is rejected without affecting the current data
+        let long = [AdStructure::CompleteLocalName(
+            "a name that is much too long to fit",
+        )];
+        assert!(ll.set_advertising_data(&long, &[]).is_err());
+        ll.timer()
+            .set(Instant::from_raw_micros(0) + interval + interval);
+        let _ = ll.update_timer(&mut radio);
+        assert!(last_payload(&radio).ends_with(b"\x04\x09new"));
+    }
+
+    /// `MockConfig` with a radio that has a receive delay and a TX ramp-up time.
+    enum DelayConfig {}
+
+    impl Config for DelayConfig {
+        type Timer = MockTimer;
+        type Transmitter = MockTransmitter;
+        type Rng = MockRng;
+        type KeyStore = MockKeyStore;
+        type ChannelMapper = <MockConfig as Config>::ChannelMapper;
+
+        type PacketQueue = <MockConfig as Config>::PacketQueue;
+        type PacketProducer = <MockConfig as Config>::PacketProducer;
+        type PacketConsumer = <MockConfig as Config>::PacketConsumer;
+        type L2CAPBuffer = <MockConfig as Config>::L2CAPBuffer;
+
+        const RADIO_RX_DELAY: Duration = Duration::from_micros(10);
+        const RADIO_TX_DELAY: Duration = Duration::from_micros(40);
+    }
+
+    #[test]
+    fn radio_delays() {
+        fn advertise<C>() -> (LinkLayer<C>, MockTransmitter)
+        where
+            C: Config<
+                Timer = MockTimer,
+                Transmitter = MockTransmitter,
+                PacketProducer = MockProducer,
+                PacketConsumer = MockConsumer,
+            >,
+        {
+            let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+            let mut ll = LinkLayer::<C>::new(addr, MockTimer::new());
+            let mut radio = MockTransmitter::new();
+            let ((tx, _), (_, rx)) = queues();
+            ll.start_advertise(Duration::from_millis(100), &[], &mut radio, rx, tx)
+                .unwrap();
+            (ll, radio)
+        }
+
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let scanner = DeviceAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF], AddressKind::Public);
+        let mut scan_req = scanner.raw().to_vec();
+        scan_req.extend_from_slice(addr.raw());
+        let mut scan_header =
+            Header::with_addresses(advertising::PduType::ScanReq, &scanner, Some(&addr));
+        scan_header.set_payload_length(12);
+        let rx_end = Instant::from_raw_micros(1_000);
+
+        // Without delays, the response starts `T_IFS` after the request
+        let (mut ll, mut radio) = advertise::<MockConfig>();
+        let cmd = ll.process_adv_packet(rx_end, &mut radio, scan_header, &scan_req, true);
+        assert_eq!(cmd.turnaround, Some(rx_end + Duration::T_IFS));
+        let (header, payload) = connect_ind(&scanner, &addr);
+        let undelayed = ll.process_adv_packet(rx_end, &mut radio, header, &payload, true);
+
+        // The receive delay moves the end of the packet back, the TX delay moves the start of the
+        // transmission forward
+        let (mut ll, mut radio) = advertise::<DelayConfig>();
+        let cmd = ll.process_adv_packet(rx_end, &mut radio, scan_header, &scan_req, true);
+        assert_eq!(
+            cmd.turnaround,
+            Some(rx_end + Duration::from_micros(150 - 10 - 40))
+        );
+        let delayed = ll.process_adv_packet(rx_end, &mut radio, header, &payload, true);
+        match (undelayed.next_update, delayed.next_update) {
+            (NextUpdate::At(undelayed), NextUpdate::At(delayed)) => {
+                assert_eq!(undelayed - delayed, Duration::from_micros(10))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Builds a `CONNECT_IND` header and payload sent by `init` to `adv`.
+    fn connect_ind(init: &DeviceAddress, adv: &DeviceAddress) -> (Header, Vec<u8>) {
+        connect_ind_with_aa(init, adv, 0x5065_9A2B)
+    }
+
+    /// Builds a `CONNECT_IND` proposing `access_address` for the connection.
+    fn connect_ind_with_aa(
+        init: &DeviceAddress,
+        adv: &DeviceAddress,
+        access_address: u32,
+    ) -> (Header, Vec<u8>) {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(init.raw());
+        payload.extend_from_slice(adv.raw());
+        payload.extend_from_slice(&access_address.to_le_bytes()); // Access Address
+        payload.extend_from_slice(&[0x12, 0x34, 0x56]); // CRC init
+        payload.push(1); // WinSize
+        payload.extend_from_slice(&0u16.to_le_bytes()); // WinOffset
+        payload.extend_from_slice(&6u16.to_le_bytes()); // Interval
+        payload.extend_from_slice(&0u16.to_le_bytes()); // Latency
+        payload.extend_from_slice(&400u16.to_le_bytes()); // Timeout
+        payload.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x1f]); // Channel map
+        payload.push(7); // Hop increment and SCA
+
+        let mut header = Header::with_addresses(advertising::PduType::ConnectReq, init, Some(adv));
+        header.set_payload_length(payload.len() as u8);
+        (header, payload)
+    }
+
+    #[test]
+    fn ignores_requests_for_other_devices() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let init = DeviceAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF], AddressKind::Public);
+        let mut ll = LinkLayer::<MockConfig>::new(addr, MockTimer::new());
+        let mut radio = MockTransmitter::new();
+        let ((tx, _), (_, rx)) = queues();
+        ll.start_advertise(Duration::from_millis(100), &[], &mut radio, rx, tx)
+            .unwrap();
+        let sent = radio.sent.len();
+
+        // A different device address, and our address with the wrong address type
+        let others = [
+            DeviceAddress::new([6, 5, 4, 3, 2, 1], AddressKind::Random),
+            DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Public),
+        ];
+        for other in &others {
+            let mut scan_req = init.raw().to_vec();
+            scan_req.extend_from_slice(other.raw());
+            let mut header =
+                Header::with_addresses(advertising::PduType::ScanReq, &init, Some(other));
+            header.set_payload_length(12);
+            let cmd = ll.process_adv_packet(ll.timer.now(), &mut radio, header, &scan_req, true);
+            assert_eq!(cmd.turnaround, None);
+
+            let (header, payload) = connect_ind(&init, other);
+            let cmd = ll.process_adv_packet(ll.timer.now(), &mut radio, header, &payload, true);
+            assert_eq!(
+                cmd.radio.rx_params().map(|rx| rx.access_address),
+                Some(advertising::ACCESS_ADDRESS)
+            );
+            assert!(ll.is_advertising());
+        }
+        assert_eq!(radio.sent.len(), sent);
+
+        // Only a CONNECT_IND addressed at us creates the connection
+        let (header, payload) = connect_ind(&init, &addr);
+        let _ = ll.process_adv_packet(ll.timer.now(), &mut radio, header, &payload, true);
+        assert!(ll.is_connected());
+    }
+
+    #[test]
+    fn random_static_address() {
+        let mut rng = MockRng::new();
+        let ll = LinkLayer::<MockConfig>::with_random_static_address(&mut rng, MockTimer::new());
+        let addr = ll.device_address();
+        assert!(addr.is_random_static());
+
+        // A new boot generates a different address
+        let ll = LinkLayer::<MockConfig>::with_random_static_address(&mut rng, MockTimer::new());
+        assert_ne!(ll.device_address(), addr);
+    }
+
+    #[test]
+    fn allow_list_filters_requests() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let allowed = DeviceAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF], AddressKind::Public);
+        let other = DeviceAddress::new([0x11, 0x22, 0x33, 0x44, 0x55, 0x66], AddressKind::Public);
+        let mut ll = LinkLayer::<MockConfig>::new(addr, MockTimer::new());
+        let mut radio = MockTransmitter::new();
+        let mut allow_list = AllowList::new();
+        allow_list.add(allowed).unwrap();
+        ll.set_allow_list(Some(allow_list));
+        let ((tx, _), (_, rx)) = queues();
+        ll.start_advertise(Duration::from_millis(100), &[], &mut radio, rx, tx)
+            .unwrap();
+        let sent = radio.sent.len();
+
+        let scan = |ll: &mut LinkLayer<MockConfig>,
+                    radio: &mut MockTransmitter,
+                    scanner: DeviceAddress| {
+            let mut scan_req = scanner.raw().to_vec();
+            scan_req.extend_from_slice(addr.raw());
+            let mut header =
+                Header::with_addresses(advertising::PduType::ScanReq, &scanner, Some(&addr));
+            header.set_payload_length(12);
+            ll.process_adv_packet(ll.timer.now(), radio, header, &scan_req, true)
+        };
+
+        // Requests addressed at us by a device that isn't on the list are ignored
+        let cmd = scan(&mut ll, &mut radio, other);
+        assert_eq!(cmd.turnaround, None);
+        let (header, payload) = connect_ind(&other, &addr);
+        let _ = ll.process_adv_packet(ll.timer.now(), &mut radio, header, &payload, true);
+        assert!(ll.is_advertising());
+        assert_eq!(radio.sent.len(), sent);
+
+        // The device on the list can scan and connect
+        let cmd = scan(&mut ll, &mut radio, allowed);
+        assert!(cmd.turnaround.is_some());
+        assert_eq!(radio.sent.len(), sent + 1);
+        let (header, payload) = connect_ind(&allowed, &addr);
+        let _ = ll.process_adv_packet(ll.timer.now(), &mut radio, header, &payload, true);
+        assert!(ll.is_connected());
+    }
+
+    #[test]
+    fn rejects_advertising_access_address() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let init = DeviceAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF], AddressKind::Public);
+        let mut ll = LinkLayer::<MockConfig>::new(addr, MockTimer::new());
+        let mut radio = MockTransmitter::new();
+        let ((tx, _), (_, rx)) = queues();
+        ll.start_advertise(Duration::from_millis(100), &[], &mut radio, rx, tx)
+            .unwrap();
+
+        let (header, payload) = connect_ind_with_aa(&init, &addr, advertising::ACCESS_ADDRESS);
+        let cmd = ll.process_adv_packet(ll.timer.now(), &mut radio, header, &payload, true);
+        assert_eq!(
+            cmd.radio.rx_params().map(|rx| rx.access_address),
+            Some(advertising::ACCESS_ADDRESS)
+        );
+        assert!(ll.is_advertising());
+
+        // A valid request is still accepted afterwards
+        let (header, payload) = connect_ind(&init, &addr);
+        let _ = ll.process_adv_packet(ll.timer.now(), &mut radio, header, &payload, true);
+        assert!(ll.is_connected());
+    }
+
+    #[test]
+    fn unacknowledged_pdus_dropped() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let init = DeviceAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF], AddressKind::Public);
+        let mut ll = LinkLayer::<MockConfig>::new(addr, MockTimer::new());
+        let mut radio = MockTransmitter::new();
+        let ((rx_prod, _), (mut tx_prod, tx_cons)) = queues();
+        ll.start_advertise(
+            Duration::from_millis(100),
+            &[],
+            &mut radio,
+            tx_cons,
+            rx_prod,
+        )
+        .unwrap();
+
+        let (header, payload) = connect_ind(&init, &addr);
+        let _ = ll.process_adv_packet(ll.timer.now(), &mut radio, header, &payload, true);
+        let mut l2cap = L2CAPState::new(BleChannelMap::<NoAttributes, NoSecurity>::empty());
+        let ticket = l2cap
+            .tx(&mut tx_prod)
+            .att()
+            .unwrap()
+            .notify_raw(Handle::from_raw(0x0003), &[1, 2])
+            .unwrap();
+        assert_eq!(ll.tx_status(ticket), TxStatus::Pending);
+
+        // The master never shows up, so the notification is never delivered
+        let _cmd = ll.update_timer(&mut radio);
+        assert_eq!(ll.state(), LinkState::Standby);
+        assert_eq!(ll.tx_status(ticket), TxStatus::Dropped);
+    }
+
+    #[test]
+    fn state_transitions() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let init = DeviceAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF], AddressKind::Public);
+        let mut ll = LinkLayer::<MockConfig>::new(addr, MockTimer::new());
+        let mut radio = MockTransmitter::new();
+        assert_eq!(ll.state(), LinkState::Standby);
+        assert_eq!(ll.state().ensure_connected(), Err(NotConnected));
+
+        let ((tx, _), (_, rx)) = queues();
+        ll.start_advertise(Duration::from_millis(100), &[], &mut radio, rx, tx)
+            .unwrap();
+        assert_eq!(ll.state(), LinkState::Advertising);
+        assert_eq!(ll.state().ensure_connected(), Err(NotConnected));
+
+        let (header, payload) = connect_ind(&init, &addr);
+        let _ = ll.process_adv_packet(ll.timer.now(), &mut radio, header, &payload, true);
+        assert_eq!(ll.state(), LinkState::Connected(Role::Slave));
+        assert_eq!(ll.state().ensure_connected(), Ok(Role::Slave));
+
+        // The master never sends a packet in the transmit window, so the connection fails
+        let cmd = ll.update_timer(&mut radio);
+        assert!(cmd.radio.rx_params().is_none());
+        assert_eq!(ll.state(), LinkState::Standby);
+        assert_eq!(
+            ll.disconnect_reason(),
+            Some(DisconnectReason::FailedToEstablish)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "profiling")]
+    fn profiling_events() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let init = DeviceAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF], AddressKind::Public);
+        let mut ll = LinkLayer::<MockConfig>::new(addr, MockTimer::new());
+        let mut radio = MockTransmitter::new();
+        let ((tx, _), (_, rx)) = queues();
+        let at = |micros| Instant::from_raw_micros(micros);
+        take_events();
+
+        ll.start_advertise(Duration::from_millis(100), &[], &mut radio, rx, tx)
+            .unwrap();
+        ll.timer.set(at(100_000));
+        let _ = ll.update_timer(&mut radio);
+        assert_eq!(
+            take_events(),
+            [
+                (EventKind::Advertising, true, at(0)),
+                (EventKind::Advertising, false, at(100_000)),
+                (EventKind::Advertising, true, at(100_000)),
+            ]
+        );
+
+        // Connecting ends the advertising event
+        let (header, payload) = connect_ind(&init, &addr);
+        let _ = ll.process_adv_packet(at(100_500), &mut radio, header, &payload, true);
+        assert_eq!(
+            take_events(),
+            [(EventKind::Advertising, false, at(100_500))]
+        );
+
+        // An empty PDU from the master (80 µs on air) opens and closes a connection event
+        let empty = data::Header::new(data::Llid::DataCont);
+        let _ = ll.process_data_packet(at(103_080), &mut radio, empty, &[], true);
+        assert_eq!(
+            take_events(),
+            [
+                (EventKind::Connection, true, at(103_000)),
+                (EventKind::Connection, false, at(103_080)),
+            ]
+        );
+    }
+
+    #[test]
+    fn peer_address_after_connect() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let identity =
+            DeviceAddress::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF], AddressKind::Public);
+        let irk = privacy::IdentityResolvingKey::from_raw([0x11; 16]);
+        let mut resolving_list = privacy::ResolvingList::new();
+        resolving_list.add(identity, irk).unwrap();
+
+        let connect = |init: DeviceAddress| {
+            let mut ll = LinkLayer::<MockConfig>::new(addr, MockTimer::new());
+            ll.set_resolving_list(resolving_list.clone());
+            let mut radio = MockTransmitter::new();
+            let ((tx, _), (_, rx)) = queues();
+            ll.start_advertise(Duration::from_millis(100), &[], &mut radio, rx, tx)
+                .unwrap();
+
+            let (header, payload) = connect_ind(&init, &addr);
+            let _ = ll.process_adv_packet(ll.timer.now(), &mut radio, header, &payload, true);
+            ll.connection().unwrap().peer_address()
+        };
+
+        // An RPA of a bonded peer is resolved to its identity
+        let rpa = irk.generate_rpa(0x123456);
+        let peer = connect(rpa);
+        assert_eq!(peer.address(), rpa);
+        assert_eq!(peer.identity(), Some(identity));
+        assert!(peer.is_resolved());
+
+        // An unknown RPA is kept, but its identity is unknown
+        let stranger = privacy::IdentityResolvingKey::from_raw([0x22; 16]).generate_rpa(0x123456);
+        let peer = connect(stranger);
+        assert_eq!(peer.address(), stranger);
+        assert_eq!(peer.identity(), None);
+        assert!(peer.is_unresolved_private());
+
+        // Identity addresses are used as-is
+        let peer = connect(identity);
+        assert_eq!(peer.address(), identity);
+        assert_eq!(peer.identity(), Some(identity));
+        assert!(!peer.is_resolved());
+        assert!(!peer.is_unresolved_private());
+    }
+}
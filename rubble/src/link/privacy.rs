@@ -0,0 +1,323 @@
+//! Link-Layer privacy using Resolvable Private Addresses.
+//!
+//! A device using privacy periodically changes its device address to a new *Resolvable Private
+//! Address* (RPA). An RPA can only be associated with the device's *identity address* by a device
+//! that knows its *Identity Resolving Key* (IRK). IRKs are exchanged during bonding.
+//!
+//! An RPA is a random device address consisting of a 24-bit random number `prand` (with the 2 most
+//! significant bits set to `0b01`) and a 24-bit `hash`, computed from `prand` using the IRK:
+//!
+//! ```notrust
+//! LSB                                MSB
+//! +------------------+------------------+
+//! |       hash       |      prand       |
+//! |    (24 bits)     |    (24 bits)     |
+//! +------------------+------------------+
+//! ```
+
+use {
+    crate::{
+        crypto,
+        link::{filter::AddressFilter, AddressKind, DeviceAddress},
+        Error,
+    },
+    core::fmt,
+    heapless::{consts::U8, Vec},
+};
+
+/// A 128-bit Identity Resolving Key (IRK).
+///
+/// IRKs are used to generate and resolve Resolvable Private Addresses.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct IdentityResolvingKey([u8; 16]);
+
+impl IdentityResolvingKey {
+    /// Creates an IRK from its raw bytes, most significant octet first.
+    pub fn from_raw(raw: [u8; 16]) -> Self {
+        IdentityResolvingKey(raw)
+    }
+
+    /// Returns the raw bytes of this IRK, most significant octet first.
+    pub fn raw(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    /// Generates a Resolvable Private Address from this IRK and a random number.
+    ///
+    /// Only the least significant 22 bits of `prand` are used, the 2 bits above them will be set to
+    /// `0b01` to mark the address as resolvable. `prand` should come from a proper random number
+    /// generator, and its random part must not be all 0s or all 1s.
+    pub fn generate_rpa(&self, prand: u32) -> DeviceAddress {
+        let prand = (prand & 0x3F_FFFF) | 0x40_0000;
+        let hash = crypto::ah(&self.0, prand);
+
+        let mut bytes = [0; 6];
+        bytes[..3].copy_from_slice(&hash.to_le_bytes()[..3]);
+        bytes[3..].copy_from_slice(&prand.to_le_bytes()[..3]);
+        DeviceAddress::new(bytes, AddressKind::Random)
+    }
+
+    /// Returns whether `address` is a Resolvable Private Address that was generated from this IRK.
+    pub fn resolves(&self, address: DeviceAddress) -> bool {
+        if !address.is_resolvable_private() {
+            return false;
+        }
+
+        let raw = address.raw();
+        let hash = u32::from_le_bytes([raw[0], raw[1], raw[2], 0]);
+        let prand = u32::from_le_bytes([raw[3], raw[4], raw[5], 0]);
+        crypto::ah(&self.0, prand) == hash
+    }
+}
+
+/// Does not print the key itself.
+impl fmt::Debug for IdentityResolvingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("IdentityResolvingKey(..)")
+    }
+}
+
+/// A fixed-capacity list mapping peer identity addresses to their Identity Resolving Keys.
+///
+/// This is used to recognize bonded peers that use Resolvable Private Addresses.
+#[derive(Debug, Clone, Default)]
+pub struct ResolvingList {
+    entries: Vec<(DeviceAddress, IdentityResolvingKey), U8>,
+}
+
+impl ResolvingList {
+    /// The maximum number of entries a `ResolvingList` can hold.
+    pub const CAPACITY: usize = 8;
+
+    /// Creates an empty resolving list.
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds a peer with identity address `identity` and IRK `irk` to the list.
+    ///
+    /// If `identity` is already on the list, its IRK is replaced. Returns `Error::Eof` when the
+    /// list is full.
+    pub fn add(&mut self, identity: DeviceAddress, irk: IdentityResolvingKey) -> Result<(), Error> {
+        if let Some(entry) = self.entries.iter_mut().find(|(id, _)| *id == identity) {
+            entry.1 = irk;
+            return Ok(());
+        }
+
+        self.entries.push((identity, irk)).map_err(|_| Error::Eof)
+    }
+
+    /// Removes the peer with identity address `identity` from the list.
+    ///
+    /// Returns whether the peer was on the list.
+    pub fn remove(&mut self, identity: DeviceAddress) -> bool {
+        if let Some(index) = self.entries.iter().position(|(id, _)| *id == identity) {
+            self.entries.swap_remove(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Removes all entries from the list.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the number of entries on the list.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the list is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Tries to resolve `address` to the identity address of a peer on the list.
+    ///
+    /// Returns `None` if `address` isn't a Resolvable Private Address, or if none of the stored
+    /// IRKs resolve it.
+    pub fn resolve(&self, address: DeviceAddress) -> Option<DeviceAddress> {
+        if !address.is_resolvable_private() {
+            return None;
+        }
+
+        self.entries
+            .iter()
+            .find(|(_, irk)| irk.resolves(address))
+            .map(|(identity, _)| *identity)
+    }
+
+    /// Resolves the address of a peer, keeping both the address and its resolved identity.
+    pub fn resolve_peer(&self, address: DeviceAddress) -> PeerAddress {
+        PeerAddress {
+            address,
+            resolved: self.resolve(address),
+        }
+    }
+}
+
+/// The address of a peer device, along with its identity address if it could be determined.
+///
+/// Obtained from `ResolvingList::resolve_peer`, or via `From<DeviceAddress>` if no resolution was
+/// attempted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PeerAddress {
+    address: DeviceAddress,
+    /// Identity address the RPA in `address` resolved to.
+    resolved: Option<DeviceAddress>,
+}
+
+impl PeerAddress {
+    /// Returns the address the peer used over the air.
+    pub fn address(&self) -> DeviceAddress {
+        self.address
+    }
+
+    /// Returns the identity address of the peer.
+    ///
+    /// If the peer used a Resolvable Private Address, this is the identity address it resolved to,
+    /// or `None` if it could not be resolved. Public and static addresses are identity addresses
+    /// themselves and are returned unchanged.
+    pub fn identity(&self) -> Option<DeviceAddress> {
+        if self.address.is_resolvable_private() {
+            self.resolved
+        } else {
+            Some(self.address)
+        }
+    }
+
+    /// Returns whether the peer used a Resolvable Private Address that was resolved to its
+    /// identity address.
+    pub fn is_resolved(&self) -> bool {
+        self.resolved.is_some()
+    }
+
+    /// Returns whether the peer used a Resolvable Private Address that could not be resolved.
+    ///
+    /// The identity of such a peer is unknown, eg. because it is not bonded.
+    pub fn is_unresolved_private(&self) -> bool {
+        self.address.is_resolvable_private() && self.resolved.is_none()
+    }
+}
+
+impl From<DeviceAddress> for PeerAddress {
+    fn from(address: DeviceAddress) -> Self {
+        Self {
+            address,
+            resolved: None,
+        }
+    }
+}
+
+/// An `AddressFilter` that resolves Resolvable Private Addresses before consulting another filter.
+///
+/// Addresses that can be resolved using the `ResolvingList` are replaced by the peer's identity
+/// address, all other addresses are passed to the inner filter unchanged. This allows putting the
+/// identity addresses of bonded devices on an allow list, even when they use privacy.
+pub struct ResolvingFilter<'a, F: AddressFilter> {
+    filter: &'a F,
+    resolving_list: &'a ResolvingList,
+}
+
+impl<'a, F: AddressFilter> ResolvingFilter<'a, F> {
+    /// Creates a filter that resolves addresses using `resolving_list` before checking them against
+    /// `filter`.
+    pub fn new(filter: &'a F, resolving_list: &'a ResolvingList) -> Self {
+        Self {
+            filter,
+            resolving_list,
+        }
+    }
+}
+
+impl<F: AddressFilter> AddressFilter for ResolvingFilter<'_, F> {
+    fn matches(&self, address: DeviceAddress) -> bool {
+        let identity = self.resolving_list.resolve(address).unwrap_or(address);
+        self.filter.matches(identity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::filter::AllowList;
+
+    const IRK: [u8; 16] = [
+        0xec, 0x02, 0x34, 0xa3, 0x57, 0xc8, 0xad, 0x05, 0x34, 0x10, 0x10, 0xa6, 0x0a, 0x39, 0x7d,
+        0x9b,
+    ];
+
+    #[test]
+    fn generate_and_resolve() {
+        let irk = IdentityResolvingKey::from_raw(IRK);
+        let rpa = irk.generate_rpa(0x308194);
+
+        // Spec sample data: prand = 0x708194 hashes to 0x0dfbaa
+        assert_eq!(rpa.raw(), &[0xaa, 0xfb, 0x0d, 0x94, 0x81, 0x70]);
+        assert!(rpa.is_resolvable_private());
+        assert!(irk.resolves(rpa));
+
+        let other = IdentityResolvingKey::from_raw([0x42; 16]);
+        assert!(!other.resolves(rpa));
+    }
+
+    #[test]
+    fn resolved_rpa_passes_allow_list() {
+        let identity = DeviceAddress::new([1, 2, 3, 4, 5, 0xC6], AddressKind::Random);
+        let irk = IdentityResolvingKey::from_raw(IRK);
+
+        let mut allow_list = AllowList::new();
+        allow_list.add(identity).unwrap();
+        let mut resolving_list = ResolvingList::new();
+        resolving_list.add(identity, irk).unwrap();
+        let filter = ResolvingFilter::new(&allow_list, &resolving_list);
+
+        // The identity address itself is allowed
+        assert!(filter.matches(identity));
+
+        // So are RPAs generated from the bonded peer's IRK
+        for &prand in &[0x123456, 0x3ABCDE, 0x000001] {
+            let rpa = irk.generate_rpa(prand);
+            assert!(!allow_list.matches(rpa));
+            assert!(filter.matches(rpa));
+        }
+
+        // RPAs from unknown IRKs are not
+        let stranger = IdentityResolvingKey::from_raw([0x42; 16]).generate_rpa(0x123456);
+        assert!(!filter.matches(stranger));
+    }
+
+    #[test]
+    fn peer_address() {
+        let identity = DeviceAddress::new([1, 2, 3, 4, 5, 0xC6], AddressKind::Random);
+        let irk = IdentityResolvingKey::from_raw(IRK);
+        let mut resolving_list = ResolvingList::new();
+        resolving_list.add(identity, irk).unwrap();
+
+        let rpa = irk.generate_rpa(0x123456);
+        let peer = resolving_list.resolve_peer(rpa);
+        assert_eq!(peer.address(), rpa);
+        assert_eq!(peer.identity(), Some(identity));
+        assert!(peer.is_resolved());
+        assert!(!peer.is_unresolved_private());
+
+        let stranger = IdentityResolvingKey::from_raw([0x42; 16]).generate_rpa(0x123456);
+        let peer = resolving_list.resolve_peer(stranger);
+        assert_eq!(peer.address(), stranger);
+        assert_eq!(peer.identity(), None);
+        assert!(!peer.is_resolved());
+        assert!(peer.is_unresolved_private());
+
+        // Identity addresses don't need resolving
+        let public = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Public);
+        for &peer in &[resolving_list.resolve_peer(public), public.into()] {
+            assert_eq!(peer.identity(), Some(public));
+            assert!(!peer.is_resolved());
+            assert!(!peer.is_unresolved_private());
+        }
+    }
+}
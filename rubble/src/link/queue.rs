@@ -71,6 +71,15 @@ pub trait Producer {
     /// passed.
     fn free_space(&self) -> u8;
 
+    /// Returns the number of packets currently in the queue.
+    ///
+    /// Like `free_space`, this is only a snapshot, since the consumer half might remove a packet
+    /// immediately after this function returns.
+    fn occupancy(&self) -> usize;
+
+    /// Returns the maximum number of packets the queue can hold.
+    fn capacity(&self) -> usize;
+
     /// Enqueues a PDU with known size using a closure.
     ///
     /// *This is an object-safe method complemented by its generic counterpart `produce_with`. Only
@@ -107,7 +116,7 @@ pub trait Producer {
 
         let mut f = Some(f);
         let mut r = None;
-        self.produce_dyn(payload_bytes, &mut |bytes| {
+        let result = self.produce_dyn(payload_bytes, &mut |bytes| {
             let f = f.take().unwrap();
             let result = f(bytes);
             if let Ok(llid) = result {
@@ -117,10 +126,13 @@ pub trait Producer {
                 r = Some(result.map(|_| ()));
                 Err(Error::InvalidValue)
             }
-        })
-        .ok();
+        });
 
-        r.unwrap()
+        match r {
+            Some(r) => r,
+            // `f` wasn't called because the queue is out of space
+            None => Err(result.err().unwrap_or(Error::Eof).into()),
+        }
     }
 }
 
@@ -129,6 +141,15 @@ pub trait Consumer {
     /// Returns whether there is a packet to dequeue.
     fn has_data(&self) -> bool;
 
+    /// Returns the number of packets currently in the queue.
+    ///
+    /// This is only a snapshot, since the producer half might add a packet immediately after this
+    /// function returns.
+    fn occupancy(&self) -> usize;
+
+    /// Returns the maximum number of packets the queue can hold.
+    fn capacity(&self) -> usize;
+
     /// Passes the next raw packet in the queue to a closure.
     ///
     /// The closure returns a `Consume` value to indicate whether the packet should remain in the
@@ -200,6 +221,11 @@ impl<T> Consume<T> {
             result,
         }
     }
+
+    /// Returns whether to consume the packet, and the result to return.
+    pub(crate) fn into_parts(self) -> (bool, Result<T, Error>) {
+        (self.consume, self.result)
+    }
 }
 
 /// A simple packet queue that can hold a single packet.
@@ -248,6 +274,18 @@ impl<'a> Producer for SimpleProducer<'a> {
         }
     }
 
+    fn occupancy(&self) -> usize {
+        if self.inner.ready() {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        1
+    }
+
     fn produce_dyn(
         &mut self,
         payload_bytes: u8,
@@ -284,6 +322,18 @@ impl<'a> Consumer for SimpleConsumer<'a> {
         self.inner.ready()
     }
 
+    fn occupancy(&self) -> usize {
+        if self.inner.ready() {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        1
+    }
+
     fn consume_raw_with<R>(
         &mut self,
         f: impl FnOnce(data::Header, &[u8]) -> Consume<R>,
@@ -320,6 +370,11 @@ impl<'a> Consumer for SimpleConsumer<'a> {
 pub fn run_tests(queue: impl PacketQueue) {
     fn assert_empty(c: &mut impl Consumer) {
         assert!(!c.has_data(), "empty queue `has_data()` returned true");
+        assert_eq!(
+            c.occupancy(),
+            0,
+            "empty queue `occupancy()` returned non-zero"
+        );
 
         let err = c
             .consume_raw_with(|_, _| -> Consume<()> {
@@ -348,6 +403,17 @@ pub fn run_tests(queue: impl PacketQueue) {
 
     let (mut p, mut c) = queue.split();
     assert_empty(&mut c);
+    assert_eq!(
+        p.occupancy(),
+        0,
+        "empty queue `occupancy()` returned non-zero"
+    );
+    assert!(p.capacity() >= 1, "queue can't hold any packets");
+    assert_eq!(
+        p.capacity(),
+        c.capacity(),
+        "producer and consumer report different capacities"
+    );
 
     let free_space = p.free_space();
     assert!(
@@ -373,6 +439,16 @@ pub fn run_tests(queue: impl PacketQueue) {
         c.has_data(),
         "consumer's `has_data()` still false after enqueuing packet"
     );
+    assert_eq!(
+        p.occupancy(),
+        1,
+        "producer's `occupancy()` wrong after enqueuing packet"
+    );
+    assert_eq!(
+        c.occupancy(),
+        1,
+        "consumer's `occupancy()` wrong after enqueuing packet"
+    );
 
     // Peek at the packet
     c.consume_raw_with(|header, data| -> Consume<()> {
@@ -451,3 +527,11 @@ pub fn run_tests(queue: impl PacketQueue) {
 fn simple_queue() {
     run_tests(&mut SimpleQueue::new());
 }
+
+#[test]
+fn mock_queue() {
+    use super::mock::MockQueue;
+
+    run_tests(MockQueue::with_capacity(1));
+    run_tests(MockQueue::with_capacity(4));
+}
@@ -1,16 +1,87 @@
-use crate::{
-    bytes::ToBytes,
-    config::Config,
-    l2cap::{L2CAPState, L2CAPStateTx},
-    link::{
-        data::{Llid, Pdu},
-        llcp::ControlPdu,
-        queue::{Consume, Consumer, Producer},
+use {
+    crate::{
+        bytes::{ByteReader, ByteWriter, FromBytes, ToBytes},
+        config::Config,
+        l2cap::{self, L2CAPState, L2CAPStateTx},
+        link::{
+            data::{Llid, Pdu},
+            encryption::ConnectionSecurity,
+            llcp::ControlPdu,
+            queue::{Consume, Consumer, Producer},
+        },
+        time::Instant,
+        utils::HexSlice,
+        Error,
     },
-    utils::HexSlice,
-    Error,
+    core::cmp,
 };
 
+/// An event reported by the Link-Layer to the `Responder` through the RX queue.
+///
+/// Reports are carried in PDUs with `Llid::Reserved`, which are never sent over the air. The first
+/// payload Byte identifies the event.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum LinkReport {
+    /// A connection was established.
+    Connected,
+
+    /// The connection was closed.
+    Disconnected,
+
+    /// Encryption was started, changing the security level of the connection.
+    Security(ConnectionSecurity),
+
+    /// Time of a connection event, reported periodically.
+    Time(Instant),
+}
+
+impl LinkReport {
+    /// Returns the size of the encoded report in Bytes.
+    pub(crate) fn encoded_size(&self) -> u8 {
+        match self {
+            LinkReport::Connected | LinkReport::Disconnected => 1,
+            LinkReport::Security(_) => 2,
+            LinkReport::Time(_) => 5,
+        }
+    }
+}
+
+impl ToBytes for LinkReport {
+    fn to_bytes(&self, writer: &mut ByteWriter<'_>) -> Result<(), Error> {
+        match self {
+            LinkReport::Connected => writer.write_u8(0),
+            LinkReport::Disconnected => writer.write_u8(1),
+            LinkReport::Security(level) => {
+                writer.write_u8(2)?;
+                writer.write_u8(level.to_raw())
+            }
+            LinkReport::Time(instant) => {
+                writer.write_u8(3)?;
+                writer.write_u32_le(instant.raw_micros())
+            }
+        }
+    }
+}
+
+impl FromBytes<'_> for LinkReport {
+    fn from_bytes(bytes: &mut ByteReader<'_>) -> Result<Self, Error> {
+        let report = match bytes.read_u8()? {
+            0 => LinkReport::Connected,
+            1 => LinkReport::Disconnected,
+            2 => LinkReport::Security(
+                ConnectionSecurity::from_raw(bytes.read_u8()?).ok_or(Error::InvalidValue)?,
+            ),
+            3 => LinkReport::Time(Instant::from_raw_micros(bytes.read_u32_le()?)),
+            _ => return Err(Error::InvalidValue),
+        };
+        if bytes.is_empty() {
+            Ok(report)
+        } else {
+            Err(Error::IncompleteParse)
+        }
+    }
+}
+
 /// Data channel packet processor.
 ///
 /// This hooks up to the Real-Time part of the LE Link Layer via a packet queue. This part can run
@@ -23,16 +94,38 @@ use crate::{
 pub struct Responder<C: Config> {
     tx: C::PacketProducer,
     rx: Option<C::PacketConsumer>,
-    l2cap: L2CAPState<C::ChannelMapper>,
+    l2cap: L2CAPState<C::ChannelMapper, C::L2CAPBuffer>,
 }
 
 impl<C: Config> Responder<C> {
     /// Creates a new packet processor hooked up to data channel packet queues.
+    ///
+    /// The ATT server of `l2cap` is configured to support an `ATT_MTU` of up to
+    /// `C::MAX_ATT_MTU`, limited to the largest L2CAP payload the link can carry. That is
+    /// `l2cap::MAX_PAYLOAD_LEN`, or less if the fragments of such a message don't all fit into the
+    /// TX queue at once, or if it doesn't fit into the message buffers of `l2cap`.
     pub fn new(
         tx: C::PacketProducer,
         rx: C::PacketConsumer,
-        l2cap: L2CAPState<C::ChannelMapper>,
+        mut l2cap: L2CAPState<C::ChannelMapper, C::L2CAPBuffer>,
     ) -> Self {
+        let max_mtu = cmp::min(
+            C::MAX_ATT_MTU,
+            cmp::min(
+                l2cap::max_payload_len(tx.capacity()),
+                l2cap.max_buffered_payload(),
+            ),
+        );
+        if max_mtu < C::MAX_ATT_MTU {
+            warn!(
+                "MAX_ATT_MTU of {} exceeds link capacity, using {}",
+                C::MAX_ATT_MTU,
+                max_mtu
+            );
+        }
+        l2cap.att_server().set_max_mtu(max_mtu).unwrap();
+        // The Link-Layer reports when a connection is established
+        l2cap.att_server().set_connected(false);
         Self {
             tx,
             rx: Some(rx),
@@ -40,6 +133,19 @@ impl<C: Config> Responder<C> {
         }
     }
 
+    /// Returns the queue of packets waiting to be sent by the Link-Layer.
+    ///
+    /// Its `occupancy` and `capacity` tell how many more PDUs (eg. notifications) can be sent
+    /// before the queue is full.
+    pub fn tx_queue(&self) -> &C::PacketProducer {
+        &self.tx
+    }
+
+    /// Returns the queue of packets received by the Link-Layer and waiting to be processed.
+    pub fn rx_queue(&self) -> &C::PacketConsumer {
+        self.rx.as_ref().unwrap()
+    }
+
     /// Returns `true` when this responder has work to do.
     ///
     /// If this returns `true`, `process` may be called to process incoming packets and send
@@ -50,50 +156,110 @@ impl<C: Config> Responder<C> {
 
     /// Processes a single incoming packet in the packet queue.
     ///
-    /// Returns `Error::Eof` if there are no incoming packets in the RX queue.
+    /// Besides data channel PDUs, the Link-Layer uses the queue to report connection state changes
+    /// and changes of the connection's security level, which are applied to the ATT server.
+    ///
+    /// Returns `Error::Eof` if there are no incoming packets in the RX queue. Returns
+    /// `Error::ConfirmationTimeout` (once) when the client failed to confirm an indication in
+    /// time. The ATT server stops communicating in that case, and the application should close the
+    /// connection.
     pub fn process_one(&mut self) -> Result<(), Error> {
         self.with_rx(|rx, this| {
-            rx.consume_pdu_with(|_, pdu| match pdu {
-                Pdu::Control { data } => {
-                    // Also see:
-                    // https://github.com/jonas-schievink/rubble/issues/26
-
-                    let pdu = data.read();
-                    info!("<- LL Control PDU: {:?}", pdu);
-                    let response = match pdu {
-                        // These PDUs are handled by the real-time code:
-                        ControlPdu::FeatureReq { .. } | ControlPdu::VersionInd { .. } => {
-                            unreachable!("LLCPDU not handled by LL");
-                        }
-                        _ => ControlPdu::UnknownRsp {
-                            unknown_type: pdu.opcode(),
-                        },
-                    };
-                    info!("-> Response: {:?}", response);
-
-                    // Consume the LL Control PDU iff we can fit the response in the TX buffer:
-                    Consume::on_success(this.tx.produce_with(
-                        response.encoded_size().into(),
-                        |writer| {
-                            response.to_bytes(writer)?;
-                            Ok(Llid::Control)
-                        },
-                    ))
+            rx.consume_raw_with(|header, raw| {
+                if header.llid() == Llid::Reserved {
+                    return this.process_link_report(raw);
                 }
-                Pdu::DataStart { message } => {
-                    info!("L2start: {:?}", HexSlice(message));
-                    this.l2cap().process_start(message)
-                }
-                Pdu::DataCont { message } => {
-                    info!("L2cont {:?}", HexSlice(message));
-                    this.l2cap().process_cont(message)
+
+                match Pdu::parse(header, raw) {
+                    Ok(pdu) => this.process_pdu(pdu),
+                    Err(e) => Consume::always(Err(e)),
                 }
             })
         })
     }
 
+    /// Processes a data channel PDU received by the Link-Layer.
+    fn process_pdu(&mut self, pdu: Pdu<'_, &[u8]>) -> Consume<()> {
+        match pdu {
+            Pdu::Control { data } => {
+                // Also see:
+                // https://github.com/jonas-schievink/rubble/issues/26
+
+                let pdu = data.read();
+                info!("<- LL Control PDU: {:?}", pdu);
+                let response = match pdu {
+                    // These PDUs are handled by the real-time code:
+                    ControlPdu::FeatureReq { .. } | ControlPdu::VersionInd { .. } => {
+                        unreachable!("LLCPDU not handled by LL");
+                    }
+                    _ => ControlPdu::UnknownRsp {
+                        unknown_type: pdu.opcode(),
+                    },
+                };
+                info!("-> Response: {:?}", response);
+
+                // Consume the LL Control PDU iff we can fit the response in the TX buffer:
+                let result = self
+                    .tx
+                    .produce_with(response.encoded_size().into(), |writer| {
+                        response.to_bytes(writer)?;
+                        Ok(Llid::Control)
+                    });
+                if result.is_ok() {
+                    // The response occupies a slot in the TX queue, like L2CAP messages
+                    self.l2cap.record_sent();
+                }
+                Consume::on_success(result)
+            }
+            Pdu::DataStart { message } => {
+                info!("L2start: {:?}", HexSlice(message));
+                self.l2cap().process_start(message)
+            }
+            Pdu::DataCont { message } => {
+                info!("L2cont {:?}", HexSlice(message));
+                self.l2cap().process_cont(message)
+            }
+        }
+    }
+
+    /// Processes an event reported by the Link-Layer and passes it on to the ATT server.
+    ///
+    /// The security level is used to enforce attribute permissions, and the time of connection
+    /// events to check whether the client failed to confirm an indication in time.
+    fn process_link_report(&mut self, raw: &[u8]) -> Consume<()> {
+        let report = match LinkReport::from_bytes(&mut ByteReader::new(raw)) {
+            Ok(report) => report,
+            Err(e) => return Consume::always(Err(e)),
+        };
+
+        if let LinkReport::Connected = report {
+            // Restart the `TxTicket`s in sync with the new connection's acknowledgement counter
+            let queued = self.tx.occupancy() as u32;
+            self.l2cap.reset_connection(queued);
+        }
+
+        let server = self.l2cap.att_server();
+        match report {
+            LinkReport::Connected => server.set_connected(true),
+            LinkReport::Disconnected => server.set_connected(false),
+            LinkReport::Security(level) => {
+                info!("security level changed to {:?}", level);
+                server.set_security_level(level);
+            }
+            LinkReport::Time(now) => {
+                // The server stops sending ATT PDUs on its own, the app should disconnect
+                if server.check_confirmation_timeout(now).is_err() {
+                    return Consume::always(Err(Error::ConfirmationTimeout));
+                }
+            }
+        }
+        Consume::always(Ok(()))
+    }
+
     /// Obtains access to the L2CAP instance.
-    pub fn l2cap(&mut self) -> L2CAPStateTx<'_, C::ChannelMapper, C::PacketProducer> {
+    pub fn l2cap(
+        &mut self,
+    ) -> L2CAPStateTx<'_, C::ChannelMapper, C::PacketProducer, C::L2CAPBuffer> {
         self.l2cap.tx(&mut self.tx)
     }
 
@@ -108,3 +274,353 @@ impl<C: Config> Responder<C> {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            att::{Handle, NoAttributes, NotifyError, CONFIRMATION_TIMEOUT},
+            l2cap::BleChannelMap,
+            link::{encryption::NoKeys, mock::*, queue::*, ConnectionHandle},
+            security::NoSecurity,
+        },
+    };
+
+    enum LargeMtuConfig {}
+
+    impl Config for LargeMtuConfig {
+        type Timer = MockTimer;
+        type Transmitter = MockTransmitter;
+        type Rng = MockRng;
+        type KeyStore = NoKeys;
+        type ChannelMapper = BleChannelMap<NoAttributes, NoSecurity>;
+
+        type PacketQueue = MockQueue;
+        type PacketProducer = MockProducer;
+        type PacketConsumer = MockConsumer;
+        type L2CAPBuffer = [u8; l2cap::MAX_MESSAGE_LEN];
+
+        const MAX_ATT_MTU: u16 = 247;
+    }
+
+    /// `LargeMtuConfig` with message buffers for an `ATT_MTU` of 100.
+    enum SmallBufferConfig {}
+
+    impl Config for SmallBufferConfig {
+        type Timer = MockTimer;
+        type Transmitter = MockTransmitter;
+        type Rng = MockRng;
+        type KeyStore = NoKeys;
+        type ChannelMapper = BleChannelMap<NoAttributes, NoSecurity>;
+
+        type PacketQueue = MockQueue;
+        type PacketProducer = MockProducer;
+        type PacketConsumer = MockConsumer;
+        type L2CAPBuffer = [u8; 104];
+
+        const MAX_ATT_MTU: u16 = 247;
+    }
+
+    #[test]
+    fn max_att_mtu_from_config() {
+        let ((tx, _), (_, rx)) = queues();
+        let mut responder =
+            Responder::<MockConfig>::new(tx, rx, L2CAPState::new(BleChannelMap::empty()));
+        assert_eq!(responder.l2cap().att_server().max_mtu(), 23);
+
+        // Clamped to what fits into the TX queue at once
+        let ((tx, _), (_, rx)) = queues();
+        let mut responder =
+            Responder::<LargeMtuConfig>::new(tx, rx, L2CAPState::new(BleChannelMap::empty()));
+        assert_eq!(responder.l2cap().att_server().max_mtu(), 23);
+
+        let ((tx, _), (_, rx)) = queues_with_capacity(4);
+        let mut responder =
+            Responder::<LargeMtuConfig>::new(tx, rx, L2CAPState::new(BleChannelMap::empty()));
+        assert_eq!(responder.l2cap().att_server().max_mtu(), 4 * 27 - 4);
+
+        // Large queues support the full `MAX_ATT_MTU`
+        let ((tx, _), (_, rx)) = queues_with_capacity(10);
+        let mut responder =
+            Responder::<LargeMtuConfig>::new(tx, rx, L2CAPState::new(BleChannelMap::empty()));
+        assert_eq!(responder.l2cap().att_server().max_mtu(), 247);
+
+        // Clamped to what fits into the message buffers
+        let ((tx, _), (_, rx)) = queues_with_capacity(10);
+        let l2cap = L2CAPState::with_buffers(
+            ConnectionHandle::SINGLE,
+            BleChannelMap::empty(),
+            [0; 104],
+            [0; 104],
+        );
+        let mut responder = Responder::<SmallBufferConfig>::new(tx, rx, l2cap);
+        assert_eq!(responder.l2cap().att_server().max_mtu(), 100);
+    }
+
+    /// Puts a raw report into the RX queue of the `Responder`.
+    fn report_raw(ll_rx: &mut impl Producer, raw: &[u8]) {
+        ll_rx
+            .produce_with(raw.len() as u8, |writer| -> Result<_, Error> {
+                writer.write_slice(raw)?;
+                Ok(Llid::Reserved)
+            })
+            .unwrap();
+    }
+
+    /// Puts a data channel PDU received from the master into the RX queue of the `Responder`.
+    fn receive(ll_rx: &mut impl Producer, llid: Llid, raw: &[u8]) {
+        ll_rx
+            .produce_with(raw.len() as u8, |writer| -> Result<_, Error> {
+                writer.write_slice(raw)?;
+                Ok(llid)
+            })
+            .unwrap();
+    }
+
+    /// Reports `report` to the `Responder`, like the Link-Layer does.
+    fn report(ll_rx: &mut impl Producer, report: LinkReport) {
+        let mut buf = [0; 5];
+        let mut writer = ByteWriter::new(&mut buf);
+        report.to_bytes(&mut writer).unwrap();
+        let len = writer.bytes_written();
+        assert_eq!(len, usize::from(report.encoded_size()));
+        report_raw(ll_rx, &buf[..len]);
+    }
+
+    #[test]
+    fn security_level_from_link_layer() {
+        let ((tx, _), (mut ll_rx, rx)) = queues();
+        let mut responder =
+            Responder::<MockConfig>::new(tx, rx, L2CAPState::new(BleChannelMap::empty()));
+
+        report(
+            &mut ll_rx,
+            LinkReport::Security(ConnectionSecurity::Authenticated),
+        );
+        assert_eq!(responder.process_one(), Ok(()));
+        assert_eq!(
+            responder.l2cap().att_server().security_level(),
+            ConnectionSecurity::Authenticated
+        );
+
+        // Malformed reports are dropped
+        report_raw(&mut ll_rx, &[2, 0xFF]);
+        assert_eq!(responder.process_one(), Err(Error::InvalidValue));
+        report_raw(&mut ll_rx, &[2]);
+        assert_eq!(responder.process_one(), Err(Error::Eof));
+        report_raw(&mut ll_rx, &[0, 0]);
+        assert_eq!(responder.process_one(), Err(Error::IncompleteParse));
+        report_raw(&mut ll_rx, &[0xFF]);
+        assert_eq!(responder.process_one(), Err(Error::InvalidValue));
+        assert!(!responder.has_work());
+        assert_eq!(
+            responder.l2cap().att_server().security_level(),
+            ConnectionSecurity::Authenticated
+        );
+    }
+
+    #[test]
+    fn notify_requires_connection() {
+        let ((tx, mut ll_tx), (mut ll_rx, rx)) = queues();
+        let mut responder =
+            Responder::<MockConfig>::new(tx, rx, L2CAPState::new(BleChannelMap::empty()));
+        let handle = Handle::from_raw(0x0003);
+
+        // Nothing is sent before the Link-Layer reports a connection
+        let mut l2cap = responder.l2cap();
+        let att = l2cap.att().unwrap();
+        assert_eq!(att.notify_raw(handle, &[1]), Err(NotifyError::NotConnected));
+        assert!(!ll_tx.has_data());
+
+        report(&mut ll_rx, LinkReport::Connected);
+        responder.process_one().unwrap();
+        let mut l2cap = responder.l2cap();
+        let att = l2cap.att().unwrap();
+        assert!(att.notify_raw(handle, &[2]).is_ok());
+        ll_tx
+            .consume_raw_with(|_, _| Consume::always(Ok(())))
+            .unwrap();
+
+        report(&mut ll_rx, LinkReport::Disconnected);
+        responder.process_one().unwrap();
+        let mut l2cap = responder.l2cap();
+        let att = l2cap.att().unwrap();
+        assert_eq!(att.notify_raw(handle, &[3]), Err(NotifyError::NotConnected));
+        assert!(!ll_tx.has_data());
+    }
+
+    #[test]
+    fn confirmation_timeout_from_link_layer() {
+        let ((tx, mut ll_tx), (mut ll_rx, rx)) = queues();
+        let mut responder =
+            Responder::<MockConfig>::new(tx, rx, L2CAPState::new(BleChannelMap::empty()));
+        report(&mut ll_rx, LinkReport::Connected);
+        responder.process_one().unwrap();
+
+        let mut l2cap = responder.l2cap();
+        let att = l2cap.att().unwrap();
+        att.indicate_raw(Handle::from_raw(0x0003), &[1]).unwrap();
+        assert!(ll_tx.has_data());
+
+        // The first connection event starts the timeout, which expires 30 seconds later
+        let start = Instant::from_raw_micros(1_000);
+        report(&mut ll_rx, LinkReport::Time(start));
+        assert_eq!(responder.process_one(), Ok(()));
+        let server = responder.l2cap.att_server();
+        assert_eq!(
+            server.confirmation_deadline(),
+            Some(start + CONFIRMATION_TIMEOUT)
+        );
+        assert!(!server.is_confirmation_timed_out());
+
+        // The timeout is reported to the application once
+        let deadline = start + CONFIRMATION_TIMEOUT;
+        report(&mut ll_rx, LinkReport::Time(deadline));
+        assert_eq!(responder.process_one(), Err(Error::ConfirmationTimeout));
+        assert!(responder.l2cap.att_server().is_confirmation_timed_out());
+        report(
+            &mut ll_rx,
+            LinkReport::Time(deadline + CONFIRMATION_TIMEOUT),
+        );
+        assert_eq!(responder.process_one(), Ok(()));
+
+        // Afterwards, requests aren't answered and notifications can't be sent
+        ll_tx
+            .consume_raw_with(|_, _| Consume::always(Ok(())))
+            .unwrap();
+        // ATT Read Request for handle 0x0001
+        let message = [0x03, 0x00, 0x04, 0x00, 0x0A, 0x01, 0x00];
+        ll_rx
+            .produce_with(message.len() as u8, |writer| -> Result<_, Error> {
+                writer.write_slice(&message)?;
+                Ok(Llid::DataStart)
+            })
+            .unwrap();
+        assert_eq!(responder.process_one(), Ok(()));
+        assert!(!responder.has_work());
+        assert!(!ll_tx.has_data());
+        let mut l2cap = responder.l2cap();
+        let att = l2cap.att().unwrap();
+        assert_eq!(
+            att.notify_raw(Handle::from_raw(0x0003), &[2]),
+            Err(NotifyError::ConfirmationTimeout)
+        );
+        assert!(!ll_tx.has_data());
+
+        // A new connection starts out fresh
+        report(&mut ll_rx, LinkReport::Disconnected);
+        responder.process_one().unwrap();
+        report(&mut ll_rx, LinkReport::Connected);
+        responder.process_one().unwrap();
+        let mut l2cap = responder.l2cap();
+        let att = l2cap.att().unwrap();
+        assert!(att.notify_raw(Handle::from_raw(0x0003), &[3]).is_ok());
+    }
+
+    #[test]
+    fn pipelined_request_doesnt_stall_queue() {
+        let ((tx, _ll_tx), (mut ll_rx, rx)) = queues_with_capacity(4);
+        let mut responder =
+            Responder::<MockConfig>::new(tx, rx, L2CAPState::new(BleChannelMap::empty()));
+        report(&mut ll_rx, LinkReport::Connected);
+        responder.process_one().unwrap();
+        let mut l2cap = responder.l2cap();
+        let att = l2cap.att().unwrap();
+        att.indicate_raw(Handle::from_raw(0x0003), &[1]).unwrap();
+
+        // Two Read Requests and a Handle Value Confirmation arrive back-to-back
+        let read = |handle| [0x03, 0x00, 0x04, 0x00, 0x0A, handle, 0x00];
+        receive(&mut ll_rx, Llid::DataStart, &read(0x01));
+        receive(&mut ll_rx, Llid::DataStart, &read(0x02));
+        receive(&mut ll_rx, Llid::DataStart, &[0x01, 0x00, 0x04, 0x00, 0x1E]);
+
+        // All of them are processed right away
+        for _ in 0..3 {
+            assert_eq!(responder.process_one(), Ok(()));
+        }
+        assert!(!responder.has_work());
+        assert!(!responder.l2cap().att_server().is_indication_pending());
+        assert_eq!(responder.tx_queue().occupancy(), 3);
+    }
+
+    #[test]
+    fn reconnect_resets_l2cap() {
+        let ((tx, mut ll_tx), (mut ll_rx, rx)) = queues_with_capacity(2);
+        let mut responder =
+            Responder::<MockConfig>::new(tx, rx, L2CAPState::new(BleChannelMap::empty()));
+        let handle = Handle::from_raw(0x0003);
+
+        // The first notification is transmitted, the second one is still queued when the
+        // connection is lost, and so is the start of a Read Request
+        report(&mut ll_rx, LinkReport::Connected);
+        responder.process_one().unwrap();
+        let mut l2cap = responder.l2cap();
+        l2cap.att().unwrap().notify_raw(handle, &[1]).unwrap();
+        ll_tx
+            .consume_raw_with(|_, _| Consume::always(Ok(())))
+            .unwrap();
+        let mut l2cap = responder.l2cap();
+        let second = l2cap.att().unwrap().notify_raw(handle, &[2]).unwrap();
+        receive(&mut ll_rx, Llid::DataStart, &[0x03, 0x00, 0x04, 0x00, 0x0A]);
+        responder.process_one().unwrap();
+        report(&mut ll_rx, LinkReport::Disconnected);
+        responder.process_one().unwrap();
+
+        // The queued notification is the first PDU of the next connection, so tickets continue
+        // from there
+        report(&mut ll_rx, LinkReport::Connected);
+        responder.process_one().unwrap();
+        let mut l2cap = responder.l2cap();
+        let ticket = l2cap.att().unwrap().notify_raw(handle, &[3]).unwrap();
+        assert_eq!(ticket, second);
+
+        // The rest of the Read Request doesn't complete it
+        ll_tx
+            .consume_raw_with(|_, _| Consume::always(Ok(())))
+            .unwrap();
+        ll_tx
+            .consume_raw_with(|_, _| Consume::always(Ok(())))
+            .unwrap();
+        receive(&mut ll_rx, Llid::DataCont, &[0x01, 0x00]);
+        assert_eq!(responder.process_one(), Ok(()));
+        assert!(!ll_tx.has_data());
+    }
+
+    #[test]
+    fn queue_occupancy() {
+        let ((tx, mut ll_tx), (mut ll_rx, rx)) = queues();
+        let mut responder =
+            Responder::<MockConfig>::new(tx, rx, L2CAPState::new(BleChannelMap::empty()));
+        assert_eq!(responder.tx_queue().capacity(), 1);
+        assert_eq!(responder.tx_queue().occupancy(), 0);
+        report(&mut ll_rx, LinkReport::Connected);
+        responder.process_one().unwrap();
+
+        // Queuing a notification fills the queue, sending it frees the space again
+        let mut l2cap = responder.l2cap();
+        let att = l2cap.att().unwrap();
+        att.notify_raw(Handle::from_raw(0x0003), &[1, 2]).unwrap();
+        assert_eq!(responder.tx_queue().occupancy(), 1);
+        assert!(responder.l2cap().att().is_none());
+
+        ll_tx
+            .consume_raw_with(|_, _| Consume::always(Ok(())))
+            .unwrap();
+        assert_eq!(responder.tx_queue().occupancy(), 0);
+
+        // Received packets occupy the RX queue until processed
+        assert_eq!(responder.rx_queue().occupancy(), 0);
+        // ATT Write Command without a value, which isn't answered
+        let message = [0x03, 0x00, 0x04, 0x00, 0x52, 0x01, 0x00];
+        ll_rx
+            .produce_with(message.len() as u8, |writer| -> Result<_, Error> {
+                writer.write_slice(&message)?;
+                Ok(Llid::DataStart)
+            })
+            .unwrap();
+        assert_eq!(responder.rx_queue().occupancy(), 1);
+        responder.process_one().unwrap();
+        assert_eq!(responder.rx_queue().occupancy(), 0);
+    }
+}
@@ -0,0 +1,184 @@
+//! PDU-level tracing hooks.
+//!
+//! The Link-Layer reports every PDU it sends or receives to [`Config::trace_pdu`], tagged with the
+//! protocol it belongs to and its direction. This allows inspecting the traffic of a connection
+//! without patching Rubble, eg. by forwarding the PDUs to a debug probe or by asserting on them in
+//! tests.
+//!
+//! The default implementation of `Config::trace_pdu` does nothing, so tracing costs nothing unless
+//! a configuration overrides it.
+//!
+//! With the `profiling` feature, the Link-Layer additionally reports the start and end of every
+//! advertising and connection event to `Config::event_started` and `Config::event_ended`. This can
+//! be used to measure how long the radio is active, eg. by toggling a GPIO pin that is recorded by
+//! a logic analyzer.
+//!
+//! [`Config::trace_pdu`]: ../../config/trait.Config.html#method.trace_pdu
+
+use crate::{
+    bytes::{ByteReader, FromBytes},
+    config::Config,
+    l2cap::Channel,
+    link::data::Llid,
+    time::Instant,
+};
+
+/// The protocol a traced PDU belongs to.
+///
+/// This also determines which part of the PDU is passed to the hook.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PduKind {
+    /// An advertising channel PDU payload (without the 2-Byte header).
+    Advertising,
+
+    /// An LL Control PDU, starting with its opcode.
+    LinkControl,
+
+    /// An ATT PDU, starting with its opcode (the L2CAP header is stripped).
+    Att,
+
+    /// Any other L2CAP data: Messages addressed to channels other than ATT (including their L2CAP
+    /// header), and continuation fragments.
+    L2cap,
+}
+
+/// Whether a traced PDU was sent or received.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// The PDU was received from the other device.
+    Inbound,
+
+    /// The PDU was sent to the other device.
+    Outbound,
+}
+
+/// The kind of radio event reported to the profiling hooks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    /// An advertising event.
+    ///
+    /// It starts when the advertising PDU is sent. Since the Link-Layer keeps listening for scan
+    /// and connection requests afterwards, it only ends when the next advertising event starts,
+    /// when a connection is established, or when advertising is restarted.
+    Advertising,
+
+    /// A connection event.
+    ///
+    /// It starts at the anchor point, when the master starts sending its first PDU of the event,
+    /// and ends when the Link-Layer closes the event or the connection.
+    Connection,
+}
+
+/// Reports the start of a `kind` event at `at` to `C::event_started`.
+///
+/// Does nothing unless the `profiling` feature is enabled.
+#[inline(always)]
+pub(crate) fn event_started<C: Config>(kind: EventKind, at: Instant) {
+    if cfg!(feature = "profiling") {
+        C::event_started(kind, at);
+    }
+}
+
+/// Reports the end of a `kind` event at `at` to `C::event_ended`.
+///
+/// Does nothing unless the `profiling` feature is enabled.
+#[inline(always)]
+pub(crate) fn event_ended<C: Config>(kind: EventKind, at: Instant) {
+    if cfg!(feature = "profiling") {
+        C::event_ended(kind, at);
+    }
+}
+
+/// Reports a non-empty data channel PDU payload with LLID `llid` to `C::trace_pdu`.
+///
+/// Payloads are traced unencrypted.
+pub(crate) fn trace_data<C: Config>(direction: Direction, llid: Llid, payload: &[u8]) {
+    if payload.is_empty() {
+        return;
+    }
+
+    let (kind, pdu) = classify(llid, payload);
+    C::trace_pdu(kind, direction, pdu);
+}
+
+/// Determines the kind of a data channel PDU and the part of its payload to trace.
+fn classify(llid: Llid, payload: &[u8]) -> (PduKind, &[u8]) {
+    match llid {
+        Llid::Control => (PduKind::LinkControl, payload),
+        Llid::DataStart if payload.len() >= 4 => {
+            // L2CAP header: 2-Byte length, followed by the 2-Byte channel ID
+            let channel = Channel::from_bytes(&mut ByteReader::new(&payload[2..4]));
+            if channel == Ok(Channel::ATT) {
+                (PduKind::Att, &payload[4..])
+            } else {
+                (PduKind::L2cap, payload)
+            }
+        }
+        _ => (PduKind::L2cap, payload),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            link::{ad_structure::AdStructure, mock::*, AddressKind, DeviceAddress, LinkLayer},
+            time::Duration,
+        },
+    };
+
+    #[test]
+    fn classify_data_pdus() {
+        // ATT Exchange MTU Request
+        let att = [0x03, 0x00, 0x04, 0x00, 0x02, 0x17, 0x00];
+        assert_eq!(
+            classify(Llid::DataStart, &att),
+            (PduKind::Att, &[0x02, 0x17, 0x00][..])
+        );
+
+        // LE signaling channel
+        let sig = [0x02, 0x00, 0x05, 0x00, 0x01, 0x02];
+        assert_eq!(classify(Llid::DataStart, &sig), (PduKind::L2cap, &sig[..]));
+
+        // Continuation fragments and truncated messages can't be attributed to a channel
+        assert_eq!(classify(Llid::DataCont, &att), (PduKind::L2cap, &att[..]));
+        assert_eq!(
+            classify(Llid::DataStart, &att[..3]),
+            (PduKind::L2cap, &att[..3])
+        );
+
+        // LL_FEATURE_REQ
+        let llcp = [0x08, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            classify(Llid::Control, &llcp),
+            (PduKind::LinkControl, &llcp[..])
+        );
+    }
+
+    #[test]
+    fn advertising_is_traced() {
+        let addr = DeviceAddress::new([1, 2, 3, 4, 5, 6], AddressKind::Random);
+        let mut ll = LinkLayer::<MockConfig>::new(addr, MockTimer::new());
+        let mut radio = MockTransmitter::new();
+        let ((tx, _), (_, rx)) = queues();
+        take_trace();
+
+        ll.start_advertise(
+            Duration::from_millis(100),
+            &[AdStructure::CompleteLocalName("rubble")],
+            &mut radio,
+            rx,
+            tx,
+        )
+        .unwrap();
+
+        let sent = match radio.sent.last().unwrap() {
+            Sent::Advertising { payload, .. } => payload.clone(),
+            _ => unreachable!(),
+        };
+        let trace = take_trace();
+        assert_eq!(trace.len(), 1);
+        assert_eq!(trace[0], (PduKind::Advertising, Direction::Outbound, sent));
+    }
+}
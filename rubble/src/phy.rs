@@ -9,6 +9,8 @@
 //! (presumably to simplify channel hopping). The Link-Layer is only interested in these channel
 //! indices, so only those are implemented here.
 
+use crate::{time::Duration, Error};
+
 /// Returns the center frequency in MHz corresponding to an RF channel.
 fn rf_channel_freq(rf_channel: u8) -> u16 {
     2402 + u16::from(rf_channel) * 2
@@ -20,8 +22,32 @@ fn whitening_iv(channel_idx: u8) -> u8 {
     0b01000000 | channel_idx
 }
 
+/// Applies (or removes) BLE data whitening to `data`, in place.
+///
+/// `iv` is the initial value of the whitening LFSR, as returned by the channel's `whitening_iv`
+/// method. Whitening is its own inverse, so this function is used to both whiten and de-whiten
+/// data.
+///
+/// Most radios perform whitening in hardware, but it must be done in software when capturing raw
+/// packets.
+pub fn whiten(data: &mut [u8], iv: u8) {
+    // Bit 6 of the LFSR is Position 0, bit 0 is Position 6 (the output)
+    let mut lfsr = iv & 0x7F;
+    for byte in data {
+        for bit in 0..8 {
+            let out = lfsr & 1;
+            lfsr >>= 1;
+            if out != 0 {
+                // Feed back into Position 0 and Position 4 (x^7 + x^4 + 1)
+                lfsr ^= 0b0100_0100;
+            }
+            *byte ^= out << bit;
+        }
+    }
+}
+
 /// One of the three advertising channels (channel indices 37, 38 or 39).
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct AdvertisingChannel(u8);
 
 impl AdvertisingChannel {
@@ -133,6 +159,141 @@ impl DataChannel {
     }
 }
 
+/// The physical layer modulation and coding used to transmit packets.
+///
+/// Bluetooth 4.x only supports `Le1M`. Bluetooth 5 adds `Le2M` for higher throughput and the coded
+/// PHYs `LeCodedS2` and `LeCodedS8` for longer range.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Phy {
+    /// 1 Msym/s, uncoded. This is the only PHY used by legacy advertising.
+    Le1M,
+
+    /// 2 Msym/s, uncoded.
+    Le2M,
+
+    /// 1 Msym/s, with each bit coded as 2 symbols (500 kbit/s).
+    LeCodedS2,
+
+    /// 1 Msym/s, with each bit coded as 8 symbols (125 kbit/s).
+    LeCodedS8,
+}
+
+impl Phy {
+    /// Returns the length of the packet preamble in octets (for uncoded PHYs).
+    ///
+    /// The coded PHYs use a fixed 80 µs preamble that can't be expressed in octets, so this returns
+    /// `None` for them.
+    pub fn preamble_len(&self) -> Option<u8> {
+        match self {
+            Phy::Le1M => Some(1),
+            Phy::Le2M => Some(2),
+            Phy::LeCodedS2 | Phy::LeCodedS8 => None,
+        }
+    }
+
+    /// Returns whether this is one of the coded (long range) PHYs.
+    pub fn is_coded(&self) -> bool {
+        self.coding_indicator().is_some()
+    }
+
+    /// Returns the Coding Indicator a packet sent on this PHY carries, or `None` if this is an
+    /// uncoded PHY.
+    pub fn coding_indicator(&self) -> Option<CodingIndicator> {
+        match self {
+            Phy::Le1M | Phy::Le2M => None,
+            Phy::LeCodedS2 => Some(CodingIndicator::S2),
+            Phy::LeCodedS8 => Some(CodingIndicator::S8),
+        }
+    }
+
+    /// Returns the inter frame space to use between packets on this PHY (`T_IFS`).
+    ///
+    /// This is 150 µs for all PHYs.
+    pub fn ifs(&self) -> Duration {
+        Duration::T_IFS
+    }
+
+    /// Returns the on-air duration of a packet containing a PDU of `pdu_len` octets.
+    ///
+    /// `pdu_len` includes the 2-octet PDU header, but not the MIC. The returned duration includes
+    /// preamble, access address and CRC (and the coding indicator and terminators on the coded
+    /// PHYs).
+    pub fn packet_duration(&self, pdu_len: u16) -> Duration {
+        // Access address + PDU + CRC
+        let octets = 4 + u32::from(pdu_len) + 3;
+        let micros = match self {
+            Phy::Le1M => (1 + octets) * 8,
+            Phy::Le2M => (2 + octets) * 4,
+            Phy::LeCodedS2 | Phy::LeCodedS8 => {
+                // The first block (access address, coding indicator and TERM1) is always S=8
+                let s = if *self == Phy::LeCodedS2 { 2 } else { 8 };
+                let preamble = 80;
+                let block1 = (32 + 2 + 3) * 8;
+                // PDU and CRC, followed by the 3-bit TERM2
+                let block2 = ((u32::from(pdu_len) + 3) * 8 + 3) * s;
+                preamble + block1 + block2
+            }
+        };
+        Duration::from_micros(micros)
+    }
+}
+
+/// The Coding Indicator of a packet sent on the LE Coded PHY.
+///
+/// Packets on the coded PHY consist of 2 FEC blocks. The first one always uses S=8 coding and
+/// contains the Access Address and the Coding Indicator, which determines the coding used for the
+/// second block containing the PDU and CRC.
+///
+/// ```notrust
+/// LSB                                                                                   MSB
+/// +----------+----------------+-----------+----------+-------------+-----------+----------+
+/// | Preamble | Access Address |    CI     |  TERM1   |     PDU     |    CRC    |  TERM2   |
+/// |  (80 µs) |   (32 bits)    | (2 bits)  | (3 bits) | (2-257 oct) | (3 oct)   | (3 bits) |
+/// +----------+----------------+-----------+----------+-------------+-----------+----------+
+/// \-------------- FEC block 1 (S=8) ----------------/ \------ FEC block 2 (S=2 or S=8) ---/
+/// ```
+///
+/// Convolutional coding and pattern mapping are expected to be done by the radio hardware, which
+/// also has to report the received Coding Indicator.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CodingIndicator {
+    /// FEC block 2 is coded with S=8 (125 kbit/s).
+    S8,
+
+    /// FEC block 2 is coded with S=2 (500 kbit/s).
+    S2,
+}
+
+impl CodingIndicator {
+    /// Parses a received 2-bit Coding Indicator field.
+    ///
+    /// Returns `Error::InvalidValue` for the reserved values `0b10` and `0b11`, in which case the
+    /// packet must be ignored.
+    pub fn from_raw(raw: u8) -> Result<Self, Error> {
+        match raw {
+            0b00 => Ok(CodingIndicator::S8),
+            0b01 => Ok(CodingIndicator::S2),
+            _ => Err(Error::InvalidValue),
+        }
+    }
+
+    /// Returns the raw 2-bit value of this Coding Indicator.
+    pub fn to_raw(&self) -> u8 {
+        match self {
+            CodingIndicator::S8 => 0b00,
+            CodingIndicator::S2 => 0b01,
+        }
+    }
+
+    /// Returns the PHY that the rest of the packet is coded with.
+    pub fn phy(&self) -> Phy {
+        match self {
+            CodingIndicator::S8 => Phy::LeCodedS8,
+            CodingIndicator::S2 => Phy::LeCodedS2,
+        }
+    }
+}
+
 /// Trait for raw 2.4 GHz non-BLE-specific radios.
 ///
 /// You probably won't need to implement this trait, unless you're working with hardware that has
@@ -144,3 +305,63 @@ pub trait Radio {
     /// TODO: Document all radio requirements
     fn transmit(&mut self, buf: &mut [u8], freq: u16);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packet_duration() {
+        // Empty data channel PDU (2-octet header)
+        assert_eq!(Phy::Le1M.packet_duration(2), Duration::from_micros(80));
+        assert_eq!(Phy::Le2M.packet_duration(2), Duration::from_micros(44));
+
+        // Maximum legacy advertising PDU (2 + 37 octets)
+        assert_eq!(Phy::Le1M.packet_duration(39), Duration::from_micros(376));
+        assert_eq!(Phy::Le2M.packet_duration(39), Duration::from_micros(192));
+
+        // Minimum and maximum packet durations from the spec (Vol 6, Part B, 2.1.1)
+        assert_eq!(
+            Phy::LeCodedS8.packet_duration(2),
+            Duration::from_micros(720)
+        );
+        assert_eq!(
+            Phy::LeCodedS2.packet_duration(2),
+            Duration::from_micros(462)
+        );
+        assert_eq!(
+            Phy::LeCodedS8.packet_duration(257),
+            Duration::from_micros(17_040)
+        );
+        assert_eq!(
+            Phy::LeCodedS2.packet_duration(257),
+            Duration::from_micros(4_542)
+        );
+    }
+
+    #[test]
+    fn coding_indicator() {
+        for &phy in &[Phy::LeCodedS2, Phy::LeCodedS8] {
+            let ci = phy.coding_indicator().unwrap();
+            assert_eq!(CodingIndicator::from_raw(ci.to_raw()), Ok(ci));
+            assert_eq!(ci.phy(), phy);
+        }
+
+        assert_eq!(Phy::Le1M.coding_indicator(), None);
+        assert_eq!(Phy::Le2M.coding_indicator(), None);
+        assert_eq!(CodingIndicator::from_raw(0b00), Ok(CodingIndicator::S8));
+        assert_eq!(CodingIndicator::from_raw(0b10), Err(Error::InvalidValue));
+        assert_eq!(CodingIndicator::from_raw(0b11), Err(Error::InvalidValue));
+    }
+
+    #[test]
+    fn whitening_sequence() {
+        // Whitening all-zero data yields the raw LFSR output
+        let mut data = [0; 4];
+        whiten(&mut data, AdvertisingChannel::first().whitening_iv());
+        assert_eq!(data, [0x8D, 0xD2, 0x57, 0xA1]);
+
+        whiten(&mut data, AdvertisingChannel::first().whitening_iv());
+        assert_eq!(data, [0; 4]);
+    }
+}
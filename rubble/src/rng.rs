@@ -0,0 +1,18 @@
+//! Random number generation.
+
+/// Trait for random number generators.
+///
+/// The hardware interface should provide an implementation of `Rng` backed by a hardware random
+/// number generator, if the MCU has one. The generated numbers are used for security-relevant
+/// purposes such as random device addresses, so a predictable software PRNG is not suitable
+/// outside of tests.
+pub trait Rng {
+    /// Fills `buf` with random bytes.
+    fn fill_bytes(&mut self, buf: &mut [u8]);
+}
+
+impl<R: Rng + ?Sized> Rng for &'_ mut R {
+    fn fill_bytes(&mut self, buf: &mut [u8]) {
+        (**self).fill_bytes(buf)
+    }
+}
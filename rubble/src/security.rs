@@ -134,23 +134,57 @@ impl SecurityLevel for SecureConnections {
 #[derive(Debug)]
 pub struct SecurityManager<S: SecurityLevel> {
     _security: S,
+
+    /// Why the last pairing attempt failed, if it did and the application hasn't been told yet.
+    failure: Option<PairingFailure>,
 }
 
 impl SecurityManager<NoSecurity> {
     pub fn no_security() -> Self {
         Self {
             _security: NoSecurity,
+            failure: None,
         }
     }
 }
 
+impl<S: SecurityLevel> SecurityManager<S> {
+    /// Returns why the last pairing attempt failed, and clears it.
+    ///
+    /// Returns `None` if no pairing attempt failed since the last call. Applications can use the
+    /// reason to tell the user what went wrong (eg. that the entered passkey was incorrect).
+    pub fn take_pairing_failure(&mut self) -> Option<PairingFailure> {
+        self.failure.take()
+    }
+
+    /// Aborts pairing by sending a *Pairing Failed* command with `reason` to the peer.
+    fn fail(
+        &mut self,
+        reason: PairingFailedReason,
+        mut responder: Sender<'_>,
+    ) -> Result<(), Error> {
+        debug!("SMP pairing failed locally: {:?}", reason);
+        self.failure = Some(PairingFailure::Local(reason));
+        responder.send_with(|writer| {
+            writer.write_u8(CommandCode::PairingFailed.into())?;
+            writer.write_u8(reason.into())
+        })
+    }
+}
+
 impl<S: SecurityLevel> ProtocolObj for SecurityManager<S> {
-    fn process_message(&mut self, message: &[u8], _responder: Sender<'_>) -> Result<(), Error> {
+    fn process_message(&mut self, message: &[u8], responder: Sender<'_>) -> Result<(), Error> {
         let cmd = Command::from_bytes(&mut ByteReader::new(message))?;
         trace!("SMP cmd {:?}, {:?}", cmd, HexSlice(message));
         match cmd {
             Command::PairingRequest { .. } => {
-                warn!("pairing request NYI");
+                // Pairing isn't implemented yet. Reject the request right away, so the peer doesn't
+                // have to wait for the SMP timeout.
+                return self.fail(PairingFailedReason::PairingNotSupported, responder);
+            }
+            Command::PairingFailed { reason } => {
+                debug!("SMP pairing failed by peer: {:?}", reason);
+                self.failure = Some(PairingFailure::Remote(reason));
             }
             Command::Unknown {
                 code: CommandCode::Unknown(code),
@@ -196,6 +230,11 @@ enum Command<'a> {
         /// Set of keys the initiator requests the responder to generate and distribute.
         responder_dist: KeyDistribution,
     },
+    /// `0x05` Pairing failed
+    PairingFailed {
+        /// Why pairing was aborted.
+        reason: PairingFailedReason,
+    },
     Unknown {
         code: CommandCode,
         data: &'a [u8],
@@ -214,6 +253,9 @@ impl<'a> FromBytes<'a> for Command<'a> {
                 initiator_dist: KeyDistribution::from_bits_truncate(bytes.read_u8()?),
                 responder_dist: KeyDistribution::from_bits_truncate(bytes.read_u8()?),
             },
+            CommandCode::PairingFailed => Command::PairingFailed {
+                reason: PairingFailedReason::from(bytes.read_u8()?),
+            },
             _ => Command::Unknown {
                 code,
                 data: bytes.read_rest(),
@@ -242,6 +284,74 @@ enum_with_unknown! {
     }
 }
 
+enum_with_unknown! {
+    /// Reason sent in a *Pairing Failed* command.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum PairingFailedReason(u8) {
+        /// The user input of the passkey failed (eg. it was canceled).
+        PasskeyEntryFailed = 0x01,
+
+        /// The OOB data is not available.
+        OobNotAvailable = 0x02,
+
+        /// The requested authentication requirements (eg. MITM protection) can't be met.
+        AuthenticationRequirements = 0x03,
+
+        /// The confirm value doesn't match the calculated value (eg. the wrong passkey was
+        /// entered).
+        ConfirmValueFailed = 0x04,
+
+        /// Pairing is not supported by the device.
+        PairingNotSupported = 0x05,
+
+        /// The resulting key size is insufficient for the security requirements of the device.
+        EncryptionKeySize = 0x06,
+
+        /// The received SMP command is not supported.
+        CommandNotSupported = 0x07,
+
+        /// Pairing failed due to an unspecified reason.
+        UnspecifiedReason = 0x08,
+
+        /// Pairing is not allowed because too little time has passed since the last attempt.
+        RepeatedAttempts = 0x09,
+
+        /// A command contained invalid parameters.
+        InvalidParameters = 0x0A,
+
+        /// The DHKey Check value doesn't match the calculated value.
+        DhKeyCheckFailed = 0x0B,
+
+        /// The confirm values in the Numeric Comparison protocol don't match.
+        NumericComparisonFailed = 0x0C,
+
+        /// Pairing over BR/EDR is in progress.
+        BrEdrPairingInProgress = 0x0D,
+
+        /// The derived BR/EDR link key or LE LTK can't be used.
+        CrossTransportKeyDerivationNotAllowed = 0x0E,
+    }
+}
+
+/// A failed pairing attempt.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PairingFailure {
+    /// Pairing was aborted by this device.
+    Local(PairingFailedReason),
+
+    /// Pairing was aborted by the peer.
+    Remote(PairingFailedReason),
+}
+
+impl PairingFailure {
+    /// Returns the reason sent in the *Pairing Failed* command.
+    pub fn reason(&self) -> PairingFailedReason {
+        match *self {
+            PairingFailure::Local(reason) | PairingFailure::Remote(reason) => reason,
+        }
+    }
+}
+
 enum_with_unknown! {
     /// Describes the I/O capabilities of a device that can be used for the pairing process.
     #[derive(Debug, Copy, Clone)]
@@ -352,3 +462,60 @@ bitflags! {
         const LINK_KEY = (1 << 3);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            att::NoAttributes,
+            l2cap::{exchange, BleChannelMap, Channel, L2CAPState},
+        },
+        std::vec::Vec,
+    };
+
+    /// Sends an SMP command to the security manager and returns the response, if any.
+    fn command(
+        l2cap: &mut L2CAPState<BleChannelMap<NoAttributes, NoSecurity>>,
+        cmd: &[u8],
+    ) -> Option<Vec<u8>> {
+        exchange(l2cap, Channel::LE_SECURITY_MANAGER, cmd)
+    }
+
+    #[test]
+    fn pairing_failure_reasons() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::empty());
+        assert_eq!(
+            l2cap
+                .channel_mapper()
+                .security_manager()
+                .take_pairing_failure(),
+            None
+        );
+
+        // Pairing requests are rejected, since pairing isn't supported
+        let request = [0x01, 0x03, 0x00, 0x01, 0x10, 0x00, 0x01];
+        assert_eq!(command(&mut l2cap, &request), Some(vec![0x05, 0x05]));
+        let sm = l2cap.channel_mapper().security_manager();
+        assert_eq!(
+            sm.take_pairing_failure(),
+            Some(PairingFailure::Local(
+                PairingFailedReason::PairingNotSupported
+            ))
+        );
+        assert_eq!(sm.take_pairing_failure(), None);
+
+        // The peer aborts pairing because of a confirm value mismatch
+        assert_eq!(command(&mut l2cap, &[0x05, 0x04]), None);
+        let failure = l2cap
+            .channel_mapper()
+            .security_manager()
+            .take_pairing_failure()
+            .unwrap();
+        assert_eq!(
+            failure,
+            PairingFailure::Remote(PairingFailedReason::ConfirmValueFailed)
+        );
+        assert_eq!(failure.reason(), PairingFailedReason::ConfirmValueFailed);
+    }
+}
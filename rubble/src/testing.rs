@@ -0,0 +1,287 @@
+//! Known-good PDU encodings for use in tests.
+//!
+//! This module contains canonical wire encodings of common PDUs of every layer, along with helpers
+//! to build and parse them. Integration tests and examples can use these to check their output
+//! against (or feed them into the stack) instead of repeating magic byte arrays.
+//!
+//! Advertising and data channel PDUs include their 2-Byte Link-Layer header, but not the preamble,
+//! Access Address, or CRC. ATT PDUs are given without their L2CAP header, which can be added with
+//! `l2cap_message`.
+//!
+//! This module is only available when the `testing` Cargo feature is enabled.
+
+use crate::{
+    bytes::{ByteReader, ByteWriter},
+    l2cap::{self, Channel},
+    link::{advertising, data, MIN_DATA_PAYLOAD_BUF},
+    Error,
+};
+
+/// Random static device address of the advertiser in the advertising channel PDUs.
+///
+/// As with all device addresses, the Bytes are in over-the-air order (LSB first).
+pub const ADVERTISER_ADDR: [u8; 6] = [0x01, 0x02, 0x03, 0x04, 0x05, 0xC6];
+
+/// Random static device address of the initiator in `CONNECT_IND`.
+pub const INITIATOR_ADDR: [u8; 6] = [0x11, 0x12, 0x13, 0x14, 0x15, 0xD6];
+
+/// `ADV_IND` sent from `ADVERTISER_ADDR`, advertising as discoverable with a complete local name
+/// of "rubble".
+///
+/// This is what `PduBuf::discoverable` creates when given a `CompleteLocalName` AD structure.
+pub const ADV_IND: [u8; 19] = [
+    0x40, 0x11, // PDU type ADV_IND, TxAdd = random, 17 Bytes of payload
+    0x01, 0x02, 0x03, 0x04, 0x05, 0xC6, // AdvA
+    0x02, 0x01, 0x06, // Flags: LE General Discoverable, BR/EDR Not Supported
+    0x07, 0x09, b'r', b'u', b'b', b'b', b'l', b'e', // Complete Local Name
+];
+
+/// `ADV_NONCONN_IND` beacon sent from `ADVERTISER_ADDR`, carrying only the mandatory flags.
+///
+/// This is what `PduBuf::beacon` creates when given no additional AD structures.
+pub const ADV_NONCONN_IND: [u8; 11] = [
+    0x42, 0x09, // PDU type ADV_NONCONN_IND, TxAdd = random, 9 Bytes of payload
+    0x01, 0x02, 0x03, 0x04, 0x05, 0xC6, // AdvA
+    0x02, 0x01, 0x04, // Flags: BR/EDR Not Supported
+];
+
+/// `CONNECT_IND` sent from `INITIATOR_ADDR` to `ADVERTISER_ADDR`.
+///
+/// The connection uses a 30 ms interval, no slave latency, a 720 ms supervision timeout, and all
+/// data channels. The master's sleep clock accuracy is 31-50 ppm.
+pub const CONNECT_IND: [u8; 36] = [
+    0xC5, 0x22, // PDU type CONNECT_IND, TxAdd = RxAdd = random, 34 Bytes of payload
+    0x11, 0x12, 0x13, 0x14, 0x15, 0xD6, // InitA
+    0x01, 0x02, 0x03, 0x04, 0x05, 0xC6, // AdvA
+    0x29, 0x4B, 0x65, 0x50, // Access Address = 0x50654B29
+    0x1A, 0x4C, 0x8F, // CRCInit = 0x8F4C1A
+    0x02, // WinSize = 2.5 ms
+    0x03, 0x00, // WinOffset = 3.75 ms
+    0x18, 0x00, // Interval = 30 ms
+    0x00, 0x00, // Latency = 0
+    0x48, 0x00, // Timeout = 720 ms
+    0xFF, 0xFF, 0xFF, 0xFF, 0x1F, // ChM: all 37 data channels
+    0xA7, // Hop = 7, SCA = 31-50 ppm
+];
+
+/// `LL_VERSION_IND` data channel PDU announcing Bluetooth 5.0 and Nordic Semiconductor as the
+/// company ID, with `SN` and `NESN` both 0.
+pub const LL_VERSION_IND: [u8; 8] = [
+    0x03, 0x06, // LLID = Control, 6 Bytes of payload
+    0x0C, // LL_VERSION_IND
+    0x09, // VersNr = 5.0
+    0x59, 0x00, // CompId = 0x0059
+    0x00, 0x00, // SubVersNr
+];
+
+/// ATT *Read By Group Type Request* discovering all primary services.
+pub const ATT_READ_BY_GROUP_REQ: [u8; 7] = [
+    0x10, // Read By Group Type Request
+    0x01, 0x00, // Starting Handle
+    0xFF, 0xFF, // Ending Handle
+    0x00, 0x28, // Primary Service
+];
+
+/// ATT *Read By Group Type Response* to `ATT_READ_BY_GROUP_REQ`, sent by a server hosting
+/// `BatteryServiceAttrs`.
+pub const ATT_READ_BY_GROUP_RSP: [u8; 8] = [
+    0x11, // Read By Group Type Response
+    0x06, // Length of each entry
+    0x01, 0x00, 0x03, 0x00, 0x0F, 0x18, // Handles 0x0001-0x0003: Battery Service
+];
+
+/// ATT *Read Request* for the attribute at handle `0x0003`.
+pub const ATT_READ_REQ: [u8; 3] = [
+    0x0A, // Read Request
+    0x03, 0x00, // Handle
+];
+
+/// ATT *Read Response* to `ATT_READ_REQ`, sent by a server hosting `BatteryServiceAttrs`.
+pub const ATT_READ_RSP: [u8; 2] = [
+    0x0B, // Read Response
+    0x30, // Battery Level = 48%
+];
+
+/// ATT *Handle Value Notification* of the value `"hi"` for the attribute at handle `0x0003`.
+pub const ATT_HANDLE_VALUE_NTF: [u8; 5] = [
+    0x1B, // Handle Value Notification
+    0x03, 0x00, // Handle
+    b'h', b'i', // Value
+];
+
+/// Writes the header and payload of an advertising channel PDU into `buf`.
+///
+/// Returns the written part of `buf`, which can be compared against the advertising channel PDUs
+/// in this module.
+pub fn encode_advertising<'a>(
+    pdu: &advertising::PduBuf,
+    buf: &'a mut [u8],
+) -> Result<&'a [u8], Error> {
+    let used = {
+        let mut writer = ByteWriter::new(buf);
+        writer.write_u16_le(pdu.header().to_u16())?;
+        writer.write_slice(pdu.payload())?;
+        writer.bytes_written()
+    };
+    Ok(&buf[..used])
+}
+
+/// Parses an advertising channel PDU, consisting of the 2-Byte header and the payload.
+pub fn parse_advertising(raw: &[u8]) -> Result<advertising::Pdu<'_>, Error> {
+    if raw.len() < 2 {
+        return Err(Error::Eof);
+    }
+
+    let (header, payload) = raw.split_at(2);
+    let header = advertising::Header::parse(header);
+    advertising::Pdu::from_header_and_payload(header, &mut ByteReader::new(payload))
+}
+
+/// Parses a data channel PDU, consisting of the 2-Byte header and the (unencrypted) payload.
+///
+/// Returns the header along with the parsed PDU.
+pub fn parse_data(raw: &[u8]) -> Result<(data::Header, data::Pdu<'_, &[u8]>), Error> {
+    data::Pdu::from_raw(raw)
+}
+
+/// Prepends a basic L2CAP header addressing `channel` to `payload` and writes the resulting message
+/// into `buf`.
+///
+/// The message can be passed to `L2CAPStateTx::process_start`, as long as it fits in a single data
+/// channel PDU.
+pub fn l2cap_message<'a>(
+    channel: Channel,
+    payload: &[u8],
+    buf: &'a mut [u8],
+) -> Result<&'a [u8], Error> {
+    if payload.len() + 4 > MIN_DATA_PAYLOAD_BUF {
+        return Err(Error::InvalidLength);
+    }
+
+    let used = {
+        let mut writer = ByteWriter::new(buf);
+        writer.write_u16_le(payload.len() as u16)?;
+        writer.write_u16_le(channel.as_raw())?;
+        writer.write_slice(payload)?;
+        writer.bytes_written()
+    };
+    Ok(&buf[..used])
+}
+
+/// Parses an unfragmented L2CAP message and returns the destination channel and the payload.
+pub fn parse_l2cap(raw: &[u8]) -> Result<(Channel, &[u8]), Error> {
+    l2cap::parse_message(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            gatt::BatteryServiceAttrs,
+            l2cap::{self, BleChannelMap, L2CAPState},
+            link::{
+                ad_structure::AdStructure,
+                advertising::{Pdu, PduBuf},
+                data::Llid,
+                llcp::ControlPdu,
+                AddressKind, DeviceAddress,
+            },
+            time::Duration,
+        },
+    };
+
+    fn advertiser() -> DeviceAddress {
+        DeviceAddress::new(ADVERTISER_ADDR, AddressKind::Random)
+    }
+
+    #[test]
+    fn advertising_pdus() {
+        let mut buf = [0; 39];
+        let pdu = PduBuf::discoverable(advertiser(), &[AdStructure::CompleteLocalName("rubble")])
+            .unwrap();
+        assert_eq!(encode_advertising(&pdu, &mut buf).unwrap(), &ADV_IND[..]);
+
+        let pdu = PduBuf::beacon(advertiser(), &[]).unwrap();
+        assert_eq!(
+            encode_advertising(&pdu, &mut buf).unwrap(),
+            &ADV_NONCONN_IND[..]
+        );
+
+        match parse_advertising(&ADV_IND).unwrap() {
+            Pdu::ConnectableUndirected {
+                advertiser_addr, ..
+            } => assert!(advertiser_addr == advertiser()),
+            pdu => panic!("unexpected PDU {:?}", pdu),
+        }
+    }
+
+    #[test]
+    fn connect_ind() {
+        match parse_advertising(&CONNECT_IND).unwrap() {
+            Pdu::ConnectRequest {
+                initiator_addr,
+                advertiser_addr,
+                lldata,
+            } => {
+                assert!(initiator_addr == DeviceAddress::new(INITIATOR_ADDR, AddressKind::Random));
+                assert!(advertiser_addr == advertiser());
+                assert_eq!(lldata.access_address(), 0x50654B29);
+                assert_eq!(lldata.crc_init(), 0x8F4C1A);
+                assert_eq!(lldata.interval(), Duration::from_millis(30));
+                assert_eq!(lldata.slave_latency(), 0);
+                assert_eq!(lldata.supervision_timeout(), Duration::from_millis(720));
+                assert_eq!(lldata.hop(), 7);
+            }
+            pdu => panic!("unexpected PDU {:?}", pdu),
+        }
+    }
+
+    #[test]
+    fn ll_version_ind() {
+        let (header, pdu) = parse_data(&LL_VERSION_IND).unwrap();
+        assert_eq!(header.llid(), Llid::Control);
+        match pdu {
+            data::Pdu::Control { data } => match data.read() {
+                ControlPdu::VersionInd {
+                    vers_nr, comp_id, ..
+                } => {
+                    assert_eq!(u8::from(vers_nr), 9);
+                    assert_eq!(comp_id.as_u16(), 0x0059);
+                }
+                pdu => panic!("unexpected LL Control PDU {:?}", pdu),
+            },
+            pdu => panic!("unexpected PDU {:?}", pdu),
+        }
+    }
+
+    #[test]
+    fn att_exchange() {
+        let mut l2cap = L2CAPState::new(BleChannelMap::with_attributes(BatteryServiceAttrs::new()));
+
+        for &(req, rsp) in &[
+            (&ATT_READ_BY_GROUP_REQ[..], &ATT_READ_BY_GROUP_RSP[..]),
+            (&ATT_READ_REQ[..], &ATT_READ_RSP[..]),
+        ] {
+            let mut buf = [0; MIN_DATA_PAYLOAD_BUF];
+            let message = l2cap_message(Channel::ATT, req, &mut buf).unwrap();
+            assert_eq!(parse_l2cap(message).unwrap(), (Channel::ATT, req));
+
+            let sent = l2cap::exchange(&mut l2cap, Channel::ATT, req).unwrap();
+            assert_eq!(sent, rsp);
+        }
+    }
+
+    #[test]
+    fn l2cap_too_long() {
+        let mut buf = [0; 64];
+        assert_eq!(
+            l2cap_message(Channel::ATT, &[0; 24], &mut buf).unwrap_err(),
+            Error::InvalidLength
+        );
+        assert_eq!(
+            parse_l2cap(&ATT_HANDLE_VALUE_NTF).unwrap_err(),
+            Error::InvalidLength
+        );
+    }
+}
@@ -3,9 +3,12 @@
 //! These APIs are made for the BLE stack and are not meant to be general-purpose. The APIs here
 //! have microsecond resolution and use 32-bit arithmetic wherever possible.
 
-use core::{
-    fmt,
-    ops::{Add, AddAssign, Sub, SubAssign},
+use {
+    core::{
+        fmt,
+        ops::{Add, AddAssign, Sub, SubAssign},
+    },
+    heapless::{ArrayLength, Vec},
 };
 
 /// A duration with microsecond resolution.
@@ -19,11 +22,38 @@ impl Duration {
     /// The duration of the interframe spacing between BLE packets.
     pub const T_IFS: Self = Duration(150);
 
+    /// Tick rate of a 16 MHz high-frequency radio timer, in Hz.
+    pub const TICKS_16MHZ: u32 = 16_000_000;
+
+    /// Tick rate of a 32.768 kHz low-frequency (RTC) timer, in Hz.
+    pub const TICKS_32KHZ: u32 = 32_768;
+
     /// Creates a `Duration` from a number of microseconds.
-    pub fn from_micros(micros: u32) -> Self {
+    pub const fn from_micros(micros: u32) -> Self {
         Duration(micros)
     }
 
+    /// Creates a `Duration` from a number of 1.25 ms units.
+    ///
+    /// Connection intervals and transmit window parameters are specified in these units.
+    pub fn from_1250us_units(units: u16) -> Self {
+        Duration(u32::from(units) * 1_250)
+    }
+
+    /// Creates a `Duration` from a number of ticks of a timer running at `tick_hz` Hz.
+    ///
+    /// The result is rounded down to whole microseconds. Conversions from a 1 MHz or 16 MHz timer
+    /// are always exact, while conversions from a 32.768 kHz timer generally are not.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `tick_hz` is 0 or the resulting duration doesn't fit in a `Duration`.
+    pub fn from_ticks(ticks: u32, tick_hz: u32) -> Self {
+        let micros = u64::from(ticks) * 1_000_000 / u64::from(tick_hz);
+        assert!(micros <= u64::from(u32::MAX), "duration overflow");
+        Duration(micros as u32)
+    }
+
     /// Creates a `Duration` representing the given number of milliseconds.
     pub fn from_millis(millis: u16) -> Self {
         Duration(u32::from(millis) * 1_000)
@@ -49,6 +79,24 @@ impl Duration {
         self.0
     }
 
+    /// Returns the number of whole 1.25 ms units that fit in `self`.
+    pub fn whole_1250us_units(&self) -> u32 {
+        self.0 / 1_250
+    }
+
+    /// Converts `self` to a number of ticks of a timer running at `tick_hz` Hz.
+    ///
+    /// The result is rounded down to whole ticks.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the number of ticks doesn't fit in a `u32`.
+    pub fn to_ticks(&self, tick_hz: u32) -> u32 {
+        let ticks = u64::from(self.0) * u64::from(tick_hz) / 1_000_000;
+        assert!(ticks <= u64::from(u32::MAX), "tick overflow");
+        ticks as u32
+    }
+
     /// Returns the fractional part of microseconds in `self`.
     pub fn subsec_micros(&self) -> u32 {
         self.0 % 1_000_000
@@ -142,7 +190,7 @@ impl fmt::Debug for Duration {
 ///
 /// [`Duration`]: struct.Duration.html
 /// [`Timer`]: trait.Timer.html
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct Instant(u32);
 
 impl Instant {
@@ -283,3 +331,267 @@ pub trait Timer {
     /// underlying value wraps around.
     fn now(&self) -> Instant;
 }
+
+/// A timer scheduled in a [`TimerWheel`].
+///
+/// [`TimerWheel`]: struct.TimerWheel.html
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScheduledTimer {
+    id: u8,
+    deadline: Instant,
+    period: Option<Duration>,
+}
+
+impl ScheduledTimer {
+    /// Returns the identifier the timer was scheduled with.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Returns the time at which the timer expires next.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Returns the period of a repeating timer, or `None` if the timer is a one-shot timer.
+    pub fn period(&self) -> Option<Duration> {
+        self.period
+    }
+}
+
+/// Error returned when a `TimerWheel` has no space for another timer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WheelFull;
+
+/// A fixed-capacity set of one-shot and repeating timers.
+///
+/// `N` is the maximum number of timers that can be scheduled at once. Timers are identified by an
+/// application-chosen `u8`; scheduling a timer with an identifier that's already in use replaces
+/// the existing timer.
+///
+/// The wheel doesn't drive any hardware itself. After changing the timers, program the hardware
+/// timer to fire at `next_expiry`, and when it does, call `expired` repeatedly to obtain the
+/// timers that are due.
+///
+/// Deadlines are compared using wrapping arithmetic, so all scheduled deadlines must be within
+/// `Instant::MAX_TIME_BETWEEN` of each other and of the current time.
+pub struct TimerWheel<N: ArrayLength<ScheduledTimer>> {
+    timers: Vec<ScheduledTimer, N>,
+}
+
+impl<N: ArrayLength<ScheduledTimer>> TimerWheel<N> {
+    /// Creates a timer wheel without any scheduled timers.
+    pub fn new() -> Self {
+        Self { timers: Vec::new() }
+    }
+
+    /// Schedules timer `id` to expire once at `deadline`.
+    pub fn schedule_once(&mut self, id: u8, deadline: Instant) -> Result<(), WheelFull> {
+        self.schedule(ScheduledTimer {
+            id,
+            deadline,
+            period: None,
+        })
+    }
+
+    /// Schedules timer `id` to expire at `first`, and every `period` after that.
+    ///
+    /// This can be used for events that happen at a fixed interval, like advertising events.
+    pub fn schedule_periodic(
+        &mut self,
+        id: u8,
+        first: Instant,
+        period: Duration,
+    ) -> Result<(), WheelFull> {
+        self.schedule(ScheduledTimer {
+            id,
+            deadline: first,
+            period: Some(period),
+        })
+    }
+
+    fn schedule(&mut self, timer: ScheduledTimer) -> Result<(), WheelFull> {
+        if let Some(existing) = self.timers.iter_mut().find(|t| t.id == timer.id) {
+            *existing = timer;
+            Ok(())
+        } else {
+            self.timers.push(timer).map_err(|_| WheelFull)
+        }
+    }
+
+    /// Cancels timer `id`.
+    ///
+    /// Returns the cancelled timer, or `None` if no timer with that identifier was scheduled.
+    pub fn cancel(&mut self, id: u8) -> Option<ScheduledTimer> {
+        let index = self.timers.iter().position(|t| t.id == id)?;
+        Some(self.timers.swap_remove(index))
+    }
+
+    /// Returns the timer with identifier `id`, if it is scheduled.
+    pub fn get(&self, id: u8) -> Option<&ScheduledTimer> {
+        self.timers.iter().find(|t| t.id == id)
+    }
+
+    /// Returns the number of scheduled timers.
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Returns whether no timers are scheduled.
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Returns the deadline of the timer that expires next, or `None` if no timers are scheduled.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.next_timer().map(|index| self.timers[index].deadline)
+    }
+
+    /// Returns the identifier of the next timer that has expired at `now`.
+    ///
+    /// Expired timers are returned in the order of their deadlines. One-shot timers are removed
+    /// from the wheel, while repeating timers are rescheduled one period after their previous
+    /// deadline (not after `now`, so they don't drift when handled late).
+    ///
+    /// Call this in a loop until it returns `None` to handle all expired timers.
+    pub fn expired(&mut self, now: Instant) -> Option<u8> {
+        let index = self.next_timer()?;
+        let timer = self.timers[index];
+        if is_before(now, timer.deadline) {
+            return None;
+        }
+
+        match timer.period {
+            Some(period) => self.timers[index].deadline += period,
+            None => {
+                self.timers.swap_remove(index);
+            }
+        }
+        Some(timer.id)
+    }
+
+    /// Returns the index of the timer with the earliest deadline.
+    fn next_timer(&self) -> Option<usize> {
+        let mut next: Option<usize> = None;
+        for (index, timer) in self.timers.iter().enumerate() {
+            match next {
+                Some(n) if !is_before(timer.deadline, self.timers[n].deadline) => {}
+                _ => next = Some(index),
+            }
+        }
+        next
+    }
+}
+
+impl<N: ArrayLength<ScheduledTimer>> Default for TimerWheel<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns whether `a` is earlier than `b`, taking wraparound into account.
+pub(crate) fn is_before(a: Instant, b: Instant) -> bool {
+    (a.raw_micros().wrapping_sub(b.raw_micros()) as i32) < 0
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, heapless::consts::U4};
+
+    #[test]
+    fn ble_intervals_round_trip() {
+        // 7.5 ms (the minimum connection interval), 1.25 ms, and the 4 s maximum
+        for &units in &[6, 1, 3200] {
+            let dur = Duration::from_1250us_units(units);
+            assert_eq!(dur.whole_1250us_units(), u32::from(units));
+
+            for &hz in &[1_000_000, Duration::TICKS_16MHZ] {
+                let ticks = dur.to_ticks(hz);
+                assert_eq!(Duration::from_ticks(ticks, hz), dur);
+            }
+        }
+
+        assert_eq!(Duration::from_1250us_units(6), Duration::from_micros(7_500));
+        assert_eq!(
+            Duration::from_micros(7_500).to_ticks(Duration::TICKS_16MHZ),
+            120_000
+        );
+    }
+
+    #[test]
+    fn ticks_32khz() {
+        // 32 768 ticks are exactly one second
+        assert_eq!(
+            Duration::from_ticks(Duration::TICKS_32KHZ, Duration::TICKS_32KHZ),
+            Duration::from_secs(1)
+        );
+
+        // 7.5 ms is 245.76 ticks, so the round trip loses less than one tick
+        let dur = Duration::from_micros(7_500);
+        let ticks = dur.to_ticks(Duration::TICKS_32KHZ);
+        assert_eq!(ticks, 245);
+        let back = Duration::from_ticks(ticks, Duration::TICKS_32KHZ);
+        assert!(back <= dur);
+        assert!(
+            dur - back < Duration::from_ticks(1, Duration::TICKS_32KHZ) + Duration::from_micros(1)
+        );
+    }
+
+    #[test]
+    fn timer_wheel_ordering() {
+        let at = Instant::from_raw_micros;
+        let mut wheel = TimerWheel::<U4>::new();
+        assert_eq!(wheel.next_expiry(), None);
+
+        wheel.schedule_once(1, at(3000)).unwrap();
+        wheel
+            .schedule_periodic(2, at(1000), Duration::from_millis(2))
+            .unwrap();
+        wheel.schedule_once(3, at(2000)).unwrap();
+        assert_eq!(wheel.next_expiry(), Some(at(1000)));
+
+        assert_eq!(wheel.expired(at(999)), None);
+
+        // Timers are returned in deadline order, and the periodic timer comes back every 2 ms
+        assert_eq!(wheel.expired(at(3500)), Some(2));
+        assert_eq!(wheel.expired(at(3500)), Some(3));
+        assert_eq!(wheel.expired(at(3500)), Some(1));
+        assert_eq!(wheel.expired(at(3500)), Some(2));
+        assert_eq!(wheel.expired(at(3500)), None);
+        assert_eq!(wheel.len(), 1);
+        assert_eq!(wheel.next_expiry(), Some(at(5000)));
+
+        // Deadlines on either side of a wraparound are ordered correctly
+        let mut wheel = TimerWheel::<U4>::new();
+        wheel.schedule_once(1, at(100)).unwrap();
+        wheel.schedule_once(2, at(u32::max_value() - 100)).unwrap();
+        assert_eq!(wheel.next_expiry(), Some(at(u32::max_value() - 100)));
+        assert_eq!(wheel.expired(at(50)), Some(2));
+        assert_eq!(wheel.expired(at(50)), None);
+    }
+
+    #[test]
+    fn timer_wheel_cancel() {
+        let at = Instant::from_raw_micros;
+        let mut wheel = TimerWheel::<U4>::new();
+        for id in 0..4 {
+            wheel.schedule_once(id, at(1000 * u32::from(id))).unwrap();
+        }
+        assert_eq!(wheel.schedule_once(4, at(0)), Err(WheelFull));
+
+        // Rescheduling an existing timer doesn't take up more space
+        wheel.schedule_once(3, at(500)).unwrap();
+        assert_eq!(wheel.get(3).map(|t| t.deadline()), Some(at(500)));
+
+        assert_eq!(wheel.cancel(0).map(|t| t.id()), Some(0));
+        assert_eq!(wheel.cancel(0), None);
+        assert_eq!(wheel.next_expiry(), Some(at(500)));
+
+        // Cancelled timers never expire
+        wheel.cancel(3);
+        assert_eq!(wheel.expired(at(10_000)), Some(1));
+        assert_eq!(wheel.expired(at(10_000)), Some(2));
+        assert_eq!(wheel.expired(at(10_000)), None);
+        assert!(wheel.is_empty());
+    }
+}
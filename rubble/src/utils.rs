@@ -45,6 +45,71 @@ macro_rules! enum_with_unknown {
     }
 }
 
+/// Asserts at compile time that a constant attribute table is valid.
+///
+/// The argument must be a constant expression evaluating to an array or slice of `Attribute`s,
+/// which is checked using `att::check_attribute_table`. This can be invoked anywhere an item is
+/// allowed, eg. at the start of the constructor of the provider hosting the table.
+///
+/// ```
+/// use rubble::{
+///     assert_attribute_table,
+///     att::{AttUuid, Attribute, Handle},
+///     uuid::Uuid16,
+/// };
+///
+/// const ATTRS: [Attribute<'static>; 3] = [
+///     Attribute::new(
+///         AttUuid::Uuid16(Uuid16::PRIMARY_SERVICE),
+///         Handle::from_raw(0x0001),
+///         &[0x0F, 0x18],
+///     ),
+///     Attribute::new(
+///         AttUuid::Uuid16(Uuid16::CHARACTERISTIC),
+///         Handle::from_raw(0x0002),
+///         &[0x02, 0x03, 0x00, 0x19, 0x2A],
+///     ),
+///     Attribute::new(
+///         AttUuid::Uuid16(Uuid16::BATTERY_LEVEL),
+///         Handle::from_raw(0x0003),
+///         &[100],
+///     ),
+/// ];
+///
+/// assert_attribute_table!(ATTRS);
+/// ```
+///
+/// A table whose characteristic declaration doesn't point to the following attribute is rejected:
+///
+/// ```compile_fail
+/// use rubble::{
+///     assert_attribute_table,
+///     att::{AttUuid, Attribute, Handle},
+///     uuid::Uuid16,
+/// };
+///
+/// const ATTRS: [Attribute<'static>; 2] = [
+///     Attribute::new(
+///         AttUuid::Uuid16(Uuid16::CHARACTERISTIC),
+///         Handle::from_raw(0x0001),
+///         &[0x02, 0x05, 0x00, 0x19, 0x2A],
+///     ),
+///     Attribute::new(
+///         AttUuid::Uuid16(Uuid16::BATTERY_LEVEL),
+///         Handle::from_raw(0x0002),
+///         &[100],
+///     ),
+/// ];
+///
+/// assert_attribute_table!(ATTRS);
+/// ```
+#[macro_export]
+macro_rules! assert_attribute_table {
+    ($table:expr) => {
+        const _: () = $crate::att::check_attribute_table(&$table);
+    };
+}
+
 /// `Debug`-formats its contents as a hexadecimal byte slice.
 #[derive(Copy, Clone)]
 pub struct HexSlice<T>(pub T)
@@ -13,11 +13,38 @@
 //! A 32-bit UUID alias can then be converted to its full 128-bit equivalent by placing it in the
 //! first 4 Bytes of the Base UUID. Hence `0x1234ABCD` would become
 //! `1234ABCD-0000-1000-8000-00805F9B34FB`.
+//!
+//! # Byte order
+//!
+//! Bluetooth transmits UUIDs in little-endian byte order, which is the reverse of the order in
+//! which the bytes appear in the textual representation. `6E400001-B5A3-F393-E0A9-E50E24DCCA9E`
+//! appears on air as `[0x9E, 0xCA, 0xDC, 0x24, ..., 0x01, 0x00, 0x40, 0x6E]`.
+//!
+//! A 128-bit [`Uuid`] used by Rubble always stores its bytes in this on-air order. The
+//! [`ToBytes`] and [`FromBytes`] implementations write and read the bytes of `Uuid::as_bytes`
+//! unchanged, and UUIDs converted from 16- and 32-bit aliases use the same order. Hence:
+//!
+//! * `Uuid::from_bytes` expects little-endian bytes, as received over the air.
+//! * A UUID given in its textual form is created with `Uuid::from_u128_le`, eg.
+//!   `Uuid::from_u128_le(0x6E400001_B5A3_F393_E0A9_E50E24DCCA9E)`.
+//! * The `Display` and `Debug` implementations of `Uuid`, which come from the `uuid` crate, print
+//!   the stored bytes, and thus the UUID reversed. `AttUuid` prints the textual form instead.
+//!
+//! The explicitly named methods of the [`UuidExt`] trait convert between a `Uuid` and byte arrays
+//! in either order.
+//!
+//! [`Uuid`]: struct.Uuid.html
+//! [`ToBytes`]: ../bytes/trait.ToBytes.html
+//! [`FromBytes`]: ../bytes/trait.FromBytes.html
+//! [`UuidExt`]: trait.UuidExt.html
 
 use {
     crate::{bytes::*, Error},
     byteorder::{BigEndian, ByteOrder},
-    core::fmt,
+    core::{
+        convert::{TryFrom, TryInto},
+        fmt,
+    },
 };
 
 pub use uuid::Uuid;
@@ -28,15 +55,176 @@ const BASE_UUID: [u8; 16] = [
     /*-*/ 0x00, 0x80, 0x5F, 0x9B, 0x34, 0xFB,
 ];
 
+/// Conversions between 128-bit UUIDs and byte arrays with an explicit byte order.
+///
+/// This is implemented for [`Uuid`], which is defined by the `uuid` crate. Bring it into scope to
+/// use the methods, eg. `Uuid::from_bytes_le(bytes)`.
+///
+/// A `Uuid` stores its bytes in little-endian (on-air) order (see the [module docs]), so the
+/// little-endian conversions match `Uuid::from_bytes` and `Uuid::as_bytes`, and the big-endian
+/// ones reverse the bytes.
+///
+/// [`Uuid`]: struct.Uuid.html
+/// [module docs]: index.html
+pub trait UuidExt {
+    /// Creates a UUID from bytes in big-endian order (the order of the textual representation).
+    fn from_bytes_be(bytes: [u8; 16]) -> Self;
+
+    /// Creates a UUID from bytes in little-endian order (the order used on air, like
+    /// `Uuid::from_bytes`).
+    fn from_bytes_le(bytes: [u8; 16]) -> Self;
+
+    /// Returns the bytes of the UUID in big-endian order (the order of the textual
+    /// representation).
+    fn to_bytes_be(&self) -> [u8; 16];
+
+    /// Returns the bytes of the UUID in little-endian order (the order used on air, like
+    /// `Uuid::as_bytes`).
+    fn to_bytes_le(&self) -> [u8; 16];
+}
+
+impl UuidExt for Uuid {
+    fn from_bytes_be(mut bytes: [u8; 16]) -> Self {
+        bytes.reverse();
+        Uuid::from_bytes(bytes)
+    }
+
+    fn from_bytes_le(bytes: [u8; 16]) -> Self {
+        Uuid::from_bytes(bytes)
+    }
+
+    fn to_bytes_be(&self) -> [u8; 16] {
+        let mut bytes = *self.as_bytes();
+        bytes.reverse();
+        bytes
+    }
+
+    fn to_bytes_le(&self) -> [u8; 16] {
+        *self.as_bytes()
+    }
+}
+
 /// A 16-bit UUID alias.
 ///
-/// Can be converted to its 32- and 128-bit equivalents via `.into()`.
+/// Can be converted to its 32- and 128-bit equivalents via `.into()`. Longer UUIDs can be
+/// converted back with `Uuid16::try_from` if they are based on the Bluetooth Base UUID.
 #[derive(PartialEq, Eq, Copy, Clone)]
 pub struct Uuid16(pub u16);
 
+/// SIG-assigned 16-bit UUIDs.
+///
+/// These are the UUIDs most commonly needed when building attribute tables. Since they are
+/// associated constants, they can be used in `const` and `static` items.
+impl Uuid16 {
+    // GATT declarations
+
+    /// *Primary Service* declaration.
+    pub const PRIMARY_SERVICE: Self = Uuid16(0x2800);
+    /// *Secondary Service* declaration.
+    pub const SECONDARY_SERVICE: Self = Uuid16(0x2801);
+    /// *Include* declaration.
+    pub const INCLUDE: Self = Uuid16(0x2802);
+    /// *Characteristic* declaration.
+    pub const CHARACTERISTIC: Self = Uuid16(0x2803);
+
+    // GATT descriptors
+
+    /// *Characteristic Extended Properties* descriptor.
+    pub const CHARACTERISTIC_EXTENDED_PROPERTIES: Self = Uuid16(0x2900);
+    /// *Characteristic User Description* descriptor.
+    pub const CHARACTERISTIC_USER_DESCRIPTION: Self = Uuid16(0x2901);
+    /// *Client Characteristic Configuration* descriptor (CCCD).
+    pub const CLIENT_CHARACTERISTIC_CONFIGURATION: Self = Uuid16(0x2902);
+    /// *Server Characteristic Configuration* descriptor.
+    pub const SERVER_CHARACTERISTIC_CONFIGURATION: Self = Uuid16(0x2903);
+    /// *Characteristic Presentation Format* descriptor.
+    pub const CHARACTERISTIC_PRESENTATION_FORMAT: Self = Uuid16(0x2904);
+    /// *Characteristic Aggregate Format* descriptor.
+    pub const CHARACTERISTIC_AGGREGATE_FORMAT: Self = Uuid16(0x2905);
+    /// *Valid Range* descriptor.
+    pub const VALID_RANGE: Self = Uuid16(0x2906);
+    /// *External Report Reference* descriptor.
+    pub const EXTERNAL_REPORT_REFERENCE: Self = Uuid16(0x2907);
+    /// *Report Reference* descriptor.
+    pub const REPORT_REFERENCE: Self = Uuid16(0x2908);
+
+    // Services
+
+    /// *Generic Access* service.
+    pub const GENERIC_ACCESS: Self = Uuid16(0x1800);
+    /// *Generic Attribute* service.
+    pub const GENERIC_ATTRIBUTE: Self = Uuid16(0x1801);
+    /// *Immediate Alert* service.
+    pub const IMMEDIATE_ALERT: Self = Uuid16(0x1802);
+    /// *Link Loss* service.
+    pub const LINK_LOSS: Self = Uuid16(0x1803);
+    /// *Tx Power* service.
+    pub const TX_POWER: Self = Uuid16(0x1804);
+    /// *Device Information* service.
+    pub const DEVICE_INFORMATION: Self = Uuid16(0x180A);
+    /// *Heart Rate* service.
+    pub const HEART_RATE: Self = Uuid16(0x180D);
+    /// *Battery Service*.
+    pub const BATTERY_SERVICE: Self = Uuid16(0x180F);
+    /// *Human Interface Device* service.
+    pub const HUMAN_INTERFACE_DEVICE: Self = Uuid16(0x1812);
+    /// *Environmental Sensing* service.
+    pub const ENVIRONMENTAL_SENSING: Self = Uuid16(0x181A);
+
+    // Characteristics
+
+    /// *Device Name* characteristic of the *Generic Access* service.
+    pub const DEVICE_NAME: Self = Uuid16(0x2A00);
+    /// *Appearance* characteristic of the *Generic Access* service.
+    pub const APPEARANCE: Self = Uuid16(0x2A01);
+    /// *Peripheral Preferred Connection Parameters* characteristic.
+    pub const PERIPHERAL_PREFERRED_CONNECTION_PARAMETERS: Self = Uuid16(0x2A04);
+    /// *Service Changed* characteristic of the *Generic Attribute* service.
+    pub const SERVICE_CHANGED: Self = Uuid16(0x2A05);
+    /// *Battery Level* characteristic.
+    pub const BATTERY_LEVEL: Self = Uuid16(0x2A19);
+    /// *Model Number String* characteristic.
+    pub const MODEL_NUMBER_STRING: Self = Uuid16(0x2A24);
+    /// *Serial Number String* characteristic.
+    pub const SERIAL_NUMBER_STRING: Self = Uuid16(0x2A25);
+    /// *Firmware Revision String* characteristic.
+    pub const FIRMWARE_REVISION_STRING: Self = Uuid16(0x2A26);
+    /// *Hardware Revision String* characteristic.
+    pub const HARDWARE_REVISION_STRING: Self = Uuid16(0x2A27);
+    /// *Software Revision String* characteristic.
+    pub const SOFTWARE_REVISION_STRING: Self = Uuid16(0x2A28);
+    /// *Manufacturer Name String* characteristic.
+    pub const MANUFACTURER_NAME_STRING: Self = Uuid16(0x2A29);
+    /// *Heart Rate Measurement* characteristic.
+    pub const HEART_RATE_MEASUREMENT: Self = Uuid16(0x2A37);
+    /// *Temperature* characteristic.
+    pub const TEMPERATURE: Self = Uuid16(0x2A6E);
+    /// *Client Supported Features* characteristic of the *Generic Attribute* service.
+    pub const CLIENT_SUPPORTED_FEATURES: Self = Uuid16(0x2B29);
+    /// *Database Hash* characteristic of the *Generic Attribute* service.
+    pub const DATABASE_HASH: Self = Uuid16(0x2B2A);
+
+    // Units (used in *Characteristic Presentation Format* descriptors)
+
+    /// Unitless quantity.
+    pub const UNITLESS: Self = Uuid16(0x2700);
+    /// Length in metres.
+    pub const METRE: Self = Uuid16(0x2701);
+    /// Mass in kilograms.
+    pub const KILOGRAM: Self = Uuid16(0x2702);
+    /// Time in seconds.
+    pub const SECOND: Self = Uuid16(0x2703);
+    /// Electric potential difference in volts.
+    pub const VOLT: Self = Uuid16(0x2728);
+    /// Thermodynamic temperature in degrees Celsius.
+    pub const DEGREE_CELSIUS: Self = Uuid16(0x272F);
+    /// Percentage.
+    pub const PERCENTAGE: Self = Uuid16(0x27AD);
+}
+
 /// A 32-bit UUID alias.
 ///
-/// Can be converted to its 128-bit equivalent via `.into()`.
+/// Can be converted to its 128-bit equivalent via `.into()`, and back via `Uuid32::try_from`.
 #[derive(PartialEq, Eq, Copy, Clone)]
 pub struct Uuid32(pub u32);
 
@@ -46,17 +234,52 @@ impl From<Uuid16> for Uuid32 {
     }
 }
 
-impl Into<Uuid> for Uuid16 {
-    fn into(self) -> Uuid {
-        Uuid32::from(self).into()
+impl From<Uuid16> for Uuid {
+    fn from(smol: Uuid16) -> Self {
+        Uuid32::from(smol).into()
     }
 }
 
-impl Into<Uuid> for Uuid32 {
-    fn into(self) -> Uuid {
+impl From<Uuid32> for Uuid {
+    fn from(smol: Uuid32) -> Self {
         let mut buf = BASE_UUID;
-        BigEndian::write_u32(&mut buf, self.0);
-        Uuid::from_bytes(buf)
+        BigEndian::write_u32(&mut buf, smol.0);
+        Uuid::from_bytes_be(buf)
+    }
+}
+
+impl TryFrom<Uuid32> for Uuid16 {
+    type Error = Error;
+
+    /// Fails with `Error::InvalidValue` if `uuid` doesn't fit in 16 bits.
+    fn try_from(uuid: Uuid32) -> Result<Self, Error> {
+        uuid.0
+            .try_into()
+            .map(Uuid16)
+            .map_err(|_| Error::InvalidValue)
+    }
+}
+
+impl TryFrom<Uuid> for Uuid32 {
+    type Error = Error;
+
+    /// Fails with `Error::InvalidValue` if `uuid` isn't based on the Bluetooth Base UUID.
+    fn try_from(uuid: Uuid) -> Result<Self, Error> {
+        let bytes = uuid.to_bytes_be();
+        if bytes[4..] == BASE_UUID[4..] {
+            Ok(Uuid32(BigEndian::read_u32(&bytes[..4])))
+        } else {
+            Err(Error::InvalidValue)
+        }
+    }
+}
+
+impl TryFrom<Uuid> for Uuid16 {
+    type Error = Error;
+
+    /// Fails with `Error::InvalidValue` if `uuid` isn't the 128-bit equivalent of a 16-bit alias.
+    fn try_from(uuid: Uuid) -> Result<Self, Error> {
+        Uuid32::try_from(uuid)?.try_into()
     }
 }
 
@@ -111,6 +334,40 @@ impl fmt::Debug for Uuid32 {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Uuid16 {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "Uuid16({=u16:04x})", self.0)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Uuid32 {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "Uuid32({=u32:08x})", self.0)
+    }
+}
+
+/// Formats a 128-bit UUID in its hyphenated form.
+///
+/// `Uuid` is defined by the `uuid` crate, so it can't implement `defmt::Format` itself.
+#[cfg(feature = "defmt")]
+pub(crate) fn format_uuid(f: defmt::Formatter<'_>, uuid: &Uuid) {
+    let uuid = Uuid::from_bytes(uuid.to_bytes_be());
+    let (d1, d2, d3, d4) = uuid.as_fields();
+    let d4_hi = u16::from_be_bytes([d4[0], d4[1]]);
+    let d4_lo = u64::from_be_bytes([0, 0, d4[2], d4[3], d4[4], d4[5], d4[6], d4[7]]);
+    defmt::write!(
+        f,
+        "{=u32:08x}-{=u16:04x}-{=u16:04x}-{=u16:04x}-{=u64:012x}",
+        d1,
+        d2,
+        d3,
+        d4_hi,
+        d4_lo
+    )
+}
+
 /// List of the supported UUID types.
 #[derive(Debug, Copy, Clone)]
 pub enum UuidKind {
@@ -139,3 +396,109 @@ impl IsUuid for Uuid32 {
 impl IsUuid for Uuid {
     const KIND: UuidKind = UuidKind::Uuid128;
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::att::AttUuid};
+
+    #[test]
+    fn byte_order_round_trip() {
+        let bytes = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ];
+        assert_eq!(Uuid::from_bytes_le(bytes).to_bytes_le(), bytes);
+        assert_eq!(Uuid::from_bytes_be(bytes).to_bytes_be(), bytes);
+        assert_eq!(Uuid::from_bytes_le(bytes), Uuid::from_bytes(bytes));
+
+        // The wire encoding doesn't reorder bytes
+        let mut buf = [0; 16];
+        let uuid = Uuid::from_bytes_le(bytes);
+        uuid.to_bytes(&mut ByteWriter::new(&mut buf)).unwrap();
+        assert_eq!(buf, *uuid.as_bytes());
+        assert_eq!(
+            <Uuid as FromBytes>::from_bytes(&mut ByteReader::new(&buf)).unwrap(),
+            uuid
+        );
+    }
+
+    #[test]
+    fn known_uuid_byte_order() {
+        // Nordic UART Service
+        let nus = Uuid::from_u128_le(0x6E400001_B5A3_F393_E0A9_E50E24DCCA9E);
+        let be = [
+            0x6E, 0x40, 0x00, 0x01, 0xB5, 0xA3, 0xF3, 0x93, 0xE0, 0xA9, 0xE5, 0x0E, 0x24, 0xDC,
+            0xCA, 0x9E,
+        ];
+        let le = [
+            0x9E, 0xCA, 0xDC, 0x24, 0x0E, 0xE5, 0xA9, 0xE0, 0x93, 0xF3, 0xA3, 0xB5, 0x01, 0x00,
+            0x40, 0x6E,
+        ];
+        assert_eq!(nus.to_bytes_be(), be);
+        assert_eq!(nus.to_bytes_le(), le);
+        assert_eq!(Uuid::from_bytes_le(le), nus);
+        assert_eq!(Uuid::from_bytes_be(be), nus);
+
+        // The on-air encoding is little-endian
+        let mut buf = [0; 16];
+        nus.to_bytes(&mut ByteWriter::new(&mut buf)).unwrap();
+        assert_eq!(buf, le);
+
+        // Aliases expand to the Base UUID in the same order
+        let battery: Uuid = Uuid16::BATTERY_SERVICE.into();
+        let le = [
+            0xFB, 0x34, 0x9B, 0x5F, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0x0F, 0x18,
+            0x00, 0x00,
+        ];
+        assert_eq!(battery.to_bytes_le(), le);
+        battery.to_bytes(&mut ByteWriter::new(&mut buf)).unwrap();
+        assert_eq!(buf, le);
+        let received = AttUuid::from_bytes(&mut ByteReader::new(&le)).unwrap();
+        assert_eq!(received, Uuid16::BATTERY_SERVICE);
+
+        // `AttUuid` prints the textual form
+        assert_eq!(
+            std::format!("{:?}", AttUuid::from(nus)),
+            "6e400001-b5a3-f393-e0a9-e50e24dcca9e"
+        );
+    }
+
+    #[test]
+    fn alias_conversions() {
+        let battery = Uuid::from_u128_le(0x0000180F_0000_1000_8000_00805F9B34FB);
+        assert_eq!(Uuid::from(Uuid16::BATTERY_SERVICE), battery);
+        assert_eq!(Uuid::from(Uuid32(0x0000180F)), battery);
+        assert_eq!(Uuid16::try_from(battery), Ok(Uuid16::BATTERY_SERVICE));
+        assert_eq!(Uuid32::try_from(battery), Ok(Uuid32(0x180F)));
+
+        // 32-bit aliases only fit into `Uuid32`
+        let long = Uuid::from_u128_le(0x1234ABCD_0000_1000_8000_00805F9B34FB);
+        assert_eq!(Uuid32::try_from(long), Ok(Uuid32(0x1234ABCD)));
+        assert_eq!(Uuid16::try_from(long), Err(Error::InvalidValue));
+        assert_eq!(
+            Uuid16::try_from(Uuid32(0x1234ABCD)),
+            Err(Error::InvalidValue)
+        );
+
+        // UUIDs not based on the Base UUID have no alias
+        let nus = Uuid::from_u128_le(0x6E400001_B5A3_F393_E0A9_E50E24DCCA9E);
+        assert_eq!(Uuid16::try_from(nus), Err(Error::InvalidValue));
+        assert_eq!(Uuid32::try_from(nus), Err(Error::InvalidValue));
+        let almost = Uuid::from_u128_le(0x0000180F_0000_1000_8000_00805F9B34FC);
+        assert_eq!(Uuid16::try_from(almost), Err(Error::InvalidValue));
+
+        // `AttUuid`s convert to and from all of them
+        assert_eq!(AttUuid::from(Uuid16::BATTERY_SERVICE), battery);
+        assert_eq!(AttUuid::from(battery), Uuid16::BATTERY_SERVICE);
+        assert_eq!(Uuid::from(AttUuid::from(Uuid16::BATTERY_SERVICE)), battery);
+        assert_eq!(Uuid::from(AttUuid::from(nus)), nus);
+        assert_eq!(
+            Uuid16::try_from(AttUuid::from(battery)),
+            Ok(Uuid16::BATTERY_SERVICE)
+        );
+        assert_eq!(
+            Uuid16::try_from(AttUuid::from(nus)),
+            Err(Error::InvalidValue)
+        );
+    }
+}